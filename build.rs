@@ -0,0 +1,53 @@
+//! Embeds build information, served at `/version` and printed by `container-registry --version`.
+//!
+//! Sets `CONTAINER_REGISTRY_GIT_HASH` to the commit built from, or `unknown` outside a git
+//! checkout, and `CONTAINER_REGISTRY_BUILD_TIMESTAMP` to the build time in seconds since the epoch,
+//! taken from `SOURCE_DATE_EPOCH` if set for reproducible builds.
+
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default()
+        });
+
+    println!("cargo:rustc-env=CONTAINER_REGISTRY_GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=CONTAINER_REGISTRY_BUILD_TIMESTAMP={timestamp}");
+
+    // Only rebuild when the checked out commit changes, not on every build.
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=build.rs");
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(reference) = std::fs::read_to_string(head)
+            .ok()
+            .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_owned()))
+        {
+            let reference = Path::new(".git").join(reference);
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    }
+}
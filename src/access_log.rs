@@ -0,0 +1,231 @@
+//! Access log.
+//!
+//! Optionally, one line per request is written in the Combined Log Format known from Apache and
+//! nginx, separate from the `tracing` output, to be fed to existing log analyzers:
+//!
+//! ```text
+//! 192.0.2.1 - user [14/Aug/2024:12:00:00 +0000] "GET /v2/ HTTP/1.1" 200 2 "-" "docker/24.0.5"
+//! ```
+//!
+//! The user is only known for requests that were authenticated, `-` is logged otherwise.
+
+use std::{
+    fmt::{self, Display},
+    io::Write,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use axum::{
+    body::HttpBody,
+    extract::{Request, State},
+    http::{
+        header::{CONTENT_LENGTH, REFERER, USER_AGENT},
+        HeaderMap, HeaderName, StatusCode,
+    },
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+use crate::{client_ip::ClientIp, webhooks};
+
+/// Destination of the access log.
+#[derive(Clone)]
+pub(crate) struct AccessLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl AccessLog {
+    /// Creates a new access log, writing to the given writer.
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+}
+
+/// The user a request was authenticated as.
+///
+/// Attached to every request by the access log middleware, filled in once credentials have been
+/// validated.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AccessLogUser(Arc<Mutex<Option<String>>>);
+
+impl AccessLogUser {
+    /// Records the user the request was authenticated as.
+    pub(crate) fn set(&self, username: &str) {
+        *self.0.lock().expect("lock poisoned") = Some(username.to_owned());
+    }
+
+    fn get(&self) -> Option<String> {
+        self.0.lock().expect("lock poisoned").clone()
+    }
+}
+
+/// Middleware writing an access log line for every request.
+pub(crate) async fn log_request(
+    State(access_log): State<AccessLog>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user = AccessLogUser::default();
+    request.extensions_mut().insert(user.clone());
+
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|&ClientIp(addr)| addr);
+    let time = SystemTime::now();
+    let request_line = format!(
+        "{} {} {:?}",
+        request.method(),
+        request.uri(),
+        request.version()
+    );
+    let referer = header(request.headers(), REFERER);
+    let user_agent = header(request.headers(), USER_AGENT);
+
+    let response = next.run(request).await;
+
+    let bytes = response.body().size_hint().exact().or_else(|| {
+        header(response.headers(), CONTENT_LENGTH).and_then(|value| value.parse().ok())
+    });
+    let entry = Entry {
+        client_ip,
+        user: user.get(),
+        time,
+        request_line,
+        status: response.status(),
+        bytes,
+        referer,
+        user_agent,
+    };
+
+    // Lines are short, writing them synchronously is fine.
+    let mut writer = access_log.writer.lock().expect("lock poisoned");
+    if let Err(err) = writeln!(writer, "{entry}").and_then(|()| writer.flush()) {
+        warn!(%err, "failed to write access log");
+    }
+    drop(writer);
+
+    response
+}
+
+/// Returns a header value, with non-printable characters escaped.
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers.get(name).map(|value| escape(value.as_bytes()))
+}
+
+/// Escapes quotes, backslashes and non-printable characters, as Apache does.
+fn escape(raw: &[u8]) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for &byte in raw {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    escaped
+}
+
+/// A single access log line.
+struct Entry {
+    client_ip: Option<IpAddr>,
+    user: Option<String>,
+    time: SystemTime,
+    request_line: String,
+    status: StatusCode,
+    bytes: Option<u64>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+
+        match self.client_ip {
+            Some(client_ip) => write!(f, "{client_ip} - ")?,
+            None => f.write_str("- - ")?,
+        }
+        match self.user {
+            Some(ref user) => f.write_str(&escape(user.as_bytes()))?,
+            None => f.write_str("-")?,
+        }
+
+        let (year, month, day, secs_of_day) = webhooks::civil_time(self.time);
+        write!(
+            f,
+            " [{day:02}/{}/{year:04}:{:02}:{:02}:{:02} +0000]",
+            MONTHS[month as usize - 1],
+            secs_of_day / 3600,
+            (secs_of_day / 60) % 60,
+            secs_of_day % 60
+        )?;
+
+        write!(
+            f,
+            " \"{}\" {}",
+            escape(self.request_line.as_bytes()),
+            self.status.as_u16()
+        )?;
+
+        // Like Apache's `%b`, empty responses are logged as `-`.
+        match self.bytes {
+            Some(bytes) if bytes > 0 => write!(f, " {bytes}")?,
+            _ => f.write_str(" -")?,
+        }
+
+        write!(
+            f,
+            " \"{}\" \"{}\"",
+            self.referer.as_deref().unwrap_or("-"),
+            self.user_agent.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::http::StatusCode;
+
+    use super::Entry;
+
+    #[test]
+    fn formats_combined_log_format() {
+        let entry = Entry {
+            client_ip: Some("192.0.2.1".parse().unwrap()),
+            user: Some("user".to_owned()),
+            time: UNIX_EPOCH + Duration::from_secs(1723636800),
+            request_line: "GET /v2/ HTTP/1.1".to_owned(),
+            status: StatusCode::OK,
+            bytes: Some(2),
+            referer: None,
+            user_agent: Some("docker/24.0.5".to_owned()),
+        };
+        assert_eq!(
+            entry.to_string(),
+            r#"192.0.2.1 - user [14/Aug/2024:12:00:00 +0000] "GET /v2/ HTTP/1.1" 200 2 "-" "docker/24.0.5""#
+        );
+
+        let entry = Entry {
+            client_ip: None,
+            user: None,
+            bytes: Some(0),
+            user_agent: Some(super::escape(b"evil\" agent\n")),
+            ..entry
+        };
+        assert_eq!(
+            entry.to_string(),
+            r#"- - - [14/Aug/2024:12:00:00 +0000] "GET /v2/ HTTP/1.1" 200 - "-" "evil\" agent\x0a""#
+        );
+    }
+}
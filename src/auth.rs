@@ -5,42 +5,88 @@
 //! are implementations for the following types:
 //!
 //! * `Permissions`: The [`Permissions`] type itself is an auth provider, it will allow
-//!                  access with the given permissions to any non-anonymous client.
+//!   access with the given permissions to any non-anonymous client.
 //! * `HashMap<String, Secret<String>>`: A mapping of usernames to (unencrypted) passwords.
+//! * `HashMap<String, PasswordHash>`: A mapping of usernames to passwords, which may be stored
+//!   in plaintext or hashed using bcrypt or argon2.
 //! * `Secret<String>`: Master password, ignores all usernames and just compares the password.
 //! * `Anonymous`: A decorator that wraps around another [`AuthProvider`], will grant a fixed set
-//!                of permissions to anonymous user, while deferring everything else to the inner
-//!                provider.
+//!   of permissions to anonymous user, while deferring everything else to the inner provider.
+//! * `AnyOf`: Combines multiple providers, accepting credentials if any of them does.
+//! * `AllOf`: Combines multiple providers, accepting credentials only if all of them do.
+//! * `CachingAuthProvider`: A decorator that remembers successful logins of another
+//!   [`AuthProvider`] for a while, useful if checking credentials is expensive.
+//! * `ClientCertAuthProvider`: Accepts clients presenting a TLS client certificate whose
+//!   identity is on an allow-list, see [`ClientCertificate`].
 //!
 //! All the above implementations deal with **authentication** only, once authorized, full
 //! write access to everything is granted.
 //!
+//! Applications configuring authentication at runtime can describe one of these providers using
+//! an [`AuthConfig`], e.g. read from the environment by [`AuthConfig::from_env`], and construct
+//! it through [`build_auth_provider`].
+//!
 //! To provide some safety against accidentally leaking passwords via stray `Debug` implementations,
 //! this crate uses the [`sec`]'s crate [`Secret`] type.
 
-use std::{any::Any, collections::HashMap, str, sync::Arc};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    str,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use axum::{
     async_trait,
-    extract::FromRequestParts,
+    extract::{FromRequestParts, MatchedPath, RawPathParams},
     http::{
         header::{self},
         request::Parts,
         StatusCode,
     },
+    response::{IntoResponse, Response},
 };
 use sec::Secret;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tracing::{field::display, info, warn};
 
-use crate::{storage::ImageLocation, ImageDigest};
+use crate::{
+    storage::{ImageLocation, RegistryStorage},
+    ImageDigest,
+};
 
 use super::{
+    access_log::AccessLogUser,
+    client_ip::ClientIp,
+    rate_limit::{self, Access, ClientKey},
+    types::{ErrorCode, OciError, OciErrors},
+    unauthorized,
     www_authenticate::{self},
-    ContainerRegistry,
+    ContainerRegistry, Realm,
 };
 
+/// Hashes a secret to a fixed-length digest for comparison.
+pub(crate) fn secret_digest(secret: &Secret<String>) -> [u8; 32] {
+    Sha256::digest(secret.reveal().as_bytes()).into()
+}
+
+/// Compares two plaintext secrets in constant time.
+///
+/// Comparing the secrets directly would leak their length, as comparisons of inputs of differing
+/// lengths return early. Their SHA256 digests are compared instead, which are always of equal
+/// length.
+pub(crate) fn secrets_match(expected: &Secret<String>, provided: &Secret<String>) -> bool {
+    constant_time_eq::constant_time_eq_32(&secret_digest(expected), &secret_digest(provided))
+}
+
 /// A set of credentials supplied that has not been verified.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Unverified {
     /// A set of username and password credentials.
     UsernameAndPassword {
@@ -49,6 +95,8 @@ pub enum Unverified {
         /// The provided password.
         password: Secret<String>,
     },
+    /// A TLS client certificate, verified against a trusted CA by the server.
+    ClientCertificate(ClientCertificate),
     /// No credentials were given.
     NoCredentials,
 }
@@ -59,33 +107,187 @@ impl Unverified {
     pub fn is_no_credentials(&self) -> bool {
         matches!(self, Unverified::NoCredentials)
     }
+
+    /// Returns the username supplied, if any.
+    #[inline(always)]
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Unverified::UsernameAndPassword { username, .. } => Some(username),
+            Unverified::ClientCertificate(cert) => cert.username(),
+            Unverified::NoCredentials => None,
+        }
+    }
 }
 
-#[async_trait]
-impl<S> FromRequestParts<S> for Unverified {
-    type Rejection = StatusCode;
+/// Identity of a TLS client certificate.
+///
+/// Servers terminating TLS insert this as a request extension once the peer certificate has been
+/// verified against a trusted CA; it is then picked up as [`Unverified::ClientCertificate`] by
+/// requests not carrying an `Authorization` header. It must never be inserted for certificates
+/// that have not been verified.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClientCertificate {
+    /// Common name of the certificate subject.
+    common_name: Option<String>,
+    /// DNS names, email addresses and URIs of the subject alternative name extension.
+    alt_names: Vec<String>,
+}
+
+/// A client certificate could not be parsed.
+#[cfg(feature = "client-certs")]
+#[derive(Debug, Error)]
+#[error("invalid client certificate: {0}")]
+pub struct InvalidClientCertificate(String);
+
+impl ClientCertificate {
+    /// Extracts the identity of a DER encoded X.509 certificate.
+    ///
+    /// Requires the `client-certs` feature.
+    #[cfg(feature = "client-certs")]
+    pub fn from_der(der: &[u8]) -> Result<Self, InvalidClientCertificate> {
+        use x509_parser::{
+            certificate::X509Certificate, extensions::GeneralName, prelude::FromDer,
+        };
+
+        let (_rest, cert) = X509Certificate::from_der(der)
+            .map_err(|err| InvalidClientCertificate(err.to_string()))?;
+
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .map(|cn| cn.as_str().map(ToOwned::to_owned))
+            .transpose()
+            .map_err(|err| InvalidClientCertificate(err.to_string()))?;
+
+        let alt_names = cert
+            .subject_alternative_name()
+            .map_err(|err| InvalidClientCertificate(err.to_string()))?
+            .map(|san| {
+                san.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(name)
+                        | GeneralName::RFC822Name(name)
+                        | GeneralName::URI(name) => Some((*name).to_owned()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(ClientCertificate {
+            common_name,
+            alt_names,
+        })
+    }
+
+    /// Returns the common name of the certificate subject, if any.
+    pub fn common_name(&self) -> Option<&str> {
+        self.common_name.as_deref()
+    }
+
+    /// Returns the subject alternative names of the certificate.
+    pub fn alt_names(&self) -> &[String] {
+        &self.alt_names
+    }
+
+    /// Returns all identities of the certificate, the common name first.
+    pub fn identities(&self) -> impl Iterator<Item = &str> {
+        self.common_name
+            .as_deref()
+            .into_iter()
+            .chain(self.alt_names.iter().map(String::as_str))
+    }
+
+    /// Returns the username derived from the certificate.
+    ///
+    /// This is the common name, or the first subject alternative name if there is none.
+    pub fn username(&self) -> Option<&str> {
+        self.identities().next()
+    }
+}
+
+/// An `Authorization` header that could not be used.
+#[derive(Debug, Error)]
+pub enum InvalidAuthorization {
+    /// The header uses a scheme other than `Basic`.
+    #[error("unsupported authorization scheme")]
+    UnsupportedScheme,
+    /// The header uses the `Basic` scheme, but the credentials could not be decoded.
+    #[error("malformed basic credentials: {0}")]
+    MalformedBasic(&'static str),
+}
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+impl InvalidAuthorization {
+    /// Builds the response rejecting a request with this header.
+    fn into_rejection(self, registry: &ContainerRegistry, parts: &Parts) -> Response {
+        match self {
+            // Clients are prompted to retry with a supported scheme.
+            InvalidAuthorization::UnsupportedScheme => {
+                unauthorized(registry, parts.extensions.get::<Realm>(), &parts.headers)
+            }
+            InvalidAuthorization::MalformedBasic(_) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(
+                    OciError::new(ErrorCode::Unauthorized).with_message(self.to_string()),
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl Unverified {
+    /// Extracts the credentials supplied with a request.
+    fn from_parts(parts: &Parts) -> Result<Self, InvalidAuthorization> {
         if let Some(auth_header) = parts.headers.get(header::AUTHORIZATION) {
+            let scheme = auth_header
+                .as_bytes()
+                .trim_ascii_start()
+                .split(u8::is_ascii_whitespace)
+                .next()
+                .unwrap_or_default();
+            if !scheme.eq_ignore_ascii_case(b"basic") {
+                return Err(InvalidAuthorization::UnsupportedScheme);
+            }
+
             let (_unparsed, basic) = www_authenticate::basic_auth_response(auth_header.as_bytes())
-                .map_err(|_| StatusCode::BAD_REQUEST)?;
+                .map_err(|_| InvalidAuthorization::MalformedBasic("invalid base64"))?;
+            let utf8 = |bytes| {
+                str::from_utf8(bytes)
+                    .map(ToOwned::to_owned)
+                    .map_err(|_| InvalidAuthorization::MalformedBasic("invalid UTF-8"))
+            };
 
             Ok(Unverified::UsernameAndPassword {
-                username: str::from_utf8(&basic.username)
-                    .map_err(|_| StatusCode::BAD_REQUEST)?
-                    .to_owned(),
-                password: Secret::new(
-                    str::from_utf8(&basic.password)
-                        .map_err(|_| StatusCode::BAD_REQUEST)?
-                        .to_owned(),
-                ),
+                username: utf8(&basic.username)?,
+                password: Secret::new(utf8(&basic.password)?),
             })
+        } else if let Some(cert) = parts.extensions.get::<ClientCertificate>() {
+            Ok(Unverified::ClientCertificate(cert.clone()))
         } else {
             Ok(Unverified::NoCredentials)
         }
     }
 }
 
+#[async_trait]
+impl FromRequestParts<Arc<ContainerRegistry>> for Unverified {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ContainerRegistry>,
+    ) -> Result<Self, Self::Rejection> {
+        Unverified::from_parts(parts).map_err(|err| err.into_rejection(state, parts))
+    }
+}
+
+/// Target of the `tracing` events emitted for every authentication decision.
+pub const AUDIT_TARGET: &str = "registry::audit";
+
 /// A set of credentials that has been validated.
 ///
 /// Every [`AuthProvider`] is free to put [`Any`] type in the credentials and is guaranteed
@@ -109,19 +311,241 @@ impl ValidCredentials {
 
 #[async_trait]
 impl FromRequestParts<Arc<ContainerRegistry>> for ValidCredentials {
-    type Rejection = StatusCode;
+    type Rejection = Response;
 
     #[inline(always)]
     async fn from_request_parts(
         parts: &mut Parts,
         state: &Arc<ContainerRegistry>,
     ) -> Result<Self, Self::Rejection> {
-        let unverified = Unverified::from_request_parts(parts, state).await?;
+        // Every authentication decision is logged for auditing, client IPs are only available
+        // if the router is served with connect info.
+        let route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_owned());
+        let client_addr = parts
+            .extensions
+            .get::<ClientIp>()
+            .map(|&ClientIp(addr)| addr);
+        let client_ip = client_addr.map(display);
+        let method = parts.method.clone();
+
+        let unverified = match Unverified::from_parts(parts) {
+            Ok(unverified) => unverified,
+            Err(err @ InvalidAuthorization::UnsupportedScheme) => {
+                warn!(
+                    target: AUDIT_TARGET,
+                    %method, route, client_ip, outcome = "unsupported_scheme",
+                    "authentication failed"
+                );
+                return Err(err.into_rejection(state, parts));
+            }
+            Err(err) => {
+                warn!(
+                    target: AUDIT_TARGET,
+                    %method, route, client_ip, outcome = "malformed", %err,
+                    "authentication failed"
+                );
+                return Err(err.into_rejection(state, parts));
+            }
+        };
+        let username = unverified.username();
+
+        // Anonymous clients may read public repositories, but never access private ones.
+        let visibility = if unverified.is_no_credentials() {
+            requested_location(parts, state)
+                .await
+                .and_then(|location| repository_visibility(&state.visibility, &location))
+        } else {
+            None
+        };
+        let creds = match visibility {
+            Some(Visibility::Private) => None,
+            Some(Visibility::Public) if Access::from_method(&method) == Access::Read => {
+                requested_location(parts, state)
+                    .await
+                    .map(|location| ValidCredentials::new(VisibilityCreds::Public(location)))
+            }
+            _ => state.auth_provider.check_credentials(&unverified).await,
+        };
 
         // We got a set of credentials, now verify.
-        match state.auth_provider.check_credentials(&unverified).await {
-            Some(creds) => Ok(creds),
-            None => Err(StatusCode::UNAUTHORIZED),
+        match creds {
+            Some(creds) => {
+                info!(
+                    target: AUDIT_TARGET,
+                    username, %method, route, client_ip, outcome = "success",
+                    "authentication succeeded"
+                );
+                if let (Some(user), Some(username)) =
+                    (parts.extensions.get::<AccessLogUser>(), username)
+                {
+                    user.set(username);
+                }
+
+                // Rate limits apply after authentication, so they can be keyed by username.
+                let client = match (username, client_addr) {
+                    (Some(username), _) => Some(ClientKey::User(username.to_owned())),
+                    (None, Some(addr)) => Some(ClientKey::Ip(addr)),
+                    (None, None) => None,
+                };
+                if let Some(client) = client {
+                    if let Err(retry_after) = state
+                        .rate_limiter
+                        .check(client, Access::from_method(&method))
+                    {
+                        warn!(username, %method, route, client_ip, "rate limit exceeded");
+                        return Err(rate_limit::too_many_requests(retry_after));
+                    }
+                }
+
+                Ok(creds)
+            }
+            None => {
+                warn!(
+                    target: AUDIT_TARGET,
+                    username, %method, route, client_ip, outcome = "failure",
+                    "authentication failed"
+                );
+                Err(unauthorized(
+                    state,
+                    parts.extensions.get::<Realm>(),
+                    &parts.headers,
+                ))
+            }
+        }
+    }
+}
+
+/// Returns the image location addressed by a request, if any.
+async fn requested_location(
+    parts: &mut Parts,
+    state: &Arc<ContainerRegistry>,
+) -> Option<ImageLocation> {
+    let params = RawPathParams::from_request_parts(parts, state).await.ok()?;
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_owned())
+    };
+
+    Some(ImageLocation::new(param("repository")?, param("image")?))
+}
+
+/// Visibility of repositories to anonymous clients.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Visibility {
+    /// Anonymous clients may pull, pushing still requires authentication.
+    Public,
+    /// Only authenticated clients have access.
+    Private,
+}
+
+/// Returns the visibility of the first pattern matching `<repository>/<image>`, if any.
+pub(crate) fn repository_visibility(
+    patterns: &[(String, Visibility)],
+    location: &ImageLocation,
+) -> Option<Visibility> {
+    let name = format!("{}/{}", location.repository(), location.image());
+    patterns
+        .iter()
+        .find(|(pattern, _)| crate::glob_matches(pattern, &name))
+        .map(|(_, visibility)| *visibility)
+}
+
+/// Credentials of a registry with per-repository visibility.
+#[derive(Debug)]
+enum VisibilityCreds {
+    /// An anonymous client reading the given public repository.
+    Public(ImageLocation),
+    /// Valid credentials supplied by the inner auth provider.
+    Valid(ValidCredentials),
+}
+
+/// Auth provider granting anonymous clients read access to public repositories.
+///
+/// Installed by the registry itself if visibilities are configured, deferring all other
+/// decisions to the configured [`AuthProvider`]. Blobs are only readable through a public
+/// repository if they were pushed to it, or are referenced by a manifest pushed to it.
+pub(crate) struct VisibilityAuthProvider {
+    visibility: Vec<(String, Visibility)>,
+    inner: Arc<dyn AuthProvider>,
+    storage: Arc<dyn RegistryStorage>,
+}
+
+impl VisibilityAuthProvider {
+    pub(crate) fn new(
+        visibility: Vec<(String, Visibility)>,
+        inner: Arc<dyn AuthProvider>,
+        storage: Arc<dyn RegistryStorage>,
+    ) -> Self {
+        Self {
+            visibility,
+            inner,
+            storage,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for VisibilityAuthProvider {
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        self.inner
+            .check_credentials(unverified)
+            .await
+            .map(|creds| ValidCredentials::new(VisibilityCreds::Valid(creds)))
+    }
+
+    async fn image_permissions(
+        &self,
+        creds: &ValidCredentials,
+        image: &ImageLocation,
+    ) -> Permissions {
+        match creds.extract_ref::<VisibilityCreds>() {
+            VisibilityCreds::Public(_) => {
+                if repository_visibility(&self.visibility, image) == Some(Visibility::Public) {
+                    Permissions::ReadOnly
+                } else {
+                    Permissions::NoAccess
+                }
+            }
+            VisibilityCreds::Valid(inner_creds) => {
+                self.inner.image_permissions(inner_creds, image).await
+            }
+        }
+    }
+
+    async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
+        match creds.extract_ref::<VisibilityCreds>() {
+            // Only ever issued for reads of a public repository, which must hold the blob.
+            VisibilityCreds::Public(location) => {
+                match self
+                    .storage
+                    .repository_blob_size(location, blob.digest)
+                    .await
+                {
+                    Ok(Some(_)) => Permissions::ReadOnly,
+                    Ok(None) => Permissions::NoAccess,
+                    Err(err) => {
+                        warn!(%err, %location, %blob, "could not check blob visibility");
+                        Permissions::NoAccess
+                    }
+                }
+            }
+            VisibilityCreds::Valid(inner_creds) => {
+                self.inner.blob_permissions(inner_creds, blob).await
+            }
+        }
+    }
+
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        match creds.extract_ref::<VisibilityCreds>() {
+            VisibilityCreds::Public(_) => Permissions::NoAccess,
+            VisibilityCreds::Valid(inner_creds) => {
+                self.inner.registry_permissions(inner_creds).await
+            }
         }
     }
 }
@@ -161,6 +585,20 @@ impl Permissions {
         }
     }
 
+    /// Returns the permissions included in both `self` and `other`.
+    #[inline(always)]
+    pub fn intersect(self, other: Permissions) -> Permissions {
+        match (
+            self.has_read_permission() && other.has_read_permission(),
+            self.has_write_permission() && other.has_write_permission(),
+        ) {
+            (true, true) => Permissions::ReadWrite,
+            (true, false) => Permissions::ReadOnly,
+            (false, true) => Permissions::WriteOnly,
+            (false, false) => Permissions::NoAccess,
+        }
+    }
+
     /// Returns an error if no read permission is included.
     #[inline(always)]
     pub fn require_read(self) -> Result<(), MissingPermission> {
@@ -220,6 +658,13 @@ pub trait AuthProvider: Send + Sync {
     /// involve the uploader sending a hash beforehand, thus this function cannot be used to
     /// implement a blacklist for specific blobs.
     async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions;
+
+    /// Determine permissions for given credentials on the registry as a whole.
+    ///
+    /// This is an **authorizing** function consulted for administrative operations not limited to
+    /// a single [`ImageLocation`], such as garbage collection. Write permission is required to run
+    /// those that modify storage.
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions;
 }
 
 /// Anonymous access auth provider.
@@ -259,7 +704,11 @@ where
     async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
         match unverified {
             Unverified::NoCredentials => Some(ValidCredentials::new(AnonCreds::Anonymous)),
-            _other => self.inner.check_credentials(unverified).await,
+            _other => self
+                .inner
+                .check_credentials(unverified)
+                .await
+                .map(|creds| ValidCredentials::new(AnonCreds::Valid(creds))),
         }
     }
 
@@ -270,16 +719,296 @@ where
     ) -> Permissions {
         match creds.extract_ref::<AnonCreds>() {
             AnonCreds::Anonymous => self.anon_permissions,
-            _other => self.inner.image_permissions(creds, image).await,
+            AnonCreds::Valid(inner_creds) => self.inner.image_permissions(inner_creds, image).await,
         }
     }
 
     async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
         match creds.extract_ref::<AnonCreds>() {
             AnonCreds::Anonymous => self.anon_permissions,
-            _other => self.inner.blob_permissions(creds, blob).await,
+            AnonCreds::Valid(inner_creds) => self.inner.blob_permissions(inner_creds, blob).await,
         }
     }
+
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        match creds.extract_ref::<AnonCreds>() {
+            AnonCreds::Anonymous => self.anon_permissions,
+            AnonCreds::Valid(inner_creds) => self.inner.registry_permissions(inner_creds).await,
+        }
+    }
+}
+
+/// Auth provider accepting credentials if any of its inner providers does.
+///
+/// Providers are tried in order, the first one accepting the credentials is also asked for
+/// permissions later on.
+pub struct AnyOf(pub Vec<Box<dyn AuthProvider>>);
+
+impl fmt::Debug for AnyOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AnyOf").field(&self.0.len()).finish()
+    }
+}
+
+/// Credentials accepted by one of the providers of an [`AnyOf`].
+#[derive(Debug)]
+struct AnyOfCreds {
+    /// Index of the provider that accepted the credentials.
+    provider: usize,
+    /// Credentials returned by said provider.
+    inner: ValidCredentials,
+}
+
+#[async_trait]
+impl AuthProvider for AnyOf {
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        for (provider, inner) in self.0.iter().enumerate() {
+            if let Some(inner) = inner.check_credentials(unverified).await {
+                return Some(ValidCredentials::new(AnyOfCreds { provider, inner }));
+            }
+        }
+
+        None
+    }
+
+    async fn image_permissions(
+        &self,
+        creds: &ValidCredentials,
+        image: &ImageLocation,
+    ) -> Permissions {
+        let creds = creds.extract_ref::<AnyOfCreds>();
+        self.0[creds.provider]
+            .image_permissions(&creds.inner, image)
+            .await
+    }
+
+    async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
+        let creds = creds.extract_ref::<AnyOfCreds>();
+        self.0[creds.provider]
+            .blob_permissions(&creds.inner, blob)
+            .await
+    }
+
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        let creds = creds.extract_ref::<AnyOfCreds>();
+        self.0[creds.provider]
+            .registry_permissions(&creds.inner)
+            .await
+    }
+}
+
+/// Auth provider accepting credentials only if all of its inner providers do.
+///
+/// Permissions granted are those granted by every inner provider. An empty `AllOf` accepts no
+/// credentials at all.
+pub struct AllOf(pub Vec<Box<dyn AuthProvider>>);
+
+impl fmt::Debug for AllOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AllOf").field(&self.0.len()).finish()
+    }
+}
+
+#[async_trait]
+impl AuthProvider for AllOf {
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let mut all_creds = Vec::with_capacity(self.0.len());
+        for inner in &self.0 {
+            all_creds.push(inner.check_credentials(unverified).await?);
+        }
+
+        Some(ValidCredentials::new(all_creds))
+    }
+
+    async fn image_permissions(
+        &self,
+        creds: &ValidCredentials,
+        image: &ImageLocation,
+    ) -> Permissions {
+        let mut permissions = Permissions::ReadWrite;
+        for (inner, inner_creds) in self
+            .0
+            .iter()
+            .zip(creds.extract_ref::<Vec<ValidCredentials>>())
+        {
+            permissions = permissions.intersect(inner.image_permissions(inner_creds, image).await);
+        }
+        permissions
+    }
+
+    async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
+        let mut permissions = Permissions::ReadWrite;
+        for (inner, inner_creds) in self
+            .0
+            .iter()
+            .zip(creds.extract_ref::<Vec<ValidCredentials>>())
+        {
+            permissions = permissions.intersect(inner.blob_permissions(inner_creds, blob).await);
+        }
+        permissions
+    }
+
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        let mut permissions = Permissions::ReadWrite;
+        for (inner, inner_creds) in self
+            .0
+            .iter()
+            .zip(creds.extract_ref::<Vec<ValidCredentials>>())
+        {
+            permissions = permissions.intersect(inner.registry_permissions(inner_creds).await);
+        }
+        permissions
+    }
+}
+
+/// Default number of logins remembered by a [`CachingAuthProvider`].
+const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Caching auth provider.
+///
+/// Remembers successful credential checks of the wrapped [`AuthProvider`] `A` for a fixed time,
+/// skipping potentially expensive password hashing on repeated requests. Passwords are never
+/// stored, the cache is keyed by username and a salted hash of the password. Failed checks are
+/// not cached.
+///
+/// Once the cache is full, the least recently used entry is evicted.
+pub struct CachingAuthProvider<A> {
+    inner: A,
+    ttl: Duration,
+    capacity: usize,
+    /// Random per-instance salt for password hashes.
+    salt: [u8; 16],
+    entries: Mutex<HashMap<(String, [u8; 32]), CacheEntry>>,
+}
+
+impl<A> fmt::Debug for CachingAuthProvider<A>
+where
+    A: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachingAuthProvider")
+            .field("inner", &self.inner)
+            .field("ttl", &self.ttl)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A cached successful credential check.
+#[derive(Debug)]
+struct CacheEntry {
+    creds: Arc<ValidCredentials>,
+    created: Instant,
+    last_used: Instant,
+}
+
+impl<A> CachingAuthProvider<A> {
+    /// Creates a new caching auth provider that remembers logins checked by `inner` for `ttl`.
+    pub fn new(inner: A, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            capacity: DEFAULT_CACHE_CAPACITY,
+            salt: *uuid::Uuid::new_v4().as_bytes(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the maximum number of logins remembered.
+    ///
+    /// Defaults to 1024.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Hashes a password for use as part of a cache key.
+    fn hash_password(&self, password: &Secret<String>) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        hasher.update(password.reveal().as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+#[async_trait]
+impl<A> AuthProvider for CachingAuthProvider<A>
+where
+    A: AuthProvider,
+{
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        let Unverified::UsernameAndPassword { username, password } = unverified else {
+            return self
+                .inner
+                .check_credentials(unverified)
+                .await
+                .map(|creds| ValidCredentials::new(Arc::new(creds)));
+        };
+
+        let key = (username.clone(), self.hash_password(password));
+        {
+            let mut entries = self.entries.lock().expect("lock poisoned");
+            if let Some(entry) = entries.get_mut(&key) {
+                if entry.created.elapsed() < self.ttl {
+                    entry.last_used = Instant::now();
+                    return Some(ValidCredentials::new(entry.creds.clone()));
+                }
+                entries.remove(&key);
+            }
+        }
+
+        let creds = Arc::new(self.inner.check_credentials(unverified).await?);
+
+        let mut entries = self.entries.lock().expect("lock poisoned");
+        if entries.len() >= self.capacity {
+            entries.retain(|_, entry| entry.created.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.capacity {
+            let lru = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(lru) = lru {
+                entries.remove(&lru);
+            }
+        }
+        if self.capacity > 0 {
+            let now = Instant::now();
+            entries.insert(
+                key,
+                CacheEntry {
+                    creds: creds.clone(),
+                    created: now,
+                    last_used: now,
+                },
+            );
+        }
+
+        Some(ValidCredentials::new(creds))
+    }
+
+    async fn image_permissions(
+        &self,
+        creds: &ValidCredentials,
+        image: &ImageLocation,
+    ) -> Permissions {
+        let inner_creds = creds.extract_ref::<Arc<ValidCredentials>>();
+        self.inner.image_permissions(inner_creds, image).await
+    }
+
+    async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
+        let inner_creds = creds.extract_ref::<Arc<ValidCredentials>>();
+        self.inner.blob_permissions(inner_creds, blob).await
+    }
+
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        let inner_creds = creds.extract_ref::<Arc<ValidCredentials>>();
+        self.inner.registry_permissions(inner_creds).await
+    }
 }
 
 #[async_trait]
@@ -309,6 +1038,11 @@ impl AuthProvider for Permissions {
     ) -> Permissions {
         *self
     }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, _creds: &ValidCredentials) -> Permissions {
+        *self
+    }
 }
 
 #[async_trait]
@@ -320,17 +1054,14 @@ impl AuthProvider for HashMap<String, Secret<String>> {
                 password: unverified_password,
             } => {
                 if let Some(correct_password) = self.get(unverified_username) {
-                    if constant_time_eq::constant_time_eq(
-                        correct_password.reveal().as_bytes(),
-                        unverified_password.reveal().as_bytes(),
-                    ) {
+                    if secrets_match(correct_password, unverified_password) {
                         return Some(ValidCredentials::new(unverified_username.clone()));
                     }
                 }
 
                 None
             }
-            Unverified::NoCredentials => None,
+            Unverified::ClientCertificate(_) | Unverified::NoCredentials => None,
         }
     }
 
@@ -351,16 +1082,107 @@ impl AuthProvider for HashMap<String, Secret<String>> {
     ) -> Permissions {
         Permissions::ReadWrite
     }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, _creds: &ValidCredentials) -> Permissions {
+        Permissions::ReadWrite
+    }
+}
+
+/// A stored password, either in plaintext or hashed.
+///
+/// When deserialized or parsed, the format is detected from the prefix: `$2a$`, `$2b$`, `$2x$`
+/// and `$2y$` indicate bcrypt, `$argon2` an argon2 PHC string. Anything else is taken to be a
+/// plaintext password.
+#[derive(Debug)]
+pub enum PasswordHash {
+    /// An unhashed password.
+    Plain(Secret<String>),
+    /// A bcrypt hash.
+    Bcrypt(Secret<String>),
+    /// An argon2 hash in PHC string format.
+    Argon2(Secret<String>),
+}
+
+impl PasswordHash {
+    /// Verifies a password against the stored one.
+    ///
+    /// Hash verification is CPU intensive and performed on a blocking thread.
+    async fn verify(&self, password: &Secret<String>) -> bool {
+        let (hash, password) = match self {
+            PasswordHash::Plain(correct) => {
+                return secrets_match(correct, password);
+            }
+            PasswordHash::Bcrypt(hash) | PasswordHash::Argon2(hash) => {
+                (hash.reveal().clone(), password.reveal().clone())
+            }
+        };
+
+        let is_bcrypt = matches!(self, PasswordHash::Bcrypt(_));
+        tokio::task::spawn_blocking(move || {
+            if is_bcrypt {
+                bcrypt::verify(password, &hash).unwrap_or(false)
+            } else {
+                use argon2::PasswordVerifier;
+
+                argon2::PasswordHash::new(&hash)
+                    .and_then(|parsed| {
+                        argon2::Argon2::default().verify_password(password.as_bytes(), &parsed)
+                    })
+                    .is_ok()
+            }
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+impl From<String> for PasswordHash {
+    fn from(raw: String) -> Self {
+        if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| raw.starts_with(prefix))
+        {
+            PasswordHash::Bcrypt(Secret::new(raw))
+        } else if raw.starts_with("$argon2") {
+            PasswordHash::Argon2(Secret::new(raw))
+        } else {
+            PasswordHash::Plain(Secret::new(raw))
+        }
+    }
+}
+
+impl str::FromStr for PasswordHash {
+    type Err = std::convert::Infallible;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        Ok(raw.to_owned().into())
+    }
+}
+
+impl<'de> Deserialize<'de> for PasswordHash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(Into::into)
+    }
 }
 
 #[async_trait]
-impl<T> AuthProvider for Box<T>
-where
-    T: AuthProvider,
-{
-    #[inline(always)]
+impl AuthProvider for HashMap<String, PasswordHash> {
     async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
-        <T as AuthProvider>::check_credentials(self, unverified).await
+        match unverified {
+            Unverified::UsernameAndPassword { username, password } => {
+                let stored = self.get(username)?;
+                if stored.verify(password).await {
+                    Some(ValidCredentials::new(username.clone()))
+                } else {
+                    None
+                }
+            }
+            Unverified::ClientCertificate(_) | Unverified::NoCredentials => None,
+        }
     }
 
     #[inline(always)]
@@ -380,12 +1202,17 @@ where
     ) -> Permissions {
         Permissions::ReadWrite
     }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, _creds: &ValidCredentials) -> Permissions {
+        Permissions::ReadWrite
+    }
 }
 
 #[async_trait]
-impl<T> AuthProvider for Arc<T>
+impl<T> AuthProvider for Box<T>
 where
-    T: AuthProvider,
+    T: AuthProvider + ?Sized,
 {
     #[inline(always)]
     async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
@@ -395,19 +1222,50 @@ where
     #[inline(always)]
     async fn image_permissions(
         &self,
-        _creds: &ValidCredentials,
-        _image: &ImageLocation,
+        creds: &ValidCredentials,
+        image: &ImageLocation,
     ) -> Permissions {
-        Permissions::ReadWrite
+        <T as AuthProvider>::image_permissions(self, creds, image).await
     }
 
     #[inline(always)]
-    async fn blob_permissions(
+    async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
+        <T as AuthProvider>::blob_permissions(self, creds, blob).await
+    }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        <T as AuthProvider>::registry_permissions(self, creds).await
+    }
+}
+
+#[async_trait]
+impl<T> AuthProvider for Arc<T>
+where
+    T: AuthProvider + ?Sized,
+{
+    #[inline(always)]
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        <T as AuthProvider>::check_credentials(self, unverified).await
+    }
+
+    #[inline(always)]
+    async fn image_permissions(
         &self,
-        _creds: &ValidCredentials,
-        _blob: &ImageDigest,
+        creds: &ValidCredentials,
+        image: &ImageLocation,
     ) -> Permissions {
-        Permissions::ReadWrite
+        <T as AuthProvider>::image_permissions(self, creds, image).await
+    }
+
+    #[inline(always)]
+    async fn blob_permissions(&self, creds: &ValidCredentials, blob: &ImageDigest) -> Permissions {
+        <T as AuthProvider>::blob_permissions(self, creds, blob).await
+    }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, creds: &ValidCredentials) -> Permissions {
+        <T as AuthProvider>::registry_permissions(self, creds).await
     }
 }
 
@@ -420,16 +1278,13 @@ impl AuthProvider for Secret<String> {
                 username: _,
                 password,
             } => {
-                if constant_time_eq::constant_time_eq(
-                    password.reveal().as_bytes(),
-                    self.reveal().as_bytes(),
-                ) {
+                if secrets_match(self, password) {
                     Some(ValidCredentials::new(()))
                 } else {
                     None
                 }
             }
-            Unverified::NoCredentials => None,
+            Unverified::ClientCertificate(_) | Unverified::NoCredentials => None,
         }
     }
 
@@ -450,4 +1305,249 @@ impl AuthProvider for Secret<String> {
     ) -> Permissions {
         Permissions::ReadWrite
     }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, _creds: &ValidCredentials) -> Permissions {
+        Permissions::ReadWrite
+    }
+}
+
+/// Auth provider accepting TLS client certificates.
+///
+/// A certificate is accepted if its common name or any of its subject alternative names is
+/// allowed, the first allowed identity becomes the username. Certificates must already have been
+/// verified against a trusted CA by the server terminating TLS, see [`ClientCertificate`].
+#[derive(Debug)]
+pub struct ClientCertAuthProvider {
+    /// Identities allowed to authenticate.
+    allowed: HashSet<String>,
+}
+
+/// A user authenticated by TLS client certificate.
+#[derive(Debug)]
+pub struct ClientCertUser {
+    /// The allowed identity the certificate was accepted for.
+    pub username: String,
+}
+
+impl ClientCertAuthProvider {
+    /// Creates a new client certificate auth provider, allowing the given identities.
+    pub fn new<I, S>(allowed: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ClientCertAuthProvider {
+            allowed: allowed.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for ClientCertAuthProvider {
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        let Unverified::ClientCertificate(cert) = unverified else {
+            return None;
+        };
+
+        let identity = cert
+            .identities()
+            .find(|identity| self.allowed.contains(*identity))?;
+
+        Some(ValidCredentials::new(ClientCertUser {
+            username: identity.to_owned(),
+        }))
+    }
+
+    #[inline(always)]
+    async fn image_permissions(
+        &self,
+        _creds: &ValidCredentials,
+        _image: &ImageLocation,
+    ) -> Permissions {
+        Permissions::ReadWrite
+    }
+
+    #[inline(always)]
+    async fn blob_permissions(
+        &self,
+        _creds: &ValidCredentials,
+        _blob: &ImageDigest,
+    ) -> Permissions {
+        Permissions::ReadWrite
+    }
+
+    #[inline(always)]
+    async fn registry_permissions(&self, _creds: &ValidCredentials) -> Permissions {
+        Permissions::ReadWrite
+    }
+}
+
+/// Environment variable selecting the auth provider, see [`AuthConfig::from_env`].
+pub const AUTH_ENV: &str = "CONTAINER_REGISTRY_AUTH";
+/// Environment variable holding the master key of [`AuthConfig::MasterKey`].
+pub const MASTER_KEY_ENV: &str = "CONTAINER_REGISTRY_MASTER_KEY";
+/// Environment variable holding the path of the htpasswd file of [`AuthConfig::Htpasswd`].
+pub const HTPASSWD_ENV: &str = "CONTAINER_REGISTRY_HTPASSWD";
+/// Environment variable holding the users of [`AuthConfig::Users`], as a JSON object.
+pub const USERS_ENV: &str = "CONTAINER_REGISTRY_USERS";
+
+/// Configuration of one of the included auth providers.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AuthConfig {
+    /// Grants full access to everyone, with or without credentials.
+    None,
+    /// Accepts any username with the master key as password.
+    MasterKey {
+        /// The master key.
+        key: Secret<String>,
+    },
+    /// Accepts users of an htpasswd file, whose passwords must be hashed using bcrypt or argon2.
+    Htpasswd {
+        /// Path of the htpasswd file.
+        path: PathBuf,
+    },
+    /// Accepts the given users.
+    Users {
+        /// Passwords by username.
+        users: HashMap<String, PasswordHash>,
+    },
+}
+
+/// An invalid auth provider configuration.
+#[derive(Debug, Error)]
+pub enum AuthConfigError {
+    /// The selected auth provider does not exist.
+    #[error("unknown auth provider {0:?}, expected one of none, master_key, htpasswd or users")]
+    UnknownProvider(String),
+    /// A setting required by the selected auth provider is missing.
+    #[error("auth provider {provider} selected, but {setting} is not set")]
+    NotConfigured {
+        provider: &'static str,
+        setting: &'static str,
+    },
+    /// The selected auth provider would not accept any credentials.
+    #[error("auth provider {0} selected, but no users are configured")]
+    NoUsers(&'static str),
+    /// The htpasswd file could not be read.
+    #[error("could not read htpasswd file {}", path.display())]
+    ReadHtpasswd {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A line of the htpasswd file is malformed or uses an unsupported hash.
+    #[error("line {line} of htpasswd file {} is invalid, only bcrypt and argon2 hashes are supported", path.display())]
+    InvalidHtpasswd { path: PathBuf, line: usize },
+    /// The users given in the environment are not a JSON object of usernames and passwords.
+    #[error("could not parse {USERS_ENV}")]
+    InvalidUsers(#[source] serde_json::Error),
+}
+
+impl AuthConfig {
+    /// Reads the auth provider configuration from the environment.
+    ///
+    /// The provider is selected by setting [`AUTH_ENV`] to `none`, `master_key`, `htpasswd` or
+    /// `users`, each of the latter requiring [`MASTER_KEY_ENV`], [`HTPASSWD_ENV`] or
+    /// [`USERS_ENV`] to be set as well. Returns `None` if no provider is selected.
+    pub fn from_env() -> Result<Option<Self>, AuthConfigError> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the auth provider configuration from variables returned by `var`.
+    pub(crate) fn from_lookup(
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Self>, AuthConfigError> {
+        let Some(provider) = var(AUTH_ENV) else {
+            return Ok(None);
+        };
+        let required = |provider, setting| {
+            var(setting).ok_or(AuthConfigError::NotConfigured { provider, setting })
+        };
+
+        let config = match provider.as_str() {
+            "none" => AuthConfig::None,
+            "master_key" => AuthConfig::MasterKey {
+                key: Secret::new(required("master_key", MASTER_KEY_ENV)?),
+            },
+            "htpasswd" => AuthConfig::Htpasswd {
+                path: required("htpasswd", HTPASSWD_ENV)?.into(),
+            },
+            "users" => AuthConfig::Users {
+                users: serde_json::from_str(&required("users", USERS_ENV)?)
+                    .map_err(AuthConfigError::InvalidUsers)?,
+            },
+            _ => return Err(AuthConfigError::UnknownProvider(provider)),
+        };
+        Ok(Some(config))
+    }
+}
+
+/// Constructs the auth provider described by a configuration.
+///
+/// Fails if the provider would not accept any credentials, e.g. due to an empty master key.
+pub fn build_auth_provider(config: AuthConfig) -> Result<Box<dyn AuthProvider>, AuthConfigError> {
+    match config {
+        AuthConfig::None => Ok(Box::new(Anonymous::new(
+            Permissions::ReadWrite,
+            Permissions::ReadWrite,
+        ))),
+        AuthConfig::MasterKey { key } => {
+            if key.reveal().is_empty() {
+                return Err(AuthConfigError::NotConfigured {
+                    provider: "master_key",
+                    setting: "key",
+                });
+            }
+            Ok(Box::new(key))
+        }
+        AuthConfig::Htpasswd { path } => {
+            let users = read_htpasswd(&path)?;
+            if users.is_empty() {
+                return Err(AuthConfigError::NoUsers("htpasswd"));
+            }
+            Ok(Box::new(users))
+        }
+        AuthConfig::Users { users } => {
+            if users.is_empty() {
+                return Err(AuthConfigError::NoUsers("users"));
+            }
+            Ok(Box::new(users))
+        }
+    }
+}
+
+/// Reads the users of an htpasswd file.
+///
+/// Apache's MD5, SHA1 and crypt hashes are insecure and not supported, neither are plaintext
+/// passwords, as they cannot be told apart from these.
+fn read_htpasswd(path: &Path) -> Result<HashMap<String, PasswordHash>, AuthConfigError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|source| AuthConfigError::ReadHtpasswd {
+            path: path.to_owned(),
+            source,
+        })?;
+
+    let mut users = HashMap::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let hash = line
+            .split_once(':')
+            .map(|(username, hash)| (username, PasswordHash::from(hash.to_owned())));
+        let Some((username, hash @ (PasswordHash::Bcrypt(_) | PasswordHash::Argon2(_)))) = hash
+        else {
+            return Err(AuthConfigError::InvalidHtpasswd {
+                path: path.to_owned(),
+                line: idx + 1,
+            });
+        };
+        users.insert(username.to_owned(), hash);
+    }
+    Ok(users)
 }
@@ -1,28 +1,291 @@
-use std::{fmt, fs, net::SocketAddr, path, process::ExitCode, sync::Arc};
+use std::{
+    fmt, fs, io, net::SocketAddr, num::NonZeroU32, path, process::ExitCode, str::FromStr,
+    sync::Arc, time::Duration,
+};
 
-use anyhow::Context;
-use axum::{async_trait, extract::DefaultBodyLimit, Router};
+use anyhow::{anyhow, Context};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, DefaultBodyLimit},
+    http::{HeaderName, HeaderValue},
+    Extension, Router,
+};
 use container_registry::{
-    auth::{self, AuthProvider},
+    auth::{self, AuthProvider, ClientCertAuthProvider, ClientCertificate},
+    client_ip::Cidr,
+    cors::Cors,
     hooks::RegistryHooks,
-    storage::ManifestReference,
+    proxy::Upstream,
+    storage::{ManifestReference, StorageConfig},
+    BlobReadVerification, MultiRangePolicy, ReferrerDeletePolicy, TagLimitPolicy,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{
+        conn::auto,
+        graceful::{GracefulShutdown, Watcher},
+    },
+    service::TowerToHyperService,
 };
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
 use sec::Secret;
 use structopt::StructOpt;
-use tower_http::trace::TraceLayer;
-use tracing::{error, info, warn, Level};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tower_http::add_extension::AddExtension;
+use tracing::{debug, error, info, warn, Level};
+
+/// Version printed by `--version`, including the commit built from.
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("CONTAINER_REGISTRY_GIT_HASH"),
+    ")"
+);
 
 #[derive(Debug, StructOpt)]
+#[structopt(version = VERSION)]
 struct Opts {
     /// Which address to bind to.
     #[structopt(short, long, default_value = "127.0.0.1:3000")]
     bind: SocketAddr,
+    /// Serve health checks and `/admin/` on a separate address instead of along with the API.
+    #[structopt(long)]
+    admin_bind: Option<SocketAddr>,
     /// Directory to use as storage.
     #[structopt(short, long)]
     storage: Option<path::PathBuf>,
-    /// Password to require.
+    /// Number of directory levels blobs are sharded into.
+    #[structopt(long, default_value = "2")]
+    blob_shard_depth: usize,
+    /// Flush blobs and manifests to disk before acknowledging pushes, at the cost of throughput.
+    #[structopt(long)]
+    durable_writes: bool,
+    /// Store manifests and blobs smaller than this many bytes zstd-compressed.
+    #[structopt(long)]
+    compress_small_objects: Option<u64>,
+    /// Password to require. Alternatively, an auth provider can be selected by setting
+    /// `CONTAINER_REGISTRY_AUTH`.
     #[structopt(short, long)]
     password: Option<String>,
+    /// Mirror an upstream repository, e.g. `dockerhub=https://registry-1.docker.io/library`.
+    #[structopt(long)]
+    mirror: Vec<Mirror>,
+    /// Timeout in seconds for connecting to mirrored upstreams.
+    #[structopt(long, default_value = "10")]
+    upstream_connect_timeout: u64,
+    /// Timeout in seconds for reads from mirrored upstreams.
+    #[structopt(long, default_value = "60")]
+    upstream_read_timeout: u64,
+    /// Stop contacting a mirrored upstream after this many consecutive failures.
+    #[structopt(long)]
+    upstream_failure_threshold: Option<u32>,
+    /// Time in seconds a failing upstream is not contacted for.
+    #[structopt(long, default_value = "30")]
+    upstream_cooldown: u64,
+    /// Timeout in seconds for requests other than blob uploads.
+    #[structopt(long)]
+    request_timeout: Option<u64>,
+    /// Maximum number of requests handled concurrently.
+    #[structopt(long)]
+    max_concurrent_requests: Option<usize>,
+    /// Maximum number of blob uploads and downloads handled concurrently.
+    #[structopt(long)]
+    max_concurrent_transfers: Option<usize>,
+    /// Maximum number of reads per minute and client.
+    #[structopt(long)]
+    read_rate_limit: Option<u32>,
+    /// Maximum number of writes per minute and client.
+    #[structopt(long)]
+    write_rate_limit: Option<u32>,
+    /// Externally visible base URL, e.g. `https://registry.example.com`.
+    #[structopt(long)]
+    base_url: Option<String>,
+    /// Realm sent in authentication challenges.
+    #[structopt(long)]
+    realm: Option<String>,
+    /// URL explaining how to get access, linked when browsers are denied access.
+    #[structopt(long)]
+    login_url: Option<String>,
+    /// Trust `Forwarded` and `X-Forwarded-*` headers set by a reverse proxy.
+    #[structopt(long)]
+    trust_forwarded_headers: bool,
+    /// Trust proxies in a CIDR range, e.g. `10.0.0.0/8`, to report client IPs in
+    /// `X-Forwarded-For`.
+    #[structopt(long)]
+    trusted_proxy: Vec<Cidr>,
+    /// Redact a header in logs, in addition to `Authorization` and cookies.
+    #[structopt(long)]
+    sensitive_header: Vec<HeaderName>,
+    /// Always send absolute URLs in `Location` headers.
+    #[structopt(long)]
+    absolute_locations: bool,
+    /// Allow deleting content.
+    #[structopt(long)]
+    enable_delete: bool,
+    /// Delete signatures and other referrers along with their manifest, instead of refusing to
+    /// delete manifests that have referrers.
+    #[structopt(long)]
+    cascade_referrer_deletes: bool,
+    /// Minimum age in seconds of unreferenced blobs to be garbage collected.
+    #[structopt(long)]
+    gc_grace_period: Option<u64>,
+    /// Make tag deletions soft, restorable for the given number of seconds.
+    #[structopt(long)]
+    soft_delete_window: Option<u64>,
+    /// Describe the capabilities of the registry at `/`.
+    #[structopt(long)]
+    capabilities_endpoint: bool,
+    /// Number of entries kept in the history of each tag, zero disables recording.
+    #[structopt(long, default_value = "100")]
+    tag_history_retention: usize,
+    /// Only accept manifests and configs of media types matching a pattern, e.g.
+    /// `application/vnd.oci.image.*`.
+    #[structopt(long)]
+    allowed_media_type: Vec<String>,
+    /// Maximum number of tags per repository, pushing new tags beyond it is rejected.
+    #[structopt(long)]
+    max_tags_per_repo: Option<usize>,
+    /// Delete the least recently pushed tag instead of rejecting new tags beyond
+    /// `--max-tags-per-repo`.
+    #[structopt(long, requires = "max-tags-per-repo")]
+    evict_oldest_tags: bool,
+    /// Accept manifests referencing blobs not pushed yet.
+    #[structopt(long)]
+    allow_missing_manifest_blobs: bool,
+    /// Never allow pointing a pushed tag at a different manifest.
+    #[structopt(long)]
+    write_once: bool,
+    /// Minimum size in bytes of JSON responses to compress.
+    #[structopt(long, default_value = "256")]
+    compression_min_size: u16,
+    /// Maximum number of repositories labeled individually in upload metrics.
+    #[structopt(long)]
+    max_metric_repositories: Option<usize>,
+    /// Reject blob downloads requesting multiple byte ranges instead of sending the full blob.
+    #[structopt(long)]
+    reject_multi_range: bool,
+    /// Re-hash manifests on read, failing requests for manifests not matching their digest.
+    #[structopt(long)]
+    verify_manifest_digests: bool,
+    /// Re-hash one in N blob downloads, failing and quarantining blobs not matching their
+    /// digest. 1 verifies every download.
+    #[structopt(long)]
+    verify_blobs_on_read: Option<NonZeroU32>,
+    /// Only mount blobs from another repository if their size matches the one recorded on push.
+    #[structopt(long)]
+    strict_mounts: bool,
+    /// Allow cross-origin requests from a browser origin, e.g. `https://ui.example.com`.
+    #[structopt(long)]
+    cors_origin: Vec<HeaderValue>,
+    /// Check storage is usable before serving requests, exiting on failure. Also enabled by
+    /// setting `CHECK=1`.
+    #[structopt(long)]
+    check: bool,
+    /// Seconds to wait for outstanding requests to finish when shutting down.
+    #[structopt(long, default_value = "30")]
+    shutdown_timeout: u64,
+    /// Accept HTTP/2 connections with prior knowledge (h2c) along with HTTP/1.1.
+    #[structopt(long)]
+    http2: bool,
+    /// Seconds idle connections are kept open. HTTP/2 connections are pinged after being idle as
+    /// long and closed if the ping is not answered in time.
+    #[structopt(long)]
+    keep_alive_timeout: Option<u64>,
+    /// PEM file with the certificate chain to serve TLS with.
+    #[structopt(long, requires = "tls-key")]
+    tls_cert: Option<path::PathBuf>,
+    /// PEM file with the private key of the TLS certificate.
+    #[structopt(long, requires = "tls-cert")]
+    tls_key: Option<path::PathBuf>,
+    /// PEM file with CA certificates to verify TLS client certificates against. Clients without a
+    /// certificate can still authenticate using other credentials.
+    #[structopt(long, requires = "tls-cert")]
+    tls_client_ca: Option<path::PathBuf>,
+    /// Allow clients presenting a certificate with this common name or subject alternative name.
+    #[structopt(long, requires = "tls-client-ca")]
+    client_cert_identity: Vec<String>,
+    /// Log output format, either `text` or `json`.
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+    /// File to append an access log in Combined Log Format to, `-` for stdout.
+    #[structopt(long)]
+    access_log: Option<path::PathBuf>,
+}
+
+/// Format of log output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line, including span fields.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(anyhow!(
+                "unknown log format `{other}`, expected `text` or `json`"
+            )),
+        }
+    }
+}
+
+/// Constructs the subscriber for all log output.
+fn make_subscriber(log_format: LogFormat) -> Box<dyn tracing::Subscriber + Send + Sync> {
+    let env_filter =
+        tracing_subscriber::EnvFilter::from_default_env().add_directive(Level::INFO.into());
+
+    match log_format {
+        LogFormat::Text => Box::new(
+            tracing_subscriber::fmt()
+                .with_env_filter(env_filter)
+                .finish(),
+        ),
+        LogFormat::Json => Box::new(
+            tracing_subscriber::fmt()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_env_filter(env_filter)
+                .finish(),
+        ),
+    }
+}
+
+/// A local repository mirroring an upstream repository.
+#[derive(Debug)]
+struct Mirror {
+    repository: String,
+    base_url: String,
+    upstream_repository: String,
+}
+
+impl FromStr for Mirror {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (repository, url) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected `REPOSITORY=URL/UPSTREAM_REPOSITORY`"))?;
+        let (base_url, upstream_repository) = url
+            .rsplit_once('/')
+            .ok_or_else(|| anyhow!("upstream URL is missing a repository"))?;
+
+        Ok(Mirror {
+            repository: repository.to_owned(),
+            base_url: base_url.to_owned(),
+            upstream_repository: upstream_repository.to_owned(),
+        })
+    }
 }
 
 struct LoggingHook;
@@ -31,20 +294,21 @@ struct LoggingHook;
 
 impl RegistryHooks for LoggingHook {
     /// Notify about an uploaded manifest.
-    async fn on_manifest_uploaded(&self, manifest_reference: &ManifestReference) {
-        info!(%manifest_reference, "new manifest uploaded");
+    async fn on_manifest_pushed(
+        &self,
+        manifest_reference: &ManifestReference,
+        actor: Option<&str>,
+    ) {
+        info!(%manifest_reference, actor, "new manifest uploaded");
     }
 }
 
 async fn run() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env().add_directive(Level::INFO.into()),
-        )
-        .init();
-
     let opts = Opts::from_args();
 
+    tracing::subscriber::set_global_default(make_subscriber(opts.log_format))
+        .context("failed to install log subscriber")?;
+
     let (_tmpdir, storage) = if let Some(storage) = opts.storage {
         info!(path=%storage.display(), "storage set");
         if !storage.exists() {
@@ -61,7 +325,20 @@ async fn run() -> anyhow::Result<()> {
         (Some(tmp_dir), storage)
     };
 
-    let auth_provider: Arc<dyn AuthProvider> = if let Some(password) = opts.password {
+    let auth_config = auth::AuthConfig::from_env().context("invalid auth configuration")?;
+    let auth_provider: Arc<dyn AuthProvider> = if let Some(auth_config) = auth_config {
+        if opts.password.is_some() {
+            return Err(anyhow!(
+                "--password cannot be combined with {}",
+                auth::AUTH_ENV
+            ));
+        }
+        info!(
+            ?auth_config,
+            "using auth provider configured in environment"
+        );
+        Arc::from(auth::build_auth_provider(auth_config).context("could not build auth provider")?)
+    } else if let Some(password) = opts.password {
         info!("using password supplied on command line");
         let password = Secret::new(password);
         Arc::new(password)
@@ -69,31 +346,466 @@ async fn run() -> anyhow::Result<()> {
         warn!("no password set, allowing access with any credential");
         Arc::new(auth::Permissions::ReadWrite)
     };
+    let auth_provider: Arc<dyn AuthProvider> = if opts.client_cert_identity.is_empty() {
+        auth_provider
+    } else {
+        info!(identities = ?opts.client_cert_identity, "allowing client certificates");
+        Arc::new(auth::AnyOf(vec![
+            Box::new(ClientCertAuthProvider::new(opts.client_cert_identity)),
+            Box::new(auth_provider),
+        ]))
+    };
 
-    let registry = container_registry::ContainerRegistry::builder()
-        .storage(storage)
+    let mut builder = container_registry::ContainerRegistry::builder()
+        .storage_config(StorageConfig::Filesystem {
+            path: storage,
+            shard_depth: opts.blob_shard_depth,
+            durable_writes: opts.durable_writes,
+            compress_small_objects: opts.compress_small_objects,
+        })
         .hooks(Box::new(LoggingHook))
         .auth_provider(auth_provider)
-        .build()
-        .context("failed to instantiate registry")?;
+        .trust_forwarded_headers(opts.trust_forwarded_headers)
+        .absolute_locations(opts.absolute_locations)
+        .delete_enabled(opts.enable_delete)
+        .capabilities_endpoint(opts.capabilities_endpoint)
+        .verify_manifest_blobs(!opts.allow_missing_manifest_blobs)
+        .verify_manifest_digests(opts.verify_manifest_digests)
+        .strict_mounts(opts.strict_mounts)
+        .write_once(opts.write_once)
+        .compression_min_size(opts.compression_min_size)
+        .tag_history_retention(opts.tag_history_retention);
 
-    let app = Router::new()
-        .merge(registry.make_router())
-        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024))
-        .layer(TraceLayer::new_for_http());
+    if let Some(max) = opts.max_metric_repositories {
+        builder = builder.max_metric_repositories(max);
+    }
 
-    let listener = tokio::net::TcpListener::bind(opts.bind)
+    if let Some(max_concurrent_requests) = opts.max_concurrent_requests {
+        builder = builder.max_concurrent_requests(max_concurrent_requests);
+    }
+
+    if let Some(max_concurrent_transfers) = opts.max_concurrent_transfers {
+        builder = builder.max_concurrent_transfers(max_concurrent_transfers);
+    }
+
+    if let Some(read_rate_limit) = opts.read_rate_limit {
+        builder = builder.read_rate_limit(read_rate_limit, Duration::from_secs(60));
+    }
+
+    if let Some(write_rate_limit) = opts.write_rate_limit {
+        builder = builder.write_rate_limit(write_rate_limit, Duration::from_secs(60));
+    }
+
+    if let Some(max_tags_per_repo) = opts.max_tags_per_repo {
+        let policy = if opts.evict_oldest_tags {
+            TagLimitPolicy::EvictOldest
+        } else {
+            TagLimitPolicy::Reject
+        };
+        builder = builder.max_tags_per_repo(max_tags_per_repo, policy);
+    }
+
+    if opts.reject_multi_range {
+        builder = builder.multi_range_policy(MultiRangePolicy::Reject);
+    }
+
+    if let Some(n) = opts.verify_blobs_on_read {
+        builder = builder.blob_read_verification(if n.get() == 1 {
+            BlobReadVerification::Always
+        } else {
+            BlobReadVerification::Sampled(n)
+        });
+    }
+
+    if opts.cascade_referrer_deletes {
+        builder = builder.referrer_delete_policy(ReferrerDeletePolicy::Cascade);
+    }
+
+    if let Some(base_url) = opts.base_url {
+        builder = builder.base_url(base_url);
+    }
+
+    if let Some(access_log) = opts.access_log {
+        builder = if access_log == path::Path::new("-") {
+            builder.access_log(std::io::stdout())
+        } else {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&access_log)
+                .with_context(|| format!("failed to open access log {}", access_log.display()))?;
+            builder.access_log(file)
+        };
+    }
+
+    if let Some(gc_grace_period) = opts.gc_grace_period {
+        builder = builder.gc_grace_period(Duration::from_secs(gc_grace_period));
+    }
+
+    if let Some(soft_delete_window) = opts.soft_delete_window {
+        builder = builder.soft_delete(Duration::from_secs(soft_delete_window));
+    }
+
+    if let Some(realm) = opts.realm {
+        builder = builder.realm(realm);
+    }
+
+    if let Some(login_url) = opts.login_url {
+        builder = builder.login_url(login_url);
+    }
+
+    if let Some(request_timeout) = opts.request_timeout {
+        builder = builder.request_timeout(Duration::from_secs(request_timeout));
+    }
+
+    if !opts.cors_origin.is_empty() {
+        info!(origins = ?opts.cors_origin, "enabling CORS");
+        builder = builder.cors(Cors::new(opts.cors_origin));
+    }
+
+    for cidr in opts.trusted_proxy {
+        builder = builder.trusted_proxy(cidr);
+    }
+
+    for name in opts.sensitive_header {
+        builder = builder.sensitive_header(name);
+    }
+
+    for media_type in opts.allowed_media_type {
+        builder = builder.allowed_media_type(media_type);
+    }
+
+    for mirror in opts.mirror {
+        info!(?mirror, "mirroring upstream repository");
+        let mut upstream = Upstream::new(mirror.base_url, mirror.upstream_repository)
+            .connect_timeout(Duration::from_secs(opts.upstream_connect_timeout))
+            .read_timeout(Duration::from_secs(opts.upstream_read_timeout));
+        if let Some(threshold) = opts.upstream_failure_threshold {
+            upstream =
+                upstream.circuit_breaker(threshold, Duration::from_secs(opts.upstream_cooldown));
+        }
+        builder = builder.upstream(mirror.repository, upstream);
+    }
+
+    let registry = builder.build().context("failed to instantiate registry")?;
+
+    if opts.check || std::env::var("CHECK").is_ok_and(|value| value == "1") {
+        registry
+            .self_check()
+            .await
+            .context("self-check failed, storage is not usable")?;
+        info!("self-check passed");
+    }
+
+    // Without a separate admin listener, admin routes are served along with the API.
+    let (app, admin_app) = match opts.admin_bind {
+        Some(admin_bind) => (
+            registry.clone().make_api_router(),
+            Some((admin_bind, registry.make_admin_router())),
+        ),
+        None => (registry.make_router(), None),
+    };
+    let app = app.layer(DefaultBodyLimit::max(1024 * 1024 * 1024));
+
+    // All listeners shut down together once a signal is received.
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
+
+    let http = HttpConfig {
+        http2: opts.http2,
+        keep_alive_timeout: opts.keep_alive_timeout.map(Duration::from_secs),
+    };
+    let tls = match (opts.tls_cert, opts.tls_key) {
+        (Some(cert), Some(key)) => {
+            info!(cert=%cert.display(), "serving TLS");
+            Some(TlsAcceptor::from(load_tls_config(
+                &cert,
+                &key,
+                opts.tls_client_ca.as_deref(),
+                http,
+            )?))
+        }
+        _ => None,
+    };
+    let server = serve(
+        bind(opts.bind, "api").await?,
+        app,
+        http,
+        tls.clone(),
+        shutdown_rx.clone(),
+    );
+    let admin_server = match admin_app {
+        Some((admin_bind, admin_app)) => Some(serve(
+            bind(admin_bind, "admin").await?,
+            admin_app,
+            http,
+            tls,
+            shutdown_rx.clone(),
+        )),
+        None => None,
+    };
+    let servers = async {
+        match admin_server {
+            Some(admin_server) => tokio::try_join!(server, admin_server).map(|_| ()),
+            None => server.await,
+        }
+    };
+
+    // Outstanding requests are given a grace period to finish before we exit regardless.
+    let drain_timeout = Duration::from_secs(opts.shutdown_timeout);
+    let mut shutdown_started = shutdown_rx;
+    tokio::select! {
+        result = servers => result?,
+        _ = async {
+            let _ = shutdown_started.wait_for(|&started| started).await;
+            tokio::time::sleep(drain_timeout).await;
+        } => warn!(?drain_timeout, "outstanding requests did not finish in time"),
+    }
+
+    info!("shut down");
+    Ok(())
+}
+
+/// HTTP protocol settings of the listeners.
+#[derive(Clone, Copy, Debug)]
+struct HttpConfig {
+    /// Whether to accept HTTP/2 connections.
+    http2: bool,
+    /// Time idle connections are kept open, unlimited if not set.
+    keep_alive_timeout: Option<Duration>,
+}
+
+impl HttpConfig {
+    /// Constructs a connection builder for these settings.
+    fn connection_builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        if let Some(timeout) = self.keep_alive_timeout {
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(timeout);
+            builder
+                .http2()
+                .timer(TokioTimer::new())
+                .keep_alive_interval(timeout)
+                .keep_alive_timeout(timeout);
+        }
+
+        if self.http2 {
+            builder
+        } else {
+            builder.http1_only()
+        }
+    }
+}
+
+/// Time allowed for clients to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads all PEM encoded certificates from a file.
+fn load_certs(
+    path: &path::Path,
+) -> anyhow::Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    let file =
+        fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut io::BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to read certificates from {}", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+
+    Ok(certs)
+}
+
+/// Constructs the TLS configuration of the listeners.
+///
+/// If a client CA is given, client certificates signed by it are requested, but not required.
+fn load_tls_config(
+    cert: &path::Path,
+    key: &path::Path,
+    client_ca: Option<&path::Path>,
+    http: HttpConfig,
+) -> anyhow::Result<Arc<ServerConfig>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let certs = load_certs(cert)?;
+    let key_file =
+        fs::File::open(key).with_context(|| format!("failed to open {}", key.display()))?;
+    let key = rustls_pemfile::private_key(&mut io::BufReader::new(key_file))
+        .with_context(|| format!("failed to read private key from {}", key.display()))?
+        .ok_or_else(|| anyhow!("no private key found in {}", key.display()))?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("no usable TLS protocol versions")?;
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca in load_certs(client_ca)? {
+                roots.add(ca).context("invalid client CA certificate")?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .context("failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+    config.alpn_protocols = if http.http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+
+    Ok(Arc::new(config))
+}
+
+/// Binds a listener to serve on.
+async fn bind(addr: SocketAddr, name: &'static str) -> anyhow::Result<TcpListener> {
+    let listener = TcpListener::bind(addr)
         .await
-        .context("failed to bind listener")?;
+        .with_context(|| format!("failed to bind {name} listener"))?;
 
     let addr = listener
         .local_addr()
         .context("failed to get local listener address")?;
-    info!(%addr, "bound, starting to serve");
+    info!(%addr, listener = name, "bound, starting to serve");
 
-    axum::serve(listener, app).await?;
+    Ok(listener)
+}
 
-    Ok(())
+/// Serves a router on a listener until shutdown is started.
+///
+/// Once shutdown is started, no new connections are accepted and the returned future completes
+/// after all open connections have finished their outstanding requests. If a TLS acceptor is
+/// given, connections are served over TLS and verified client certificates are passed on to the
+/// router as [`ClientCertificate`] extension.
+fn serve(
+    listener: TcpListener,
+    app: Router,
+    http: HttpConfig,
+    tls: Option<TlsAcceptor>,
+    mut shutdown_started: tokio::sync::watch::Receiver<bool>,
+) -> impl std::future::Future<Output = std::io::Result<()>> {
+    let builder = http.connection_builder();
+
+    async move {
+        let connections = GracefulShutdown::new();
+
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        // Usually running out of file descriptors, back off instead of spinning.
+                        warn!(%err, "failed to accept connection");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                },
+                _ = async { let _ = shutdown_started.wait_for(|&started| started).await; } => break,
+            };
+
+            let app = app.clone();
+            let builder = builder.clone();
+            let watcher = connections.watcher();
+            let Some(tls) = tls.clone() else {
+                tokio::spawn(serve_connection(builder, stream, app, remote_addr, watcher));
+                continue;
+            };
+
+            // The handshake is completed on the connection's task to not hold up accepting others.
+            tokio::spawn(async move {
+                let stream =
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(err)) => {
+                            debug!(%err, %remote_addr, "TLS handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            debug!(%remote_addr, "TLS handshake timed out");
+                            return;
+                        }
+                    };
+
+                let peer_cert = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first());
+                let app = match peer_cert.map(|der| ClientCertificate::from_der(der)) {
+                    Some(Ok(cert)) => app.layer(Extension(cert)),
+                    Some(Err(err)) => {
+                        warn!(%err, %remote_addr, "ignoring unusable client certificate");
+                        app
+                    }
+                    None => app,
+                };
+
+                serve_connection(builder, stream, app, remote_addr, watcher).await;
+            });
+        }
+
+        drop(listener);
+        connections.shutdown().await;
+        Ok(())
+    }
+}
+
+/// Serves a router on a single accepted connection.
+async fn serve_connection<S>(
+    builder: auto::Builder<TokioExecutor>,
+    stream: S,
+    app: Router,
+    remote_addr: SocketAddr,
+    watcher: Watcher,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = TowerToHyperService::new(AddExtension::new(app, ConnectInfo(remote_addr)));
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+
+    if let Err(err) = watcher.watch(connection).await {
+        debug!(%err, %remote_addr, "connection closed with error");
+    }
+}
+
+/// Waits for a `SIGINT` (Ctrl+C) or `SIGTERM` signal.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("received shutdown signal, waiting for outstanding requests to finish");
 }
 
 struct FormatErr(anyhow::Error);
@@ -122,3 +834,195 @@ async fn main() -> ExitCode {
         ExitCode::SUCCESS
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::http::{
+        header::{CONTENT_LENGTH, LOCATION},
+        StatusCode,
+    };
+    use container_registry::{
+        auth::{ClientCertAuthProvider, Permissions},
+        ContainerRegistry,
+    };
+    use sha2::{Digest, Sha256};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{load_tls_config, make_subscriber, serve, HttpConfig, LogFormat};
+
+    #[test]
+    fn constructs_subscribers_for_all_log_formats() {
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+
+        for log_format in [LogFormat::Text, LogFormat::Json] {
+            let subscriber = make_subscriber(log_format);
+            tracing::subscriber::with_default(subscriber, || {
+                tracing::info_span!("request", request_id = "test").in_scope(|| {
+                    tracing::info!("smoke test");
+                })
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn pulls_succeed_over_http2() {
+        let storage = tempdir::TempDir::new("container_registry_http2").unwrap();
+        let registry = ContainerRegistry::builder()
+            .storage(storage.path())
+            .auth_provider(Arc::new(Permissions::ReadWrite))
+            .build()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let http = HttpConfig {
+            http2: true,
+            keep_alive_timeout: Some(Duration::from_secs(30)),
+        };
+        let server = tokio::spawn(serve(
+            listener,
+            registry.make_router(),
+            http,
+            None,
+            shutdown_rx,
+        ));
+
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        let base = format!("http://{addr}/v2/tests/sample/blobs");
+        let blob = b"sent over http/2";
+        let digest = format!("sha256:{}", hex::encode(Sha256::digest(blob)));
+
+        let response = client
+            .post(format!("{base}/uploads/"))
+            .basic_auth("user", Some("password"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+
+        let response = client
+            .patch(format!("http://{addr}{location}"))
+            .basic_auth("user", Some("password"))
+            .header(CONTENT_LENGTH, blob.len())
+            .body(&blob[..])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = client
+            .put(format!("http://{addr}{location}?digest={digest}"))
+            .basic_auth("user", Some("password"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = client
+            .get(format!("{base}/{digest}"))
+            .basic_auth("user", Some("password"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap(), &blob[..]);
+
+        shutdown_tx.send(true).unwrap();
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_verified_over_tls() {
+        use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+        let dir = tempdir::TempDir::new("container_registry_tls").unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let issue = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![name.to_owned()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+        let (server_cert, server_key) = issue("localhost");
+        let (client_cert, client_key) = issue("ci-runner");
+
+        std::fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+        std::fs::write(dir.path().join("cert.pem"), server_cert).unwrap();
+        std::fs::write(dir.path().join("key.pem"), server_key).unwrap();
+        let http = HttpConfig {
+            http2: true,
+            keep_alive_timeout: None,
+        };
+        let tls = load_tls_config(
+            &dir.path().join("cert.pem"),
+            &dir.path().join("key.pem"),
+            Some(&dir.path().join("ca.pem")),
+            http,
+        )
+        .unwrap();
+
+        let storage = tempdir::TempDir::new("container_registry_tls_storage").unwrap();
+        let registry = ContainerRegistry::builder()
+            .storage(storage.path())
+            .auth_provider(Arc::new(ClientCertAuthProvider::new(["ci-runner"])))
+            .build()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let server = tokio::spawn(serve(
+            listener,
+            registry.make_router(),
+            http,
+            Some(TlsAcceptor::from(tls)),
+            shutdown_rx,
+        ));
+
+        let url = format!("https://localhost:{port}/v2/");
+        let ca = reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap();
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca.clone())
+            .identity(reqwest::Identity::from_pem((client_cert + &client_key).as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+
+        // Clients without a certificate may still connect, but are not authenticated.
+        let anonymous = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca)
+            .build()
+            .unwrap();
+        let response = anonymous.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        shutdown_tx.send(true).unwrap();
+        drop((client, anonymous));
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+//! Client IP resolution.
+//!
+//! Without further configuration, the client IP of a request is the address of its peer, which is
+//! only known if the router is served with connect info. Behind reverse proxies, the peer is the
+//! proxy instead. If the peer is a trusted proxy, the client IP is taken from the
+//! `X-Forwarded-For` header: Going from right to left, the first address not belonging to a
+//! trusted proxy is the client. Entries left of it may have been forged by the client and are
+//! ignored, as is the header if the peer is not trusted.
+
+use std::{
+    fmt::{self, Display},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use thiserror::Error;
+
+/// Header listing the addresses a request has been forwarded for.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+///
+/// A single address without prefix length is a range containing only itself.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+/// An invalid CIDR range.
+#[derive(Debug, Error)]
+pub enum CidrParseError {
+    /// The address part is not an IP address.
+    #[error("invalid address")]
+    InvalidAddress,
+    /// The prefix length is not a number or too long for the address.
+    #[error("invalid prefix length")]
+    InvalidPrefixLength,
+}
+
+impl Cidr {
+    /// Returns whether the range contains an address.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len));
+                u32::from(network) & mask.unwrap_or(0) == u32::from(ip) & mask.unwrap_or(0)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len));
+                u128::from(network) & mask.unwrap_or(0) == u128::from(ip) & mask.unwrap_or(0)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match raw.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (raw, None),
+        };
+        let network = address
+            .parse::<IpAddr>()
+            .map_err(|_| CidrParseError::InvalidAddress)?
+            .to_canonical();
+
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|&prefix_len| prefix_len <= max_len)
+                .ok_or(CidrParseError::InvalidPrefixLength)?,
+            None => max_len,
+        };
+
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// The resolved IP address of the client of a request.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClientIp(pub(crate) IpAddr);
+
+/// Middleware resolving the client IP of every request.
+pub(crate) async fn resolve_client_ip(
+    State(trusted_proxies): State<Arc<[Cidr]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(peer) = peer {
+        let client_ip = client_ip(peer, request.headers(), &trusted_proxies);
+        request.extensions_mut().insert(ClientIp(client_ip));
+    }
+
+    next.run(request).await
+}
+
+/// Determines the client IP of a request received from `peer`.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(ip));
+
+    // Multiple headers are to be treated as a single comma separated list.
+    let forwarded_for = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    let mut client_ip = peer.to_canonical();
+    for entry in forwarded_for.into_iter().rev() {
+        if !is_trusted(client_ip) {
+            break;
+        }
+        // Garbage cannot be attributed to anyone, the last proxy is the best guess.
+        let Ok(ip) = entry.parse::<IpAddr>() else {
+            break;
+        };
+        client_ip = ip.to_canonical();
+    }
+    client_ip
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{client_ip, Cidr};
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn cidrs_contain_their_addresses() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains(ip("10.1.2.3")));
+        assert!(private.contains(ip("::ffff:10.1.2.3")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(!private.contains(ip("fd00::1")));
+
+        let single: Cidr = "192.0.2.1".parse().unwrap();
+        assert_eq!(single.to_string(), "192.0.2.1/32");
+        assert!(single.contains(ip("192.0.2.1")));
+        assert!(!single.contains(ip("192.0.2.2")));
+
+        let everything: Cidr = "::/0".parse().unwrap();
+        assert!(everything.contains(ip("2001:db8::1")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn forwarded_for_is_only_trusted_from_trusted_proxies() {
+        let trusted: Vec<Cidr> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.51.100.7, 203.0.113.5"),
+        );
+        headers.append("x-forwarded-for", HeaderValue::from_static("10.0.0.2"));
+
+        // The rightmost entry not belonging to a trusted proxy is the client.
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted),
+            ip("203.0.113.5")
+        );
+        assert_eq!(
+            client_ip(ip("192.0.2.1"), &headers, &trusted),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Cross-origin resource sharing.
+//!
+//! Browser-based registry UIs need CORS headers to talk to the registry directly. CORS is disabled
+//! unless at least one allowed origin is configured, wildcard origins are never sent.
+
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::CorsLayer;
+
+/// Name of the header carrying content digests.
+const DOCKER_CONTENT_DIGEST: HeaderName = HeaderName::from_static("docker-content-digest");
+
+/// CORS configuration.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    /// Allowed origins, e.g. `https://registry-ui.example.com`.
+    origins: Vec<HeaderValue>,
+    /// Allowed methods.
+    methods: Vec<Method>,
+    /// Allowed request headers.
+    headers: Vec<HeaderName>,
+}
+
+impl Cors {
+    /// Creates a new CORS configuration allowing the given origins.
+    ///
+    /// By default, all methods used by the registry API are allowed, as are the `Authorization`,
+    /// `Content-Type`, `Content-Length`, `Content-Range`, `Docker-Content-Digest` and `Range`
+    /// request headers.
+    pub fn new<I>(origins: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderValue>,
+    {
+        Self {
+            origins: origins.into_iter().collect(),
+            methods: vec![
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            headers: vec![
+                AUTHORIZATION,
+                CONTENT_TYPE,
+                CONTENT_LENGTH,
+                CONTENT_RANGE,
+                DOCKER_CONTENT_DIGEST,
+                RANGE,
+            ],
+        }
+    }
+
+    /// Sets the allowed methods.
+    pub fn methods<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Sets the allowed request headers.
+    pub fn headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Constructs the layer handling CORS, if any origins are allowed.
+    pub(crate) fn layer(&self) -> Option<CorsLayer> {
+        if self.origins.is_empty() {
+            return None;
+        }
+
+        Some(
+            CorsLayer::new()
+                .allow_origin(self.origins.clone())
+                .allow_methods(self.methods.clone())
+                .allow_headers(self.headers.clone())
+                .expose_headers([DOCKER_CONTENT_DIGEST, LOCATION, RANGE]),
+        )
+    }
+}
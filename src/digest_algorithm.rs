@@ -0,0 +1,135 @@
+//! Digest algorithms.
+//!
+//! Digests are written as `<algorithm>:<hex encoded hash>`. Algorithms are described by a table,
+//! supporting another one only requires adding an entry. Content is addressed by SHA256 in
+//! storage, digests of other algorithms in the table are recognized, but rejected as unsupported.
+//!
+//! Hex is accepted in either case, but always written in lowercase, the canonical form.
+
+use sha2::digest::DynDigest;
+
+use crate::ImageDigestParseError;
+
+/// A digest algorithm.
+#[derive(Debug)]
+pub(crate) struct DigestAlgorithm {
+    /// Name of the algorithm, as used as prefix of digests.
+    pub(crate) name: &'static str,
+    /// Length of hex encoded hashes.
+    pub(crate) hex_len: usize,
+    /// Constructs a hasher for the algorithm.
+    new_hasher: fn() -> Box<dyn DynDigest>,
+}
+
+impl DigestAlgorithm {
+    /// Hashes the given contents.
+    pub(crate) fn hash(&self, contents: &[u8]) -> Box<[u8]> {
+        let mut hasher = (self.new_hasher)();
+        hasher.update(contents);
+        hasher.finalize()
+    }
+}
+
+/// SHA256, the algorithm content is addressed by.
+pub(crate) const SHA256: DigestAlgorithm = DigestAlgorithm {
+    name: "sha256",
+    hex_len: 64,
+    new_hasher: || Box::new(<sha2::Sha256 as sha2::Digest>::new()),
+};
+
+/// SHA512, recognized but not supported for addressing content.
+pub(crate) const SHA512: DigestAlgorithm = DigestAlgorithm {
+    name: "sha512",
+    hex_len: 128,
+    new_hasher: || Box::new(<sha2::Sha512 as sha2::Digest>::new()),
+};
+
+/// All known algorithms.
+static ALGORITHMS: &[DigestAlgorithm] = &[SHA256, SHA512];
+
+/// Splits a digest into its algorithm and hex encoded hash, validating both.
+pub(crate) fn split_digest(
+    raw: &str,
+) -> Result<(&'static DigestAlgorithm, &str), ImageDigestParseError> {
+    let (name, hex) = raw
+        .split_once(':')
+        .ok_or(ImageDigestParseError::WrongPrefix)?;
+    let algorithm = ALGORITHMS
+        .iter()
+        .find(|algorithm| algorithm.name == name)
+        .ok_or(ImageDigestParseError::WrongPrefix)?;
+
+    if hex.len() != algorithm.hex_len {
+        return Err(ImageDigestParseError::WrongLength);
+    }
+    if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ImageDigestParseError::HexDecodeError);
+    }
+
+    Ok((algorithm, hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_digest, SHA256, SHA512};
+    use crate::{ImageDigest, ImageDigestParseError};
+
+    #[test]
+    fn known_algorithms_parse_via_table() {
+        for algorithm in [&SHA256, &SHA512] {
+            let hex = hex::encode(algorithm.hash(b"contents"));
+            let digest = format!("{}:{hex}", algorithm.name);
+            let (parsed, parsed_hex) = split_digest(&digest).expect("should parse known algorithm");
+
+            assert_eq!(parsed.name, algorithm.name);
+            assert_eq!(parsed_hex, hex);
+        }
+    }
+
+    #[test]
+    fn only_sha256_digests_address_content() {
+        let sha256 = format!("sha256:{}", hex::encode(SHA256.hash(b"contents")));
+        let sha512 = format!("sha512:{}", hex::encode(SHA512.hash(b"contents")));
+
+        let digest: ImageDigest = sha256.parse().expect("should parse sha256 digest");
+        assert_eq!(digest.to_string(), sha256);
+        assert!(matches!(
+            sha512.parse::<ImageDigest>(),
+            Err(ImageDigestParseError::UnsupportedAlgorithm("sha512"))
+        ));
+    }
+
+    #[test]
+    fn malformed_digests_are_rejected() {
+        let hex = "a".repeat(64);
+
+        assert!(matches!(
+            split_digest(&format!("md5:{hex}")),
+            Err(ImageDigestParseError::WrongPrefix)
+        ));
+        assert!(matches!(
+            split_digest(&hex),
+            Err(ImageDigestParseError::WrongPrefix)
+        ));
+        assert!(matches!(
+            split_digest(&format!("sha512:{hex}")),
+            Err(ImageDigestParseError::WrongLength)
+        ));
+        assert!(matches!(
+            split_digest(&format!("sha256:{}", "g".repeat(64))),
+            Err(ImageDigestParseError::HexDecodeError)
+        ));
+    }
+
+    #[test]
+    fn digests_are_normalized_to_lowercase() {
+        let hex = hex::encode(SHA256.hash(b"contents"));
+        let lowercase: ImageDigest = format!("sha256:{hex}").parse().unwrap();
+        let uppercase: ImageDigest = format!("sha256:{}", hex.to_uppercase())
+            .parse()
+            .expect("should parse uppercase hex");
+
+        assert_eq!(lowercase.digest(), uppercase.digest());
+        assert_eq!(uppercase.to_string(), format!("sha256:{hex}"));
+    }
+}
@@ -15,6 +15,19 @@ pub trait RegistryHooks: Send + Sync {
     async fn on_manifest_uploaded(&self, manifest_reference: &ManifestReference) {
         let _ = manifest_reference;
     }
+
+    /// Notify about a manifest uploaded by `actor`, the authenticated username if any.
+    ///
+    /// Called by the registry instead of [`Self::on_manifest_uploaded`], which it calls by
+    /// default.
+    async fn on_manifest_pushed(
+        &self,
+        manifest_reference: &ManifestReference,
+        actor: Option<&str>,
+    ) {
+        let _ = actor;
+        self.on_manifest_uploaded(manifest_reference).await;
+    }
 }
 
 impl RegistryHooks for () {}
@@ -0,0 +1,139 @@
+//! Coordination of concurrent operations on the same item.
+//!
+//! Popular layers are often pushed by several clients at once. Finalizing uploads of the same
+//! digest is serialized, so all but the first find the blob already stored and discard their
+//! upload instead of writing it again. Likewise, updates of a tag are serialized, so conditional
+//! pushes compare against the manifest they actually replace.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::OwnedMutexGuard;
+
+/// Items currently being operated on.
+#[derive(Debug)]
+pub(crate) struct InFlight<K> {
+    items: Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl<K> Default for InFlight<K> {
+    fn default() -> Self {
+        Self {
+            items: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> InFlight<K> {
+    /// Marks an item as in flight, waiting up to `wait` for a concurrent operation on it.
+    ///
+    /// Waits indefinitely if `wait` is `None`, otherwise returns `None` if the concurrent
+    /// operation did not complete in time. The item remains marked until the returned guard is
+    /// dropped.
+    pub(crate) async fn acquire(
+        self: &Arc<Self>,
+        key: K,
+        wait: Option<Duration>,
+    ) -> Option<InFlightGuard<K>> {
+        let lock = self
+            .items
+            .lock()
+            .expect("lock poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let guard = match wait {
+            Some(wait) => tokio::time::timeout(wait, lock.lock_owned()).await.ok(),
+            None => Some(lock.lock_owned().await),
+        };
+        let Some(guard) = guard else {
+            self.release(&key);
+            return None;
+        };
+
+        Some(InFlightGuard {
+            in_flight: self.clone(),
+            key,
+            guard: Some(guard),
+        })
+    }
+
+    /// Forgets an item once nobody is operating on or waiting for it anymore.
+    fn release(&self, key: &K) {
+        let mut items = self.items.lock().expect("lock poisoned");
+        // The map holds the only remaining reference if nobody else is interested.
+        if items
+            .get(key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            items.remove(key);
+        }
+    }
+
+    /// Returns the number of items tracked.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.items.lock().expect("lock poisoned").len()
+    }
+}
+
+/// Marks an item as in flight while held.
+#[derive(Debug)]
+pub(crate) struct InFlightGuard<K: Clone + Eq + Hash> {
+    in_flight: Arc<InFlight<K>>,
+    key: K,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl<K: Clone + Eq + Hash> Drop for InFlightGuard<K> {
+    fn drop(&mut self) {
+        // Release the lock first, so its reference no longer counts.
+        self.guard.take();
+        self.in_flight.release(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::InFlight;
+    use crate::storage::Digest;
+
+    #[tokio::test]
+    async fn operations_on_an_item_are_serialized() {
+        let in_flight = Arc::new(InFlight::default());
+        let digest = Digest::from_contents(b"layer");
+        let other = Digest::from_contents(b"other layer");
+
+        let first = in_flight
+            .acquire(digest, Some(Duration::from_secs(1)))
+            .await
+            .expect("should not be in flight");
+
+        // Other items are independent, the same one has to wait.
+        assert!(in_flight
+            .acquire(other, Some(Duration::from_millis(10)))
+            .await
+            .is_some());
+        assert!(in_flight
+            .acquire(digest, Some(Duration::from_millis(10)))
+            .await
+            .is_none());
+
+        let waiting = tokio::spawn({
+            let in_flight = in_flight.clone();
+            async move { in_flight.acquire(digest, None).await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(first);
+        assert!(waiting.await.unwrap());
+
+        assert_eq!(in_flight.len(), 0);
+    }
+}
@@ -37,51 +37,120 @@
 //!     .layer(DefaultBodyLimit::max(1024 * 1024 * 1024));
 //! ```
 //!
+//! The registry can also be nested at a path of an existing app, e.g.
+//! `Router::new().nest("/registry", registry.make_router())`. Locations it returns include the
+//! path.
+//!
 //! Afterwards, `app` can be launched via [`axum::serve()`], see its documentation for details.
+//! Serving it using `app.into_make_service_with_connect_info::<SocketAddr>()` will include client
+//! IP addresses in the audit log events emitted for every authentication attempt (see
+//! [`auth::AUDIT_TARGET`]).
 
+mod access_log;
 pub mod auth;
+pub mod client_ip;
+pub mod cors;
+mod digest_algorithm;
 pub mod hooks;
+mod inflight;
+mod limit;
+mod manifest;
+mod metrics;
+mod pagination;
+pub mod proxy;
+mod range;
+mod rate_limit;
+mod redact;
+mod request_id;
+pub mod retention;
+mod schema1;
 pub mod storage;
 #[cfg(any(feature = "test-support", test))]
 pub mod test_support;
 #[cfg(test)]
 mod tests;
 mod types;
+mod verify;
+pub mod webhooks;
 mod www_authenticate;
 
+pub use manifest::ManifestError;
+
 use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error::Error as _,
     fmt::{self, Display},
-    io,
+    io::{self, Write},
+    num::NonZeroU32,
     path::PathBuf,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use self::{
+    access_log::AccessLog,
     auth::ValidCredentials,
-    storage::{FilesystemStorage, ImageLocation, RegistryStorage},
-    types::{ImageManifest, OciError, OciErrors},
+    inflight::InFlight,
+    manifest::{
+        parse_manifest, parse_pushed_manifest, Descriptor, ImageIndex, ImageManifest, Manifest,
+        OCI_IMAGE_INDEX, OCI_IMAGE_MANIFEST,
+    },
+    metrics::UploadMetrics,
+    pagination::PaginationQuery,
+    proxy::{Upstream, UpstreamClient, UpstreamError},
+    range::RangeError,
+    rate_limit::{RateLimit, RateLimiter},
+    redact::RedactedHeaders,
+    retention::{PrunedTag, RetentionPolicy, RetentionRun},
+    storage::{ImageLocation, RegistryStorage, StorageConfig, TagHistoryEntry},
+    types::{OciError, OciErrors},
+    webhooks::{Webhook, Webhooks},
 };
-use auth::{MissingPermission, Permissions};
+use auth::{MissingPermission, Permissions, Visibility, VisibilityAuthProvider};
 use axum::{
-    body::Body,
-    extract::{Path, Query, State},
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequestParts, OriginalUri, Path, Query, RawQuery, State},
     http::{
-        header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RANGE},
-        StatusCode,
+        header::{
+            ACCEPT, ACCEPT_RANGES, ALLOW, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            FORWARDED, HOST, IF_MATCH, IF_NONE_MATCH, LINK, LOCATION, RANGE, RETRY_AFTER,
+        },
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, StatusCode, Uri,
     },
     response::{IntoResponse, Response},
-    routing::{get, head, patch, post, put},
-    Router,
+    routing::{delete, get, post, put},
+    Extension, Router,
 };
-use futures::stream::StreamExt;
+use client_ip::Cidr;
+use cors::Cors;
+use futures::stream::{Stream, StreamExt};
 use hex::FromHex;
+use http_body_util::LengthLimitError;
 use serde::{Deserialize, Deserializer, Serialize};
 use storage::Reference;
 use thiserror::Error;
-use tokio::io::AsyncWriteExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    sync::Semaphore,
+};
 use tokio_util::io::ReaderStream;
-use tracing::info;
+use tower_http::{
+    compression::{
+        predicate::{Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    limit::RequestBodyLimitLayer,
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 pub(crate) use {
@@ -109,6 +178,57 @@ pub enum RegistryError {
     /// Error parsing image manifest.
     #[error("could not parse manifest")]
     ParseManifest(serde_json::Error),
+    /// A submitted manifest was invalid.
+    #[error("invalid manifest")]
+    InvalidManifest(#[from] ManifestError),
+    /// A tag does not match the tag grammar.
+    #[error("invalid tag {0:?}")]
+    TagInvalid(String),
+    /// A repository or image name does not match the name grammar.
+    #[error("invalid name {0:?}")]
+    NameInvalid(String),
+    /// A submitted manifest or its config has a media type not allowed on this registry.
+    #[error("media type {0} not allowed")]
+    MediaTypeNotAllowed(String),
+    /// A push would exceed the user's storage quota.
+    #[error("storage quota of {0} bytes exceeded")]
+    QuotaExceeded(u64),
+    /// Attempted to overwrite an immutable tag.
+    #[error("tag {0} is immutable")]
+    TagImmutable(String),
+    /// A new tag would exceed the maximum number of tags of a repository.
+    #[error("repository already has the maximum of {0} tags")]
+    TooManyTags(usize),
+    /// A submitted manifest referenced blobs that do not exist.
+    #[error("manifest references {} unknown blobs", .0.len())]
+    ManifestBlobUnknown(Vec<ImageDigest>),
+    /// No manifest in a format accepted by the client exists, or a tag is unknown.
+    #[error("manifest unknown")]
+    ManifestUnknown,
+    /// A manifest to delete is still tagged or part of an image index.
+    #[error("manifest is still referenced")]
+    ManifestInUse,
+    /// A manifest to delete is the subject of other manifests.
+    #[error("manifest has {} referrers", .0.len())]
+    ManifestHasReferrers(Vec<ImageDigest>),
+    /// The manifest currently stored does not match an `If-Match` precondition.
+    #[error("precondition failed")]
+    PreconditionFailed,
+    /// The `Docker-Upload-UUID` header of a request names another upload than its path.
+    #[error("upload id header does not match upload {0}")]
+    UploadUuidMismatch(Uuid),
+    /// An upload chunk does not continue where the upload ended, or its range is malformed.
+    #[error("invalid upload range, {0} bytes received so far")]
+    UploadRangeInvalid(u64),
+    /// The repository is not known to the registry.
+    #[error("name unknown")]
+    NameUnknown,
+    /// Deleting content is disabled.
+    #[error("deletes are disabled")]
+    DeleteDisabled,
+    /// A verification of all blobs is already running.
+    #[error("blob verification already in progress")]
+    VerificationInProgress,
     /// A requested/required feature was not supported by this registry.
     #[error("feature not supported: {0}")]
     NotSupported(&'static str),
@@ -121,12 +241,23 @@ pub enum RegistryError {
     /// Failed to write local data to storage.
     #[error("local write failed")]
     LocalWriteFailed(#[source] io::Error),
+    /// An uploaded blob or manifest exceeded the configured size limit.
+    #[error("payload exceeds size limit of {0} bytes")]
+    PayloadTooLarge(u64),
+    /// Fetching data from an upstream registry failed.
+    #[error("upstream registry error")]
+    Upstream(#[from] UpstreamError),
     /// Error building HTTP response.
     #[error("axum http error")]
     // Note: These should never occur.
     AxumHttp(#[from] axum::http::Error),
 }
 
+/// Non-standard status of requests the client closed before sending the full body.
+fn client_closed_request() -> StatusCode {
+    StatusCode::from_u16(499).expect("499 is a valid status code")
+}
+
 impl IntoResponse for RegistryError {
     #[inline(always)]
     fn into_response(self) -> Response {
@@ -149,6 +280,101 @@ impl IntoResponse for RegistryError {
                 format!("could not parse manifest: {}", err),
             )
                 .into_response(),
+            RegistryError::InvalidManifest(_err) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::ManifestInvalid)),
+            )
+                .into_response(),
+            RegistryError::TagInvalid(_tag) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::ManifestInvalid)),
+            )
+                .into_response(),
+            RegistryError::NameInvalid(_name) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::NameInvalid)),
+            )
+                .into_response(),
+            RegistryError::MediaTypeNotAllowed(_media_type) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::ManifestInvalid)),
+            )
+                .into_response(),
+            RegistryError::QuotaExceeded(_quota) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                OciErrors::single(OciError::new(types::ErrorCode::Denied)),
+            )
+                .into_response(),
+            RegistryError::TagImmutable(_tag) => (
+                StatusCode::CONFLICT,
+                OciErrors::single(OciError::new(types::ErrorCode::Denied)),
+            )
+                .into_response(),
+            RegistryError::TooManyTags(_max) => (
+                StatusCode::FORBIDDEN,
+                OciErrors::single(OciError::new(types::ErrorCode::Denied)),
+            )
+                .into_response(),
+            RegistryError::ManifestBlobUnknown(digests) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(
+                    OciError::new(types::ErrorCode::ManifestBlobUnknown)
+                        .with_missing_blobs(digests),
+                ),
+            )
+                .into_response(),
+            RegistryError::ManifestUnknown => (
+                StatusCode::NOT_FOUND,
+                OciErrors::single(OciError::new(types::ErrorCode::ManifestUnknown)),
+            )
+                .into_response(),
+            RegistryError::ManifestInUse => (
+                StatusCode::CONFLICT,
+                OciErrors::single(
+                    OciError::new(types::ErrorCode::Denied)
+                        .with_message("manifest is still tagged or part of an image index"),
+                ),
+            )
+                .into_response(),
+            RegistryError::ManifestHasReferrers(digests) => (
+                StatusCode::CONFLICT,
+                OciErrors::single(
+                    OciError::new(types::ErrorCode::Denied)
+                        .with_message("manifest is the subject of other manifests")
+                        .with_referrers(digests),
+                ),
+            )
+                .into_response(),
+            RegistryError::PreconditionFailed => StatusCode::PRECONDITION_FAILED.into_response(),
+            RegistryError::UploadUuidMismatch(_upload) => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::BlobUploadInvalid)),
+            )
+                .into_response(),
+            RegistryError::UploadRangeInvalid(completed) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(RANGE, format!("0-{}", completed.saturating_sub(1)))],
+                OciErrors::single(OciError::new(types::ErrorCode::BlobUploadInvalid)),
+            )
+                .into_response(),
+            RegistryError::NameUnknown => (
+                StatusCode::NOT_FOUND,
+                OciErrors::single(OciError::new(types::ErrorCode::NameUnknown)),
+            )
+                .into_response(),
+            RegistryError::DeleteDisabled => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                OciErrors::single(OciError::new(types::ErrorCode::Unsupported)),
+            )
+                .into_response(),
+            RegistryError::VerificationInProgress => (
+                StatusCode::CONFLICT,
+                OciErrors::single(
+                    OciError::new(types::ErrorCode::Denied)
+                        .with_message("blob verification already in progress"),
+                ),
+            )
+                .into_response(),
             RegistryError::NotSupported(feature) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("feature not supported: {}", feature),
@@ -159,16 +385,29 @@ impl IntoResponse for RegistryError {
                 format!("invalid content length value: {}", err),
             )
                 .into_response(),
-            RegistryError::IncomingReadFailed(_err) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "could not read input stream",
-            )
-                .into_response(),
+            RegistryError::IncomingReadFailed(_err) => {
+                (client_closed_request(), "could not read input stream").into_response()
+            }
             RegistryError::LocalWriteFailed(_err) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "could not write image locally",
             )
                 .into_response(),
+            RegistryError::PayloadTooLarge(_limit) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                OciErrors::single(OciError::new(types::ErrorCode::SizeInvalid)),
+            )
+                .into_response(),
+            RegistryError::Upstream(UpstreamError::CircuitOpen) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "upstream registry unavailable, only cached content is served",
+            )
+                .into_response(),
+            RegistryError::Upstream(_err) => (
+                StatusCode::BAD_GATEWAY,
+                "could not fetch data from upstream registry",
+            )
+                .into_response(),
             RegistryError::AxumHttp(_err) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 // Fixed message, we don't want to leak anything. This should never happen anyway.
@@ -179,18 +418,205 @@ impl IntoResponse for RegistryError {
     }
 }
 
+/// Version of this crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit this crate was built from, `unknown` if built outside a git checkout.
+pub const GIT_HASH: &str = env!("CONTAINER_REGISTRY_GIT_HASH");
+
+/// Build time, in seconds since the epoch.
+const BUILD_TIMESTAMP: &str = env!("CONTAINER_REGISTRY_BUILD_TIMESTAMP");
+
+/// Default maximum size of a single blob (16 GiB).
+const DEFAULT_MAX_BLOB_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+/// Default maximum size of a manifest (4 MiB).
+const DEFAULT_MAX_MANIFEST_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Default maximum number of layers of an image manifest.
+const DEFAULT_MAX_MANIFEST_LAYERS: usize = 1000;
+
+/// Default maximum total size of the layers of an image manifest (1 TiB).
+const DEFAULT_MAX_MANIFEST_LAYERS_SIZE: u64 = 1024 * 1024 * 1024 * 1024;
+
+/// Default time to wait for a concurrent finalization of the same blob.
+const DEFAULT_CONCURRENT_FINALIZE_WAIT: Duration = Duration::from_secs(60);
+
+/// Default minimum age of unreferenced blobs to be garbage collected.
+const DEFAULT_GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Default realm of authentication challenges.
+const DEFAULT_REALM: &str = "ContainerRegistry";
+
+/// Default number of entries kept in the history of each tag.
+const DEFAULT_TAG_HISTORY_RETENTION: usize = 100;
+
+/// Interval at which access times of blobs and manifests are updated at most.
+const ACCESS_TIME_RESOLUTION: Duration = Duration::from_secs(60 * 60);
+
+/// Default minimum size of JSON responses to compress.
+const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 256;
+
+/// Default maximum number of repositories labeled individually in upload metrics.
+const DEFAULT_MAX_METRIC_REPOSITORIES: usize = 100;
+
+/// What to do when pushing a new tag would exceed the maximum number of tags of a repository.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TagLimitPolicy {
+    /// Reject the push with `403 Forbidden`.
+    #[default]
+    Reject,
+    /// Delete the least recently pushed tag to make room for the new one.
+    EvictOldest,
+}
+
+/// How to answer blob downloads requesting multiple byte ranges, e.g. `Range: bytes=0-9,20-29`.
+///
+/// Responses with multiple ranges (`multipart/byteranges`) are never sent.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MultiRangePolicy {
+    /// Ignore the `Range` header, sending the full blob with `200 OK`.
+    #[default]
+    FullContent,
+    /// Reject the request with `416 Range Not Satisfiable`.
+    Reject,
+}
+
+/// Whether blob downloads are verified against their digest, to detect blobs tampered with or
+/// corrupted on disk.
+///
+/// Only downloads of whole blobs are verified, not those of byte ranges.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BlobReadVerification {
+    /// Serve blobs as stored.
+    #[default]
+    Disabled,
+    /// Verify every download.
+    Always,
+    /// Verify one in every `n` downloads.
+    Sampled(NonZeroU32),
+}
+
+/// What to do when deleting a manifest other manifests refer to through their `subject`, e.g.
+/// signatures or SBOMs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReferrerDeletePolicy {
+    /// Reject the delete with `409 Conflict`, listing the referrers.
+    #[default]
+    Refuse,
+    /// Delete the referrers along with the manifest, including their own referrers.
+    Cascade,
+}
+
 /// A container registry storing OCI containers.
 pub struct ContainerRegistry {
     /// The realm name for the registry.
     ///
     /// Solely used for HTTP auth.
     realm: String,
+    /// URL explaining how to get access, linked when browsers are denied access.
+    login_url: Option<String>,
     /// An implementation for authentication.
     auth_provider: Arc<dyn AuthProvider>,
     /// A storage backend for the registry.
-    storage: Box<dyn RegistryStorage>,
+    storage: Arc<dyn RegistryStorage>,
     /// A hook consumer for the registry.
     hooks: Box<dyn RegistryHooks>,
+    /// Upstream registries mirrored, by local repository.
+    upstreams: HashMap<String, UpstreamClient>,
+    /// Webhooks to notify about changes.
+    webhooks: Webhooks,
+    /// Maximum size of a single blob in bytes.
+    max_blob_size: u64,
+    /// Maximum size of a manifest in bytes.
+    max_manifest_size: u64,
+    /// Maximum number of layers of an image manifest.
+    max_manifest_layers: usize,
+    /// Maximum total size of the layers of an image manifest, as declared by it, in bytes.
+    max_manifest_layers_size: u64,
+    /// Media type assumed for pushed image manifests not declaring one.
+    default_manifest_media_type: String,
+    /// Timeout for requests other than blob uploads.
+    request_timeout: Option<Duration>,
+    /// Whether to reject image manifests referencing blobs not in storage.
+    verify_manifest_blobs: bool,
+    /// Blobs currently being finalized.
+    finalizing: Arc<InFlight<storage::Digest>>,
+    /// Tags currently being updated.
+    updating_tags: Arc<InFlight<String>>,
+    /// Held shared while blobs are finalized or referenced by manifests, exclusively while garbage
+    /// collection removes blobs or repositories are moved.
+    gc_lock: tokio::sync::RwLock<()>,
+    /// Held while all blobs are verified, so only one verification runs at a time.
+    verifying: tokio::sync::Mutex<()>,
+    /// Time to wait for a concurrent finalization of the same blob.
+    concurrent_finalize_wait: Duration,
+    /// Patterns of tags that cannot be overwritten once pushed.
+    immutable_tags: Vec<String>,
+    /// Whether no tag can be overwritten once pushed.
+    write_once: bool,
+    /// Maximum number of tags per repository and what to do when a push would exceed it.
+    max_tags_per_repo: Option<(usize, TagLimitPolicy)>,
+    /// Minimum size of JSON responses to compress.
+    compression_min_size: u16,
+    /// How to answer blob downloads requesting multiple ranges.
+    multi_range_policy: MultiRangePolicy,
+    /// Blob upload metrics, by repository.
+    upload_metrics: UploadMetrics,
+    /// Patterns of manifest and config media types accepted on push, any if empty.
+    allowed_media_types: Vec<String>,
+    /// Number of entries kept in the history of each tag.
+    tag_history_retention: usize,
+    /// Whether deleting content is allowed.
+    delete_enabled: bool,
+    /// What to do with referrers of deleted manifests.
+    referrer_delete_policy: ReferrerDeletePolicy,
+    /// Visibility of repositories to anonymous clients, by pattern.
+    visibility: Vec<(String, Visibility)>,
+    /// Retention policies of repositories, by pattern.
+    retention: Vec<(String, RetentionPolicy)>,
+    /// CORS configuration, if enabled.
+    cors: Option<Cors>,
+    /// Storage quotas in bytes, by username.
+    quotas: HashMap<String, u64>,
+    /// Per-client rate limiter.
+    rate_limiter: RateLimiter,
+    /// Platform (os, architecture) to resolve image indexes to for clients not accepting them.
+    index_platform: Option<(String, String)>,
+    /// Whether to synthesize schema 1 manifests for clients only accepting those.
+    schema1_manifests: bool,
+    /// Whether image indexes can be resolved to a platform given in the query.
+    platform_query: bool,
+    /// Whether to re-hash manifests on read, instead of trusting the digest recorded on push.
+    verify_manifest_digests: bool,
+    /// Which blob downloads to verify against their digest.
+    blob_read_verification: BlobReadVerification,
+    /// Number of blob downloads so far, for sampling those to verify.
+    blob_reads: AtomicU32,
+    /// Whether mounts check the size of the blob against the one recorded for the source.
+    strict_mounts: bool,
+    /// Whether to describe the capabilities of the registry at `/`.
+    capabilities_endpoint: bool,
+    /// Externally visible base URL of the registry.
+    base_url: Option<String>,
+    /// Whether to derive the base URL from `Forwarded` and `X-Forwarded-*` headers.
+    trust_forwarded_headers: bool,
+    /// Proxies trusted to report client IPs in `X-Forwarded-For`.
+    trusted_proxies: Arc<[Cidr]>,
+    /// Headers redacted in logs, in addition to those carrying credentials.
+    sensitive_headers: Arc<[HeaderName]>,
+    /// Whether `Location` headers must be absolute, even without a known base URL.
+    absolute_locations: bool,
+    /// Maximum number of requests handled concurrently.
+    max_concurrent_requests: Option<usize>,
+    /// Maximum number of blob uploads and downloads handled concurrently.
+    max_concurrent_transfers: Option<usize>,
+    /// Destination of the access log, if any.
+    access_log: Option<AccessLog>,
+    /// Minimum age of unreferenced blobs to be garbage collected.
+    gc_grace_period: Duration,
+    /// Time deleted tags can be restored within, if deletes are soft.
+    soft_delete_window: Option<Duration>,
 }
 
 impl ContainerRegistry {
@@ -201,33 +627,460 @@ impl ContainerRegistry {
         ContainerRegistryBuilder::default()
     }
 
+    /// Checks whether the registry is ready to serve requests.
+    ///
+    /// Verifies the storage is usable by writing, reading back and removing a probe file. Meant to
+    /// be called on startup, to fail early instead of on the first request.
+    pub async fn self_check(&self) -> Result<(), storage::Error> {
+        self.storage.health_check().await
+    }
+
+    /// Prunes tags not kept by the retention policy of their repository.
+    ///
+    /// Manifests left without tags are removed, then garbage is collected. With `dry_run`, storage
+    /// is left untouched and only the tags that would be pruned are reported.
+    async fn apply_retention(
+        &self,
+        dry_run: bool,
+        actor: Option<&str>,
+    ) -> Result<RetentionRun, RegistryError> {
+        // Manifests must not be removed while a push is referencing them.
+        let _collecting = if dry_run {
+            None
+        } else {
+            Some(self.gc_lock.write().await)
+        };
+
+        let mut run = RetentionRun {
+            dry_run,
+            ..Default::default()
+        };
+        let now = SystemTime::now();
+        for location in self.storage.tagged_locations().await? {
+            let name = format!("{}/{}", location.repository(), location.image());
+            let Some((_, policy)) = self
+                .retention
+                .iter()
+                .find(|(pattern, _)| glob_matches(pattern, &name))
+            else {
+                continue;
+            };
+
+            let tags = self.storage.tags_by_age(&location).await?;
+            for tag in policy.expired(&tags, now) {
+                if !dry_run {
+                    let Some(deleted) = self.storage.delete_tag(&location, tag).await? else {
+                        continue;
+                    };
+                    let digest = ImageDigest::new(deleted.digest);
+                    info!(%location, tag, %digest, "pruned tag");
+                    notify_tag_deleted(self, &location, tag, &deleted, actor);
+
+                    if deleted.unreferenced
+                        && self
+                            .storage
+                            .delete_manifest(&location, deleted.digest)
+                            .await?
+                    {
+                        run.manifests.push(ImageDigest::new(deleted.digest));
+                    }
+                }
+
+                run.tags.push(PrunedTag {
+                    location: location.clone(),
+                    tag: tag.to_owned(),
+                });
+            }
+        }
+
+        if !dry_run {
+            run.garbage_collection = Some(
+                self.storage
+                    .garbage_collect(self.gc_grace_period, false)
+                    .await?,
+            );
+        }
+
+        Ok(run)
+    }
+
+    /// Removes tags deleted longer ago than the soft delete window for good.
+    ///
+    /// Manifests left without tags are removed, then garbage is collected.
+    async fn sweep_deleted_tags(&self, window: Duration) -> Result<RetentionRun, RegistryError> {
+        // Manifests must not be removed while a push is referencing them.
+        let _collecting = self.gc_lock.write().await;
+
+        let mut run = RetentionRun::default();
+        for tombstone in self.storage.sweep_tombstones(window).await? {
+            let digest = ImageDigest::new(tombstone.digest);
+            info!(location = %tombstone.location, tag = tombstone.tag, %digest, "swept deleted tag");
+
+            if self
+                .storage
+                .delete_manifest(&tombstone.location, tombstone.digest)
+                .await?
+            {
+                run.manifests.push(digest);
+            }
+            run.tags.push(PrunedTag {
+                location: tombstone.location,
+                tag: tombstone.tag,
+            });
+        }
+
+        run.garbage_collection = Some(
+            self.storage
+                .garbage_collect(self.gc_grace_period, false)
+                .await?,
+        );
+
+        Ok(run)
+    }
+
+    /// Removes a manifest no tag or image index refers to.
+    ///
+    /// Manifests referring to it through their `subject` are handled according to the referrer
+    /// delete policy. Returns the referrers removed along with it. If a referrer to remove is
+    /// still tagged, part of an image index or was pushed to another location, nothing is removed.
+    async fn delete_manifest(
+        &self,
+        location: &ImageLocation,
+        digest: storage::Digest,
+    ) -> Result<Vec<ImageDigest>, RegistryError> {
+        // Manifests must not be removed while a push is referencing them.
+        let _collecting = self.gc_lock.write().await;
+
+        let reference = ManifestReference::new(location.clone(), Reference::new_digest(digest));
+        if self.storage.get_manifest(&reference).await?.is_none() {
+            return Err(RegistryError::ManifestUnknown);
+        }
+
+        if self.storage.manifest_in_use(digest).await? {
+            return Err(RegistryError::ManifestInUse);
+        }
+
+        // Referrers block deletes wherever they were pushed, not just in this location.
+        let referrers = self.storage.all_referrers(digest).await?;
+        if self.referrer_delete_policy == ReferrerDeletePolicy::Refuse && !referrers.is_empty() {
+            return Err(RegistryError::ManifestHasReferrers(
+                referrers.into_iter().map(ImageDigest::new).collect(),
+            ));
+        }
+
+        // Referrers are collected depth first, removing them in reverse removes the referrers of
+        // each manifest before the manifest itself. Everything is checked before removing
+        // anything, so a cascade is never left half done.
+        let mut collected = Vec::new();
+        let mut in_use = Vec::new();
+        let mut pending = vec![digest];
+        while let Some(subject) = pending.pop() {
+            let local = self.storage.get_referrers(location, subject).await?;
+            for referrer in self.storage.all_referrers(subject).await? {
+                if !local.contains(&referrer) || self.storage.manifest_in_use(referrer).await? {
+                    in_use.push(ImageDigest::new(referrer));
+                    continue;
+                }
+                pending.push(referrer);
+                collected.push(referrer);
+            }
+        }
+        if !in_use.is_empty() {
+            return Err(RegistryError::ManifestHasReferrers(in_use));
+        }
+
+        let mut removed = Vec::new();
+        for referrer in collected.into_iter().rev() {
+            if self.storage.delete_manifest(location, referrer).await? {
+                removed.push(ImageDigest::new(referrer));
+            }
+        }
+
+        if !self.storage.delete_manifest(location, digest).await? {
+            return Err(RegistryError::ManifestUnknown);
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns whether to verify the current blob download, according to the configured policy.
+    fn sample_blob_read(&self) -> bool {
+        match self.blob_read_verification {
+            BlobReadVerification::Disabled => false,
+            BlobReadVerification::Always => true,
+            BlobReadVerification::Sampled(n) => self
+                .blob_reads
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(n.get()),
+        }
+    }
+
+    /// Quarantines a blob found not to match its digest while downloading it.
+    ///
+    /// Failing to quarantine it does not change the outcome of the download, it is only logged.
+    async fn quarantine_corrupted_blob(&self, digest: storage::Digest, actual: storage::Digest) {
+        error!(
+            target: auth::AUDIT_TARGET,
+            %digest,
+            %actual,
+            "stored blob does not match its digest, quarantining it"
+        );
+        match self.storage.quarantine_blob(digest).await {
+            Ok(true) => {}
+            Ok(false) => warn!(%digest, "corrupted blob was removed before it was quarantined"),
+            Err(err) => error!(%err, %digest, "could not quarantine corrupted blob"),
+        }
+    }
+
+    /// Records an access of a blob or manifest, for finding least recently used content.
+    ///
+    /// Failing to record it does not fail the download, it is only logged.
+    async fn record_access(&self, digest: storage::Digest) {
+        if let Err(err) = self
+            .storage
+            .record_access(digest, ACCESS_TIME_RESOLUTION)
+            .await
+        {
+            warn!(%err, %digest, "could not record access time");
+        }
+    }
+
+    /// Checks whether storing `size` more bytes under `digest` keeps `owner` within their quota.
+    ///
+    /// Content already attributed to the owner does not count twice. Space reserved by the
+    /// owner's unfinished uploads counts as used, except for that of `upload` itself.
+    async fn check_quota(
+        &self,
+        owner: Option<&str>,
+        digest: Option<storage::Digest>,
+        size: u64,
+        upload: Option<Uuid>,
+    ) -> Result<(), RegistryError> {
+        let Some((owner, &quota)) = owner.and_then(|owner| Some((owner, self.quotas.get(owner)?)))
+        else {
+            return Ok(());
+        };
+
+        if let Some(digest) = digest {
+            if self.storage.is_owner(owner, digest).await? {
+                return Ok(());
+            }
+        }
+
+        let usage = self
+            .storage
+            .owner_usage(owner)
+            .await?
+            .saturating_add(self.storage.reserved_quota(owner, upload).await?);
+        if usage.saturating_add(size) > quota {
+            warn!(%owner, usage, size, quota, "storage quota exceeded");
+            return Err(RegistryError::QuotaExceeded(quota));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the upstream client for a location, if its repository is a mirror.
+    fn upstream_for(&self, location: &ImageLocation) -> Option<&UpstreamClient> {
+        self.upstreams.get(location.repository())
+    }
+
     /// Builds an [`axum::routing::Router`] for this registry.
     ///
     /// Produces the core entry point for the registry; create and mount the router into an `axum`
     /// application to use it.
+    ///
+    /// Request bodies on all routes except blob uploads are limited to the maximum manifest size,
+    /// blob uploads are streamed and limited by the maximum blob size instead. The request timeout,
+    /// if set, does not apply to blob uploads either. Concurrency limits, if set, shed requests
+    /// with `503 Service Unavailable`. CORS headers are added to all routes if configured.
+    /// Manifests and other JSON responses are compressed with gzip or zstd if the client accepts
+    /// it, blobs never are.
+    ///
+    /// Every request is traced in a span carrying a request ID, which is taken from the
+    /// `X-Request-Id` header if given and echoed in the response.
+    ///
+    /// Serves both the registry API and the administrative routes, see [`Self::make_api_router`]
+    /// and [`Self::make_admin_router`] to serve them separately.
     pub fn make_router(self: Arc<ContainerRegistry>) -> Router {
-        Router::new()
-            .route("/v2/", get(index_v2))
-            .route("/v2/:repository/:image/blobs/:digest", head(blob_check))
-            .route("/v2/:repository/:image/blobs/:digest", get(blob_get))
-            .route("/v2/:repository/:image/blobs/uploads/", post(upload_new))
-            .route(
-                "/v2/:repository/:image/uploads/:upload",
-                patch(upload_add_chunk),
-            )
-            .route(
-                "/v2/:repository/:image/uploads/:upload",
-                put(upload_finalize),
-            )
-            .route(
-                "/v2/:repository/:image/manifests/:reference",
-                put(manifest_put),
-            )
-            .route(
-                "/v2/:repository/:image/manifests/:reference",
-                get(manifest_get),
+        self.router(true, true)
+    }
+
+    /// Builds a router serving only the registry API, i.e. `/v2/` and the capabilities endpoint.
+    ///
+    /// Behaves like [`Self::make_router`] otherwise.
+    pub fn make_api_router(self: Arc<ContainerRegistry>) -> Router {
+        self.router(true, false)
+    }
+
+    /// Builds a router serving only the administrative routes, i.e. the health and readiness
+    /// probes, the version, metrics and everything under `/admin/`.
+    ///
+    /// Allows serving them on a separate listener, not exposed publicly along with the API.
+    /// Behaves like [`Self::make_router`] otherwise.
+    pub fn make_admin_router(self: Arc<ContainerRegistry>) -> Router {
+        self.router(false, true)
+    }
+
+    /// Builds a router serving the API and/or the administrative routes.
+    fn router(self: Arc<ContainerRegistry>, api: bool, admin: bool) -> Router {
+        let mut router = Router::new().fallback(not_found);
+
+        if admin {
+            router = router
+                .route("/healthz", get(healthz))
+                .route("/readyz", get(readyz))
+                .route("/version", get(version))
+                .route("/metrics", get(metrics))
+                .route(
+                    "/admin/usage",
+                    get(admin_usage).layer(compression(self.compression_min_size)),
+                )
+                .route("/admin/gc", post(admin_garbage_collect))
+                .route("/admin/retention", post(admin_apply_retention))
+                .route("/admin/undelete", post(admin_undelete_tag))
+                .route("/admin/sweep", post(admin_sweep_deleted_tags))
+                .route("/admin/verify", post(admin_verify))
+                .route(
+                    "/admin/lru",
+                    get(admin_least_recently_used).layer(compression(self.compression_min_size)),
+                )
+                .route(
+                    "/admin/uploads",
+                    get(admin_list_uploads)
+                        .delete(admin_prune_uploads)
+                        .layer(compression(self.compression_min_size)),
+                )
+                .route("/admin/repositories/move", post(admin_move_repository))
+                .route("/admin/:repository/:image/tags/:tag", put(admin_retag))
+                .route(
+                    "/admin/:repository/:image/tags/:tag/history",
+                    get(admin_tag_history).layer(compression(self.compression_min_size)),
+                );
+        }
+
+        // Uploads used to be served without the `blobs/` prefix, which is kept for compatibility.
+        let upload = || {
+            get(upload_status)
+                .patch(upload_add_chunk)
+                .put(upload_finalize)
+                .fallback(|| method_not_allowed("GET, HEAD, PATCH, PUT"))
+        };
+        let mut uploads = Router::new()
+            .route("/v2/:repository/:image/blobs/uploads/:upload", upload())
+            .route("/v2/:repository/:image/uploads/:upload", upload());
+        let mut blob_download = get(blob_get);
+
+        if let Some(max_concurrent_transfers) = self.max_concurrent_transfers {
+            let limit = axum::middleware::from_fn_with_state(
+                Arc::new(Semaphore::new(max_concurrent_transfers)),
+                limit::limit_concurrency,
+            );
+            uploads = uploads.layer(limit.clone());
+            blob_download = blob_download.layer(limit);
+        }
+
+        if api {
+            router = router
+                .route(
+                    "/v2/",
+                    get(index_v2).fallback(|| method_not_allowed("GET, HEAD")),
+                )
+                .route(
+                    "/v2/:repository/:image/blobs/:digest",
+                    blob_download
+                        .head(blob_check)
+                        .fallback(|| method_not_allowed("GET, HEAD")),
+                )
+                .route(
+                    "/v2/:repository/:image/blobs/uploads/",
+                    post(upload_new).fallback(|| method_not_allowed("POST")),
+                )
+                .route(
+                    "/v2/:repository/:image/manifests/:reference",
+                    get(manifest_get)
+                        .layer(compression(self.compression_min_size))
+                        .put(manifest_put)
+                        .delete(manifest_delete)
+                        .fallback(|| method_not_allowed("GET, HEAD, PUT, DELETE")),
+                )
+                .route(
+                    "/v2/:repository/:image/referrers/:digest",
+                    get(referrers_get)
+                        .layer(compression(self.compression_min_size))
+                        .fallback(|| method_not_allowed("GET, HEAD")),
+                )
+                .route(
+                    "/v2/:repository/:image/tags/list",
+                    get(tags_list)
+                        .layer(compression(self.compression_min_size))
+                        .fallback(|| method_not_allowed("GET, HEAD")),
+                )
+                .route(
+                    "/v2/:repository/:image/tags/:tag",
+                    delete(tag_delete).fallback(|| method_not_allowed("DELETE")),
+                );
+        }
+
+        router = router.layer(RequestBodyLimitLayer::new(
+            usize::try_from(self.max_manifest_size).unwrap_or(usize::MAX),
+        ));
+
+        if let Some(request_timeout) = self.request_timeout {
+            router = router.layer(TimeoutLayer::new(request_timeout));
+        }
+
+        if api {
+            router = router.merge(uploads);
+
+            if self.capabilities_endpoint {
+                router = router.route("/", get(capabilities));
+            }
+        }
+
+        if let Some(max_concurrent_requests) = self.max_concurrent_requests {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(Semaphore::new(max_concurrent_requests)),
+                limit::limit_concurrency,
+            ));
+        }
+
+        if let Some(cors) = self.cors.as_ref().and_then(Cors::layer) {
+            router = router.layer(cors);
+        }
+
+        let sensitive_headers = self.sensitive_headers.clone();
+        router = router
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(
+                        // The request span is created by the request ID middleware.
+                        |_request: &axum::extract::Request| tracing::Span::current(),
+                    )
+                    .on_request(
+                        move |request: &axum::extract::Request, _span: &tracing::Span| {
+                            debug!(
+                                headers = ?RedactedHeaders::new(request.headers(), &sensitive_headers),
+                                "started processing request"
+                            );
+                        },
+                    ),
             )
-            .with_state(self)
+            .layer(axum::middleware::from_fn(request_id::tag_request));
+
+        if let Some(ref access_log) = self.access_log {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                access_log.clone(),
+                access_log::log_request,
+            ));
+        }
+
+        router = router.layer(axum::middleware::from_fn_with_state(
+            self.trusted_proxies.clone(),
+            client_ip::resolve_client_ip,
+        ));
+
+        router.with_state(self)
     }
 }
 
@@ -239,14 +1092,158 @@ impl ContainerRegistry {
 ///
 /// By default, no hooks are set up and the auth provider requires authentication, but does not
 /// grant access to anything.
-#[derive(Default)]
 pub struct ContainerRegistryBuilder {
     /// Storage to use.
-    storage: Option<PathBuf>,
+    storage: Option<StorageConfig>,
     /// Hooks to use.
     hooks: Option<Box<dyn RegistryHooks>>,
     /// Auth provider to use.
     auth_provider: Option<Arc<dyn AuthProvider>>,
+    /// Upstream registries to mirror, by local repository.
+    upstreams: HashMap<String, Upstream>,
+    /// Webhooks to notify.
+    webhooks: Vec<Webhook>,
+    /// Maximum size of a single blob in bytes.
+    max_blob_size: u64,
+    /// Maximum size of a manifest in bytes.
+    max_manifest_size: u64,
+    /// Maximum number of layers of an image manifest.
+    max_manifest_layers: usize,
+    /// Maximum total size of the layers of an image manifest, as declared by it, in bytes.
+    max_manifest_layers_size: u64,
+    /// Media type assumed for pushed image manifests not declaring one.
+    default_manifest_media_type: String,
+    /// Timeout for requests other than blob uploads.
+    request_timeout: Option<Duration>,
+    /// Whether to reject image manifests referencing blobs not in storage.
+    verify_manifest_blobs: bool,
+    /// Time to wait for a concurrent finalization of the same blob.
+    concurrent_finalize_wait: Duration,
+    /// Patterns of tags that cannot be overwritten once pushed.
+    immutable_tags: Vec<String>,
+    /// Whether no tag can be overwritten once pushed.
+    write_once: bool,
+    /// Maximum number of tags per repository and what to do when a push would exceed it.
+    max_tags_per_repo: Option<(usize, TagLimitPolicy)>,
+    /// Minimum size of JSON responses to compress.
+    compression_min_size: u16,
+    /// How to answer blob downloads requesting multiple ranges.
+    multi_range_policy: MultiRangePolicy,
+    /// Maximum number of repositories labeled individually in upload metrics.
+    max_metric_repositories: usize,
+    /// Patterns of manifest and config media types accepted on push, any if empty.
+    allowed_media_types: Vec<String>,
+    /// Number of entries kept in the history of each tag.
+    tag_history_retention: usize,
+    /// Whether deleting content is allowed.
+    delete_enabled: bool,
+    /// What to do with referrers of deleted manifests.
+    referrer_delete_policy: ReferrerDeletePolicy,
+    /// Visibility of repositories to anonymous clients, by pattern.
+    visibility: Vec<(String, Visibility)>,
+    /// Retention policies of repositories, by pattern.
+    retention: Vec<(String, RetentionPolicy)>,
+    /// CORS configuration, if enabled.
+    cors: Option<Cors>,
+    /// Storage quotas in bytes, by username.
+    quotas: HashMap<String, u64>,
+    /// Rate limit for reads, per client.
+    read_rate_limit: Option<RateLimit>,
+    /// Rate limit for writes, per client.
+    write_rate_limit: Option<RateLimit>,
+    /// Platform (os, architecture) to resolve image indexes to for clients not accepting them.
+    index_platform: Option<(String, String)>,
+    /// Whether to synthesize schema 1 manifests for clients only accepting those.
+    schema1_manifests: bool,
+    /// Whether image indexes can be resolved to a platform given in the query.
+    platform_query: bool,
+    /// Whether to re-hash manifests on read, instead of trusting the digest recorded on push.
+    verify_manifest_digests: bool,
+    /// Which blob downloads to verify against their digest.
+    blob_read_verification: BlobReadVerification,
+    /// Whether mounts check the size of the blob against the one recorded for the source.
+    strict_mounts: bool,
+    /// Whether to describe the capabilities of the registry at `/`.
+    capabilities_endpoint: bool,
+    /// Externally visible base URL of the registry.
+    base_url: Option<String>,
+    /// The realm of authentication challenges.
+    realm: String,
+    /// URL explaining how to get access, linked when browsers are denied access.
+    login_url: Option<String>,
+    /// Whether to derive the base URL from `Forwarded` and `X-Forwarded-*` headers.
+    trust_forwarded_headers: bool,
+    /// Proxies trusted to report client IPs in `X-Forwarded-For`.
+    trusted_proxies: Vec<Cidr>,
+    /// Headers redacted in logs, in addition to those carrying credentials.
+    sensitive_headers: Vec<HeaderName>,
+    /// Whether `Location` headers must be absolute, even without a known base URL.
+    absolute_locations: bool,
+    /// Maximum number of requests handled concurrently.
+    max_concurrent_requests: Option<usize>,
+    /// Maximum number of blob uploads and downloads handled concurrently.
+    max_concurrent_transfers: Option<usize>,
+    /// Destination of the access log, if any.
+    access_log: Option<AccessLog>,
+    /// Minimum age of unreferenced blobs to be garbage collected.
+    gc_grace_period: Duration,
+    /// Time deleted tags can be restored within, if deletes are soft.
+    soft_delete_window: Option<Duration>,
+}
+
+impl Default for ContainerRegistryBuilder {
+    fn default() -> Self {
+        Self {
+            storage: None,
+            hooks: None,
+            auth_provider: None,
+            upstreams: HashMap::new(),
+            webhooks: Vec::new(),
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            max_manifest_size: DEFAULT_MAX_MANIFEST_SIZE,
+            max_manifest_layers: DEFAULT_MAX_MANIFEST_LAYERS,
+            max_manifest_layers_size: DEFAULT_MAX_MANIFEST_LAYERS_SIZE,
+            default_manifest_media_type: OCI_IMAGE_MANIFEST.to_owned(),
+            request_timeout: None,
+            verify_manifest_blobs: true,
+            concurrent_finalize_wait: DEFAULT_CONCURRENT_FINALIZE_WAIT,
+            immutable_tags: Vec::new(),
+            write_once: false,
+            max_tags_per_repo: None,
+            compression_min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            multi_range_policy: MultiRangePolicy::default(),
+            max_metric_repositories: DEFAULT_MAX_METRIC_REPOSITORIES,
+            allowed_media_types: Vec::new(),
+            tag_history_retention: DEFAULT_TAG_HISTORY_RETENTION,
+            delete_enabled: false,
+            referrer_delete_policy: ReferrerDeletePolicy::default(),
+            visibility: Vec::new(),
+            retention: Vec::new(),
+            cors: None,
+            quotas: HashMap::new(),
+            read_rate_limit: None,
+            write_rate_limit: None,
+            index_platform: None,
+            schema1_manifests: false,
+            platform_query: false,
+            verify_manifest_digests: false,
+            blob_read_verification: BlobReadVerification::default(),
+            strict_mounts: false,
+            capabilities_endpoint: false,
+            base_url: None,
+            realm: DEFAULT_REALM.to_owned(),
+            login_url: None,
+            trust_forwarded_headers: false,
+            trusted_proxies: Vec::new(),
+            sensitive_headers: Vec::new(),
+            absolute_locations: false,
+            max_concurrent_requests: None,
+            max_concurrent_transfers: None,
+            access_log: None,
+            gc_grace_period: DEFAULT_GC_GRACE_PERIOD,
+            soft_delete_window: None,
+        }
+    }
 }
 
 impl ContainerRegistryBuilder {
@@ -262,477 +1259,3294 @@ impl ContainerRegistryBuilder {
         self
     }
 
-    /// Set the storage path for the new registry.
-    pub fn storage<P>(mut self, storage: P) -> Self
-    where
-        P: Into<PathBuf>,
-    {
-        self.storage = Some(storage.into());
+    /// Sets the maximum size of a single uploaded blob, in bytes.
+    ///
+    /// Uploads exceeding the limit are aborted. Defaults to 16 GiB.
+    pub fn max_blob_size(mut self, max_blob_size: u64) -> Self {
+        self.max_blob_size = max_blob_size;
         self
     }
 
-    /// Constructs a new registry.
+    /// Sets the maximum size of an uploaded manifest, in bytes.
     ///
-    /// # Panics
+    /// Defaults to 4 MiB.
+    pub fn max_manifest_size(mut self, max_manifest_size: u64) -> Self {
+        self.max_manifest_size = max_manifest_size;
+        self
+    }
+
+    /// Sets the maximum number of layers an image manifest may reference.
     ///
-    /// Will panic if not storage has been set through [`Self::storage`].
-    pub fn build(mut self) -> Result<Arc<ContainerRegistry>, FilesystemStorageError> {
-        let storage_path = self
-            .storage
-            .expect("attempted to construct registry with no storage path");
-        let storage = Box::new(FilesystemStorage::new(storage_path)?);
-        let auth_provider = self
-            .auth_provider
-            .take()
-            .unwrap_or_else(|| Arc::new(Permissions::NoAccess));
-        let hooks = self.hooks.take().unwrap_or_else(|| Box::new(()));
-        Ok(Arc::new(ContainerRegistry {
-            realm: "ContainerRegistry".to_string(),
-            auth_provider,
-            storage,
-            hooks,
-        }))
+    /// Manifests referencing more layers are rejected with `MANIFEST_INVALID`. Defaults to 1000.
+    pub fn max_manifest_layers(mut self, max_manifest_layers: usize) -> Self {
+        self.max_manifest_layers = max_manifest_layers;
+        self
     }
-}
 
-/// Registry index
-///
-/// Returns an empty HTTP OK response if provided credentials are okay, otherwise returns
-/// UNAUTHORIZED.
-async fn index_v2(
-    State(registry): State<Arc<ContainerRegistry>>,
-    unverified: Unverified,
-) -> Response<Body> {
-    let realm = &registry.realm;
+    /// Sets the maximum total size of the layers of an image manifest, in bytes.
+    ///
+    /// The sizes declared by the manifest are summed up, manifests exceeding the limit are
+    /// rejected with `MANIFEST_INVALID`. Defaults to 1 TiB.
+    pub fn max_manifest_layers_size(mut self, max_manifest_layers_size: u64) -> Self {
+        self.max_manifest_layers_size = max_manifest_layers_size;
+        self
+    }
 
-    // Both anonymous and named users should be verified to be able to get index. Restricted access
-    // is handled identically for both via the rules set within the registry constructor.
-    if registry
-        .auth_provider
-        .check_credentials(&unverified)
-        .await
-        .is_some()
-    {
-        return Response::builder()
-            .status(StatusCode::OK)
-            .header("WWW-Authenticate", format!("Basic realm=\"{realm}\""))
-            .body(Body::empty())
-            .unwrap();
+    /// Sets the media type assumed for pushed image manifests declaring none.
+    ///
+    /// Applies when neither the `Content-Type` header nor the `mediaType` field of the manifest
+    /// names a manifest media type. Older clients omitting both usually push Docker image
+    /// manifests, `application/vnd.docker.distribution.manifest.v2+json`. Defaults to
+    /// `application/vnd.oci.image.manifest.v1+json`.
+    pub fn default_manifest_media_type(mut self, media_type: impl Into<String>) -> Self {
+        self.default_manifest_media_type = media_type.into();
+        self
     }
 
-    // Return `UNAUTHORIZED`, since we want the client to supply credentials.
-    Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header("WWW-Authenticate", format!("Basic realm=\"{realm}\""))
-        .body(Body::empty())
-        .unwrap()
-}
+    /// Sets a timeout for all requests except blob uploads.
+    ///
+    /// Requests not completed within the timeout are answered with `REQUEST_TIMEOUT`. By default,
+    /// no timeout is set.
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
 
-/// Returns metadata of a specific image blob.
+    /// Limits the number of requests handled concurrently.
+    ///
+    /// Requests beyond the limit are rejected with `503 Service Unavailable` and a `Retry-After`
+    /// header. A request counts until its response has been sent completely. By default, the
+    /// number of requests is not limited.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = Some(max_concurrent_requests);
+        self
+    }
+
+    /// Limits the number of blob uploads and downloads handled concurrently.
+    ///
+    /// Transfers are more expensive than other requests and can be given a lower limit, which
+    /// applies in addition to [`max_concurrent_requests`](Self::max_concurrent_requests). By
+    /// default, the number of transfers is not limited.
+    pub fn max_concurrent_transfers(mut self, max_concurrent_transfers: usize) -> Self {
+        self.max_concurrent_transfers = Some(max_concurrent_transfers);
+        self
+    }
+
+    /// Writes an access log line for every request to the given writer, e.g. a file or stdout.
+    ///
+    /// Lines are in the Combined Log Format, containing the client IP, authenticated user,
+    /// request line, status, response size, referer and user agent. Client IPs are only known if
+    /// the router is served with connect info. By default, no access log is written.
+    pub fn access_log<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.access_log = Some(AccessLog::new(Box::new(writer)));
+        self
+    }
+
+    /// Sets the minimum age of unreferenced blobs to be removed by garbage collection.
+    ///
+    /// Blobs are pushed before the manifest referencing them, younger blobs are kept to not
+    /// remove those of pushes in progress. Garbage collection also waits for blob finalizations
+    /// and manifest pushes in progress, which in turn wait for it to finish, so a manifest never
+    /// references a removed blob: Pushes taking longer than the grace period between uploading
+    /// blobs and pushing the manifest are rejected with `MANIFEST_BLOB_UNKNOWN` instead. Defaults
+    /// to one hour.
+    pub fn gc_grace_period(mut self, gc_grace_period: Duration) -> Self {
+        self.gc_grace_period = gc_grace_period;
+        self
+    }
+
+    /// Makes tag deletions soft, restorable within `window`.
+    ///
+    /// Deleted tags are no longer listed or pulled, but can be restored by `POST /admin/undelete`
+    /// with a body like `{"location": {"repository": "…", "image": "…"}, "tag": "…"}`. Once
+    /// `window` has passed, `POST /admin/sweep` removes them for good, along with manifests left
+    /// without tags, and collects garbage. By default, deletions take effect immediately.
+    /// Deleting a manifest by digest always takes effect immediately.
+    pub fn soft_delete(mut self, window: Duration) -> Self {
+        self.soft_delete_window = Some(window);
+        self
+    }
+
+    /// Sets whether pushed image manifests must only reference blobs already in storage.
+    ///
+    /// If enabled, pushing a manifest whose config or layers are missing fails with
+    /// `400 Bad Request` and `MANIFEST_BLOB_UNKNOWN`, listing the missing digests as
+    /// `missingBlobs` in the error detail. Foreign layers are never checked. Enabled by default,
+    /// disable it only for workflows pushing manifests ahead of their blobs.
+    pub fn verify_manifest_blobs(mut self, verify_manifest_blobs: bool) -> Self {
+        self.verify_manifest_blobs = verify_manifest_blobs;
+        self
+    }
+
+    /// Sets the number of entries kept in the history of each tag.
+    ///
+    /// Every push moving a tag to a different manifest is recorded, older entries are dropped once
+    /// the limit is reached. The history is available at
+    /// `/admin/<repository>/<image>/tags/<tag>/history`. Defaults to 100, zero disables recording.
+    pub fn tag_history_retention(mut self, tag_history_retention: usize) -> Self {
+        self.tag_history_retention = tag_history_retention;
+        self
+    }
+
+    /// Sets how long finalizing an upload waits for a concurrent finalization of the same blob.
+    ///
+    /// Uploads of a blob already stored are discarded. If the concurrent finalization takes
+    /// longer, the client is asked to retry with `202 Accepted` and a `Retry-After` header.
+    /// Defaults to one minute.
+    pub fn concurrent_finalize_wait(mut self, concurrent_finalize_wait: Duration) -> Self {
+        self.concurrent_finalize_wait = concurrent_finalize_wait;
+        self
+    }
+
+    /// Enables describing the capabilities of the registry at `/`.
+    ///
+    /// The JSON document served lists the version, accepted manifest media types and optional
+    /// features enabled. It is a discovery aid for tooling, not part of the distribution spec, and
+    /// does not require authentication. Disabled by default.
+    pub fn capabilities_endpoint(mut self, capabilities_endpoint: bool) -> Self {
+        self.capabilities_endpoint = capabilities_endpoint;
+        self
+    }
+
+    /// Enables CORS for browser-based clients.
+    ///
+    /// See the [`cors`] module for details.
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    /// Limits the total size of blobs and manifests a user may push, in bytes.
+    ///
+    /// Pushes exceeding the quota are rejected with `413 Payload Too Large`. Content is
+    /// attributed to every user that pushed it, users without a quota are not limited.
+    ///
+    /// Uploads announcing their size in an `X-Upload-Content-Length` header are checked when
+    /// started, and reserve that size until finalized or cancelled. Abandoned uploads keep their
+    /// reservation.
+    pub fn quota<U: Into<String>>(mut self, username: U, bytes: u64) -> Self {
+        self.quotas.insert(username.into(), bytes);
+        self
+    }
+
+    /// Limits reads, i.e. `GET` and `HEAD` requests, to `requests` per client within `per`.
+    ///
+    /// Authenticated clients are limited by username, anonymous ones by IP address, provided the
+    /// router is served with connect info. Requests over the limit are rejected with
+    /// `429 Too Many Requests` and a `Retry-After` header. Unlimited by default.
+    pub fn read_rate_limit(mut self, requests: u32, per: Duration) -> Self {
+        self.read_rate_limit = Some(RateLimit::new(requests, per));
+        self
+    }
+
+    /// Limits writes to `requests` per client within `per`.
+    ///
+    /// Writes are limited independently of reads, see [`Self::read_rate_limit`] for details.
+    pub fn write_rate_limit(mut self, requests: u32, per: Duration) -> Self {
+        self.write_rate_limit = Some(RateLimit::new(requests, per));
+        self
+    }
+
+    /// Sets the realm sent in authentication challenges, `ContainerRegistry` by default.
+    ///
+    /// Clients show it when prompting for credentials, it may be a full URL such as
+    /// `https://registry.example.com`. Can be overridden per router using [`Realm`].
+    pub fn realm<R: Into<String>>(mut self, realm: R) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Sets a URL explaining how to get access, e.g. a login or support page.
+    ///
+    /// Browsers denied access, i.e. requests accepting `text/html`, receive a short HTML page
+    /// explaining how to log in with container tooling instead of an empty body, linking this URL
+    /// if set. Other clients are unaffected.
+    pub fn login_url<U: Into<String>>(mut self, login_url: U) -> Self {
+        self.login_url = Some(login_url.into());
+        self
+    }
+
+    /// Sets the externally visible base URL of the registry, e.g. `https://registry.example.com`.
+    ///
+    /// If set, `Location` headers are absolute URLs based on it instead of paths.
+    pub fn base_url<U: Into<String>>(mut self, base_url: U) -> Self {
+        let base_url = base_url.into();
+        self.base_url = Some(base_url.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Sets whether to trust `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers.
+    ///
+    /// When running behind a reverse proxy, these headers carry the scheme and host used by
+    /// clients. If trusted, they take precedence over the configured
+    /// [`base_url`](Self::base_url) when building `Location` headers. Only enable this if all
+    /// requests pass through a proxy setting these headers, as clients could otherwise forge them.
+    /// Disabled by default.
+    pub fn trust_forwarded_headers(mut self, trust_forwarded_headers: bool) -> Self {
+        self.trust_forwarded_headers = trust_forwarded_headers;
+        self
+    }
+
+    /// Trusts a proxy to report client IPs.
+    ///
+    /// Client IPs are used by the access log, audit log and rate limits. Requests received from
+    /// trusted proxies are attributed to the rightmost address in `X-Forwarded-For` not belonging
+    /// to a trusted proxy, all other requests to their peer address. By default, no proxy is
+    /// trusted.
+    pub fn trusted_proxy(mut self, cidr: Cidr) -> Self {
+        self.trusted_proxies.push(cidr);
+        self
+    }
+
+    /// Redacts a header in logs.
+    ///
+    /// Request headers are logged at debug level. `Authorization`, `Proxy-Authorization`, `Cookie`
+    /// and `Set-Cookie` are always redacted, use this for other headers carrying secrets, e.g. API
+    /// keys checked by a proxy.
+    pub fn sensitive_header(mut self, name: HeaderName) -> Self {
+        self.sensitive_headers.push(name);
+        self
+    }
+
+    /// Sets whether `Location` headers are always absolute URLs.
+    ///
+    /// Some clients require absolute URLs. If no base URL is configured or forwarded, the `Host`
+    /// header of the request is used. Disabled by default.
+    pub fn absolute_locations(mut self, absolute_locations: bool) -> Self {
+        self.absolute_locations = absolute_locations;
+        self
+    }
+
+    /// Resolves image indexes for clients that do not accept them.
+    ///
+    /// When a client requests an image index but its `Accept` header only allows image manifests,
+    /// the first acceptable manifest for the given operating system and architecture (e.g. `linux`
+    /// and `amd64`) is served instead. Without this setting, such requests fail with
+    /// `MANIFEST_UNKNOWN`.
+    pub fn resolve_index_platform<O: Into<String>, A: Into<String>>(
+        mut self,
+        os: O,
+        architecture: A,
+    ) -> Self {
+        self.index_platform = Some((os.into(), architecture.into()));
+        self
+    }
+
+    /// Serves Docker schema 1 manifests to legacy clients.
+    ///
+    /// When a client only accepts `application/vnd.docker.distribution.manifest.v1+json`, a schema 1
+    /// manifest is synthesized from the stored image manifest and its config. Requests for which
+    /// this is not possible fail with `MANIFEST_UNKNOWN`. Disabled by default.
+    pub fn synthesize_schema1_manifests(mut self, schema1_manifests: bool) -> Self {
+        self.schema1_manifests = schema1_manifests;
+        self
+    }
+
+    /// Allows pulling a single platform of an image index via a `platform` query parameter.
+    ///
+    /// With `?platform=<os>/<architecture>` or `?platform=<os>/<architecture>/<variant>`, e.g.
+    /// `?platform=linux/arm64`, pulling an image index serves the child manifest for the given
+    /// platform instead, or fails with `MANIFEST_UNKNOWN` if there is none. Other manifests are
+    /// served as usual. Eases pulling images with tools not resolving platforms themselves, like
+    /// scripts. Disabled by default.
+    pub fn platform_query(mut self, platform_query: bool) -> Self {
+        self.platform_query = platform_query;
+        self
+    }
+
+    /// Verifies manifests against their digest on every read.
+    ///
+    /// The digest of a manifest is computed once when it is pushed and served from storage
+    /// afterwards. If enabled, manifests are hashed again on read and requests for manifests that
+    /// no longer match their digest fail. Meant for debugging, disabled by default.
+    pub fn verify_manifest_digests(mut self, verify_manifest_digests: bool) -> Self {
+        self.verify_manifest_digests = verify_manifest_digests;
+        self
+    }
+
+    /// Verifies blobs against their digest while they are downloaded.
+    ///
+    /// Verified downloads hash the blob as it is sent and fail before its end if it no longer
+    /// matches its digest. Such blobs are reported as `tracing` events with target
+    /// [`auth::AUDIT_TARGET`] and quarantined, so they are no longer served and can be pushed
+    /// again. Costs CPU time for every verified download, use [`BlobReadVerification::Sampled`]
+    /// to verify only some. Disabled by default.
+    pub fn blob_read_verification(mut self, verification: BlobReadVerification) -> Self {
+        self.blob_read_verification = verification;
+        self
+    }
+
+    /// Checks the size of blobs before mounting them from another repository.
+    ///
+    /// If enabled, a blob is only mounted if its size matches the one recorded when it was pushed
+    /// to the source repository. Otherwise, e.g. if the blob has been truncated on disk, the client
+    /// is asked to upload it instead. Disabled by default.
+    pub fn strict_mounts(mut self, strict_mounts: bool) -> Self {
+        self.strict_mounts = strict_mounts;
+        self
+    }
+
+    /// Allows deleting content.
+    ///
+    /// Deletes are disabled by default, guaranteeing pushed content is never removed through the
+    /// API. While disabled, delete requests are rejected with `405 Method Not Allowed`. Once
+    /// enabled, deletes require write permissions.
+    pub fn delete_enabled(mut self, delete_enabled: bool) -> Self {
+        self.delete_enabled = delete_enabled;
+        self
+    }
+
+    /// Sets what to do when deleting a manifest other manifests refer to through their `subject`.
+    ///
+    /// Keeps the referrers API from listing signatures and SBOMs of deleted manifests. Defaults
+    /// to [`ReferrerDeletePolicy::Refuse`].
+    pub fn referrer_delete_policy(mut self, policy: ReferrerDeletePolicy) -> Self {
+        self.referrer_delete_policy = policy;
+        self
+    }
+
+    /// Sets the visibility of repositories matching a pattern to anonymous clients.
+    ///
+    /// Patterns are matched against `<repository>/<image>` and may contain `*` and `?`, see
+    /// [`Self::immutable_tag`]. The first matching pattern applies. Anonymous clients may pull
+    /// from [`Visibility::Public`] repositories and are rejected with `401 Unauthorized` by
+    /// [`Visibility::Private`] ones, regardless of the auth provider. Pushing always requires
+    /// authentication. Repositories not matching any pattern are left to the auth provider.
+    pub fn repository_visibility<P: Into<String>>(
+        mut self,
+        pattern: P,
+        visibility: Visibility,
+    ) -> Self {
+        self.visibility.push((pattern.into(), visibility));
+        self
+    }
+
+    /// Sets the retention policy of repositories matching a pattern.
+    ///
+    /// Patterns are matched like in [`Self::repository_visibility`], the first matching pattern
+    /// applies. Policies are applied by `POST /admin/retention`, which prunes tags not kept by the
+    /// policy, removes manifests left without tags and then collects garbage. Pass
+    /// `?dry_run=true` to only report what would be pruned, otherwise deletes must be enabled.
+    pub fn retention<P: Into<String>>(mut self, pattern: P, policy: RetentionPolicy) -> Self {
+        self.retention.push((pattern.into(), policy));
+        self
+    }
+
+    /// Marks tags matching a pattern as immutable.
+    ///
+    /// Patterns may contain `*` to match any number of characters and `?` to match a single
+    /// character, e.g. `v*` or `release-*`. Once pushed, a matching tag cannot be pointed at a
+    /// different manifest, attempts are rejected with `409 Conflict`. Pushing the same manifest
+    /// again is allowed.
+    pub fn immutable_tag<P: Into<String>>(mut self, pattern: P) -> Self {
+        self.immutable_tags.push(pattern.into());
+        self
+    }
+
+    /// Makes all tags immutable, like [`Self::immutable_tag`] with a pattern matching every tag.
+    ///
+    /// Turns the registry append-only: Once pushed, no tag can be pointed at a different manifest.
+    /// Pushing the same manifest again, as well as pushes by digest, are still allowed.
+    pub fn write_once(mut self, write_once: bool) -> Self {
+        self.write_once = write_once;
+        self
+    }
+
+    /// Sets the minimum size in bytes of JSON responses, e.g. manifests and tag lists, to compress.
+    ///
+    /// Responses are compressed with gzip or zstd if the client accepts it. Smaller ones are sent
+    /// as is, as are binary and already compressed content. Defaults to 256 bytes.
+    pub fn compression_min_size(mut self, min_size: u16) -> Self {
+        self.compression_min_size = min_size;
+        self
+    }
+
+    /// Sets the maximum number of repositories labeled individually in upload metrics.
+    ///
+    /// Uploads to repositories beyond the first `max` seen are reported under the `_other` label
+    /// instead, bounding the size of `/metrics` regardless of the repository names clients use.
+    /// Defaults to 100.
+    pub fn max_metric_repositories(mut self, max: usize) -> Self {
+        self.max_metric_repositories = max;
+        self
+    }
+
+    /// Limits the number of tags per repository.
+    ///
+    /// Pushing a tag that does not exist yet to a repository already holding `max` tags is handled
+    /// according to `policy`, overwriting existing tags is always allowed. Manifests of evicted
+    /// tags are left in place.
+    pub fn max_tags_per_repo(mut self, max: usize, policy: TagLimitPolicy) -> Self {
+        self.max_tags_per_repo = Some((max, policy));
+        self
+    }
+
+    /// Sets how to answer blob downloads requesting multiple byte ranges.
+    ///
+    /// Single ranges are always served with `206 Partial Content`. Defaults to
+    /// [`MultiRangePolicy::FullContent`].
+    pub fn multi_range_policy(mut self, policy: MultiRangePolicy) -> Self {
+        self.multi_range_policy = policy;
+        self
+    }
+
+    /// Restricts pushes to manifests and configs of media types matching a pattern.
+    ///
+    /// Patterns are matched like in [`Self::immutable_tag`]. Once any is set, both the media type
+    /// of a pushed manifest and, for image manifests, that of its config must match one, e.g.
+    /// `application/vnd.oci.image.*` limits the registry to OCI images. Other pushes are rejected
+    /// with `400 Bad Request`. By default, all Docker and OCI image manifests and indexes are
+    /// accepted, regardless of their config.
+    pub fn allowed_media_type<P: Into<String>>(mut self, pattern: P) -> Self {
+        self.allowed_media_types.push(pattern.into());
+        self
+    }
+
+    /// Configures a local repository as a pull-through cache of an upstream repository.
+    ///
+    /// See the [`proxy`] module for details.
+    pub fn upstream<R>(mut self, repository: R, upstream: Upstream) -> Self
+    where
+        R: Into<String>,
+    {
+        self.upstreams.insert(repository.into(), upstream);
+        self
+    }
+
+    /// Adds a webhook to notify about pushed and deleted manifests.
+    ///
+    /// See the [`webhooks`] module for details.
+    pub fn webhook(mut self, webhook: Webhook) -> Self {
+        self.webhooks.push(webhook);
+        self
+    }
+
+    /// Set the storage path for the new registry.
+    ///
+    /// Shorthand for [`Self::storage_config`] with [`StorageConfig::Filesystem`].
+    pub fn storage<P>(mut self, storage: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.storage = Some(StorageConfig::Filesystem {
+            path: storage.into(),
+            shard_depth: storage::DEFAULT_BLOB_SHARD_DEPTH,
+            durable_writes: false,
+            compress_small_objects: None,
+        });
+        self
+    }
+
+    /// Set the storage backend for the new registry.
+    pub fn storage_config(mut self, storage: StorageConfig) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Constructs a new registry.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if not storage has been set through [`Self::storage`] or
+    /// [`Self::storage_config`].
+    pub fn build(mut self) -> Result<Arc<ContainerRegistry>, FilesystemStorageError> {
+        let storage_config = self
+            .storage
+            .take()
+            .expect("attempted to construct registry with no storage configured");
+        let storage: Arc<dyn RegistryStorage> = storage::build_storage(&storage_config)?.into();
+        let mut auth_provider = self
+            .auth_provider
+            .take()
+            .unwrap_or_else(|| Arc::new(Permissions::NoAccess));
+        if !self.visibility.is_empty() {
+            auth_provider = Arc::new(VisibilityAuthProvider::new(
+                self.visibility.clone(),
+                auth_provider,
+                storage.clone(),
+            ));
+        }
+        let hooks = self.hooks.take().unwrap_or_else(|| Box::new(()));
+        let upstreams = self
+            .upstreams
+            .into_iter()
+            .map(|(repository, upstream)| (repository, UpstreamClient::new(upstream)))
+            .collect();
+        Ok(Arc::new(ContainerRegistry {
+            realm: self.realm,
+            login_url: self.login_url,
+            auth_provider,
+            storage,
+            hooks,
+            upstreams,
+            webhooks: Webhooks::new(self.webhooks),
+            max_blob_size: self.max_blob_size,
+            max_manifest_size: self.max_manifest_size,
+            max_manifest_layers: self.max_manifest_layers,
+            max_manifest_layers_size: self.max_manifest_layers_size,
+            default_manifest_media_type: self.default_manifest_media_type,
+            request_timeout: self.request_timeout,
+            verify_manifest_blobs: self.verify_manifest_blobs,
+            finalizing: Arc::default(),
+            updating_tags: Arc::default(),
+            gc_lock: tokio::sync::RwLock::new(()),
+            verifying: tokio::sync::Mutex::new(()),
+            concurrent_finalize_wait: self.concurrent_finalize_wait,
+            immutable_tags: self.immutable_tags,
+            write_once: self.write_once,
+            max_tags_per_repo: self.max_tags_per_repo,
+            compression_min_size: self.compression_min_size,
+            multi_range_policy: self.multi_range_policy,
+            upload_metrics: UploadMetrics::new(self.max_metric_repositories),
+            allowed_media_types: self.allowed_media_types,
+            tag_history_retention: self.tag_history_retention,
+            delete_enabled: self.delete_enabled,
+            referrer_delete_policy: self.referrer_delete_policy,
+            visibility: self.visibility,
+            retention: self.retention,
+            cors: self.cors,
+            quotas: self.quotas,
+            rate_limiter: RateLimiter::new(self.read_rate_limit, self.write_rate_limit),
+            index_platform: self.index_platform,
+            schema1_manifests: self.schema1_manifests,
+            platform_query: self.platform_query,
+            verify_manifest_digests: self.verify_manifest_digests,
+            blob_read_verification: self.blob_read_verification,
+            blob_reads: AtomicU32::new(0),
+            strict_mounts: self.strict_mounts,
+            capabilities_endpoint: self.capabilities_endpoint,
+            base_url: self.base_url,
+            trust_forwarded_headers: self.trust_forwarded_headers,
+            trusted_proxies: self.trusted_proxies.into(),
+            sensitive_headers: self.sensitive_headers.into(),
+            absolute_locations: self.absolute_locations,
+            max_concurrent_requests: self.max_concurrent_requests,
+            max_concurrent_transfers: self.max_concurrent_transfers,
+            access_log: self.access_log,
+            gc_grace_period: self.gc_grace_period,
+            soft_delete_window: self.soft_delete_window,
+        }))
+    }
+}
+
+/// Compression for routes returning JSON documents, of responses of at least `min_size` bytes.
+///
+/// Blobs are not compressed, as layers usually are compressed already.
+fn compression(min_size: u16) -> CompressionLayer<CompressionPredicate> {
+    CompressionLayer::new()
+        .no_br()
+        .no_deflate()
+        .compress_when(CompressionPredicate { min_size })
+}
+
+/// Decides which responses of the routes using [`compression`] are compressed.
+///
+/// Small responses are not worth the overhead, compressed or binary content does not shrink.
+#[derive(Clone, Copy, Debug)]
+struct CompressionPredicate {
+    /// Minimum size of responses to compress.
+    min_size: u16,
+}
+
+impl Predicate for CompressionPredicate {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: axum::body::HttpBody,
+    {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        SizeAbove::new(self.min_size).should_compress(response) && !is_precompressed(content_type)
+    }
+}
+
+/// Returns whether content of a media type is binary or compressed already.
+fn is_precompressed(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    matches!(
+        essence.as_str(),
+        "application/octet-stream" | "application/gzip" | "application/x-gzip" | "application/zstd"
+    ) || [".gzip", "+gzip", ".zstd", "+zstd"]
+        .iter()
+        .any(|suffix| essence.ends_with(suffix))
+}
+
+/// Reports storage usage.
+///
+/// Requires authentication, anonymous users are rejected even if the auth provider accepts them.
+async fn admin_usage(
+    State(registry): State<Arc<ContainerRegistry>>,
+    _creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let stats = registry.storage.storage_stats().await?;
+    Ok(axum::Json(stats).into_response())
+}
+
+/// Query parameters of a garbage collection run.
+#[derive(Debug, Deserialize)]
+struct GarbageCollectionQuery {
+    /// Whether to only report what would be removed.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Removes blobs no longer referenced by any manifest, reporting them.
+///
+/// Requires authentication like [`admin_usage`], as well as write access to the registry, see
+/// [`AuthProvider::registry_permissions`]. Dry runs only require read access. Unless only doing a
+/// dry run, deletes must be enabled.
+async fn admin_garbage_collect(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Query(GarbageCollectionQuery { dry_run }): Query<GarbageCollectionQuery>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let permissions = registry.auth_provider.registry_permissions(&creds).await;
+    if dry_run {
+        permissions.require_read()?;
+    } else {
+        permissions.require_write()?;
+    }
+
+    if !dry_run && !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
+
+    // Blobs must not be removed between a push checking for them and referencing them.
+    let _collecting = if dry_run {
+        None
+    } else {
+        Some(registry.gc_lock.write().await)
+    };
+
+    let collection = registry
+        .storage
+        .garbage_collect(registry.gc_grace_period, dry_run)
+        .await?;
+    info!(
+        dry_run,
+        blobs = collection.blobs.len(),
+        bytes = collection.bytes,
+        "garbage collection finished"
+    );
+    Ok(axum::Json(collection).into_response())
+}
+
+/// Prunes tags according to the configured retention policies, reporting them.
+///
+/// Requires authentication and permissions like [`admin_garbage_collect`]. Unless only doing a dry
+/// run, deletes must be enabled.
+async fn admin_apply_retention(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Query(GarbageCollectionQuery { dry_run }): Query<GarbageCollectionQuery>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let permissions = registry.auth_provider.registry_permissions(&creds).await;
+    if dry_run {
+        permissions.require_read()?;
+    } else {
+        permissions.require_write()?;
+    }
+
+    if !dry_run && !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
+
+    let run = registry
+        .apply_retention(dry_run, unverified.username())
+        .await?;
+    info!(
+        dry_run,
+        tags = run.tags.len(),
+        manifests = run.manifests.len(),
+        "retention applied"
+    );
+    Ok(axum::Json(run).into_response())
+}
+
+/// Request to restore a deleted tag.
+#[derive(Debug, Deserialize)]
+struct UndeleteRequest {
+    location: ImageLocation,
+    tag: String,
+}
+
+/// Restores a tag deleted within the soft delete window, see
+/// [`ContainerRegistryBuilder::soft_delete`].
+///
+/// Requires authentication like [`admin_usage`], as well as write access to the image.
+async fn admin_undelete_tag(
+    State(registry): State<Arc<ContainerRegistry>>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+    axum::Json(UndeleteRequest { location, tag }): axum::Json<UndeleteRequest>,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let Some(window) = registry.soft_delete_window else {
+        return Err(RegistryError::ManifestUnknown);
+    };
+    validate_location(&location)?;
+    registry
+        .auth_provider
+        .image_permissions(&creds, &location)
+        .await
+        .require_write()?;
+    validate_tag(&tag)?;
+
+    let digest = registry
+        .storage
+        .undelete_tag(&location, &tag, window)
+        .await?
+        .map(ImageDigest::new)
+        .ok_or(RegistryError::ManifestUnknown)?;
+    info!(%location, %tag, %digest, "restored deleted tag");
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Removes tags deleted longer ago than the soft delete window for good, reporting them.
+///
+/// Requires authentication like [`admin_usage`], as well as write access to the registry. Deletes
+/// must be enabled. Does nothing unless deletes are soft.
+async fn admin_sweep_deleted_tags(
+    State(registry): State<Arc<ContainerRegistry>>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    registry
+        .auth_provider
+        .registry_permissions(&creds)
+        .await
+        .require_write()?;
+    if !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
+
+    let run = match registry.soft_delete_window {
+        Some(window) => registry.sweep_deleted_tags(window).await?,
+        None => RetentionRun::default(),
+    };
+    info!(
+        tags = run.tags.len(),
+        manifests = run.manifests.len(),
+        "swept deleted tags"
+    );
+    Ok(axum::Json(run).into_response())
+}
+
+/// Query parameters of a least recently used listing.
+#[derive(Debug, Deserialize)]
+struct LeastRecentlyUsedQuery {
+    /// Maximum number of objects to list.
+    #[serde(default = "default_lru_limit")]
+    limit: usize,
+}
+
+fn default_lru_limit() -> usize {
+    100
+}
+
+/// A blob or manifest in a least recently used listing.
+#[derive(Debug, Serialize)]
+struct AccessedObject {
+    /// Digest of the object.
+    digest: ImageDigest,
+    /// Whether the object is a blob or a manifest.
+    kind: &'static str,
+    /// Time of the last access, as an RFC 3339 timestamp.
+    last_accessed: String,
+}
+
+/// Lists stored blobs and manifests least recently accessed first, as candidates for eviction.
+///
+/// Requires authentication like [`admin_usage`]. Downloads update the access time of what they
+/// serve, at most once per hour. Content never downloaded counts as accessed when stored.
+async fn admin_least_recently_used(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Query(LeastRecentlyUsedQuery { limit }): Query<LeastRecentlyUsedQuery>,
+    _creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let objects: Vec<_> = registry
+        .storage
+        .least_recently_used(limit)
+        .await?
+        .into_iter()
+        .map(|object| AccessedObject {
+            digest: ImageDigest::new(object.digest),
+            kind: if object.manifest { "manifest" } else { "blob" },
+            last_accessed: webhooks::rfc3339(object.last_accessed),
+        })
+        .collect();
+    Ok(axum::Json(objects).into_response())
+}
+
+/// Hashes all stored blobs, reporting those whose contents no longer match their digest.
+///
+/// Requires authentication like [`admin_usage`], as well as write access to the registry. Only one
+/// verification runs at a time, others are rejected with `409 Conflict` meanwhile. Corrupted blobs
+/// are only reported, not removed.
+async fn admin_verify(
+    State(registry): State<Arc<ContainerRegistry>>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    registry
+        .auth_provider
+        .registry_permissions(&creds)
+        .await
+        .require_write()?;
+    let _verifying = registry
+        .verifying
+        .try_lock()
+        .map_err(|_| RegistryError::VerificationInProgress)?;
+
+    let concurrency = std::thread::available_parallelism().map_or(1, usize::from);
+    let verification = registry.storage.verify_blobs(concurrency).await?;
+    if verification.corrupted.is_empty() {
+        info!(
+            blobs = verification.scanned,
+            bytes = verification.bytes,
+            "blob verification finished"
+        );
+    } else {
+        error!(
+            blobs = verification.scanned,
+            corrupted = ?verification.corrupted,
+            "blob verification found corrupted blobs"
+        );
+    }
+    Ok(axum::Json(verification).into_response())
+}
+
+/// Lists uploads in progress.
+///
+/// Requires authentication like [`admin_usage`].
+async fn admin_list_uploads(
+    State(registry): State<Arc<ContainerRegistry>>,
+    _creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let uploads = registry.storage.list_uploads().await?;
+    Ok(axum::Json(uploads).into_response())
+}
+
+/// Query parameters of pruning uploads.
+#[derive(Debug, Deserialize)]
+struct PruneUploadsQuery {
+    /// Minimum time in seconds since an upload was last active for it to be pruned.
+    older_than: u64,
+}
+
+/// Cancels uploads that have not been active for a while, reporting them.
+///
+/// Requires authentication like [`admin_usage`], as well as write access to the registry. Deletes
+/// must be enabled.
+async fn admin_prune_uploads(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Query(PruneUploadsQuery { older_than }): Query<PruneUploadsQuery>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    registry
+        .auth_provider
+        .registry_permissions(&creds)
+        .await
+        .require_write()?;
+    if !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
+
+    let pruned = registry
+        .storage
+        .prune_uploads(Duration::from_secs(older_than))
+        .await?;
+    info!(uploads = pruned.len(), older_than, "pruned uploads");
+    Ok(axum::Json(pruned).into_response())
+}
+
+/// Request to move a repository.
+#[derive(Debug, Deserialize)]
+struct MoveRepositoryRequest {
+    from: ImageLocation,
+    to: ImageLocation,
+    /// Whether to replace the destination if it exists.
+    #[serde(default)]
+    overwrite: bool,
+}
+
+/// Moves all tags of a repository to another one, see [`RegistryStorage::move_repository`].
+///
+/// Requires authentication like [`admin_usage`], as well as write access to both locations. As
+/// the source is removed, deletes must be enabled.
+async fn admin_move_repository(
+    State(registry): State<Arc<ContainerRegistry>>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+    axum::Json(request): axum::Json<MoveRepositoryRequest>,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    if !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
+
+    for location in [&request.from, &request.to] {
+        validate_location(location)?;
+        registry
+            .auth_provider
+            .image_permissions(&creds, location)
+            .await
+            .require_write()?;
+    }
+
+    // Pushes to either location must not interleave with moving it.
+    let _moving = registry.gc_lock.write().await;
+    registry
+        .storage
+        .move_repository(&request.from, &request.to, request.overwrite)
+        .await?;
+    info!(from = %request.from, to = %request.to, overwrite = request.overwrite, "moved repository");
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// The manifests a tag has pointed to.
+#[derive(Debug, Serialize)]
+struct TagHistory {
+    name: String,
+    tag: String,
+    history: Vec<TagHistoryEntry>,
+}
+
+/// Reports the manifests a tag has pointed to, oldest first.
+///
+/// Requires authentication like [`admin_usage`], as well as read access to the image.
+async fn admin_tag_history(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path((repository, image, tag)): Path<(String, String, String)>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let location = valid_location(repository, image)?;
+    registry
+        .auth_provider
+        .image_permissions(&creds, &location)
+        .await
+        .require_read()?;
+    validate_tag(&tag)?;
+
+    let history = registry.storage.tag_history(&location, &tag).await?;
+    Ok(axum::Json(TagHistory {
+        name: format!("{}/{}", location.repository(), location.image()),
+        tag,
+        history,
+    })
+    .into_response())
+}
+
+/// Points a tag at a manifest already stored, without pushing the manifest again.
+///
+/// Requires authentication like [`admin_usage`], as well as write access to the image. Fails with
+/// `MANIFEST_UNKNOWN` if no manifest with the digest is stored. Otherwise behaves like pushing the
+/// manifest by tag, e.g. immutable tags are not moved.
+async fn admin_retag(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path((repository, image, tag)): Path<(String, String, String)>,
+    Query(DigestQuery { digest }): Query<DigestQuery>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+    base_url: BaseUrl,
+) -> Result<Response, RegistryError> {
+    if unverified.is_no_credentials() {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let location = valid_location(repository, image)?;
+    registry
+        .auth_provider
+        .image_permissions(&creds, &location)
+        .await
+        .require_write()?;
+    validate_tag(&tag)?;
+
+    // Keeps garbage collection from removing the manifest before the tag points at it.
+    let _gc = registry.gc_lock.read().await;
+    let manifest_json = registry
+        .storage
+        .get_manifest(&ManifestReference::new(
+            location.clone(),
+            Reference::new_digest(digest.digest),
+        ))
+        .await?
+        .ok_or(RegistryError::ManifestUnknown)?;
+    let manifest = load_manifest(&registry, &manifest_json, digest.digest).await?;
+
+    let manifest_reference = ManifestReference::new(location, Reference::new_tag(&tag));
+    let _updating_tag = registry
+        .updating_tags
+        .acquire(manifest_reference.to_string(), None)
+        .await;
+    check_tag_update(
+        &registry,
+        &manifest_reference,
+        &tag,
+        digest.digest,
+        &unverified,
+    )
+    .await?;
+
+    registry
+        .storage
+        .put_manifest(&manifest_reference, &manifest_json)
+        .await?;
+    record_tag_history(
+        &registry,
+        manifest_reference.location(),
+        &tag,
+        digest.digest,
+        &unverified,
+    )
+    .await?;
+
+    info!(%manifest_reference, %digest, actor = unverified.username(), "tag promoted");
+    registry
+        .hooks
+        .on_manifest_pushed(&manifest_reference, unverified.username())
+        .await;
+    registry.webhooks.notify(webhooks::Event::manifest(
+        webhooks::Action::Push,
+        &manifest_reference,
+        digest,
+        Some(manifest.media_type().to_owned()),
+        Some(manifest_json.len() as u64),
+        unverified.username().map(ToOwned::to_owned),
+    ));
+
+    Ok(manifest_created(
+        &base_url,
+        &manifest_reference,
+        digest.digest,
+    ))
+}
+
+/// Answers requests to unknown routes.
+///
+/// Clients expect an OCI error for anything below `/v2/`: `NAME_UNKNOWN` if the path ends in a
+/// known endpoint, as names not consisting of a repository and image are never served, and
+/// `UNSUPPORTED` otherwise. Other paths get a plain JSON error.
+async fn not_found(uri: Uri) -> Response {
+    let Some(rest) = uri.path().strip_prefix("/v2/") else {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(serde_json::json!({ "error": "not found" })),
+        )
+            .into_response();
+    };
+
+    let is_endpoint = |segment| matches!(segment, "blobs" | "manifests" | "referrers" | "tags");
+    let code = if rest.split('/').rev().skip(1).any(is_endpoint) {
+        types::ErrorCode::NameUnknown
+    } else {
+        types::ErrorCode::Unsupported
+    };
+
+    (
+        StatusCode::NOT_FOUND,
+        [("Docker-Distribution-Api-Version", "registry/2.0")],
+        OciErrors::single(OciError::new(code)),
+    )
+        .into_response()
+}
+
+/// Answers a request using a method the requested resource does not support.
+///
+/// `allow` lists the supported methods and is returned in the `Allow` header.
+async fn method_not_allowed(allow: &'static str) -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [
+            (ALLOW.as_str(), allow),
+            ("Docker-Distribution-Api-Version", "registry/2.0"),
+        ],
+        OciErrors::single(OciError::new(types::ErrorCode::Unsupported)),
+    )
+        .into_response()
+}
+
+/// Exports metrics in the Prometheus text exposition format.
+///
+/// Requires authentication like [`admin_usage`].
+async fn metrics(
+    State(registry): State<Arc<ContainerRegistry>>,
+    _creds: ValidCredentials,
+    unverified: Unverified,
+) -> Response {
+    if unverified.is_no_credentials() {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        registry.upload_metrics.render(),
+    )
+        .into_response()
+}
+
+/// Liveness probe.
+///
+/// Always returns HTTP OK if the registry is running, does not require authentication.
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Build information of the registry.
+#[derive(Debug, Serialize)]
+struct BuildInfo {
+    version: &'static str,
+    git_hash: &'static str,
+    build_timestamp: String,
+}
+
+/// Reports the version of the registry.
+///
+/// Does not require authentication.
+async fn version() -> axum::Json<BuildInfo> {
+    let build_time = UNIX_EPOCH + Duration::from_secs(BUILD_TIMESTAMP.parse().unwrap_or_default());
+
+    axum::Json(BuildInfo {
+        version: VERSION,
+        git_hash: GIT_HASH,
+        build_timestamp: webhooks::rfc3339(build_time),
+    })
+}
+
+/// Capabilities of the registry, see [`ContainerRegistryBuilder::capabilities_endpoint`].
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    version: &'static str,
+    /// Media types of manifests accepted on push.
+    manifest_media_types: &'static [&'static str],
+    /// Whether schema 1 manifests are synthesized for legacy clients.
+    schema1_manifests: bool,
+    /// Whether image indexes can be resolved via the `platform` query parameter.
+    platform_query: bool,
+    delete_enabled: bool,
+    referrers_api: bool,
+    /// Whether the tags list supports the `n` and `last` parameters.
+    tags_pagination: bool,
+    /// Authentication scheme of the `/v2/` API.
+    auth_scheme: &'static str,
+}
+
+/// Describes the capabilities of the registry.
+///
+/// Does not require authentication.
+async fn capabilities(State(registry): State<Arc<ContainerRegistry>>) -> axum::Json<Capabilities> {
+    axum::Json(Capabilities {
+        version: VERSION,
+        manifest_media_types: &manifest::MANIFEST_MEDIA_TYPES,
+        schema1_manifests: registry.schema1_manifests,
+        platform_query: registry.platform_query,
+        delete_enabled: registry.delete_enabled,
+        referrers_api: true,
+        tags_pagination: true,
+        auth_scheme: "basic",
+    })
+}
+
+/// Readiness probe.
+///
+/// Returns HTTP OK if the storage backend is available, `SERVICE_UNAVAILABLE` otherwise. Does not
+/// require authentication.
+async fn readyz(State(registry): State<Arc<ContainerRegistry>>) -> StatusCode {
+    match registry.storage.health_check().await {
+        Ok(()) => StatusCode::OK,
+        Err(err) => {
+            warn!(%err, "storage health check failed");
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+/// Registry index
+///
+/// Returns an empty HTTP OK response if provided credentials are okay, otherwise returns
+/// UNAUTHORIZED.
+async fn index_v2(
+    State(registry): State<Arc<ContainerRegistry>>,
+    realm: Option<Extension<Realm>>,
+    unverified: Unverified,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let realm = realm.map(|Extension(realm)| realm);
+    let challenge = www_authenticate::basic_challenge(match realm {
+        Some(ref realm) => &realm.0,
+        None => &registry.realm,
+    });
+
+    // Both anonymous and named users should be verified to be able to get index. Restricted access
+    // is handled identically for both via the rules set within the registry constructor.
+    if registry
+        .auth_provider
+        .check_credentials(&unverified)
+        .await
+        .is_some()
+    {
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header("WWW-Authenticate", &challenge)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    // Return `UNAUTHORIZED`, since we want the client to supply credentials.
+    unauthorized(&registry, realm.as_ref(), &headers)
+}
+
+/// Builds an `UNAUTHORIZED` response, challenging the client to supply credentials.
+///
+/// Browsers additionally receive a page explaining how to authenticate, other clients an empty
+/// body.
+fn unauthorized(
+    registry: &ContainerRegistry,
+    realm: Option<&Realm>,
+    headers: &HeaderMap,
+) -> Response<Body> {
+    let challenge = www_authenticate::basic_challenge(match realm {
+        Some(realm) => &realm.0,
+        None => &registry.realm,
+    });
+    let response = Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("WWW-Authenticate", challenge);
+
+    // Container tooling never asks for HTML explicitly, a wildcard is not enough.
+    if !accepted_media_types(headers)
+        .iter()
+        .any(|media_type| media_type == "text/html")
+    {
+        return response.body(Body::empty()).unwrap();
+    }
+
+    let mut page = String::from(concat!(
+        "<!DOCTYPE html>\n",
+        "<html>\n",
+        "<head><title>Authentication required</title></head>\n",
+        "<body>\n",
+        "<h1>Authentication required</h1>\n",
+        "<p>This is a container registry. Log in using your container tooling, e.g. ",
+        "<code>docker login</code> or <code>podman login</code> followed by the address of ",
+        "this registry.</p>\n",
+    ));
+    if let Some(ref login_url) = registry.login_url {
+        let login_url = html_escape(login_url);
+        page.push_str(&format!(
+            "<p>For access, see <a href=\"{login_url}\">{login_url}</a>.</p>\n"
+        ));
+    }
+    page.push_str("</body>\n</html>\n");
+
+    response
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(page))
+        .unwrap()
+}
+
+/// Escapes text for use in HTML content and attribute values.
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns metadata of a specific image blob.
 async fn blob_check(
     State(registry): State<Arc<ContainerRegistry>>,
-    Path((_, _, image)): Path<(String, String, ImageDigest)>,
+    Path((repository, image_name, image)): Path<(String, String, ImageDigest)>,
+    creds: ValidCredentials,
+) -> Result<Response, RegistryError> {
+    registry
+        .auth_provider
+        .blob_permissions(&creds, &image)
+        .await
+        .require_read()?;
+
+    let location = valid_location(repository, image_name)?;
+
+    // Blobs not cached yet are looked up upstream, without fetching them.
+    let found = match registry.storage.get_blob_metadata(image.digest).await? {
+        Some(metadata) => Some((metadata.size(), blob_content_type(&metadata))),
+        None => match registry.upstream_for(&location) {
+            Some(upstream) => upstream
+                .blob_size(&location, image.digest)
+                .await?
+                .map(|size| (size, HeaderValue::from_static("application/octet-stream"))),
+            None => None,
+        },
+    };
+
+    if let Some((size, content_type)) = found {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(ACCEPT_RANGES, "bytes")
+            .header(CONTENT_LENGTH, size)
+            .header("Docker-Content-Digest", image.to_string())
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::empty())
+            .unwrap())
+    } else {
+        Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap())
+    }
+}
+
+/// Returns the content type of a blob, the media type given by the manifest referencing it.
+///
+/// Blobs not referenced by any manifest yet are served as `application/octet-stream`.
+fn blob_content_type(metadata: &storage::BlobMetadata) -> HeaderValue {
+    metadata
+        .media_type()
+        .and_then(|media_type| HeaderValue::from_str(media_type).ok())
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"))
+}
+
+/// Returns a specific image blob, or a single byte range of it.
+async fn blob_get(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path((repository, image_name, image)): Path<(String, String, ImageDigest)>,
+    creds: ValidCredentials,
+    headers: HeaderMap,
+) -> Result<Response, RegistryError> {
+    registry
+        .auth_provider
+        .blob_permissions(&creds, &image)
+        .await
+        .require_read()?;
+
+    let location = valid_location(repository, image_name)?;
+    if let Some(upstream) = registry.upstream_for(&location) {
+        upstream
+            .cache_blob(
+                registry.storage.as_ref(),
+                &location,
+                image.digest,
+                registry.max_blob_size,
+            )
+            .await?;
+    }
+
+    let metadata = registry
+        .storage
+        .get_blob_metadata(image.digest)
+        .await?
+        .ok_or(RegistryError::NotFound)?;
+    let total_len = metadata.size();
+    registry.record_access(image.digest).await;
+
+    // Invalid ranges are ignored, sending the full blob instead, multiple ranges depending on the
+    // configured policy.
+    let range = match range::parse_byte_range(headers.get(RANGE), total_len) {
+        Ok(range) => range,
+        Err(err)
+            if err == RangeError::Unsatisfiable
+                || (err == RangeError::MultipleRanges
+                    && registry.multi_range_policy == MultiRangePolicy::Reject) =>
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{total_len}"))
+                .body(Body::empty())
+                .unwrap());
+        }
+        Err(err) => {
+            debug!(%err, "ignoring range header");
+            None
+        }
+    };
+
+    let mut reader = registry
+        .storage
+        .get_blob_reader(image.digest)
+        .await?
+        .ok_or(RegistryError::NotFound)?;
+
+    let response = Response::builder()
+        .header(ACCEPT_RANGES, "bytes")
+        .header(CONTENT_TYPE, blob_content_type(&metadata))
+        .header("Docker-Content-Digest", image.to_string());
+    let Some((start, end)) = range else {
+        let body = if registry.sample_blob_read() {
+            let digest = image.digest;
+            Body::from_stream(verify::verified_stream(
+                reader,
+                digest,
+                move |actual| async move { registry.quarantine_corrupted_blob(digest, actual).await },
+            ))
+        } else {
+            Body::from_stream(ReaderStream::new(reader))
+        };
+        return Ok(response
+            .status(StatusCode::OK)
+            .header(CONTENT_LENGTH, total_len)
+            .body(body)
+            .expect("Building a streaming response with body works. qed"));
+    };
+
+    reader
+        .seek(io::SeekFrom::Start(start))
+        .await
+        .map_err(storage::Error::Io)?;
+    let len = end - start + 1;
+
+    Ok(response
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(CONTENT_LENGTH, len)
+        .header(CONTENT_RANGE, format!("bytes {start}-{end}/{total_len}"))
+        .body(Body::from_stream(ReaderStream::new(reader.take(len))))
+        .expect("Building a streaming response with body works. qed"))
+}
+
+/// Initiates a new blob upload.
+///
+/// If the digest of the blob is given and it is stored already, no upload is started.
+async fn upload_new(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path(location): Path<ImageLocation>,
+    Query(UploadNewQuery {
+        digest,
+        mount,
+        from,
+    }): Query<UploadNewQuery>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+    base_url: BaseUrl,
+    headers: HeaderMap,
+) -> Result<Response, RegistryError> {
+    // Names become directories of upload sidecars, reject them before anything is written.
+    validate_location(&location)?;
+    registry
+        .auth_provider
+        .image_permissions(&creds, &location)
+        .await
+        .require_write()?;
+
+    // Stored blobs are linked instead of uploaded, falling back to an upload if not mountable.
+    if let Some(digest) = mount.or(digest) {
+        let _gc = registry.gc_lock.read().await;
+        let mut stored = registry.storage.get_blob_metadata(digest.digest).await?;
+        if let (Some(_), Some(metadata)) = (mount, stored.as_ref()) {
+            if !may_mount(&registry, &creds, from.as_deref(), digest, metadata).await? {
+                stored = None;
+            }
+        }
+
+        if let Some(ref metadata) = stored {
+            registry
+                .check_quota(
+                    unverified.username(),
+                    Some(digest.digest),
+                    metadata.size(),
+                    None,
+                )
+                .await?;
+        }
+
+        if stored.is_some()
+            && registry
+                .storage
+                .reuse_blob(&location, digest.digest)
+                .await?
+        {
+            if let Some(owner) = unverified.username() {
+                registry.storage.record_owner(owner, digest.digest).await?;
+            }
+
+            debug!(%location, %digest, "blob already stored, skipping upload");
+            return Ok(Response::builder()
+                .status(StatusCode::CREATED)
+                .header("Docker-Content-Digest", digest.to_string())
+                .header(LOCATION, mk_blob_location(&base_url, &location, digest))
+                .header(CONTENT_LENGTH, 0)
+                .body(Body::empty())?);
+        }
+    }
+
+    // Reserve quota up front if the client announces the size, to fail before any data is sent.
+    let expected_size = headers
+        .get("X-Upload-Content-Length")
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|err| RegistryError::ContentLengthMalformed(Box::new(err)))?
+                .parse::<u64>()
+                .map_err(|err| RegistryError::ContentLengthMalformed(Box::new(err)))
+        })
+        .transpose()?;
+
+    // Initiate a new upload
+    let upload = match (unverified.username(), expected_size) {
+        (Some(owner), Some(size)) if registry.quotas.contains_key(owner) => {
+            registry.check_quota(Some(owner), None, size, None).await?;
+            registry
+                .storage
+                .begin_reserved_upload(&location, owner, size)
+                .await?
+        }
+        _ => registry.storage.begin_new_upload(&location).await?,
+    };
+
+    Ok(UploadState {
+        base_url,
+        location,
+        completed: None,
+        upload,
+    }
+    .into_response())
+}
+
+/// Query parameters of [`upload_new`].
+#[derive(Debug, Deserialize)]
+struct UploadNewQuery {
+    /// Digest of the blob about to be uploaded.
+    digest: Option<ImageDigest>,
+    /// Digest of a blob to mount from another repository.
+    mount: Option<ImageDigest>,
+    /// Repository to mount from, as `<repository>/<image>`.
+    from: Option<String>,
+}
+
+/// Checks whether a stored blob may be mounted from the repository named by `from`.
+///
+/// The client needs read access to the source repository. With strict mounts, the blob must also
+/// still have the size recorded when it was pushed there.
+async fn may_mount(
+    registry: &ContainerRegistry,
+    creds: &ValidCredentials,
+    from: Option<&str>,
+    digest: ImageDigest,
+    metadata: &storage::BlobMetadata,
+) -> Result<bool, RegistryError> {
+    let Some(from) = from
+        .and_then(|from| from.split_once('/'))
+        .map(|(repository, image)| ImageLocation::new(repository.to_owned(), image.to_owned()))
+        .filter(|from| validate_location(from).is_ok())
+    else {
+        return Ok(false);
+    };
+
+    if !registry
+        .auth_provider
+        .image_permissions(creds, &from)
+        .await
+        .has_read_permission()
+    {
+        return Ok(false);
+    }
+
+    if registry.strict_mounts {
+        let recorded = registry
+            .storage
+            .repository_blob_size(&from, digest.digest)
+            .await?;
+        if recorded != Some(metadata.size()) {
+            warn!(%from, %digest, ?recorded, actual = metadata.size(), "blob size differs from recorded size, not mounting");
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns the URI for a specific part of an upload.
+///
+/// The URI is absolute if the base URL of the registry is known.
+fn mk_upload_location(base_url: &BaseUrl, location: &ImageLocation, uuid: Uuid) -> String {
+    let base = base_url.0.as_deref().unwrap_or_default();
+    let repository = &location.repository();
+    let image = &location.image();
+    format!("{base}/v2/{repository}/{image}/blobs/uploads/{uuid}")
+}
+
+/// Returns the URI of a blob.
+///
+/// The URI is absolute if the base URL of the registry is known.
+fn mk_blob_location(base_url: &BaseUrl, location: &ImageLocation, digest: ImageDigest) -> String {
+    let base = base_url.0.as_deref().unwrap_or_default();
+    let repository = &location.repository();
+    let image = &location.image();
+    format!("{base}/v2/{repository}/{image}/blobs/{digest}")
+}
+
+/// Overrides the realm of authentication challenges for the requests it is attached to.
+///
+/// Allows serving the registry on several listeners, e.g. an internal and an external one, each
+/// with its own realm. Attach it to a router as an extension:
+///
+/// ```
+///# use std::sync::Arc;
+///# use container_registry::{ContainerRegistry, Realm};
+///# fn routers(registry: Arc<ContainerRegistry>) {
+/// let external = registry.clone().make_router();
+/// let internal = registry
+///     .make_router()
+///     .layer(axum::Extension(Realm::new("https://registry.internal.example.com")));
+///# }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Realm(String);
+
+impl Realm {
+    /// Creates a new realm override.
+    pub fn new<R: Into<String>>(realm: R) -> Self {
+        Self(realm.into())
+    }
+}
+
+/// The externally visible base URL of the registry, without a trailing slash.
+///
+/// Derived from forwarding headers if trusted, otherwise the configured base URL is used. If
+/// neither is available but absolute locations are required, the `Host` header is used. If the
+/// registry is nested in another router, the path it is nested at is appended.
+#[derive(Debug, Default)]
+struct BaseUrl(Option<String>);
+
+#[async_trait]
+impl FromRequestParts<Arc<ContainerRegistry>> for BaseUrl {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        registry: &Arc<ContainerRegistry>,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded = registry
+            .trust_forwarded_headers
+            .then(|| forwarded_base_url(&parts.headers))
+            .flatten();
+
+        let host = || {
+            let host = parts.headers.get(HOST)?.to_str().ok()?;
+            make_base_url("http", host)
+        };
+
+        let base = forwarded
+            .or_else(|| registry.base_url.clone())
+            .or_else(|| registry.absolute_locations.then(host).flatten());
+
+        // Nested routers only see the remainder of the path, the original one has the prefix.
+        let prefix = parts
+            .extensions
+            .get::<OriginalUri>()
+            .and_then(|original| original.path().strip_suffix(parts.uri.path()))
+            .map(|prefix| prefix.trim_end_matches('/'))
+            .filter(|prefix| !prefix.is_empty());
+        let base = match (base, prefix) {
+            (Some(base), Some(prefix)) if !base.ends_with(prefix) => Some(base + prefix),
+            (None, Some(prefix)) => Some(prefix.to_owned()),
+            (base, _) => base,
+        };
+
+        Ok(BaseUrl(base))
+    }
+}
+
+/// Reconstructs the base URL used by a client from the forwarding headers set by a proxy.
+///
+/// The standard `Forwarded` header takes precedence over `X-Forwarded-Proto` and
+/// `X-Forwarded-Host`. If only the scheme is forwarded, the `Host` header is used.
+fn forwarded_base_url(headers: &HeaderMap) -> Option<String> {
+    // Only the first entry is of interest, added by the proxy closest to the client.
+    let first_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+
+    let mut proto = None;
+    let mut host = None;
+    if let Some(forwarded) = first_value(FORWARDED.as_str()) {
+        for pair in forwarded.split(';') {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"').to_owned();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "proto" => proto = Some(value),
+                "host" => host = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    let proto = proto.or_else(|| first_value("x-forwarded-proto"));
+    let host = host.or_else(|| first_value("x-forwarded-host"));
+    if proto.is_none() && host.is_none() {
+        return None;
+    }
+
+    let proto = proto
+        .map(|proto| proto.to_ascii_lowercase())
+        .unwrap_or_else(|| "http".to_owned());
+    let host = host.or_else(|| first_value(HOST.as_str()))?;
+
+    make_base_url(&proto, &host)
+}
+
+/// Builds a base URL from a scheme and host taken from request headers.
+///
+/// Returns `None` for values that could turn the URL into something else than a base URL.
+fn make_base_url(proto: &str, host: &str) -> Option<String> {
+    if !matches!(proto, "http" | "https")
+        || host.is_empty()
+        || host.contains(|c: char| c.is_whitespace() || matches!(c, '/' | '\\' | '@' | '?' | '#'))
+    {
+        return None;
+    }
+
+    Some(format!("{proto}://{host}"))
+}
+
+/// Returns the URI of a manifest.
+///
+/// The URI is absolute if the base URL of the registry is known.
+fn mk_manifest_location(
+    base_url: &BaseUrl,
+    location: &ImageLocation,
+    reference: &Reference,
+) -> String {
+    let base = base_url.0.as_deref().unwrap_or_default();
+    let repository = &location.repository();
+    let image = &location.image();
+    format!("{base}/v2/{repository}/{image}/manifests/{reference}")
+}
+
+/// Image upload state.
+///
+/// Represents the state of a partial upload of a specific blob, which may be uploaded in chunks.
+///
+/// The OCI protocol requires the upload state communicated back through HTTP headers, this type
+/// represents said information.
+#[derive(Debug)]
+struct UploadState {
+    /// The base URL to build the upload location from.
+    base_url: BaseUrl,
+    /// The location of the image.
+    location: ImageLocation,
+    /// The total amount of bytes received for the upload so far.
+    completed: Option<u64>,
+    /// The UUID for this specific upload part.
+    upload: Uuid,
+}
+
+impl IntoResponse for UploadState {
+    fn into_response(self) -> Response {
+        let mut builder = Response::builder()
+            .header(
+                LOCATION,
+                mk_upload_location(&self.base_url, &self.location, self.upload),
+            )
+            .header(CONTENT_LENGTH, 0)
+            .header("Docker-Upload-UUID", self.upload.to_string());
+
+        if let Some(completed) = self.completed {
+            // The range is inclusive, i.e. it ends with the offset of the last byte received.
+            builder = builder
+                .header(RANGE, format!("0-{}", completed.saturating_sub(1)))
+                .status(StatusCode::ACCEPTED)
+        } else {
+            builder = builder
+                .header(CONTENT_LENGTH, 0)
+                .status(StatusCode::ACCEPTED);
+            // The spec says to use `CREATED`, but only `ACCEPTED` works?
+        }
+
+        builder.body(Body::empty()).unwrap()
+    }
+}
+
+/// Path parameters of routes addressing a specific upload.
+#[derive(Debug, Deserialize)]
+struct UploadPath {
+    /// The repository part of the image location.
+    repository: String,
+    /// The image part of the image location.
+    image: String,
+    /// The UUID representing this upload.
+    upload: Uuid,
+}
+
+impl UploadPath {
+    /// Splits the path into the image location and the upload ID.
+    fn into_parts(self) -> Result<(ImageLocation, Uuid), RegistryError> {
+        Ok((valid_location(self.repository, self.image)?, self.upload))
+    }
+}
+
+/// An image hash.
+///
+/// Currently only SHA256 hashes are supported.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDigest {
+    /// The actual image digest.
+    digest: storage::Digest,
+}
+
+impl Serialize for ImageDigest {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ImageDigest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Note: For some reason, `&str` here causes parsing inside query parameters to fail.
+        let raw = <String>::deserialize(deserializer)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl ImageDigest {
+    /// Creats a new image hash from an existing digest.
+    #[inline(always)]
+    pub const fn new(digest: storage::Digest) -> Self {
+        Self { digest }
+    }
+
+    /// Returns the actual digest.
+    pub fn digest(&self) -> storage::Digest {
+        self.digest
+    }
+}
+
+/// Error parsing a specific image digest.
+#[derive(Debug, Error)]
+pub enum ImageDigestParseError {
+    /// The given digest was of the wrong length.
+    #[error("wrong length")]
+    WrongLength,
+    /// The given digest had an invalid or unsupported prefix.
+    #[error("wrong prefix")]
+    WrongPrefix,
+    /// The hex encoding was not valid.
+    #[error("hex decoding error")]
+    HexDecodeError,
+    /// The digest algorithm is known, but content cannot be addressed by it.
+    #[error("unsupported digest algorithm {0}")]
+    UnsupportedAlgorithm(&'static str),
+}
+
+impl FromStr for ImageDigest {
+    type Err = ImageDigestParseError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex_encoded) = digest_algorithm::split_digest(raw)?;
+        if algorithm.name != digest_algorithm::SHA256.name {
+            return Err(ImageDigestParseError::UnsupportedAlgorithm(algorithm.name));
+        }
+
+        let digest = <[u8; storage::SHA256_LEN]>::from_hex(hex_encoded)
+            .map_err(|_| ImageDigestParseError::HexDecodeError)?;
+
+        Ok(Self {
+            digest: storage::Digest::new(digest),
+        })
+    }
+}
+
+impl Display for ImageDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", digest_algorithm::SHA256.name, self.digest)
+    }
+}
+
+/// Returns the progress of an upload, allowing clients to resume it after reconnecting.
+async fn upload_status(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path(upload_path): Path<UploadPath>,
     creds: ValidCredentials,
-) -> Result<Response, RegistryError> {
+    base_url: BaseUrl,
+) -> Result<Response<Body>, RegistryError> {
+    let (location, upload) = upload_path.into_parts()?;
+
     registry
         .auth_provider
-        .blob_permissions(&creds, &image)
+        .image_permissions(&creds, &location)
+        .await
+        .require_write()?;
+
+    if let Some(origin) = registry.storage.get_upload_location(upload).await? {
+        if origin != location {
+            return Err(storage::Error::UploadDoesNotExit.into());
+        }
+    }
+
+    let completed = registry.storage.get_upload_size(upload).await?;
+    registry.storage.touch_upload(upload).await?;
+
+    let mut response = UploadState {
+        base_url,
+        location,
+        completed: Some(completed),
+        upload,
+    }
+    .into_response();
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    Ok(response)
+}
+
+/// Adds a chunk to an existing upload.
+async fn upload_add_chunk(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path(upload_path): Path<UploadPath>,
+    creds: ValidCredentials,
+    base_url: BaseUrl,
+    request: axum::extract::Request,
+) -> Result<UploadState, RegistryError> {
+    let (location, upload) = upload_path.into_parts()?;
+
+    registry
+        .auth_provider
+        .image_permissions(&creds, &location)
+        .await
+        .require_write()?;
+    validate_upload_uuid(request.headers(), upload)?;
+
+    // Check if we have a range - if so, its an unsupported feature, namely monolith uploads.
+    if request.headers().contains_key(RANGE) {
+        return Err(RegistryError::NotSupported(
+            "unsupported feature: chunked uploads",
+        ));
+    }
+
+    let previously_completed = registry.storage.get_upload_size(upload).await?;
+
+    // Chunks must continue where the previous one ended.
+    let declared_range = request
+        .headers()
+        .get(CONTENT_RANGE)
+        .map(range::parse_content_range)
+        .transpose()
+        .map_err(|_| RegistryError::UploadRangeInvalid(previously_completed))?;
+    if let Some((start, _)) = declared_range {
+        if start != previously_completed {
+            return Err(RegistryError::UploadRangeInvalid(previously_completed));
+        }
+    }
+
+    let mut writer = registry.storage.get_upload_writer(0, upload).await?;
+
+    // Metrics are attributed to the repository the upload was started for.
+    let repository = registry
+        .storage
+        .get_upload_location(upload)
+        .await?
+        .unwrap_or_else(|| location.clone())
+        .to_string();
+    let _active = registry.upload_metrics.upload_started(&repository);
+
+    // We'll get the entire file in one go, no range header == monolithic uploads.
+    let completed = match stream_to_writer(
+        request.into_body().into_data_stream(),
+        &mut writer,
+        previously_completed,
+        registry.max_blob_size,
+    )
+    .await
+    {
+        Ok(completed) => completed,
+        Err(err @ RegistryError::IncomingReadFailed(_)) => {
+            // The client went away mid-chunk. What it sent is kept, so it can query the size of
+            // the upload and resume from there.
+            drop(writer);
+            let size = registry.storage.get_upload_size(upload).await?;
+            registry
+                .upload_metrics
+                .record_bytes(&repository, size.saturating_sub(previously_completed));
+            debug!(%upload, size, "client disconnected during upload chunk");
+            return Err(err);
+        }
+        Err(err) => {
+            // The partially written chunk cannot be resumed, discard the upload.
+            drop(writer);
+            registry.storage.cancel_upload(upload).await?;
+            return Err(err);
+        }
+    };
+
+    registry.upload_metrics.record_bytes(&repository, completed);
+
+    // A chunk shorter or longer than declared corrupts the upload, which is rejected on finalize.
+    if let Some((_, end)) = declared_range {
+        if end + 1 != previously_completed + completed {
+            registry
+                .storage
+                .set_upload_expected_size(upload, end + 1)
+                .await?;
+        }
+    }
+
+    Ok(UploadState {
+        base_url,
+        location,
+        completed: Some(previously_completed + completed),
+        upload,
+    })
+}
+
+/// An image digest on a query string.
+///
+/// Newtype to allow [`axum::extract::Query`] to parse it.
+#[derive(Debug, Deserialize)]
+struct DigestQuery {
+    /// The image in question.
+    digest: ImageDigest,
+}
+
+/// Finishes an upload.
+async fn upload_finalize(
+    State(registry): State<Arc<ContainerRegistry>>,
+    Path(upload_path): Path<UploadPath>,
+    Query(DigestQuery { digest }): Query<DigestQuery>,
+    creds: ValidCredentials,
+    unverified: Unverified,
+    base_url: BaseUrl,
+    request: axum::extract::Request,
+) -> Result<Response<Body>, RegistryError> {
+    let (location, upload) = upload_path.into_parts()?;
+
+    registry
+        .auth_provider
+        .image_permissions(&creds, &location)
+        .await
+        .require_write()?;
+    validate_upload_uuid(request.headers(), upload)?;
+
+    // We do not support the final chunk in the `PUT` call, so ensure that's not the case.
+    match request.headers().get(CONTENT_LENGTH) {
+        Some(value) => {
+            let num_bytes: u64 = value
+                .to_str()
+                .map_err(|err| RegistryError::ContentLengthMalformed(Box::new(err)))?
+                .parse()
+                .map_err(|err| RegistryError::ContentLengthMalformed(Box::new(err)))?;
+            if num_bytes != 0 {
+                return Err(RegistryError::NotSupported(
+                    "missing content length not implemented",
+                ));
+            }
+
+            // 0 is the only acceptable value here.
+        }
+        None => {
+            // Omitting is fine, indicating no body.
+        }
+    }
+
+    // Uploads may only be finalized through the location they were started for.
+    if let Some(origin) = registry.storage.get_upload_location(upload).await? {
+        if origin != location {
+            return Err(storage::Error::UploadDoesNotExit.into());
+        }
+    }
+
+    let size = registry.storage.get_upload_size(upload).await?;
+    if let Some(range) = request.headers().get(CONTENT_RANGE) {
+        let (_, end) = range::parse_content_range(range)
+            .map_err(|_| RegistryError::UploadRangeInvalid(size))?;
+        registry
+            .storage
+            .set_upload_expected_size(upload, end + 1)
+            .await?;
+    }
+
+    if let Err(err) = registry
+        .check_quota(
+            unverified.username(),
+            Some(digest.digest),
+            size,
+            Some(upload),
+        )
+        .await
+    {
+        registry.storage.cancel_upload(upload).await?;
+        return Err(err);
+    }
+
+    // Identical content may be finalized concurrently, only the first one is stored.
+    let Some(_finalizing) = registry
+        .finalizing
+        .acquire(digest.digest, Some(registry.concurrent_finalize_wait))
         .await
-        .require_read()?;
+    else {
+        debug!(%upload, %digest, "blob still being finalized concurrently");
+        let mut response = UploadState {
+            base_url,
+            location,
+            completed: Some(size),
+            upload,
+        }
+        .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+        return Ok(response);
+    };
 
-    if let Some(metadata) = registry.storage.get_blob_metadata(image.digest).await? {
-        Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_LENGTH, metadata.size())
-            .header("Docker-Content-Digest", image.to_string())
-            .header(CONTENT_TYPE, "application/octet-stream")
-            .body(Body::empty())
-            .unwrap())
-    } else {
-        Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
-            .unwrap())
+    let _gc = registry.gc_lock.read().await;
+    match registry
+        .storage
+        .finalize_upload(upload, digest.digest)
+        .await
+    {
+        Ok(()) => {}
+        Err(err @ storage::Error::SizeMismatch { expected, actual }) => {
+            warn!(%upload, expected, actual, "upload size does not match declared size");
+            registry.storage.cancel_upload(upload).await?;
+            return Err(err.into());
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    if let Some(owner) = unverified.username() {
+        registry.storage.record_owner(owner, digest.digest).await?;
     }
+
+    info!(%upload, %digest, actor = unverified.username(), "new image uploaded");
+    Ok(Response::builder()
+        .status(StatusCode::CREATED)
+        .header("Docker-Content-Digest", digest.to_string())
+        .header(LOCATION, mk_blob_location(&base_url, &location, digest))
+        .body(Body::empty())?)
 }
 
-/// Returns a specific image blob.
-async fn blob_get(
+/// Uploads a manifest.
+async fn manifest_put(
     State(registry): State<Arc<ContainerRegistry>>,
-    Path((_, _, image)): Path<(String, String, ImageDigest)>,
+    Path(manifest_reference): Path<ManifestReference>,
     creds: ValidCredentials,
-) -> Result<Response, RegistryError> {
+    unverified: Unverified,
+    base_url: BaseUrl,
+    request: axum::extract::Request,
+) -> Result<Response<Body>, RegistryError> {
+    validate_location(manifest_reference.location())?;
     registry
         .auth_provider
-        .blob_permissions(&creds, &image)
+        .image_permissions(&creds, manifest_reference.location())
         .await
-        .require_read()?;
+        .require_write()?;
+    validate_reference(&manifest_reference)?;
+
+    let (parts, body) = request.into_parts();
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+
+    // Never buffer more than the manifest size limit.
+    let limit = usize::try_from(registry.max_manifest_size).unwrap_or(usize::MAX);
+    let image_manifest_json = axum::body::to_bytes(body, limit).await.map_err(|err| {
+        if exceeds_length_limit(&err) {
+            RegistryError::PayloadTooLarge(registry.max_manifest_size)
+        } else {
+            RegistryError::IncomingReadFailed(err)
+        }
+    })?;
+
+    let manifest = parse_pushed_manifest(
+        content_type,
+        &registry.default_manifest_media_type,
+        &image_manifest_json,
+    )?;
+
+    if let Manifest::Image(ref image_manifest) = manifest {
+        let layers = image_manifest.layers();
+        if layers.len() > registry.max_manifest_layers {
+            return Err(ManifestError::TooManyLayers {
+                count: layers.len(),
+                max: registry.max_manifest_layers,
+            }
+            .into());
+        }
+
+        // Sizes are declared by the client, a sum overflowing is just as implausible.
+        let layers_size = layers
+            .iter()
+            .try_fold(0u64, |total, layer| total.checked_add(layer.size()));
+        if layers_size.is_none_or(|size| size > registry.max_manifest_layers_size) {
+            return Err(ManifestError::LayersTooLarge {
+                max: registry.max_manifest_layers_size,
+            }
+            .into());
+        }
+    }
+
+    if !registry.allowed_media_types.is_empty() {
+        let config_media_type = match manifest {
+            Manifest::Image(ref image_manifest) => Some(image_manifest.config().media_type()),
+            Manifest::Index(_) => None,
+        };
+        for media_type in std::iter::once(manifest.media_type()).chain(config_media_type) {
+            if !registry
+                .allowed_media_types
+                .iter()
+                .any(|pattern| glob_matches(pattern, media_type))
+            {
+                return Err(RegistryError::MediaTypeNotAllowed(media_type.to_owned()));
+            }
+        }
+    }
+
+    // Manifests pushed by digest are immutable, pushing identical contents again is a no-op.
+    if let Some(expected) = manifest_reference.reference().as_digest() {
+        if storage::Digest::from_contents(&image_manifest_json) != expected {
+            return Err(storage::Error::DigestMismatch.into());
+        }
+
+        if let Some(existing) = registry.storage.get_manifest(&manifest_reference).await? {
+            if existing != image_manifest_json {
+                return Err(storage::Error::DigestMismatch.into());
+            }
+
+            debug!(%manifest_reference, "manifest already stored");
+            return Ok(manifest_created(&base_url, &manifest_reference, expected));
+        }
+    }
+
+    // Blobs verified below are referenced before garbage collection can remove them.
+    let _gc = registry.gc_lock.read().await;
+
+    if let Manifest::Image(ref image_manifest) = manifest {
+        // Artifacts often reference the empty blob without pushing it, as its contents are known.
+        if image_manifest.blobs().any(Descriptor::is_empty) {
+            store_empty_blob(registry.storage.as_ref(), manifest_reference.location()).await?;
+        }
+
+        // Foreign layers are downloaded from elsewhere, their blobs are never pushed.
+        let referenced = registry
+            .storage
+            .referenced_blobs(&image_manifest_json, Some(image_manifest.media_type()))
+            .await?;
+        let mut missing = Vec::new();
+        for blob in referenced {
+            // Clients skip blobs already stored, which are attributed to the location here.
+            if !registry
+                .storage
+                .reuse_blob(manifest_reference.location(), blob)
+                .await?
+            {
+                missing.push(ImageDigest::new(blob));
+            }
+        }
+        if registry.verify_manifest_blobs && !missing.is_empty() {
+            return Err(RegistryError::ManifestBlobUnknown(missing));
+        }
+    }
+
+    // Tag updates are serialized, so preconditions are checked against the manifest replaced.
+    let _updating_tag = match manifest_reference.reference().as_tag() {
+        Some(_) => {
+            registry
+                .updating_tags
+                .acquire(manifest_reference.to_string(), None)
+                .await
+        }
+        None => None,
+    };
+
+    let if_match: Vec<_> = parts.headers.get_all(IF_MATCH).iter().collect();
+    if !if_match.is_empty() {
+        let current = resolve_reference(&registry, &manifest_reference)
+            .await?
+            .map(|current| format!("\"{}\"", ImageDigest::new(current)));
+        let matches = current.is_some_and(|etag| {
+            if_match
+                .iter()
+                .any(|if_match| etag_matches(if_match, &etag))
+        });
+        if !matches {
+            return Err(RegistryError::PreconditionFailed);
+        }
+    }
+
+    if let Some(tag) = manifest_reference.reference().as_tag() {
+        check_tag_update(
+            &registry,
+            &manifest_reference,
+            tag,
+            storage::Digest::from_contents(&image_manifest_json),
+            &unverified,
+        )
+        .await?;
+    }
+
+    registry
+        .check_quota(
+            unverified.username(),
+            Some(storage::Digest::from_contents(&image_manifest_json)),
+            image_manifest_json.len() as u64,
+            None,
+        )
+        .await?;
 
-    // TODO: Get size for `Content-length` header.
+    let digest = registry
+        .storage
+        .put_manifest(&manifest_reference, &image_manifest_json)
+        .await?;
 
-    let reader = registry
+    // The media type may have been given by `Content-Type` only, it is served as pushed.
+    registry
         .storage
-        .get_blob_reader(image.digest)
-        .await?
-        .ok_or(RegistryError::NotFound)?;
+        .record_manifest_media_type(digest, manifest.media_type())
+        .await?;
 
-    let stream = ReaderStream::new(reader);
-    let body = Body::from_stream(stream);
+    if let Some(owner) = unverified.username() {
+        registry.storage.record_owner(owner, digest).await?;
+    }
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .body(body)
-        .expect("Building a streaming response with body works. qed"))
+    if let Some(tag) = manifest_reference.reference().as_tag() {
+        record_tag_history(
+            &registry,
+            manifest_reference.location(),
+            tag,
+            digest,
+            &unverified,
+        )
+        .await?;
+    }
+
+    // Blobs carry no media type themselves, remember the one given by the manifest.
+    if let Manifest::Image(ref image_manifest) = manifest {
+        for blob in image_manifest.blobs() {
+            registry
+                .storage
+                .record_blob_media_type(blob.digest().digest, blob.media_type())
+                .await?;
+        }
+    }
+
+    info!(%manifest_reference, %digest, actor = unverified.username(), "new manifest received");
+    // Completed upload, call hook:
+    registry
+        .hooks
+        .on_manifest_pushed(&manifest_reference, unverified.username())
+        .await;
+
+    registry.webhooks.notify(webhooks::Event::manifest(
+        webhooks::Action::Push,
+        &manifest_reference,
+        ImageDigest::new(digest),
+        Some(manifest.media_type().to_owned()),
+        Some(image_manifest_json.len() as u64),
+        unverified.username().map(ToOwned::to_owned),
+    ));
+
+    Ok(manifest_created(&base_url, &manifest_reference, digest))
 }
 
-/// Initiates a new blob upload.
-async fn upload_new(
+/// Makes room for a new tag if a repository holds the maximum number of tags.
+async fn enforce_tag_limit(
+    registry: &ContainerRegistry,
+    manifest_reference: &ManifestReference,
+    tag: &str,
+    max: usize,
+    policy: TagLimitPolicy,
+    unverified: &Unverified,
+) -> Result<(), RegistryError> {
+    let location = manifest_reference.location();
+    let tags = registry.storage.tags_by_age(location).await?;
+    if tags.len() < max || tags.iter().any(|(existing, _pushed)| existing == tag) {
+        return Ok(());
+    }
+
+    if policy == TagLimitPolicy::Reject {
+        return Err(RegistryError::TooManyTags(max));
+    }
+
+    for (evicted, _pushed) in tags.iter().take(tags.len() + 1 - max) {
+        if let Some(deleted) = registry.storage.delete_tag(location, evicted).await? {
+            let digest = ImageDigest::new(deleted.digest);
+            info!(%location, tag = %evicted, %digest, "evicted tag to stay within limit");
+            notify_tag_deleted(registry, location, evicted, &deleted, unverified.username());
+        }
+    }
+
+    Ok(())
+}
+
+/// Notifies webhooks of a tag having been deleted.
+fn notify_tag_deleted(
+    registry: &ContainerRegistry,
+    location: &ImageLocation,
+    tag: &str,
+    deleted: &storage::DeletedTag,
+    actor: Option<&str>,
+) {
+    registry.webhooks.notify(webhooks::Event::manifest(
+        webhooks::Action::Delete,
+        &ManifestReference::new(location.clone(), Reference::new_tag(tag)),
+        ImageDigest::new(deleted.digest),
+        None,
+        None,
+        actor.map(ToOwned::to_owned),
+    ));
+}
+
+/// Notifies webhooks about a manifest deleted by digest.
+fn notify_manifest_deleted(
+    registry: &ContainerRegistry,
+    location: &ImageLocation,
+    digest: ImageDigest,
+    actor: Option<&str>,
+) {
+    registry.webhooks.notify(webhooks::Event::manifest(
+        webhooks::Action::Delete,
+        &ManifestReference::new(location.clone(), Reference::new_digest(digest.digest)),
+        digest,
+        None,
+        None,
+        actor.map(ToOwned::to_owned),
+    ));
+}
+
+/// Builds the response to a successful manifest push.
+fn manifest_created(
+    base_url: &BaseUrl,
+    manifest_reference: &ManifestReference,
+    digest: storage::Digest,
+) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(
+            LOCATION,
+            mk_manifest_location(
+                base_url,
+                manifest_reference.location(),
+                manifest_reference.reference(),
+            ),
+        )
+        .header(CONTENT_LENGTH, 0)
+        .header(
+            "Docker-Content-Digest",
+            ImageDigest::new(digest).to_string(),
+        )
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Ensures a `Docker-Upload-UUID` header, if given, names the upload in the path.
+///
+/// Clients may echo the header they received, a mismatch means they mixed up upload sessions.
+fn validate_upload_uuid(headers: &HeaderMap, upload: Uuid) -> Result<(), RegistryError> {
+    match headers.get("Docker-Upload-UUID") {
+        None => Ok(()),
+        Some(value)
+            if value
+                .to_str()
+                .ok()
+                .and_then(|value| Uuid::parse_str(value.trim()).ok())
+                == Some(upload) =>
+        {
+            Ok(())
+        }
+        Some(_) => Err(RegistryError::UploadUuidMismatch(upload)),
+    }
+}
+
+/// Ensures a tag matches the tag grammar of the distribution spec.
+fn validate_tag(tag: &str) -> Result<(), RegistryError> {
+    if storage::is_valid_tag(tag) {
+        Ok(())
+    } else {
+        Err(RegistryError::TagInvalid(tag.to_owned()))
+    }
+}
+
+/// Ensures both parts of a location match the name grammar of the distribution spec.
+fn validate_location(location: &ImageLocation) -> Result<(), RegistryError> {
+    for name in [location.repository(), location.image()] {
+        if !storage::is_valid_name_component(name) {
+            return Err(RegistryError::NameInvalid(name.to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the location named by a request path, see [`validate_location`].
+///
+/// Path parameters are percent-decoded, names are validated before they are used as directory
+/// names in storage.
+fn valid_location(repository: String, image: String) -> Result<ImageLocation, RegistryError> {
+    let location = ImageLocation::new(repository, image);
+    validate_location(&location)?;
+    Ok(location)
+}
+
+/// Resolves a manifest reference to the digest of the manifest it refers to.
+///
+/// Digests are only returned if a manifest is stored under them, tags are resolved through
+/// [`RegistryStorage::resolve_tag`].
+async fn resolve_reference(
+    registry: &ContainerRegistry,
+    manifest_reference: &ManifestReference,
+) -> Result<Option<storage::Digest>, RegistryError> {
+    match manifest_reference.reference() {
+        Reference::Digest(digest) => Ok(registry
+            .storage
+            .get_manifest(manifest_reference)
+            .await?
+            .map(|_| *digest)),
+        Reference::Tag(tag) => Ok(registry
+            .storage
+            .resolve_tag(manifest_reference.location(), tag)
+            .await?),
+    }
+}
+
+/// Ensures a manifest reference is either a digest or a valid tag.
+fn validate_reference(manifest_reference: &ManifestReference) -> Result<(), RegistryError> {
+    match manifest_reference.reference().as_tag() {
+        Some(tag) => validate_tag(tag),
+        None => Ok(()),
+    }
+}
+
+/// Matches a string against a simple glob pattern supporting `*` and `?`.
+fn glob_matches(pattern: &str, candidate: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+
+    // Iterative matching with backtracking to the last `*`.
+    let (mut p, mut c) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while c < candidate.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, c));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == candidate[c] => {
+                p += 1;
+                c += 1;
+            }
+            _ => match star {
+                Some((star_p, star_c)) => {
+                    p = star_p + 1;
+                    c = star_c + 1;
+                    star = Some((star_p, star_c + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+/// Returns whether a body read error was caused by exceeding a length limit.
+///
+/// The limit may have been hit while reading or by a body limiting layer wrapping the body, thus
+/// the whole chain of errors is checked.
+fn exceeds_length_limit(err: &axum::Error) -> bool {
+    let mut source = err.source();
+    while let Some(inner) = source {
+        if inner.is::<LengthLimitError>() {
+            return true;
+        }
+        source = inner.source();
+    }
+    false
+}
+
+/// Streams incoming data into an upload, returning the number of bytes written.
+///
+/// `offset` is the number of bytes already received for the upload and counts towards
+/// `max_size`. Only a single chunk of the stream is held in memory at a time, regardless of the
+/// total size; the storage writer hashes data incrementally as it is written.
+///
+/// If reading the stream fails, e.g. because the client disconnected, the data received up to
+/// that point is flushed and [`RegistryError::IncomingReadFailed`] returned; the upload can be
+/// resumed from its current size. On other errors, the writer may have received part of the
+/// data, callers are expected to cancel the upload.
+async fn stream_to_writer<S>(
+    mut stream: S,
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+    offset: u64,
+    max_size: u64,
+) -> Result<u64, RegistryError>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+{
+    let mut completed: u64 = 0;
+    while let Some(result) = stream.next().await {
+        let chunk = match result {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                writer
+                    .flush()
+                    .await
+                    .map_err(RegistryError::LocalWriteFailed)?;
+                return Err(RegistryError::IncomingReadFailed(err));
+            }
+        };
+        completed += chunk.len() as u64;
+
+        if offset + completed > max_size {
+            return Err(RegistryError::PayloadTooLarge(max_size));
+        }
+
+        writer
+            .write_all(chunk.as_ref())
+            .await
+            .map_err(RegistryError::LocalWriteFailed)?;
+    }
+
+    writer
+        .flush()
+        .await
+        .map_err(RegistryError::LocalWriteFailed)?;
+
+    Ok(completed)
+}
+
+/// Checks whether a tag may be pointed at the manifest with the given digest.
+///
+/// Enforces tag immutability and, evicting old tags if configured, the tag limit of the
+/// repository. Callers are expected to hold the update lock of the tag.
+async fn check_tag_update(
+    registry: &ContainerRegistry,
+    manifest_reference: &ManifestReference,
+    tag: &str,
+    digest: storage::Digest,
+    unverified: &Unverified,
+) -> Result<(), RegistryError> {
+    if registry.write_once
+        || registry
+            .immutable_tags
+            .iter()
+            .any(|pattern| glob_matches(pattern, tag))
+    {
+        let existing = registry
+            .storage
+            .resolve_tag(manifest_reference.location(), tag)
+            .await?;
+        if existing.is_some_and(|existing| existing != digest) {
+            return Err(RegistryError::TagImmutable(tag.to_owned()));
+        }
+    }
+
+    if let Some((max, policy)) = registry.max_tags_per_repo {
+        enforce_tag_limit(registry, manifest_reference, tag, max, policy, unverified).await?;
+    }
+
+    Ok(())
+}
+
+/// Records a tag having been pointed at a manifest in its history, if kept.
+async fn record_tag_history(
+    registry: &ContainerRegistry,
+    location: &ImageLocation,
+    tag: &str,
+    digest: storage::Digest,
+    unverified: &Unverified,
+) -> Result<(), RegistryError> {
+    if registry.tag_history_retention > 0 {
+        registry
+            .storage
+            .append_tag_history(
+                location,
+                tag,
+                TagHistoryEntry {
+                    timestamp: webhooks::rfc3339(SystemTime::now()),
+                    digest: ImageDigest::new(digest),
+                    actor: unverified.username().map(ToOwned::to_owned),
+                },
+                registry.tag_history_retention,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Stores the blob referenced by empty descriptors, unless already present.
+async fn store_empty_blob(
+    storage: &dyn RegistryStorage,
+    location: &ImageLocation,
+) -> Result<(), RegistryError> {
+    let digest = storage::Digest::from_contents(manifest::EMPTY_JSON);
+    if storage.blob_exists(digest).await? {
+        return Ok(());
+    }
+
+    let upload = storage.begin_new_upload(location).await?;
+    let mut writer = storage.get_upload_writer(0, upload).await?;
+    let contents = futures::stream::iter([Ok(Bytes::from_static(manifest::EMPTY_JSON))]);
+    if let Err(err) = stream_to_writer(contents, &mut writer, 0, u64::MAX).await {
+        drop(writer);
+        storage.cancel_upload(upload).await?;
+        return Err(err);
+    }
+    drop(writer);
+
+    storage.finalize_upload(upload, digest).await?;
+    Ok(())
+}
+
+/// Retrieves a manifest.
+async fn manifest_get(
     State(registry): State<Arc<ContainerRegistry>>,
-    Path(location): Path<ImageLocation>,
+    Path(manifest_reference): Path<ManifestReference>,
+    Query(ManifestQuery { platform }): Query<ManifestQuery>,
     creds: ValidCredentials,
-) -> Result<UploadState, RegistryError> {
+    headers: HeaderMap,
+) -> Result<Response<Body>, RegistryError> {
+    validate_location(manifest_reference.location())?;
     registry
         .auth_provider
-        .image_permissions(&creds, &location)
+        .image_permissions(&creds, manifest_reference.location())
         .await
-        .require_write()?;
+        .require_read()?;
+    validate_reference(&manifest_reference)?;
 
-    // Initiate a new upload
-    let upload = registry.storage.begin_new_upload().await?;
+    if let Some(upstream) = registry.upstream_for(manifest_reference.location()) {
+        upstream
+            .cache_manifest(registry.storage.as_ref(), &manifest_reference)
+            .await?;
+    }
 
-    Ok(UploadState {
-        location,
-        completed: None,
-        upload,
-    })
-}
+    let digest = match manifest_reference.reference() {
+        Reference::Digest(digest) => *digest,
+        Reference::Tag(tag) => registry
+            .storage
+            .resolve_tag(manifest_reference.location(), tag)
+            .await?
+            .ok_or(RegistryError::NotFound)?,
+    };
+    let (mut manifest_json, mut digest) = registry
+        .storage
+        .get_manifest_with_digest(&ManifestReference::new(
+            manifest_reference.location().clone(),
+            Reference::new_digest(digest),
+        ))
+        .await?
+        .ok_or(RegistryError::NotFound)?;
+    verify_manifest_digest(&registry, &manifest_json, digest)?;
 
-/// Returns the URI for a specific part of an upload.
-fn mk_upload_location(location: &ImageLocation, uuid: Uuid) -> String {
-    let repository = &location.repository();
-    let image = &location.image();
-    format!("/v2/{repository}/{image}/uploads/{uuid}")
-}
+    let mut manifest = load_manifest(&registry, &manifest_json, digest).await?;
+    if let (true, Some(platform), Manifest::Index(index)) =
+        (registry.platform_query, platform, &manifest)
+    {
+        let child = select_platform(index, &platform).ok_or(RegistryError::ManifestUnknown)?;
+        (manifest_json, digest) = registry
+            .storage
+            .get_manifest_with_digest(&ManifestReference::new(
+                manifest_reference.location().clone(),
+                Reference::new_digest(child.digest().digest()),
+            ))
+            .await?
+            .ok_or(RegistryError::ManifestUnknown)?;
+        verify_manifest_digest(&registry, &manifest_json, digest)?;
+        manifest = load_manifest(&registry, &manifest_json, digest).await?;
+    }
+
+    // Synthesized manifests are never stored, the access counts for the one stored.
+    let stored_digest = digest;
+    let (manifest_json, digest, media_type) = negotiate_manifest(
+        &registry,
+        &manifest_reference,
+        &accepted_media_types(&headers),
+        manifest_json,
+        digest,
+        manifest,
+    )
+    .await?;
 
-/// Returns the URI for a specific part of an upload.
-fn mk_manifest_location(location: &ImageLocation, reference: &Reference) -> String {
-    let repository = &location.repository();
-    let image = &location.image();
-    format!("/v2/{repository}/{image}/manifests/{reference}")
+    registry.record_access(stored_digest).await;
+    let digest = ImageDigest::new(digest);
+    let etag = format!("\"{digest}\"");
+
+    if headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .any(|value| etag_matches(value, &etag))
+    {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(ETAG, etag)
+            .header("Docker-Content-Digest", digest.to_string())
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_LENGTH, manifest_json.len())
+        .header(CONTENT_TYPE, media_type)
+        .header(ETAG, etag)
+        .header("Docker-Content-Digest", digest.to_string())
+        .body(manifest_json.into())
+        .unwrap())
 }
 
-/// Image upload state.
-///
-/// Represents the state of a partial upload of a specific blob, which may be uploaded in chunks.
-///
-/// The OCI protocol requires the upload state communicated back through HTTP headers, this type
-/// represents said information.
-#[derive(Debug)]
-struct UploadState {
-    /// The location of the image.
-    location: ImageLocation,
-    /// The amount of bytes completed.
-    completed: Option<u64>,
-    /// The UUID for this specific upload part.
-    upload: Uuid,
+/// Query parameters of a manifest pull.
+#[derive(Debug, Deserialize)]
+struct ManifestQuery {
+    /// Platform to resolve image indexes to, see [`ContainerRegistryBuilder::platform_query`].
+    platform: Option<String>,
 }
 
-impl IntoResponse for UploadState {
-    fn into_response(self) -> Response {
-        let mut builder = Response::builder()
-            .header(LOCATION, mk_upload_location(&self.location, self.upload))
-            .header(CONTENT_LENGTH, 0)
-            .header("Docker-Upload-UUID", self.upload.to_string());
+/// Finds the child manifest of an index for a platform given as `os/architecture[/variant]`.
+fn select_platform<'a>(index: &'a ImageIndex, platform: &str) -> Option<&'a Descriptor> {
+    let mut parts = platform.split('/');
+    let (os, architecture) = (parts.next()?, parts.next()?);
+    let variant = parts.next();
+    if parts.next().is_some() {
+        return None;
+    }
 
-        if let Some(completed) = self.completed {
-            builder = builder
-                .header(RANGE, format!("0-{}", completed))
-                .status(StatusCode::ACCEPTED)
-        } else {
-            builder = builder
-                .header(CONTENT_LENGTH, 0)
-                .status(StatusCode::ACCEPTED);
-            // The spec says to use `CREATED`, but only `ACCEPTED` works?
-        }
+    index.manifests().iter().find(|child| {
+        child.is_for_platform(os, architecture)
+            && variant.is_none_or(|variant| child.variant() == Some(variant))
+    })
+}
 
-        builder.body(Body::empty()).unwrap()
+/// Checks a manifest read from storage against its recorded digest, if enabled.
+fn verify_manifest_digest(
+    registry: &ContainerRegistry,
+    manifest_json: &[u8],
+    digest: storage::Digest,
+) -> Result<(), RegistryError> {
+    if registry.verify_manifest_digests && storage::Digest::from_contents(manifest_json) != digest {
+        error!(%digest, "stored manifest does not match its digest");
+        return Err(storage::Error::Corrupted(digest).into());
     }
+    Ok(())
 }
 
-/// An upload ID.
-#[derive(Copy, Clone, Debug, Deserialize)]
-struct UploadId {
-    /// The UUID representing this upload.
-    upload: Uuid,
+/// Collects the media types listed in the `Accept` headers of a request, without parameters.
+fn accepted_media_types(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .filter(|media_type| !media_type.is_empty())
+        .collect()
 }
 
-#[derive(Debug)]
-
-/// An image hash.
+/// Returns whether `media_type` is acceptable given the accepted media types of a request.
 ///
-/// Currently only SHA256 hashes are supported.
-pub struct ImageDigest {
-    /// The actual image digest.
+/// An empty list, as sent by clients without an `Accept` header, accepts everything.
+fn is_acceptable(accepted: &[String], media_type: &str) -> bool {
+    accepted.is_empty()
+        || accepted.iter().any(|candidate| {
+            candidate == "*/*"
+                || candidate.eq_ignore_ascii_case(media_type)
+                || candidate.strip_suffix("/*").is_some_and(|prefix| {
+                    media_type
+                        .split_once('/')
+                        .is_some_and(|(kind, _)| kind.eq_ignore_ascii_case(prefix))
+                })
+        })
+}
+
+/// Parses a stored manifest, taking the media type it was pushed as into account.
+async fn load_manifest(
+    registry: &ContainerRegistry,
+    manifest_json: &[u8],
     digest: storage::Digest,
+) -> Result<Manifest, RegistryError> {
+    let media_type = registry.storage.manifest_media_type(digest).await?;
+    Ok(parse_manifest(media_type.as_deref(), manifest_json)?)
 }
 
-impl Serialize for ImageDigest {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let full = format!("sha256:{}", self.digest);
-        full.serialize(serializer)
+/// Picks the manifest to serve for a client accepting the given media types.
+///
+/// Image indexes not accepted by the client are resolved to a child manifest if a platform is
+/// configured for the registry. Returns the manifest along with its media type.
+async fn negotiate_manifest(
+    registry: &ContainerRegistry,
+    manifest_reference: &ManifestReference,
+    accepted: &[String],
+    manifest_json: Vec<u8>,
+    digest: storage::Digest,
+    manifest: Manifest,
+) -> Result<(Vec<u8>, storage::Digest, String), RegistryError> {
+    if is_acceptable(accepted, manifest.media_type()) {
+        let media_type = manifest.media_type().to_owned();
+        return Ok((manifest_json, digest, media_type));
     }
-}
 
-impl<'de> Deserialize<'de> for ImageDigest {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
+    let location = manifest_reference.location();
+    if registry.schema1_manifests
+        && accepted
+            .iter()
+            .any(|media_type| media_type == schema1::DOCKER_MANIFEST_V1)
     {
-        // Note: For some reason, `&str` here causes parsing inside query parameters to fail.
-        let raw = <String>::deserialize(deserializer)?;
-        raw.parse().map_err(serde::de::Error::custom)
+        let Manifest::Image(image) = manifest else {
+            return Err(RegistryError::ManifestUnknown);
+        };
+        return synthesize_schema1(registry, manifest_reference, &image)
+            .await?
+            .map(|json| {
+                let digest = storage::Digest::from_contents(&json);
+                (json, digest, schema1::DOCKER_MANIFEST_V1.to_owned())
+            })
+            .ok_or(RegistryError::ManifestUnknown);
     }
-}
 
-impl ImageDigest {
-    /// Creats a new image hash from an existing digest.
-    #[inline(always)]
-    pub const fn new(digest: storage::Digest) -> Self {
-        Self { digest }
-    }
+    let (Manifest::Index(index), Some((os, architecture))) = (manifest, &registry.index_platform)
+    else {
+        return Err(RegistryError::ManifestUnknown);
+    };
 
-    /// Returns the actual digest.
-    pub fn digest(&self) -> storage::Digest {
-        self.digest
-    }
-}
+    let child = index
+        .manifests()
+        .iter()
+        .find(|child| {
+            child.is_for_platform(os, architecture) && is_acceptable(accepted, child.media_type())
+        })
+        .ok_or(RegistryError::ManifestUnknown)?;
 
-/// Error parsing a specific image digest.
-#[derive(Debug, Error)]
-pub enum ImageDigestParseError {
-    /// The given digest was of the wrong length.
-    #[error("wrong length")]
-    WrongLength,
-    /// The given digest had an invalid or unsupported prefix.
-    #[error("wrong prefix")]
-    WrongPrefix,
-    /// The hex encoding was not valid.
-    #[error("hex decoding error")]
-    HexDecodeError,
-}
+    let (child_json, child_digest) = registry
+        .storage
+        .get_manifest_with_digest(&ManifestReference::new(
+            location.clone(),
+            Reference::new_digest(child.digest().digest()),
+        ))
+        .await?
+        .ok_or(RegistryError::ManifestUnknown)?;
+    verify_manifest_digest(registry, &child_json, child_digest)?;
+    let child_manifest = load_manifest(registry, &child_json, child_digest).await?;
 
-impl FromStr for ImageDigest {
-    type Err = ImageDigestParseError;
+    if !is_acceptable(accepted, child_manifest.media_type()) {
+        return Err(RegistryError::ManifestUnknown);
+    }
 
-    fn from_str(raw: &str) -> Result<Self, Self::Err> {
-        const SHA256_LEN: usize = 32;
-        const PREFIX_LEN: usize = 7;
-        const DIGEST_HEX_LEN: usize = SHA256_LEN * 2;
+    let media_type = child_manifest.media_type().to_owned();
+    Ok((child_json, child_digest, media_type))
+}
 
-        if raw.len() != PREFIX_LEN + DIGEST_HEX_LEN {
-            return Err(ImageDigestParseError::WrongLength);
-        }
+/// Synthesizes a schema 1 manifest for an image, returning `None` if not possible.
+async fn synthesize_schema1(
+    registry: &ContainerRegistry,
+    manifest_reference: &ManifestReference,
+    image: &ImageManifest,
+) -> Result<Option<Vec<u8>>, RegistryError> {
+    let Some(reader) = registry
+        .storage
+        .get_blob_reader(image.config().digest().digest())
+        .await?
+    else {
+        return Ok(None);
+    };
 
-        if !raw.starts_with("sha256:") {
-            return Err(ImageDigestParseError::WrongPrefix);
-        }
+    let mut config = Vec::new();
+    reader
+        .take(registry.max_manifest_size)
+        .read_to_end(&mut config)
+        .await
+        .map_err(storage::Error::Io)?;
 
-        let hex_encoded = &raw[PREFIX_LEN..];
-        debug_assert_eq!(hex_encoded.len(), DIGEST_HEX_LEN);
+    let location = manifest_reference.location();
+    let name = format!("{}/{}", location.repository(), location.image());
+    let tag = match manifest_reference.reference() {
+        Reference::Tag(tag) => tag.clone(),
+        Reference::Digest(digest) => ImageDigest::new(*digest).to_string(),
+    };
 
-        let digest = <[u8; SHA256_LEN]>::from_hex(hex_encoded)
-            .map_err(|_| ImageDigestParseError::HexDecodeError)?;
+    Ok(schema1::synthesize(&name, &tag, image.layers(), &config))
+}
 
-        Ok(Self {
-            digest: storage::Digest::new(digest),
-        })
-    }
+/// Query parameters of the referrers API.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReferrersQuery {
+    /// Only list referrers of this artifact type.
+    artifact_type: Option<String>,
 }
 
-impl Display for ImageDigest {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "sha256:{}", self.digest)
-    }
+/// A list of tags of an image.
+#[derive(Debug, Serialize)]
+struct TagList {
+    name: String,
+    tags: Vec<String>,
 }
 
-/// Adds a chunk to an existing upload.
-async fn upload_add_chunk(
+/// Media type of newline delimited JSON, one value per line.
+const NDJSON: &str = "application/x-ndjson";
+
+/// Lists the tags of an image, paginated by `n` and `last`.
+///
+/// Also serves `HEAD` requests, with identical headers. Images nothing was pushed to are unknown.
+/// Clients accepting [`NDJSON`] explicitly are sent the tags as a stream of JSON strings instead,
+/// one per line, sparing both sides from holding the whole document for large listings.
+async fn tags_list(
     State(registry): State<Arc<ContainerRegistry>>,
     Path(location): Path<ImageLocation>,
-    Path(UploadId { upload }): Path<UploadId>,
+    Query(PaginationQuery { n, last }): Query<PaginationQuery>,
+    RawQuery(query): RawQuery,
+    base_url: BaseUrl,
+    headers: HeaderMap,
     creds: ValidCredentials,
-    request: axum::extract::Request,
-) -> Result<UploadState, RegistryError> {
+) -> Result<Response<Body>, RegistryError> {
+    validate_location(&location)?;
     registry
         .auth_provider
         .image_permissions(&creds, &location)
         .await
-        .require_write()?;
-
-    // Check if we have a range - if so, its an unsupported feature, namely monolith uploads.
-    if request.headers().contains_key(RANGE) {
-        return Err(RegistryError::NotSupported(
-            "unsupported feature: chunked uploads",
-        ));
-    }
+        .require_read()?;
 
-    let mut writer = registry.storage.get_upload_writer(0, upload).await?;
+    let tags = registry
+        .storage
+        .list_tags(&location)
+        .await?
+        .ok_or(RegistryError::NameUnknown)?;
+    let (tags, next) = pagination::paginate(tags, n, last, String::as_str);
 
-    // We'll get the entire file in one go, no range header == monolithic uploads.
-    let mut body = request.into_body().into_data_stream();
+    let ndjson = accepted_media_types(&headers)
+        .iter()
+        .any(|media_type| media_type == NDJSON);
 
-    let mut completed: u64 = 0;
-    while let Some(result) = body.next().await {
-        let chunk = result.map_err(RegistryError::IncomingReadFailed)?;
-        completed += chunk.len() as u64;
-        writer
-            .write_all(chunk.as_ref())
-            .await
-            .map_err(RegistryError::LocalWriteFailed)?;
+    let name = format!("{}/{}", location.repository(), location.image());
+    let mut response = Response::builder().status(StatusCode::OK).header(
+        CONTENT_TYPE,
+        if ndjson { NDJSON } else { "application/json" },
+    );
+    if let Some(next) = next {
+        let path = format!(
+            "{}/v2/{name}/tags/list",
+            base_url.0.as_deref().unwrap_or_default()
+        );
+        response = response.header(LINK, pagination::next_link(&path, query.as_deref(), &next));
     }
 
-    writer
-        .flush()
-        .await
-        .map_err(RegistryError::LocalWriteFailed)?;
-
-    Ok(UploadState {
-        location,
-        completed: Some(completed),
-        upload,
-    })
-}
+    if ndjson {
+        let lines = futures::stream::iter(tags).map(|tag| {
+            let mut line = serde_json::to_vec(&tag).expect("serialization should not fail");
+            line.push(b'\n');
+            Ok::<_, std::convert::Infallible>(line)
+        });
+        return Ok(response.body(Body::from_stream(lines)).unwrap());
+    }
 
-/// An image digest on a query string.
-///
-/// Newtype to allow [`axum::extract::Query`] to parse it.
-#[derive(Debug, Deserialize)]
-struct DigestQuery {
-    /// The image in question.
-    digest: ImageDigest,
+    Ok(response
+        .body(Body::from(
+            serde_json::to_vec(&TagList { name, tags }).expect("serialization should not fail"),
+        ))
+        .unwrap())
 }
 
-/// Finishes an upload.
-async fn upload_finalize(
+/// Deletes a tag, keeping the manifest it points to.
+async fn tag_delete(
     State(registry): State<Arc<ContainerRegistry>>,
-    Path((repository, image, upload)): Path<(String, String, Uuid)>,
-    Query(DigestQuery { digest }): Query<DigestQuery>,
+    Path((repository, image, tag)): Path<(String, String, String)>,
     creds: ValidCredentials,
-    request: axum::extract::Request,
+    unverified: Unverified,
 ) -> Result<Response<Body>, RegistryError> {
-    let location = ImageLocation::new(repository, image);
+    if !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
 
+    let location = valid_location(repository, image)?;
     registry
         .auth_provider
         .image_permissions(&creds, &location)
         .await
         .require_write()?;
+    validate_tag(&tag)?;
 
-    // We do not support the final chunk in the `PUT` call, so ensure that's not the case.
-    match request.headers().get(CONTENT_LENGTH) {
-        Some(value) => {
-            let num_bytes: u64 = value
-                .to_str()
-                .map_err(|err| RegistryError::ContentLengthMalformed(Box::new(err)))?
-                .parse()
-                .map_err(|err| RegistryError::ContentLengthMalformed(Box::new(err)))?;
-            if num_bytes != 0 {
-                return Err(RegistryError::NotSupported(
-                    "missing content length not implemented",
-                ));
-            }
+    let deleted = if registry.soft_delete_window.is_some() {
+        registry.storage.soft_delete_tag(&location, &tag).await?
+    } else {
+        registry.storage.delete_tag(&location, &tag).await?
+    }
+    .ok_or(RegistryError::ManifestUnknown)?;
 
-            // 0 is the only acceptable value here.
-        }
-        None => {
-            // Omitting is fine, indicating no body.
-        }
+    let digest = ImageDigest::new(deleted.digest);
+    if deleted.unreferenced {
+        info!(%location, %tag, %digest, "deleted last tag of manifest");
+    } else {
+        info!(%location, %tag, %digest, "deleted tag");
     }
 
-    registry
-        .storage
-        .finalize_upload(upload, digest.digest)
-        .await?;
+    notify_tag_deleted(&registry, &location, &tag, &deleted, unverified.username());
 
-    info!(%upload, %digest, "new image uploaded");
     Ok(Response::builder()
-        .status(StatusCode::CREATED)
-        .header("Docker-Content-Digest", digest.to_string())
-        .header(LOCATION, mk_upload_location(&location, upload))
+        .status(StatusCode::ACCEPTED)
         .body(Body::empty())?)
 }
 
-/// Uploads a manifest.
-async fn manifest_put(
+/// Deletes a manifest by digest.
+///
+/// Manifest deletes always take effect immediately, soft deletion only applies to tags.
+async fn manifest_delete(
     State(registry): State<Arc<ContainerRegistry>>,
-    Path(manifest_reference): Path<ManifestReference>,
+    Path((repository, image, digest)): Path<(String, String, ImageDigest)>,
     creds: ValidCredentials,
-    image_manifest_json: String,
+    unverified: Unverified,
 ) -> Result<Response<Body>, RegistryError> {
+    if !registry.delete_enabled {
+        return Err(RegistryError::DeleteDisabled);
+    }
+
+    let location = valid_location(repository, image)?;
     registry
         .auth_provider
-        .image_permissions(&creds, manifest_reference.location())
+        .image_permissions(&creds, &location)
         .await
         .require_write()?;
 
-    let digest = registry
-        .storage
-        .put_manifest(&manifest_reference, image_manifest_json.as_bytes())
-        .await?;
-
-    info!(%manifest_reference, %digest, "new manifest received");
-    // Completed upload, call hook:
-    registry
-        .hooks
-        .on_manifest_uploaded(&manifest_reference)
-        .await;
+    let referrers = registry.delete_manifest(&location, digest.digest).await?;
+    info!(%location, %digest, referrers = referrers.len(), "deleted manifest");
+    for referrer in referrers {
+        info!(%location, digest = %referrer, subject = %digest, "deleted referrer of manifest");
+        notify_manifest_deleted(&registry, &location, referrer, unverified.username());
+    }
+    notify_manifest_deleted(&registry, &location, digest, unverified.username());
 
     Ok(Response::builder()
-        .status(StatusCode::CREATED)
-        .header(
-            LOCATION,
-            mk_manifest_location(
-                manifest_reference.location(),
-                manifest_reference.reference(),
-            ),
-        )
-        .header(CONTENT_LENGTH, 0)
-        .header(
-            "Docker-Content-Digest",
-            ImageDigest::new(digest).to_string(),
-        )
-        .body(Body::empty())
-        .unwrap())
+        .status(StatusCode::ACCEPTED)
+        .body(Body::empty())?)
 }
 
-/// Retrieves a manifest.
-async fn manifest_get(
+/// Lists all manifests referring to a given digest through their `subject`.
+async fn referrers_get(
     State(registry): State<Arc<ContainerRegistry>>,
-    Path(manifest_reference): Path<ManifestReference>,
+    Path((repository, image_name, subject)): Path<(String, String, ImageDigest)>,
+    Query(ReferrersQuery { artifact_type }): Query<ReferrersQuery>,
     creds: ValidCredentials,
 ) -> Result<Response<Body>, RegistryError> {
+    let location = valid_location(repository, image_name)?;
     registry
         .auth_provider
-        .image_permissions(&creds, manifest_reference.location())
+        .image_permissions(&creds, &location)
         .await
         .require_read()?;
 
-    let manifest_json = registry
+    let mut manifests = Vec::new();
+    for digest in registry
         .storage
-        .get_manifest(&manifest_reference)
+        .get_referrers(&location, subject.digest)
         .await?
-        .ok_or(RegistryError::NotFound)?;
+    {
+        let reference = ManifestReference::new(location.clone(), Reference::new_digest(digest));
+        let Some(manifest_json) = registry.storage.get_manifest(&reference).await? else {
+            continue;
+        };
+        let manifest = parse_manifest(None, &manifest_json)?;
 
-    let manifest: ImageManifest =
-        serde_json::from_slice(&manifest_json).map_err(RegistryError::ParseManifest)?;
+        let descriptor = Descriptor::referrer(
+            manifest,
+            ImageDigest::new(digest),
+            manifest_json.len() as u64,
+        );
+        if artifact_type.is_none() || descriptor.artifact_type() == artifact_type.as_deref() {
+            manifests.push(descriptor);
+        }
+    }
 
-    Ok(Response::builder()
+    let mut response = Response::builder()
         .status(StatusCode::OK)
-        .header(CONTENT_LENGTH, manifest_json.len())
-        .header(CONTENT_TYPE, manifest.media_type())
-        .body(manifest_json.into())
+        .header(CONTENT_TYPE, OCI_IMAGE_INDEX);
+    if artifact_type.is_some() {
+        response = response.header("OCI-Filters-Applied", "artifactType");
+    }
+
+    Ok(response
+        .body(Body::from(
+            serde_json::to_vec(&ImageIndex::new(manifests)).expect("serialization should not fail"),
+        ))
         .unwrap())
 }
+
+/// Checks whether an `If-Match` or `If-None-Match` header value matches the given entity tag.
+///
+/// Uses weak comparison as mandated by RFC 9110 for `If-None-Match`, i.e. `W/` prefixes are
+/// ignored. Entity tags of manifests are never weak, so it is sufficient for `If-Match` as well.
+fn etag_matches(header: &HeaderValue, etag: &str) -> bool {
+    let Ok(raw) = header.to_str() else {
+        return false;
+    };
+
+    raw.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
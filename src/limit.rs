@@ -0,0 +1,49 @@
+//! Concurrency limits.
+//!
+//! Requests exceeding a configured number of concurrently handled requests are shed immediately
+//! with `503 Service Unavailable` and a `Retry-After` header, instead of queueing up. A request
+//! counts as in flight until its response body has been sent completely, so long-running blob
+//! downloads are accounted for as well.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::stream::StreamExt;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::types::{self, OciError, OciErrors};
+
+/// Seconds clients are asked to wait before retrying a shed request.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Middleware limiting the number of requests in flight to the permits of a semaphore.
+pub(crate) async fn limit_concurrency(
+    State(limit): State<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(permit) = limit.try_acquire_owned() else {
+        warn!("too many concurrent requests, shedding request");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+            OciErrors::single(OciError::new(types::ErrorCode::TooManyRequests)),
+        )
+            .into_response();
+    };
+
+    // The permit is moved into the body stream, releasing it only once the body is dropped.
+    next.run(request).await.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _permit = &permit;
+            chunk
+        }))
+    })
+}
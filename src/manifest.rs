@@ -0,0 +1,713 @@
+//! Manifests and the descriptors they reference.
+//!
+//! Docker image manifests and manifest lists are structurally the same as OCI image manifests and
+//! indexes, which is why both are parsed into the same types. Unknown fields are ignored.
+
+use std::collections::HashMap;
+
+use serde::{
+    de::{self, IgnoredAny},
+    Deserialize, Deserializer, Serialize,
+};
+use thiserror::Error;
+
+use crate::{storage::Digest, ImageDigest};
+
+/// Media type of a Docker image manifest.
+pub(crate) const DOCKER_MANIFEST_V2: &str = "application/vnd.docker.distribution.manifest.v2+json";
+/// Media type of a Docker manifest list.
+pub(crate) const DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+/// Media type of an OCI image manifest.
+pub(crate) const OCI_IMAGE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+/// Media type of an OCI image index.
+pub(crate) const OCI_IMAGE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+/// Media types of manifests accepted on push.
+pub(crate) const MANIFEST_MEDIA_TYPES: [&str; 4] = [
+    DOCKER_MANIFEST_V2,
+    DOCKER_MANIFEST_LIST,
+    OCI_IMAGE_MANIFEST,
+    OCI_IMAGE_INDEX,
+];
+/// Media type of a Docker foreign layer, whose blob is downloaded from its `urls` instead.
+pub(crate) const DOCKER_FOREIGN_LAYER: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
+/// Common prefix of the media types of OCI non-distributable layers.
+pub(crate) const OCI_NONDISTRIBUTABLE_LAYER_PREFIX: &str =
+    "application/vnd.oci.image.layer.nondistributable.v1.tar";
+/// Media type of the empty descriptor, used for artifacts without a config or layers.
+pub(crate) const OCI_EMPTY: &str = "application/vnd.oci.empty.v1+json";
+/// Contents of the blob referenced by an empty descriptor.
+pub(crate) const EMPTY_JSON: &[u8] = b"{}";
+
+/// A reference to content by its digest and size, e.g. a layer blob or a manifest in an index.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Descriptor {
+    media_type: String,
+    digest: ImageDigest,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    urls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<Platform>,
+}
+
+/// The platform an image in an index is built for.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Platform {
+    architecture: String,
+    os: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+    #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
+    os_version: Option<String>,
+    #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
+    os_features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<Vec<String>>,
+}
+
+impl Descriptor {
+    pub(crate) fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    pub(crate) fn digest(&self) -> ImageDigest {
+        self.digest
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns whether this is an empty descriptor, pointing to the [`EMPTY_JSON`] blob.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.media_type == OCI_EMPTY && self.digest.digest == Digest::from_contents(EMPTY_JSON)
+    }
+
+    /// Returns whether this describes a foreign layer, whose blob is not pushed to registries.
+    pub(crate) fn is_foreign(&self) -> bool {
+        self.media_type == DOCKER_FOREIGN_LAYER
+            || self
+                .media_type
+                .starts_with(OCI_NONDISTRIBUTABLE_LAYER_PREFIX)
+    }
+
+    /// Returns whether the descriptor is for the given operating system and architecture.
+    pub(crate) fn is_for_platform(&self, os: &str, architecture: &str) -> bool {
+        self.platform
+            .as_ref()
+            .is_some_and(|platform| platform.os == os && platform.architecture == architecture)
+    }
+
+    /// Returns the variant of the CPU the descriptor is for, e.g. `v8` for `arm64`, if given.
+    pub(crate) fn variant(&self) -> Option<&str> {
+        self.platform.as_ref()?.variant.as_deref()
+    }
+
+    pub(crate) fn artifact_type(&self) -> Option<&str> {
+        self.artifact_type.as_deref()
+    }
+
+    /// Creates a descriptor for a manifest, as listed by the referrers API.
+    pub(crate) fn referrer(manifest: Manifest, digest: ImageDigest, size: u64) -> Self {
+        let media_type = manifest.media_type().to_owned();
+        let (annotations, artifact_type) = match manifest {
+            // Per spec, the config media type stands in for a missing artifact type.
+            Manifest::Image(image) => (
+                image.annotations,
+                Some(image.artifact_type.unwrap_or(image.config.media_type)),
+            ),
+            Manifest::Index(index) => (index.annotations, index.artifact_type),
+        };
+
+        Self {
+            media_type,
+            digest,
+            size,
+            urls: None,
+            annotations,
+            data: None,
+            artifact_type,
+            platform: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImageManifest {
+    schema_version: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<Descriptor>,
+
+    /// The media type resolved when parsing, if the manifest does not declare one itself.
+    #[serde(skip)]
+    resolved_media_type: Option<String>,
+}
+
+impl ImageManifest {
+    pub(crate) fn media_type(&self) -> &str {
+        self.media_type
+            .as_deref()
+            .or(self.resolved_media_type.as_deref())
+            .unwrap_or(OCI_IMAGE_MANIFEST)
+    }
+
+    pub(crate) fn config(&self) -> &Descriptor {
+        &self.config
+    }
+
+    pub(crate) fn layers(&self) -> &[Descriptor] {
+        &self.layers
+    }
+
+    /// Returns the descriptors of all blobs referenced, i.e. the config and all layers.
+    pub(crate) fn blobs(&self) -> impl Iterator<Item = &Descriptor> {
+        std::iter::once(&self.config).chain(self.layers.iter())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImageIndex {
+    schema_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+    manifests: Vec<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<HashMap<String, String>>,
+
+    /// The media type resolved when parsing, if the index does not declare one itself.
+    #[serde(skip)]
+    resolved_media_type: Option<String>,
+}
+
+impl ImageIndex {
+    pub(crate) fn new(manifests: Vec<Descriptor>) -> Self {
+        Self {
+            schema_version: 2,
+            media_type: Some(OCI_IMAGE_INDEX.to_owned()),
+            artifact_type: None,
+            manifests,
+            subject: None,
+            annotations: None,
+            resolved_media_type: None,
+        }
+    }
+
+    pub(crate) fn media_type(&self) -> &str {
+        self.media_type
+            .as_deref()
+            .or(self.resolved_media_type.as_deref())
+            .unwrap_or(OCI_IMAGE_INDEX)
+    }
+
+    pub(crate) fn manifests(&self) -> &[Descriptor] {
+        &self.manifests
+    }
+}
+
+/// A parsed manifest of any supported media type.
+///
+/// Deserializing determines the kind of manifest like [`parse_manifest`] without a content type.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Manifest {
+    /// A Docker or OCI image manifest.
+    Image(Box<ImageManifest>),
+    /// A Docker manifest list or OCI image index.
+    Index(Box<ImageIndex>),
+}
+
+impl Manifest {
+    pub(crate) fn media_type(&self) -> &str {
+        match self {
+            Manifest::Image(image) => image.media_type(),
+            Manifest::Index(index) => index.media_type(),
+        }
+    }
+
+    /// Returns the descriptors of everything referenced, i.e. blobs, child manifests and the
+    /// subject.
+    pub(crate) fn descriptors(&self) -> Box<dyn Iterator<Item = &Descriptor> + '_> {
+        match self {
+            Manifest::Image(image) => Box::new(image.blobs().chain(&image.subject)),
+            Manifest::Index(index) => Box::new(index.manifests.iter().chain(&index.subject)),
+        }
+    }
+
+    /// Returns the descriptors of blobs and child manifests, i.e. everything referenced except
+    /// the subject.
+    pub(crate) fn children(&self) -> Box<dyn Iterator<Item = &Descriptor> + '_> {
+        match self {
+            Manifest::Image(image) => Box::new(image.blobs()),
+            Manifest::Index(index) => Box::new(index.manifests.iter()),
+        }
+    }
+
+    /// Parses an already deserialized manifest, see [`parse_pushed_manifest`].
+    fn from_value(
+        content_type: Option<&str>,
+        default_media_type: &str,
+        value: serde_json::Value,
+    ) -> Result<Self, ManifestError> {
+        let header = ManifestHeader::deserialize(&value)?;
+        if header.schema_version != 2 {
+            return Err(ManifestError::UnsupportedSchemaVersion(
+                header.schema_version,
+            ));
+        }
+
+        let content_type = content_type
+            .map(|content_type| content_type.split(';').next().unwrap_or_default().trim())
+            .filter(|content_type| MANIFEST_MEDIA_TYPES.contains(content_type));
+
+        let media_type = match (content_type, header.media_type.as_deref()) {
+            (Some(given), Some(actual)) if given != actual => {
+                return Err(ManifestError::MediaTypeMismatch {
+                    given: given.to_owned(),
+                    actual: actual.to_owned(),
+                })
+            }
+            (_, Some(media_type)) | (Some(media_type), None) => media_type,
+            // OCI manifests may omit the media type, tell them apart by their fields.
+            (None, None) if header.manifests.is_some() => OCI_IMAGE_INDEX,
+            (None, None) => default_media_type,
+        };
+
+        match media_type {
+            DOCKER_MANIFEST_V2 | OCI_IMAGE_MANIFEST => {
+                let mut image: Box<ImageManifest> = serde_json::from_value(value)?;
+                image.resolved_media_type = Some(media_type.to_owned());
+                Ok(Manifest::Image(image))
+            }
+            DOCKER_MANIFEST_LIST | OCI_IMAGE_INDEX => {
+                let mut index: Box<ImageIndex> = serde_json::from_value(value)?;
+                index.resolved_media_type = Some(media_type.to_owned());
+                Ok(Manifest::Index(index))
+            }
+            other => Err(ManifestError::UnsupportedMediaType(other.to_owned())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Manifest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Manifest::from_value(None, OCI_IMAGE_MANIFEST, value).map_err(de::Error::custom)
+    }
+}
+
+/// An invalid manifest.
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    /// The manifest is not valid JSON or is missing required fields.
+    #[error("malformed manifest")]
+    Malformed(#[from] serde_json::Error),
+    /// The schema version is not supported.
+    #[error("unsupported schema version {0}")]
+    UnsupportedSchemaVersion(u32),
+    /// The media type is not supported.
+    #[error("unsupported media type {0}")]
+    UnsupportedMediaType(String),
+    /// The manifest references more layers than allowed.
+    #[error("manifest references {count} layers, at most {max} are allowed")]
+    TooManyLayers {
+        /// Number of layers referenced.
+        count: usize,
+        /// Maximum number of layers allowed.
+        max: usize,
+    },
+    /// The sizes declared for the layers of the manifest add up to more than allowed.
+    #[error("manifest layers add up to more than {max} bytes")]
+    LayersTooLarge {
+        /// Maximum total size of all layers, in bytes.
+        max: u64,
+    },
+    /// The media type given in the request did not match the one in the manifest.
+    #[error("media type {given} does not match manifest media type {actual}")]
+    MediaTypeMismatch {
+        /// Media type given in the `Content-Type` header.
+        given: String,
+        /// Media type given in the manifest.
+        actual: String,
+    },
+}
+
+/// Fields common to all manifests, used to determine how to parse the rest.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestHeader {
+    schema_version: u32,
+    media_type: Option<String>,
+    manifests: Option<IgnoredAny>,
+}
+
+/// Parses and validates a manifest.
+///
+/// `content_type` is the media type the manifest was submitted with, if any. Media types other
+/// than manifest media types (e.g. `application/json`) are ignored.
+pub(crate) fn parse_manifest(
+    content_type: Option<&str>,
+    raw: &[u8],
+) -> Result<Manifest, ManifestError> {
+    parse_pushed_manifest(content_type, OCI_IMAGE_MANIFEST, raw)
+}
+
+/// Parses and validates a manifest like [`parse_manifest`].
+///
+/// `default_media_type` is assumed for image manifests declaring their media type neither in the
+/// body nor in `content_type`.
+pub(crate) fn parse_pushed_manifest(
+    content_type: Option<&str>,
+    default_media_type: &str,
+    raw: &[u8],
+) -> Result<Manifest, ManifestError> {
+    Manifest::from_value(
+        content_type,
+        default_media_type,
+        serde_json::from_slice(raw)?,
+    )
+}
+
+/// Returns the digests a manifest references directly, not counting its subject.
+///
+/// These are the config and layers of image manifests, except foreign layers, which are not
+/// stored by registries, and the child manifests of indexes.
+pub(crate) fn referenced_blobs(
+    raw: &[u8],
+    media_type: Option<&str>,
+) -> Result<Vec<Digest>, ManifestError> {
+    let referenced = match parse_manifest(media_type, raw)? {
+        Manifest::Image(image) => image
+            .blobs()
+            .filter(|descriptor| !descriptor.is_foreign())
+            .map(|descriptor| descriptor.digest().digest)
+            .collect(),
+        Manifest::Index(index) => index
+            .manifests()
+            .iter()
+            .map(|descriptor| descriptor.digest().digest)
+            .collect(),
+    };
+
+    Ok(referenced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_manifest, referenced_blobs, ImageManifest, Manifest, ManifestError,
+        DOCKER_MANIFEST_V2,
+    };
+
+    const CONFIG_DIGEST: &str =
+        "sha256:e4c58958181a5925816faa528ce959e487632f4cfd192f8132f71b32df2744b4";
+    const LAYER_DIGEST: &str =
+        "sha256:43f89b94cd7df92a2f7e565b8fb1b7f502eff2cd225508cbd7ea2d36a9a3a601";
+    const MANIFEST_DIGEST: &str =
+        "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f";
+
+    #[test]
+    fn simple_example_schema_parse() {
+        let raw = r#"{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {
+               "mediaType": "application/vnd.docker.container.image.v1+json",
+               "size": 2298,
+               "digest": "sha256:e4c58958181a5925816faa528ce959e487632f4cfd192f8132f71b32df2744b4"
+            },
+            "layers": [
+               {
+                  "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                  "size": 30439111,
+                  "digest": "sha256:43f89b94cd7df92a2f7e565b8fb1b7f502eff2cd225508cbd7ea2d36a9a3a601"
+               }
+            ]
+        }"#;
+
+        let _manifest: ImageManifest = serde_json::from_str(raw).expect("could not parse manifest");
+    }
+
+    const VALID_OCI_MANIFEST: &str = r#"{
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "size": 2298,
+            "digest": "sha256:e4c58958181a5925816faa528ce959e487632f4cfd192f8132f71b32df2744b4"
+        },
+        "layers": [{
+            "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+            "size": 30439111,
+            "digest": "sha256:43f89b94cd7df92a2f7e565b8fb1b7f502eff2cd225508cbd7ea2d36a9a3a601"
+        }]
+    }"#;
+
+    #[test]
+    fn parses_valid_manifests() {
+        let manifest =
+            parse_manifest(None, VALID_OCI_MANIFEST.as_bytes()).expect("could not parse manifest");
+        assert!(matches!(manifest, Manifest::Image(_)));
+        assert_eq!(
+            manifest.media_type(),
+            "application/vnd.oci.image.manifest.v1+json"
+        );
+
+        let index = r#"{
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "size": 7143,
+                "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+                "platform": { "architecture": "amd64", "os": "linux" }
+            }]
+        }"#;
+        let manifest = parse_manifest(None, index.as_bytes()).expect("could not parse index");
+        assert!(matches!(manifest, Manifest::Index(_)));
+        assert_eq!(
+            manifest.media_type(),
+            "application/vnd.oci.image.index.v1+json"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_digests() {
+        let raw = VALID_OCI_MANIFEST.replace(
+            "sha256:43f89b94cd7df92a2f7e565b8fb1b7f502eff2cd225508cbd7ea2d36a9a3a601",
+            "sha256:not-a-digest",
+        );
+        assert!(matches!(
+            parse_manifest(None, raw.as_bytes()),
+            Err(ManifestError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_fields_and_wrong_versions() {
+        let raw = r#"{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "layers": []
+        }"#;
+        assert!(matches!(
+            parse_manifest(None, raw.as_bytes()),
+            Err(ManifestError::Malformed(_))
+        ));
+
+        let raw = VALID_OCI_MANIFEST.replace("\"schemaVersion\": 2", "\"schemaVersion\": 1");
+        assert!(matches!(
+            parse_manifest(None, raw.as_bytes()),
+            Err(ManifestError::UnsupportedSchemaVersion(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_mismatched_media_types() {
+        assert!(matches!(
+            parse_manifest(Some(DOCKER_MANIFEST_V2), VALID_OCI_MANIFEST.as_bytes()),
+            Err(ManifestError::MediaTypeMismatch { .. })
+        ));
+
+        // Generic content types are ignored.
+        assert!(parse_manifest(Some("application/json"), VALID_OCI_MANIFEST.as_bytes()).is_ok());
+    }
+
+    /// Parses a manifest and serializes it again, which must not lose any known fields.
+    fn round_trip(raw: serde_json::Value) -> Manifest {
+        let manifest: Manifest =
+            serde_json::from_value(raw.clone()).expect("could not parse manifest");
+        assert_eq!(serde_json::to_value(&manifest).unwrap(), raw);
+        manifest
+    }
+
+    fn digests(manifest: &Manifest) -> Vec<String> {
+        manifest
+            .descriptors()
+            .map(|descriptor| descriptor.digest().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn docker_manifests_round_trip() {
+        let manifest = round_trip(serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "size": 1470,
+                "digest": CONFIG_DIGEST
+            },
+            "layers": [{
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": 2818413,
+                "digest": LAYER_DIGEST
+            }, {
+                "mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+                "size": 1654,
+                "digest": MANIFEST_DIGEST,
+                "urls": ["https://mcr.microsoft.com/v2/windows/servercore/blobs/sha256:e692"]
+            }]
+        }));
+        assert_eq!(manifest.media_type(), DOCKER_MANIFEST_V2);
+        assert_eq!(
+            digests(&manifest),
+            [CONFIG_DIGEST, LAYER_DIGEST, MANIFEST_DIGEST]
+        );
+
+        let list = round_trip(serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+            "manifests": [{
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "size": 7143,
+                "digest": MANIFEST_DIGEST,
+                "platform": { "architecture": "arm", "os": "linux", "variant": "v7" }
+            }, {
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "size": 1125,
+                "digest": CONFIG_DIGEST,
+                "platform": {
+                    "architecture": "amd64",
+                    "os": "windows",
+                    "os.version": "10.0.17763.5329",
+                    "os.features": ["win32k"]
+                }
+            }]
+        }));
+        assert!(matches!(list, Manifest::Index(_)));
+        assert_eq!(digests(&list), [MANIFEST_DIGEST, CONFIG_DIGEST]);
+    }
+
+    #[test]
+    fn foreign_layers_are_not_referenced_blobs() {
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "size": 1470,
+                "digest": CONFIG_DIGEST
+            },
+            "layers": [{
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": 2818413,
+                "digest": LAYER_DIGEST
+            }, {
+                "mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+                "size": 1654,
+                "digest": MANIFEST_DIGEST
+            }]
+        });
+        let referenced: Vec<_> = referenced_blobs(manifest.to_string().as_bytes(), None)
+            .unwrap()
+            .iter()
+            .map(|digest| format!("sha256:{digest}"))
+            .collect();
+        assert_eq!(referenced, [CONFIG_DIGEST, LAYER_DIGEST]);
+    }
+
+    #[test]
+    fn oci_manifests_round_trip() {
+        let artifact = round_trip(serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": "application/vnd.example.sbom.v1",
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2,
+                "data": "e30="
+            },
+            "layers": [{
+                "mediaType": "application/spdx+json",
+                "size": 1024,
+                "digest": LAYER_DIGEST,
+                "annotations": { "org.opencontainers.image.title": "sbom.spdx.json" }
+            }],
+            "subject": {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "size": 7143,
+                "digest": MANIFEST_DIGEST
+            },
+            "annotations": { "org.opencontainers.image.created": "2024-01-01T00:00:00Z" }
+        }));
+        let Manifest::Image(ref image) = artifact else {
+            panic!("not an image manifest");
+        };
+        assert!(image.config().is_empty());
+        assert_eq!(digests(&artifact)[1..], [LAYER_DIGEST, MANIFEST_DIGEST]);
+
+        // The media type is optional in OCI indexes.
+        let index = round_trip(serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "size": 7143,
+                "digest": MANIFEST_DIGEST,
+                "platform": {
+                    "architecture": "arm64",
+                    "os": "linux",
+                    "variant": "v8",
+                    "features": ["sse4"]
+                },
+                "annotations": { "org.opencontainers.image.ref.name": "latest" }
+            }],
+            "annotations": { "com.example.key": "value" }
+        }));
+        assert_eq!(
+            index.media_type(),
+            "application/vnd.oci.image.index.v1+json"
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        let manifest: Manifest = serde_json::from_value(serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "size": 2298,
+                "digest": CONFIG_DIGEST,
+                "x-vendor": { "nested": true }
+            },
+            "layers": [],
+            "x-vendor": "extension"
+        }))
+        .expect("could not parse manifest with unknown fields");
+        assert_eq!(digests(&manifest), [CONFIG_DIGEST]);
+
+        // Unknown fields do not make up for unsupported media types.
+        assert!(serde_json::from_value::<Manifest>(serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.example.unknown",
+            "layers": []
+        }))
+        .is_err());
+    }
+}
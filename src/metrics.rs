@@ -0,0 +1,137 @@
+//! Metrics in the Prometheus text exposition format.
+//!
+//! Blob upload throughput is tracked per repository, to spot repositories receiving the bulk of
+//! uploads. Repository names are chosen by clients, so only a limited number of them is labeled
+//! individually; uploads to any further repository are accounted for under [`OTHER_REPOSITORY`].
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+/// Label of repositories beyond the limit of individually tracked ones.
+pub(crate) const OTHER_REPOSITORY: &str = "_other";
+
+/// Upload metrics of a single repository.
+#[derive(Debug, Default)]
+struct RepositoryUploads {
+    /// Total bytes received in upload chunks.
+    bytes_received: u64,
+    /// Number of chunks currently being received.
+    active: u64,
+}
+
+/// Blob upload metrics, by repository.
+#[derive(Debug)]
+pub(crate) struct UploadMetrics {
+    /// Maximum number of repositories labeled individually.
+    max_repositories: usize,
+    /// Metrics by repository label.
+    repositories: Mutex<BTreeMap<String, RepositoryUploads>>,
+}
+
+impl UploadMetrics {
+    /// Creates metrics labeling at most `max_repositories` repositories individually.
+    pub(crate) fn new(max_repositories: usize) -> Self {
+        Self {
+            max_repositories,
+            repositories: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the label to record metrics of `repository` under, creating its entry.
+    fn label<'a>(
+        &self,
+        repositories: &'a mut BTreeMap<String, RepositoryUploads>,
+        repository: &str,
+    ) -> &'a mut RepositoryUploads {
+        let tracked = repositories
+            .keys()
+            .filter(|name| *name != OTHER_REPOSITORY)
+            .count();
+        let label = if repositories.contains_key(repository) || tracked < self.max_repositories {
+            repository
+        } else {
+            OTHER_REPOSITORY
+        };
+        repositories.entry(label.to_owned()).or_default()
+    }
+
+    /// Marks a chunk for `repository` as being received until the returned guard is dropped.
+    pub(crate) fn upload_started(&self, repository: &str) -> ActiveUpload<'_> {
+        let mut repositories = self.repositories.lock().expect("lock poisoned");
+        self.label(&mut repositories, repository).active += 1;
+        ActiveUpload {
+            metrics: self,
+            repository: repository.to_owned(),
+        }
+    }
+
+    /// Records `bytes` received for an upload to `repository`.
+    pub(crate) fn record_bytes(&self, repository: &str, bytes: u64) {
+        let mut repositories = self.repositories.lock().expect("lock poisoned");
+        self.label(&mut repositories, repository).bytes_received += bytes;
+    }
+
+    /// Returns the bytes received for uploads to `repository`, as labeled.
+    #[cfg(test)]
+    pub(crate) fn bytes_received(&self, repository: &str) -> u64 {
+        self.repositories
+            .lock()
+            .expect("lock poisoned")
+            .get(repository)
+            .map_or(0, |uploads| uploads.bytes_received)
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let repositories = self.repositories.lock().expect("lock poisoned");
+        let mut out = String::new();
+
+        out.push_str("# HELP registry_upload_bytes_total Bytes received in blob uploads.\n");
+        out.push_str("# TYPE registry_upload_bytes_total counter\n");
+        for (repository, uploads) in repositories.iter() {
+            let _ = writeln!(
+                out,
+                "registry_upload_bytes_total{{repository=\"{}\"}} {}",
+                escape_label(repository),
+                uploads.bytes_received
+            );
+        }
+
+        out.push_str("# HELP registry_uploads_active Blob upload chunks being received.\n");
+        out.push_str("# TYPE registry_uploads_active gauge\n");
+        for (repository, uploads) in repositories.iter() {
+            let _ = writeln!(
+                out,
+                "registry_uploads_active{{repository=\"{}\"}} {}",
+                escape_label(repository),
+                uploads.active
+            );
+        }
+
+        out
+    }
+}
+
+/// An upload chunk being received, see [`UploadMetrics::upload_started`].
+#[derive(Debug)]
+pub(crate) struct ActiveUpload<'a> {
+    /// Metrics the upload is counted in.
+    metrics: &'a UploadMetrics,
+    /// Repository the upload is for.
+    repository: String,
+}
+
+impl Drop for ActiveUpload<'_> {
+    fn drop(&mut self) {
+        let mut repositories = self.metrics.repositories.lock().expect("lock poisoned");
+        let uploads = self.metrics.label(&mut repositories, &self.repository);
+        uploads.active = uploads.active.saturating_sub(1);
+    }
+}
+
+/// Escapes a label value for the text exposition format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,140 @@
+//! Pagination of listings.
+//!
+//! Listing endpoints such as the tags list are paginated as described by the distribution spec:
+//! Clients request at most `n` entries following the entry `last`, listings are ordered lexically.
+//! If more entries are available, a `Link` header with `rel="next"` points to the next page.
+
+use serde::Deserialize;
+
+/// Query parameters of paginated endpoints.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PaginationQuery {
+    /// Maximum number of entries to return.
+    pub(crate) n: Option<usize>,
+    /// Only return entries following this one.
+    pub(crate) last: Option<String>,
+}
+
+/// Selects a page of at most `n` items following the item with key `last`.
+///
+/// Items are ordered lexically by `key`. Along with the page, the value for `last` to fetch the
+/// next page is returned if more items are available. Empty pages never point to a next page, as
+/// following it would return the same page again.
+pub(crate) fn paginate<T>(
+    mut items: Vec<T>,
+    n: Option<usize>,
+    last: Option<String>,
+    key: impl Fn(&T) -> &str,
+) -> (Vec<T>, Option<String>) {
+    items.sort_by(|a, b| key(a).cmp(key(b)));
+
+    if let Some(last) = &last {
+        items.retain(|item| key(item) > last.as_str());
+    }
+
+    let Some(n) = n else {
+        return (items, None);
+    };
+    if items.len() <= n {
+        return (items, None);
+    }
+
+    items.truncate(n);
+    let next = items.last().map(|item| key(item).to_owned());
+
+    (items, next)
+}
+
+/// Formats a `Link` header pointing to the next page.
+///
+/// The original query of the request is preserved, except for `last`, which is replaced with
+/// `next`.
+pub(crate) fn next_link(path: &str, query: Option<&str>, next: &str) -> String {
+    let mut params: Vec<String> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty() && param.split('=').next() != Some("last"))
+        .map(ToOwned::to_owned)
+        .collect();
+    params.push(format!("last={}", encode_query_value(next)));
+
+    format!("<{path}?{}>; rel=\"next\"", params.join("&"))
+}
+
+/// Percent-encodes a query parameter value, leaving only unreserved characters as is.
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~' | b'/') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_link, paginate};
+
+    fn tags() -> Vec<String> {
+        ["v2", "latest", "v1", "alpha"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    fn page(n: Option<usize>, last: Option<&str>) -> (Vec<String>, Option<String>) {
+        paginate(tags(), n, last.map(ToOwned::to_owned), String::as_str)
+    }
+
+    #[test]
+    fn without_parameters_everything_is_returned_in_order() {
+        assert_eq!(
+            page(None, None),
+            (
+                vec!["alpha".into(), "latest".into(), "v1".into(), "v2".into()],
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn pages_follow_last() {
+        assert_eq!(
+            page(Some(2), None),
+            (vec!["alpha".into(), "latest".into()], Some("latest".into()))
+        );
+        assert_eq!(
+            page(Some(2), Some("latest")),
+            (vec!["v1".into(), "v2".into()], None)
+        );
+        assert_eq!(page(None, Some("v1")), (vec!["v2".into()], None));
+    }
+
+    #[test]
+    fn zero_sized_pages_have_no_next_page() {
+        assert_eq!(page(Some(0), None), (vec![], None));
+        assert_eq!(page(Some(0), Some("latest")), (vec![], None));
+        assert_eq!(page(Some(0), Some("v2")), (vec![], None));
+    }
+
+    #[test]
+    fn last_past_the_end_yields_empty_page() {
+        assert_eq!(page(Some(2), Some("zzz")), (vec![], None));
+        assert_eq!(page(None, Some("zzz")), (vec![], None));
+    }
+
+    #[test]
+    fn next_link_preserves_query() {
+        assert_eq!(
+            next_link("/v2/a/b/tags/list", Some("n=2&last=alpha"), "latest"),
+            "</v2/a/b/tags/list?n=2&last=latest>; rel=\"next\""
+        );
+        assert_eq!(
+            next_link("/v2/a/b/tags/list", None, "a b"),
+            "</v2/a/b/tags/list?last=a%20b>; rel=\"next\""
+        );
+    }
+}
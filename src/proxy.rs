@@ -0,0 +1,528 @@
+//! Pull-through caching of upstream registries.
+//!
+//! A repository can be configured to mirror a repository on an upstream registry, e.g. a local
+//! repository `dockerhub` mirroring `library` on `https://registry-1.docker.io`. Pulling
+//! `dockerhub/nginx:latest` will then fetch `library/nginx:latest` from the upstream registry if
+//! it is not available locally, store it and serve it. Subsequent pulls are served from local
+//! storage.
+//!
+//! Manifests referenced by digest are immutable and never fetched twice, manifests referenced by
+//! tag are refreshed from upstream once they are older than the configured TTL (see
+//! [`Upstream::tag_ttl`]). If the upstream is unreachable, a stale cached tag will still be served.
+//!
+//! Requests to the upstream time out (see [`Upstream::connect_timeout`] and
+//! [`Upstream::read_timeout`]). With a circuit breaker configured (see
+//! [`Upstream::circuit_breaker`]), the upstream is not contacted at all for a while after failing
+//! repeatedly: Only content already cached is served, misses fail with `503 Service Unavailable`.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::{
+    header::{ACCEPT, CONTENT_LENGTH, WWW_AUTHENTICATE},
+    Method, StatusCode,
+};
+use futures::stream::StreamExt;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    storage::{self, Digest, ImageLocation, ManifestReference, Reference, RegistryStorage},
+    www_authenticate, ImageDigest,
+};
+
+/// Default time a manifest fetched by tag is considered fresh.
+const DEFAULT_TAG_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Default timeout for connecting to an upstream.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default timeout for reads from an upstream connection.
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Manifest media types requested from upstream registries.
+const MANIFEST_ACCEPT: &str =
+    "application/vnd.docker.distribution.manifest.v2+json, application/vnd.oci.image.manifest.v1+json";
+
+/// An upstream registry repository to mirror.
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    /// Base URL of the upstream registry, e.g. `https://registry-1.docker.io`.
+    base_url: String,
+    /// Repository on the upstream registry, e.g. `library`.
+    repository: String,
+    /// Time a manifest fetched by tag is considered fresh.
+    tag_ttl: Duration,
+    /// Timeout for connecting to the upstream.
+    connect_timeout: Duration,
+    /// Timeout for every read from an upstream connection.
+    read_timeout: Duration,
+    /// Number of consecutive failures after which the upstream is no longer contacted, and for
+    /// how long.
+    circuit_breaker: Option<(u32, Duration)>,
+}
+
+impl Upstream {
+    /// Creates a new upstream for the given registry base URL and repository.
+    pub fn new<U: Into<String>, R: Into<String>>(base_url: U, repository: R) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            repository: repository.into(),
+            tag_ttl: DEFAULT_TAG_TTL,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            circuit_breaker: None,
+        }
+    }
+
+    /// Sets the time a manifest fetched by tag is considered fresh.
+    ///
+    /// Defaults to five minutes.
+    pub fn tag_ttl(mut self, tag_ttl: Duration) -> Self {
+        self.tag_ttl = tag_ttl;
+        self
+    }
+
+    /// Sets the timeout for connecting to the upstream.
+    ///
+    /// Defaults to ten seconds.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets the timeout for every read from an upstream connection, i.e. the time the upstream
+    /// may take to respond or to send the next part of a response.
+    ///
+    /// Defaults to one minute.
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Stops contacting the upstream for `cooldown` after `threshold` consecutive failures.
+    ///
+    /// Failures are errors and timeouts of requests, as well as unexpected responses. While the
+    /// circuit is open, only cached content is served. Once the cooldown has passed, the next
+    /// request probes the upstream, a failure opens the circuit again. Disabled by default.
+    pub fn circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some((threshold, cooldown));
+        self
+    }
+
+    /// Returns the URL for a given path below `/v2/{repository}/{image}/` on the upstream.
+    fn url(&self, image: &str, path: &str) -> String {
+        format!(
+            "{}/v2/{}/{}/{}",
+            self.base_url, self.repository, image, path
+        )
+    }
+}
+
+/// An error communicating with an upstream registry.
+#[derive(Debug, Error)]
+pub enum UpstreamError {
+    /// The HTTP request to the upstream failed.
+    #[error("upstream request failed")]
+    Request(#[from] reqwest::Error),
+    /// The upstream returned an unexpected status code.
+    #[error("upstream returned unexpected status {0}")]
+    UnexpectedStatus(StatusCode),
+    /// The upstream required authentication, but did not send a usable bearer challenge.
+    #[error("upstream sent no usable bearer challenge")]
+    InvalidChallenge,
+    /// Storing fetched data locally failed.
+    #[error("could not store upstream data")]
+    Storage(#[from] storage::Error),
+    /// Writing fetched data to local storage failed.
+    #[error("could not write upstream data")]
+    LocalWriteFailed(#[source] std::io::Error),
+    /// The upstream blob exceeds the maximum blob size, given in bytes.
+    #[error("upstream blob exceeds the maximum size of {0} bytes")]
+    BlobTooLarge(u64),
+    /// The upstream did not send the size of a blob.
+    #[error("upstream sent no blob size")]
+    MissingBlobSize,
+    /// The upstream failed repeatedly and is not contacted until the cooldown has passed.
+    #[error("upstream circuit breaker is open")]
+    CircuitOpen,
+}
+
+/// State of the circuit breaker of an upstream.
+#[derive(Debug, Default)]
+struct CircuitState {
+    /// Number of consecutive failures.
+    failures: u32,
+    /// Point in time until which the upstream is not contacted.
+    open_until: Option<Instant>,
+}
+
+/// Token response of a registry token endpoint.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// A client for an upstream registry.
+///
+/// Performs the bearer token dance if required by the upstream and caches tokens per image.
+#[derive(Debug)]
+pub(crate) struct UpstreamClient {
+    /// The upstream to mirror.
+    upstream: Upstream,
+    /// HTTP client used for all requests.
+    http: reqwest::Client,
+    /// Bearer tokens for the upstream, by image.
+    tokens: Mutex<HashMap<String, String>>,
+    /// Points in time at which tags were last fetched from upstream.
+    fetched_tags: Mutex<HashMap<(String, String), Instant>>,
+    /// Consecutive failures of the upstream, for the circuit breaker.
+    circuit: Mutex<CircuitState>,
+}
+
+impl UpstreamClient {
+    /// Creates a new upstream client.
+    pub(crate) fn new(upstream: Upstream) -> Self {
+        let http = reqwest::Client::builder()
+            .connect_timeout(upstream.connect_timeout)
+            .read_timeout(upstream.read_timeout)
+            .build()
+            .expect("could not build upstream HTTP client");
+
+        Self {
+            upstream,
+            http,
+            tokens: Mutex::new(HashMap::new()),
+            fetched_tags: Mutex::new(HashMap::new()),
+            circuit: Mutex::new(CircuitState::default()),
+        }
+    }
+
+    /// Ensures a manifest is available in local storage, fetching it from upstream if required.
+    ///
+    /// Manifests not found upstream are not an error, the caller is expected to handle a local
+    /// cache miss.
+    pub(crate) async fn cache_manifest(
+        &self,
+        storage: &dyn RegistryStorage,
+        manifest_reference: &ManifestReference,
+    ) -> Result<(), UpstreamError> {
+        let image = manifest_reference.location().image();
+        let cached = storage.get_manifest(manifest_reference).await?.is_some();
+
+        let tag = match manifest_reference.reference() {
+            Reference::Digest(_) if cached => return Ok(()),
+            Reference::Digest(_) => None,
+            Reference::Tag(tag) => {
+                if cached && self.tag_is_fresh(image, tag) {
+                    return Ok(());
+                }
+                Some(tag.as_str())
+            }
+        };
+
+        let reference = manifest_reference.reference().to_string();
+        let fetched = self
+            .request(
+                Method::GET,
+                image,
+                &format!("manifests/{reference}"),
+                Some(MANIFEST_ACCEPT),
+            )
+            .await;
+
+        let response = match fetched {
+            Ok(response) => response,
+            Err(err) if cached => {
+                warn!(%manifest_reference, %err, "upstream unavailable, serving stale manifest");
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        if response.status() == StatusCode::NOT_FOUND {
+            debug!(%manifest_reference, "manifest not found upstream");
+            return Ok(());
+        }
+
+        let manifest = response.bytes().await?;
+        let digest = storage.put_manifest(manifest_reference, &manifest).await?;
+        info!(%manifest_reference, %digest, "cached manifest from upstream");
+
+        if let Some(tag) = tag {
+            self.fetched_tags
+                .lock()
+                .expect("lock poisoned")
+                .insert((image.to_owned(), tag.to_owned()), Instant::now());
+        }
+
+        Ok(())
+    }
+
+    /// Ensures a blob is available in local storage, fetching it from upstream if required.
+    ///
+    /// Blobs not found upstream are not an error, the caller is expected to handle a local cache
+    /// miss. Blobs larger than `max_size` bytes are not stored.
+    pub(crate) async fn cache_blob(
+        &self,
+        storage: &dyn RegistryStorage,
+        location: &ImageLocation,
+        digest: Digest,
+        max_size: u64,
+    ) -> Result<(), UpstreamError> {
+        if storage.blob_exists(digest).await? {
+            return Ok(());
+        }
+
+        let image_digest = ImageDigest::new(digest);
+        let response = self
+            .request(
+                Method::GET,
+                location.image(),
+                &format!("blobs/{image_digest}"),
+                None,
+            )
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            debug!(%location, %image_digest, "blob not found upstream");
+            return Ok(());
+        }
+
+        if response.content_length().is_some_and(|len| len > max_size) {
+            return Err(UpstreamError::BlobTooLarge(max_size));
+        }
+
+        // Stream the blob into a regular upload, finalizing verifies the digest.
+        let upload = storage.begin_new_upload(location).await?;
+        if let Err(err) = store_blob(storage, upload, digest, response, max_size).await {
+            match storage.cancel_upload(upload).await {
+                Ok(()) | Err(storage::Error::UploadDoesNotExit) => {}
+                Err(cancel_err) => {
+                    warn!(%upload, %cancel_err, "could not cancel failed upstream blob upload");
+                }
+            }
+            return Err(err);
+        }
+        info!(%location, %image_digest, "cached blob from upstream");
+
+        Ok(())
+    }
+
+    /// Returns the size of a blob on the upstream, without fetching it.
+    ///
+    /// Returns `None` if the blob is not found upstream.
+    pub(crate) async fn blob_size(
+        &self,
+        location: &ImageLocation,
+        digest: Digest,
+    ) -> Result<Option<u64>, UpstreamError> {
+        let image_digest = ImageDigest::new(digest);
+        let response = self
+            .request(
+                Method::HEAD,
+                location.image(),
+                &format!("blobs/{image_digest}"),
+                None,
+            )
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            debug!(%location, %image_digest, "blob not found upstream");
+            return Ok(None);
+        }
+
+        // The body of a `HEAD` response is always empty, the header carries the blob size.
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Some)
+            .ok_or(UpstreamError::MissingBlobSize)
+    }
+
+    /// Returns whether a tag has been fetched from upstream recently enough.
+    fn tag_is_fresh(&self, image: &str, tag: &str) -> bool {
+        self.fetched_tags
+            .lock()
+            .expect("lock poisoned")
+            .get(&(image.to_owned(), tag.to_owned()))
+            .map(|fetched| fetched.elapsed() < self.upstream.tag_ttl)
+            .unwrap_or(false)
+    }
+
+    /// Performs a request against the upstream, authenticating if required.
+    ///
+    /// Returns the response if it was successful or `NOT_FOUND`. Fails without contacting the
+    /// upstream while the circuit breaker is open.
+    async fn request(
+        &self,
+        method: Method,
+        image: &str,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Result<reqwest::Response, UpstreamError> {
+        let Some((threshold, cooldown)) = self.upstream.circuit_breaker else {
+            return self.request_unguarded(method, image, path, accept).await;
+        };
+
+        if self
+            .circuit
+            .lock()
+            .expect("lock poisoned")
+            .open_until
+            .is_some_and(|open_until| Instant::now() < open_until)
+        {
+            return Err(UpstreamError::CircuitOpen);
+        }
+
+        let result = self.request_unguarded(method, image, path, accept).await;
+
+        let mut circuit = self.circuit.lock().expect("lock poisoned");
+        match result {
+            Ok(_) => *circuit = CircuitState::default(),
+            Err(ref err) => {
+                circuit.failures = circuit.failures.saturating_add(1);
+                if circuit.failures >= threshold {
+                    warn!(
+                        base_url = self.upstream.base_url,
+                        failures = circuit.failures,
+                        ?cooldown,
+                        %err,
+                        "upstream failing, opening circuit breaker"
+                    );
+                    circuit.open_until = Some(Instant::now() + cooldown);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Performs a request like [`Self::request`], regardless of the circuit breaker.
+    async fn request_unguarded(
+        &self,
+        method: Method,
+        image: &str,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Result<reqwest::Response, UpstreamError> {
+        let url = self.upstream.url(image, path);
+
+        let token = self
+            .tokens
+            .lock()
+            .expect("lock poisoned")
+            .get(image)
+            .cloned();
+        let mut response = self
+            .send(method.clone(), &url, accept, token.as_deref())
+            .await?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let token = self.fetch_token(&response).await?;
+            response = self.send(method, &url, accept, Some(&token)).await?;
+            self.tokens
+                .lock()
+                .expect("lock poisoned")
+                .insert(image.to_owned(), token);
+        }
+
+        let status = response.status();
+        if status.is_success() || status == StatusCode::NOT_FOUND {
+            Ok(response)
+        } else {
+            Err(UpstreamError::UnexpectedStatus(status))
+        }
+    }
+
+    /// Sends a single request.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        accept: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<reqwest::Response, UpstreamError> {
+        let mut request = self.http.request(method, url);
+        if let Some(accept) = accept {
+            request = request.header(ACCEPT, accept);
+        }
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        Ok(request.send().await?)
+    }
+
+    /// Retrieves a bearer token as requested by an `UNAUTHORIZED` response.
+    async fn fetch_token(&self, unauthorized: &reqwest::Response) -> Result<String, UpstreamError> {
+        let challenge = unauthorized
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| www_authenticate::bearer_challenge(value.as_bytes()).ok())
+            .map(|(_rest, challenge)| challenge)
+            .ok_or(UpstreamError::InvalidChallenge)?;
+
+        let params = challenge
+            .service
+            .iter()
+            .map(|service| ("service", service))
+            .chain(challenge.scopes.iter().map(|scope| ("scope", scope)));
+        let url = reqwest::Url::parse_with_params(&challenge.realm, params)
+            .map_err(|_| UpstreamError::InvalidChallenge)?;
+
+        let response = self.http.get(url).send().await?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::UnexpectedStatus(response.status()));
+        }
+
+        let token_response: TokenResponse = response.json().await?;
+        token_response
+            .token
+            .or(token_response.access_token)
+            .ok_or(UpstreamError::InvalidChallenge)
+    }
+}
+
+/// Writes a blob fetched from upstream into an upload and finalizes it.
+///
+/// The upload is left in place on failure, it is up to the caller to cancel it.
+async fn store_blob(
+    storage: &dyn RegistryStorage,
+    upload: Uuid,
+    digest: Digest,
+    response: reqwest::Response,
+    max_size: u64,
+) -> Result<(), UpstreamError> {
+    let mut writer = storage.get_upload_writer(0, upload).await?;
+    let mut body = response.bytes_stream();
+    let mut received: u64 = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        received += chunk.len() as u64;
+        if received > max_size {
+            return Err(UpstreamError::BlobTooLarge(max_size));
+        }
+
+        writer
+            .write_all(chunk.as_ref())
+            .await
+            .map_err(UpstreamError::LocalWriteFailed)?;
+    }
+    writer
+        .flush()
+        .await
+        .map_err(UpstreamError::LocalWriteFailed)?;
+    drop(writer);
+
+    storage.finalize_upload(upload, digest).await?;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! `Range` headers of blob downloads and `Content-Range` headers of chunked uploads.
+//!
+//! Only single byte ranges are supported, i.e. `bytes=<start>-<end>`, `bytes=<start>-` and
+//! `bytes=-<suffix length>`. See RFC 9110, section 14.
+
+use axum::http::HeaderValue;
+use thiserror::Error;
+
+/// An error interpreting a `Range` header.
+#[derive(Debug, Error, Eq, PartialEq)]
+pub(crate) enum RangeError {
+    /// The header is not a valid byte range.
+    #[error("malformed range header")]
+    Malformed,
+    /// More than one range was requested.
+    #[error("multiple ranges are not supported")]
+    MultipleRanges,
+    /// The range does not overlap the content.
+    #[error("range not satisfiable")]
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for content of `total_len` bytes.
+///
+/// Returns the inclusive start and end offsets of the requested range, or `None` if no header is
+/// present and the full content should be sent. Ends past the content are clamped to its end.
+pub(crate) fn parse_byte_range(
+    header: Option<&HeaderValue>,
+    total_len: u64,
+) -> Result<Option<(u64, u64)>, RangeError> {
+    let Some(header) = header else {
+        return Ok(None);
+    };
+
+    let ranges = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+        .ok_or(RangeError::Malformed)?;
+    if ranges.contains(',') {
+        return Err(RangeError::MultipleRanges);
+    }
+
+    let (start, end) = ranges.trim().split_once('-').ok_or(RangeError::Malformed)?;
+    let parse = |value: &str| value.parse::<u64>().map_err(|_| RangeError::Malformed);
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return Err(RangeError::Malformed),
+        ("", suffix) => {
+            let suffix = parse(suffix)?;
+            if suffix == 0 {
+                return Err(RangeError::Unsatisfiable);
+            }
+            (total_len.saturating_sub(suffix), u64::MAX)
+        }
+        (start, "") => (parse(start)?, u64::MAX),
+        (start, end) => (parse(start)?, parse(end)?),
+    };
+    if end < start {
+        return Err(RangeError::Malformed);
+    }
+
+    match total_len.checked_sub(1) {
+        Some(last) if start <= last => Ok(Some((start, end.min(last)))),
+        _ => Err(RangeError::Unsatisfiable),
+    }
+}
+
+/// Parses the `Content-Range` header of an upload chunk.
+///
+/// Returns the inclusive start and end offsets. Besides the `<start>-<end>` form sent by most
+/// clients, the RFC 9110 form `bytes <start>-<end>/<total>` is accepted, ignoring the total.
+pub(crate) fn parse_content_range(header: &HeaderValue) -> Result<(u64, u64), RangeError> {
+    let value = header.to_str().map_err(|_| RangeError::Malformed)?.trim();
+    let value = value.strip_prefix("bytes ").unwrap_or(value);
+    let range = value.split_once('/').map_or(value, |(range, _total)| range);
+
+    let (start, end) = range.trim().split_once('-').ok_or(RangeError::Malformed)?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| RangeError::Malformed)
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if end < start {
+        return Err(RangeError::Malformed);
+    }
+
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::{parse_byte_range, parse_content_range, RangeError};
+
+    fn parse(header: &str, total_len: u64) -> Result<Option<(u64, u64)>, RangeError> {
+        parse_byte_range(Some(&HeaderValue::from_str(header).unwrap()), total_len)
+    }
+
+    #[test]
+    fn missing_header_yields_full_content() {
+        assert_eq!(parse_byte_range(None, 100), Ok(None));
+    }
+
+    #[test]
+    fn parses_all_range_forms() {
+        assert_eq!(parse("bytes=0-9", 100), Ok(Some((0, 9))));
+        assert_eq!(parse("bytes=10-", 100), Ok(Some((10, 99))));
+        assert_eq!(parse("bytes=-10", 100), Ok(Some((90, 99))));
+        assert_eq!(parse("bytes=99-99", 100), Ok(Some((99, 99))));
+    }
+
+    #[test]
+    fn clamps_ranges_exceeding_content() {
+        assert_eq!(parse("bytes=50-200", 100), Ok(Some((50, 99))));
+        assert_eq!(parse("bytes=-200", 100), Ok(Some((0, 99))));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_ranges() {
+        assert_eq!(parse("bytes=100-", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=200-300", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=-0", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=0-", 0), Err(RangeError::Unsatisfiable));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for header in [
+            "bytes=",
+            "bytes=-",
+            "bytes=a-b",
+            "bytes=5-1",
+            "items=0-1",
+            "0-1",
+        ] {
+            assert_eq!(parse(header, 100), Err(RangeError::Malformed), "{header}");
+        }
+        assert_eq!(parse("bytes=0-1,5-6", 100), Err(RangeError::MultipleRanges));
+    }
+
+    #[test]
+    fn parses_content_ranges() {
+        let parse = |header: &str| parse_content_range(&HeaderValue::from_str(header).unwrap());
+
+        assert_eq!(parse("0-9"), Ok((0, 9)));
+        assert_eq!(parse("bytes 10-19/20"), Ok((10, 19)));
+        assert_eq!(parse("bytes 10-19/*"), Ok((10, 19)));
+        for header in ["", "0-", "-9", "a-b", "9-0", "bytes=0-9"] {
+            assert_eq!(parse(header), Err(RangeError::Malformed), "{header}");
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Per-client rate limiting.
+//!
+//! Requests are limited using token buckets, one per client and kind of access. Authenticated
+//! clients are identified by their username, anonymous clients by their IP address. Reads and
+//! writes are limited independently, so a client busy pulling can still push and vice versa.
+//!
+//! Requests over the limit are rejected with `429 Too Many Requests` and a `Retry-After` header.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header::RETRY_AFTER, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::types::{self, OciError, OciErrors};
+
+/// Number of buckets tracked before full, thus unneeded, ones are dropped.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// A rate limit of a number of requests within a period of time.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RateLimit {
+    /// Requests allowed in a burst, i.e. the size of the bucket.
+    requests: u32,
+    /// Time it takes to refill the bucket completely.
+    per: Duration,
+}
+
+impl RateLimit {
+    pub(crate) fn new(requests: u32, per: Duration) -> Self {
+        Self { requests, per }
+    }
+
+    /// Returns the time it takes for a single token to be refilled.
+    fn refill_interval(&self) -> Duration {
+        self.per / self.requests.max(1)
+    }
+
+    /// Returns the tokens in a bucket at the given time, including those refilled since its last
+    /// update.
+    fn tokens_at(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let capacity = f64::from(self.requests);
+        let refilled = (now - bucket.updated).as_secs_f64() / self.refill_interval().as_secs_f64();
+
+        (bucket.tokens + refilled).min(capacity)
+    }
+}
+
+/// Kind of access a request is limited by.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    /// Determines the kind of access from the method of a request.
+    pub(crate) fn from_method(method: &Method) -> Self {
+        if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+            Access::Read
+        } else {
+            Access::Write
+        }
+    }
+}
+
+/// Identity a client is rate limited by.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ClientKey {
+    /// An authenticated user.
+    User(String),
+    /// An anonymous client.
+    Ip(IpAddr),
+}
+
+/// Token bucket of a single client.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Rate limiter tracking the buckets of all clients.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    read: Option<RateLimit>,
+    write: Option<RateLimit>,
+    buckets: Mutex<HashMap<(ClientKey, Access), Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(read: Option<RateLimit>, write: Option<RateLimit>) -> Self {
+        Self {
+            read,
+            write,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the limit for a kind of access, if any.
+    fn limit(&self, access: Access) -> Option<RateLimit> {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        }
+    }
+
+    /// Takes a token for a request, returning how long to wait before retrying if none is left.
+    pub(crate) fn check(&self, client: ClientKey, access: Access) -> Result<(), Duration> {
+        let Some(limit) = self.limit(access) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().expect("lock poisoned");
+        if buckets.len() >= PRUNE_THRESHOLD {
+            // Full buckets are indistinguishable from new ones.
+            buckets.retain(|(_, access), bucket| {
+                self.limit(*access)
+                    .is_some_and(|limit| limit.tokens_at(bucket, now) < f64::from(limit.requests))
+            });
+        }
+
+        let bucket = buckets.entry((client, access)).or_insert(Bucket {
+            tokens: f64::from(limit.requests),
+            updated: now,
+        });
+        bucket.tokens = limit.tokens_at(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(limit.refill_interval().mul_f64(1.0 - bucket.tokens))
+        }
+    }
+}
+
+/// Builds the response for a request over the rate limit.
+pub(crate) fn too_many_requests(retry_after: Duration) -> Response {
+    // `Retry-After` has a resolution of seconds, round up to not invite early retries.
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, secs.max(1).to_string())],
+        OciErrors::single(OciError::new(types::ErrorCode::TooManyRequests)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use super::{Access, ClientKey, RateLimit, RateLimiter};
+
+    #[test]
+    fn buckets_are_separate_per_client_and_access() {
+        let limiter = RateLimiter::new(
+            Some(RateLimit::new(2, Duration::from_secs(60))),
+            Some(RateLimit::new(1, Duration::from_secs(60))),
+        );
+        let alice = ClientKey::User("alice".to_owned());
+        let anonymous = ClientKey::Ip(Ipv4Addr::LOCALHOST.into());
+
+        assert!(limiter.check(alice.clone(), Access::Read).is_ok());
+        assert!(limiter.check(alice.clone(), Access::Read).is_ok());
+        let retry_after = limiter
+            .check(alice.clone(), Access::Read)
+            .expect_err("should be limited");
+        assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+
+        assert!(limiter.check(alice.clone(), Access::Write).is_ok());
+        assert!(limiter.check(alice, Access::Write).is_err());
+
+        assert!(limiter.check(anonymous, Access::Read).is_ok());
+    }
+
+    #[test]
+    fn unlimited_access_is_never_limited() {
+        let limiter = RateLimiter::new(None, Some(RateLimit::new(1, Duration::from_secs(60))));
+        let bob = ClientKey::User("bob".to_owned());
+
+        for _ in 0..100 {
+            assert!(limiter.check(bob.clone(), Access::Read).is_ok());
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Redaction of sensitive request data in logs.
+//!
+//! Headers carrying credentials are never written to tracing output as they are. The
+//! [`RedactedHeaders`] wrapper formats a header map with the values of sensitive headers replaced
+//! by those of a [`Secret`], so they can be recorded on spans and events safely.
+
+use std::fmt;
+
+use axum::http::{header, HeaderMap, HeaderName};
+use sec::Secret;
+
+/// Headers always redacted, regardless of configuration.
+const ALWAYS_SENSITIVE: [HeaderName; 4] = [
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::PROXY_AUTHORIZATION,
+    header::SET_COOKIE,
+];
+
+/// A header map formatted with the values of sensitive headers redacted.
+pub(crate) struct RedactedHeaders<'a> {
+    /// Headers to format.
+    headers: &'a HeaderMap,
+    /// Additional headers to redact.
+    sensitive: &'a [HeaderName],
+}
+
+impl<'a> RedactedHeaders<'a> {
+    /// Wraps a header map, redacting the given headers in addition to the always sensitive ones.
+    pub(crate) fn new(headers: &'a HeaderMap, sensitive: &'a [HeaderName]) -> Self {
+        RedactedHeaders { headers, sensitive }
+    }
+
+    /// Returns whether a header is redacted.
+    fn is_sensitive(&self, name: &HeaderName) -> bool {
+        ALWAYS_SENSITIVE.contains(name) || self.sensitive.contains(name)
+    }
+}
+
+impl fmt::Debug for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.headers {
+            if self.is_sensitive(name) || value.is_sensitive() {
+                map.entry(name, &Secret::new(value));
+            } else {
+                map.entry(name, value);
+            }
+        }
+        map.finish()
+    }
+}
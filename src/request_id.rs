@@ -0,0 +1,62 @@
+//! Request IDs.
+//!
+//! Every request is tagged with an ID, taken from an incoming `X-Request-Id` header or generated
+//! if absent. The ID is recorded on the request's tracing span, echoed in the `X-Request-Id`
+//! response header and included in error responses.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header carrying the request ID.
+pub(crate) const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Maximum length of an incoming request ID, longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// ID of the request currently being handled.
+    static REQUEST_ID: String;
+}
+
+/// Returns the ID of the request currently being handled, if any.
+pub(crate) fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Middleware tagging every request with an ID.
+pub(crate) async fn tag_request(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LEN)
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // Valid as it was either a valid header already or is a UUID.
+    let header_value =
+        HeaderValue::from_str(&request_id).expect("request ID should be a valid header value");
+    request
+        .headers_mut()
+        .insert(X_REQUEST_ID, header_value.clone());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        uri = %request.uri(),
+    );
+
+    let mut response = REQUEST_ID
+        .scope(request_id, next.run(request))
+        .instrument(span)
+        .await;
+    response.headers_mut().insert(X_REQUEST_ID, header_value);
+    response
+}
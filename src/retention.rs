@@ -0,0 +1,149 @@
+//! Retention of tags.
+//!
+//! Registries receiving images from CI pipelines accumulate tags quickly. A [`RetentionPolicy`]
+//! describes which tags of a repository to keep, all others are pruned when retention is applied
+//! through `POST /admin/retention`. Manifests left without tags are removed along with them and
+//! garbage collection is run afterwards to reclaim the space of their blobs.
+
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::{
+    storage::{GarbageCollection, ImageLocation},
+    ImageDigest,
+};
+
+/// Policy deciding which tags of a repository to keep.
+///
+/// A tag is kept if it is among the [`keep_last`](Self::keep_last) most recently pushed tags, was
+/// pushed within [`keep_newer_than`](Self::keep_newer_than) or is protected. A policy with
+/// neither limit set keeps all tags.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    /// Number of most recently pushed tags to keep.
+    keep_last: Option<usize>,
+    /// Maximum age of tags to keep.
+    keep_newer_than: Option<Duration>,
+    /// Patterns of tags never pruned.
+    protected_tags: Vec<String>,
+}
+
+impl RetentionPolicy {
+    /// Creates a new policy keeping all tags.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the `count` most recently pushed tags.
+    pub fn keep_last(mut self, count: usize) -> Self {
+        self.keep_last = Some(count);
+        self
+    }
+
+    /// Keeps tags pushed within `age`.
+    pub fn keep_newer_than(mut self, age: Duration) -> Self {
+        self.keep_newer_than = Some(age);
+        self
+    }
+
+    /// Never prunes tags matching a pattern.
+    ///
+    /// Patterns may contain `*` and `?`, e.g. `latest` or `release-*`.
+    pub fn protect_tag<P: Into<String>>(mut self, pattern: P) -> Self {
+        self.protected_tags.push(pattern.into());
+        self
+    }
+
+    /// Returns the tags to prune, given all tags along with their push time, least recently pushed
+    /// first.
+    pub(crate) fn expired<'a>(
+        &self,
+        tags: &'a [(String, SystemTime)],
+        now: SystemTime,
+    ) -> Vec<&'a str> {
+        if self.keep_last.is_none() && self.keep_newer_than.is_none() {
+            return Vec::new();
+        }
+
+        let recent = tags.len().saturating_sub(self.keep_last.unwrap_or(0));
+        tags.iter()
+            .enumerate()
+            .filter(|&(index, (tag, pushed))| {
+                let kept_by_count = self.keep_last.is_some() && index >= recent;
+                let kept_by_age = self.keep_newer_than.is_some_and(|age| {
+                    now.duration_since(*pushed)
+                        .map_or(true, |elapsed| elapsed < age)
+                });
+                let protected = self
+                    .protected_tags
+                    .iter()
+                    .any(|pattern| crate::glob_matches(pattern, tag));
+                !(kept_by_count || kept_by_age || protected)
+            })
+            .map(|(_index, (tag, _pushed))| tag.as_str())
+            .collect()
+    }
+}
+
+/// Outcome of applying retention policies or sweeping deleted tags.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RetentionRun {
+    /// Whether storage was left untouched, only reporting what would be pruned.
+    pub(crate) dry_run: bool,
+    /// Tags pruned.
+    pub(crate) tags: Vec<PrunedTag>,
+    /// Manifests removed as no tag referred to them anymore.
+    pub(crate) manifests: Vec<ImageDigest>,
+    /// Garbage collection run afterwards, unless only doing a dry run.
+    pub(crate) garbage_collection: Option<GarbageCollection>,
+}
+
+/// A tag pruned by a retention policy or swept after being deleted.
+#[derive(Debug, Serialize)]
+pub(crate) struct PrunedTag {
+    /// Location of the tag.
+    pub(crate) location: ImageLocation,
+    /// The tag itself.
+    pub(crate) tag: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::RetentionPolicy;
+
+    #[test]
+    fn policies_keep_recent_and_protected_tags() {
+        let now = SystemTime::now();
+        let tags: Vec<_> = (0..5u64)
+            .map(|days| {
+                (
+                    format!("t{days}"),
+                    now - Duration::from_secs((5 - days) * 24 * 60 * 60),
+                )
+            })
+            .collect();
+
+        assert!(RetentionPolicy::new().expired(&tags, now).is_empty());
+        assert_eq!(
+            RetentionPolicy::new().keep_last(3).expired(&tags, now),
+            ["t0", "t1"]
+        );
+        assert_eq!(
+            RetentionPolicy::new()
+                .keep_newer_than(Duration::from_secs(36 * 60 * 60))
+                .expired(&tags, now),
+            ["t0", "t1", "t2", "t3"]
+        );
+        assert_eq!(
+            RetentionPolicy::new()
+                .keep_last(1)
+                .keep_newer_than(Duration::from_secs(3 * 24 * 60 * 60 + 1))
+                .protect_tag("t0")
+                .expired(&tags, now),
+            ["t1"]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Docker schema 1 manifests.
+//!
+//! Schema 1 manifests are never stored, but some legacy clients only accept them. If enabled,
+//! they are synthesized on pull from a stored image manifest and its config blob, as the unsigned
+//! `application/vnd.docker.distribution.manifest.v1+json` variant.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{manifest::Descriptor, storage::Digest, ImageDigest};
+
+/// Media type of an unsigned Docker schema 1 manifest.
+pub(crate) const DOCKER_MANIFEST_V1: &str = "application/vnd.docker.distribution.manifest.v1+json";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestV1<'a> {
+    schema_version: u32,
+    name: &'a str,
+    tag: &'a str,
+    architecture: &'a str,
+    fs_layers: Vec<FsLayer>,
+    history: Vec<History>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FsLayer {
+    blob_sum: ImageDigest,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct History {
+    v1_compatibility: String,
+}
+
+/// Synthesizes a schema 1 manifest from the layers and config blob of an image manifest.
+///
+/// Returns `None` if the image has no layers or its config is not a JSON object naming an
+/// architecture.
+pub(crate) fn synthesize(
+    name: &str,
+    tag: &str,
+    layers: &[Descriptor],
+    config: &[u8],
+) -> Option<Vec<u8>> {
+    let Ok(Value::Object(mut config)) = serde_json::from_slice::<Value>(config) else {
+        return None;
+    };
+    let architecture = config.get("architecture")?.as_str()?.to_owned();
+    if layers.is_empty() {
+        return None;
+    }
+
+    // Schema 1 lists layers top-most first; the top-most history entry carries the config.
+    config.remove("rootfs");
+    config.remove("history");
+
+    let mut fs_layers = Vec::with_capacity(layers.len());
+    let mut history = Vec::with_capacity(layers.len());
+    let mut parent: Option<String> = None;
+    for (index, layer) in layers.iter().enumerate() {
+        let blob_sum = layer.digest();
+        let id = Digest::from_contents(
+            format!("{} {blob_sum}", parent.as_deref().unwrap_or_default()).as_bytes(),
+        )
+        .to_string();
+
+        let mut v1_compatibility = if index + 1 == layers.len() {
+            config.clone()
+        } else {
+            Map::new()
+        };
+        v1_compatibility.insert("id".to_owned(), Value::String(id.clone()));
+        if let Some(parent) = parent.replace(id) {
+            v1_compatibility.insert("parent".to_owned(), Value::String(parent));
+        }
+
+        fs_layers.push(FsLayer { blob_sum });
+        history.push(History {
+            v1_compatibility: Value::Object(v1_compatibility).to_string(),
+        });
+    }
+    fs_layers.reverse();
+    history.reverse();
+
+    serde_json::to_vec_pretty(&ManifestV1 {
+        schema_version: 1,
+        name,
+        tag,
+        architecture: &architecture,
+        fs_layers,
+        history,
+    })
+    .ok()
+}
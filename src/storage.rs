@@ -5,27 +5,49 @@
 // Note: This module is in worse shape, documentation wise, than the rest. Cleaning this up is the
 //       first step towards supporting custom implementations.
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
     fmt::{self, Display},
     fs,
     io::{self, Read},
     path::{Path, PathBuf},
+    pin::Pin,
     str::FromStr,
+    task::{ready, Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{async_trait, http::StatusCode, response::IntoResponse};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use hex::FromHex;
 use serde::{Deserialize, Serialize};
-use sha2::Digest as Sha2Digest;
+use sha2::digest::generic_array::GenericArray;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite};
 use uuid::Uuid;
 
-use super::{types::ImageManifest, ImageDigest};
+use super::{
+    digest_algorithm,
+    manifest::{self, Descriptor, Manifest, ManifestError},
+    types::{self, OciError, OciErrors},
+    ImageDigest,
+};
 
 /// Length of a SHA256 hash in bytes.
 pub const SHA256_LEN: usize = 32;
 
 const BUFFER_SIZE: usize = 1024 * 1024; // 1 MiB
 
+/// Default number of directory levels blobs are sharded into.
+pub const DEFAULT_BLOB_SHARD_DEPTH: usize = 2;
+
+/// Maximum number of directory levels blobs can be sharded into, one per hex pair of a digest.
+pub const MAX_BLOB_SHARD_DEPTH: usize = SHA256_LEN;
+
+/// Upper bound of the size below which objects are stored compressed, larger thresholds are
+/// lowered to it.
+pub const MAX_COMPRESSION_THRESHOLD: u64 = 1024 * 1024;
+
 /// An SHA256 digest.
 ///
 /// The `container_registry` crate supports only `sha256` digests at this time.
@@ -40,10 +62,13 @@ impl Digest {
 
     /// Creates a digest by hashing given contents.
     pub fn from_contents(contents: &[u8]) -> Self {
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(contents);
+        let hash = digest_algorithm::SHA256.hash(contents);
 
-        Self::new(hasher.finalize().into())
+        Self::new(
+            (*hash)
+                .try_into()
+                .expect("sha256 hashes should be 32 bytes"),
+        )
     }
 }
 
@@ -53,10 +78,16 @@ impl Display for Digest {
     }
 }
 
+/// The subject of a manifest, the only part relevant to storage.
 #[derive(Debug, Deserialize)]
+struct ManifestSubject {
+    subject: Option<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)] // TODO
 struct LayerManifest {
     #[serde(rename = "camelCase")]
-    #[allow(dead_code)] // TODO
     blob_sum: String,
 }
 
@@ -136,6 +167,49 @@ impl ImageLocation {
     }
 }
 
+/// Maximum length of a tag.
+const MAX_TAG_LEN: usize = 128;
+
+/// Returns whether a tag matches `[a-zA-Z0-9_][a-zA-Z0-9._-]{0,127}`, as required by the spec.
+///
+/// Tags are used as file names, valid ones can never escape their directory.
+pub(crate) fn is_valid_tag(tag: &str) -> bool {
+    let mut chars = tag.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+
+    tag.len() <= MAX_TAG_LEN
+        && (first.is_ascii_alphanumeric() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Returns whether a repository or image name matches `[a-z0-9]+((\.|_|__|-+)[a-z0-9]+)*`, as
+/// required by the spec for each path component of a name.
+///
+/// Names are used as directory names, valid ones can never escape their directory.
+pub(crate) fn is_valid_name_component(name: &str) -> bool {
+    let is_alphanumeric = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+
+    let mut rest = name;
+    loop {
+        let alphanumeric = rest.find(|c| !is_alphanumeric(c)).unwrap_or(rest.len());
+        if alphanumeric == 0 {
+            return false;
+        }
+        rest = &rest[alphanumeric..];
+        if rest.is_empty() {
+            return true;
+        }
+
+        let separator = &rest[..rest.find(is_alphanumeric).unwrap_or(rest.len())];
+        if !matches!(separator, "." | "_" | "__") && !separator.bytes().all(|b| b == b'-') {
+            return false;
+        }
+        rest = &rest[separator.len()..];
+    }
+}
+
 /// Reference to a specific version of an image.
 #[derive(Clone, Debug)]
 pub enum Reference {
@@ -191,13 +265,21 @@ impl Reference {
             Reference::Digest(_) => None,
         }
     }
+
+    /// Returns reference as digest, if it is a digest.
+    pub fn as_digest(&self) -> Option<Digest> {
+        match self {
+            Reference::Tag(_) => None,
+            Reference::Digest(digest) => Some(*digest),
+        }
+    }
 }
 
 impl Display for Reference {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Reference::Tag(tag) => Display::fmt(tag, f),
-            Reference::Digest(digest) => Display::fmt(digest, f),
+            Reference::Digest(digest) => Display::fmt(&ImageDigest::new(*digest), f),
         }
     }
 }
@@ -211,6 +293,9 @@ pub enum Error {
     /// A content hash mismatched.
     #[error("digest did not match")]
     DigestMismatch,
+    /// The size of an upload differs from the size declared by the client.
+    #[error("upload size {actual} does not match declared size {expected}")]
+    SizeMismatch { expected: u64, actual: u64 },
     /// An IO error.
     // TODO: Not great to have a catch-all IO error, to be replaced later.
     #[error("io error")]
@@ -221,9 +306,24 @@ pub enum Error {
     /// Invalid image manifest submitted.
     #[error("invalid image manifest")]
     InvalidManifest(#[source] serde_json::Error),
+    /// Stored content no longer matches its digest.
+    #[error("stored content does not match digest {0}")]
+    Corrupted(Digest),
     /// Attempted to store a manifest under a digest instead of a tag.
     #[error("cannot store manifest under hash")]
     NotATag,
+    /// A location does not exist.
+    #[error("location {0} does not exist")]
+    LocationUnknown(ImageLocation),
+    /// A location already exists.
+    #[error("location {0} already exists")]
+    LocationExists(ImageLocation),
+    /// A tag to restore exists already.
+    #[error("tag {0} already exists")]
+    TagExists(String),
+    /// A manifest is not of a supported kind.
+    #[error("unsupported manifest")]
+    UnsupportedManifest(#[source] ManifestError),
 }
 
 impl IntoResponse for Error {
@@ -231,8 +331,30 @@ impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         match self {
             Error::UploadDoesNotExit => StatusCode::NOT_FOUND.into_response(),
-            Error::InvalidManifest(_) | Error::NotATag => StatusCode::BAD_REQUEST.into_response(),
-            Error::DigestMismatch | Error::Io(_) | Error::BackgroundTaskPanicked(_) => {
+            Error::InvalidManifest(_) | Error::UnsupportedManifest(_) | Error::NotATag => {
+                StatusCode::BAD_REQUEST.into_response()
+            }
+            Error::SizeMismatch { .. } => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::SizeInvalid)),
+            )
+                .into_response(),
+            Error::DigestMismatch => (
+                StatusCode::BAD_REQUEST,
+                OciErrors::single(OciError::new(types::ErrorCode::DigestInvalid)),
+            )
+                .into_response(),
+            Error::LocationUnknown(_) => (
+                StatusCode::NOT_FOUND,
+                OciErrors::single(OciError::new(types::ErrorCode::NameUnknown)),
+            )
+                .into_response(),
+            Error::LocationExists(_) | Error::TagExists(_) => (
+                StatusCode::CONFLICT,
+                OciErrors::single(OciError::new(types::ErrorCode::Denied)),
+            )
+                .into_response(),
+            Error::Io(_) | Error::BackgroundTaskPanicked(_) | Error::Corrupted(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
             }
         }
@@ -244,6 +366,7 @@ pub(crate) struct BlobMetadata {
     #[allow(dead_code)] // TODO
     digest: Digest,
     size: u64,
+    media_type: Option<String>,
 }
 
 impl BlobMetadata {
@@ -255,37 +378,413 @@ impl BlobMetadata {
     pub(crate) fn size(&self) -> u64 {
         self.size
     }
+
+    /// Returns the media type of the blob, as given by a manifest referencing it.
+    pub(crate) fn media_type(&self) -> Option<&str> {
+        self.media_type.as_deref()
+    }
+}
+
+/// The result of deleting a tag.
+#[derive(Debug)]
+pub(crate) struct DeletedTag {
+    /// Digest of the manifest the tag pointed to.
+    pub(crate) digest: Digest,
+    /// Whether no other tag refers to the manifest anymore.
+    pub(crate) unreferenced: bool,
+}
+
+/// A deleted tag kept for restoring.
+#[derive(Debug)]
+pub(crate) struct Tombstone {
+    /// Location of the tag.
+    pub(crate) location: ImageLocation,
+    /// The tag itself.
+    pub(crate) tag: String,
+    /// Digest of the manifest the tag pointed to.
+    pub(crate) digest: Digest,
+}
+
+/// When a stored blob or manifest was last accessed.
+#[derive(Debug)]
+pub(crate) struct AccessTime {
+    /// Digest of the blob or manifest.
+    pub(crate) digest: Digest,
+    /// Whether the object is a manifest.
+    pub(crate) manifest: bool,
+    /// Time of the last recorded access, or of storing the object if never accessed.
+    pub(crate) last_accessed: SystemTime,
+}
+
+/// Statistics about storage usage.
+#[derive(Debug, Serialize)]
+pub(crate) struct StorageStats {
+    /// Total bytes used by blobs, manifests and in-progress uploads.
+    pub(crate) total_bytes: u64,
+    /// Number of blobs stored.
+    pub(crate) blob_count: u64,
+    /// Number of manifests stored.
+    pub(crate) manifest_count: u64,
+    /// Number of uploads in progress.
+    pub(crate) upload_count: u64,
+    /// Blobs pushed to each `<repository>/<image>`.
+    pub(crate) repositories: BTreeMap<String, RepositoryStats>,
+}
+
+/// A change of the manifest a tag points to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct TagHistoryEntry {
+    /// Time of the change, as an RFC 3339 timestamp.
+    pub(crate) timestamp: String,
+    /// Manifest the tag was pointed to.
+    pub(crate) digest: ImageDigest,
+    /// User who pushed the manifest, if authenticated.
+    pub(crate) actor: Option<String>,
+}
+
+/// Outcome of a garbage collection run.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct GarbageCollection {
+    /// Whether storage was left untouched, only reporting what would be removed.
+    pub(crate) dry_run: bool,
+    /// Blobs not referenced by any manifest.
+    pub(crate) blobs: Vec<ImageDigest>,
+    /// Total size of these blobs.
+    pub(crate) bytes: u64,
+}
+
+/// Outcome of verifying all stored blobs against their digests.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct BlobVerification {
+    /// Number of blobs hashed.
+    pub(crate) scanned: u64,
+    /// Total size of these blobs.
+    pub(crate) bytes: u64,
+    /// Blobs whose contents no longer match their digest.
+    pub(crate) corrupted: Vec<ImageDigest>,
+}
+
+/// An upload in progress.
+#[derive(Debug, Serialize)]
+pub(crate) struct UploadInfo {
+    /// Identifier of the upload.
+    pub(crate) id: Uuid,
+    /// Location the upload was started for, if recorded.
+    pub(crate) location: Option<ImageLocation>,
+    /// Number of bytes received so far.
+    pub(crate) offset: u64,
+    /// Seconds since the upload was last active.
+    pub(crate) age: u64,
+}
+
+/// Usage of a single repository.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RepositoryStats {
+    /// Number of blobs pushed to the repository.
+    pub(crate) blob_count: u64,
+    /// Total size of these blobs. Blobs shared between repositories count towards each.
+    pub(crate) blob_bytes: u64,
 }
 
+/// A reader of a stored blob.
+///
+/// Blobs are seekable, so ranges can be served without reading everything preceding them.
+pub(crate) trait BlobReader: AsyncRead + AsyncSeek + Send + Unpin {}
+
+impl<T> BlobReader for T where T: AsyncRead + AsyncSeek + Send + Unpin {}
+
 #[async_trait]
 pub(crate) trait RegistryStorage: Send + Sync {
-    async fn begin_new_upload(&self) -> Result<Uuid, Error>;
+    /// Checks whether the storage backend is reachable and writable.
+    async fn health_check(&self) -> Result<(), Error>;
 
-    async fn get_blob_reader(
+    /// Calculates current storage usage.
+    async fn storage_stats(&self) -> Result<StorageStats, Error>;
+
+    /// Starts a new upload of a blob to be pushed to `location`.
+    async fn begin_new_upload(&self, location: &ImageLocation) -> Result<Uuid, Error>;
+
+    /// Starts a new upload like [`Self::begin_new_upload`], reserving `size` bytes against the
+    /// quota of `owner`.
+    ///
+    /// The reservation lasts until the upload is finalized or cancelled, from then on only the
+    /// actual size of the stored blob counts.
+    async fn begin_reserved_upload(
         &self,
-        digest: Digest,
-    ) -> Result<Option<Box<dyn AsyncRead + Send + Unpin>>, Error>;
+        location: &ImageLocation,
+        owner: &str,
+        size: u64,
+    ) -> Result<Uuid, Error>;
+
+    /// Returns the total size reserved by unfinished uploads of `owner`, except `upload`.
+    async fn reserved_quota(&self, owner: &str, except: Option<Uuid>) -> Result<u64, Error>;
+
+    /// Returns the location an upload was started for.
+    ///
+    /// Returns `None` for uploads started without recording their location.
+    async fn get_upload_location(&self, upload: Uuid) -> Result<Option<ImageLocation>, Error>;
+
+    async fn get_blob_reader(&self, digest: Digest) -> Result<Option<Box<dyn BlobReader>>, Error>;
 
     async fn get_blob_metadata(&self, digest: Digest) -> Result<Option<BlobMetadata>, Error>;
 
+    /// Moves a blob out of the way, e.g. because it no longer matches its digest.
+    ///
+    /// Quarantined blobs are kept for inspection but no longer served, so the blob can be pushed
+    /// again. Returns `false` if the blob is not stored.
+    async fn quarantine_blob(&self, digest: Digest) -> Result<bool, Error>;
+
+    /// Returns whether a blob is stored.
+    ///
+    /// Cheaper than [`Self::get_blob_metadata`], for when neither size nor media type is needed.
+    async fn blob_exists(&self, digest: Digest) -> Result<bool, Error>;
+
+    /// Records the media type of a blob, as given by a manifest referencing it.
+    ///
+    /// Does nothing if the blob is not stored.
+    async fn record_blob_media_type(&self, digest: Digest, media_type: &str) -> Result<(), Error>;
+
     async fn get_upload_writer(
         &self,
         start_at: u64,
         upload: Uuid,
     ) -> Result<Box<dyn AsyncWrite + Send + Unpin>, Error>;
 
+    /// Returns the number of bytes received for an upload so far.
+    async fn get_upload_size(&self, upload: Uuid) -> Result<u64, Error>;
+
+    /// Records the total size of an upload declared by the client, checked when finalizing it.
+    async fn set_upload_expected_size(&self, upload: Uuid, size: u64) -> Result<(), Error>;
+
+    /// Records activity on an upload, keeping [`Self::prune_uploads`] from cancelling it.
+    ///
+    /// Receiving data counts as activity on its own, this is for requests not writing any.
+    async fn touch_upload(&self, upload: Uuid) -> Result<(), Error>;
+
+    /// Aborts an upload, removing all data received so far.
+    async fn cancel_upload(&self, upload: Uuid) -> Result<(), Error>;
+
+    /// Verifies and stores an upload as a blob, attributing it to the location of the upload.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if a declared size was recorded and differs from the
+    /// size received, leaving the upload in place. If the blob is already stored, the upload is
+    /// discarded instead of replacing it.
     async fn finalize_upload(&self, upload: Uuid, hash: Digest) -> Result<(), Error>;
 
+    /// Attributes an already stored blob to a location, as if it had been uploaded there again.
+    ///
+    /// Returns `false` if the blob is not stored.
+    async fn reuse_blob(&self, location: &ImageLocation, digest: Digest) -> Result<bool, Error>;
+
+    /// Returns the size a blob had when pushed to a location.
+    ///
+    /// Returns `None` if the blob was never pushed there, or its size was not recorded, as for
+    /// blobs pushed before sizes were.
+    async fn repository_blob_size(
+        &self,
+        location: &ImageLocation,
+        digest: Digest,
+    ) -> Result<Option<u64>, Error>;
+
+    /// Returns the digest of the manifest a tag points to, or `None` if the tag does not exist.
+    async fn resolve_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Option<Digest>, Error>;
+
     async fn get_manifest(
         &self,
         manifest_reference: &ManifestReference,
     ) -> Result<Option<Vec<u8>>, Error>;
 
+    /// Retrieves a manifest along with its digest.
+    ///
+    /// The digest is the one recorded when the manifest was stored, the manifest is not hashed
+    /// again.
+    async fn get_manifest_with_digest(
+        &self,
+        manifest_reference: &ManifestReference,
+    ) -> Result<Option<(Vec<u8>, Digest)>, Error>;
+
+    /// Returns the digests of everything a manifest references, except its subject.
+    ///
+    /// Includes the config and layers of image manifests except foreign layers, and the child
+    /// manifests of indexes. Children stored locally are followed, adding what they reference in
+    /// turn. Each digest is reported once, in the order encountered.
+    async fn referenced_blobs(
+        &self,
+        manifest: &[u8],
+        media_type: Option<&str>,
+    ) -> Result<Vec<Digest>, Error>;
+
+    /// Stores a manifest, tagging it if referenced by tag.
+    ///
+    /// Updates must be atomic: Concurrent readers of the manifest or tag observe either the
+    /// previous or the new manifest, never a partially written one.
     async fn put_manifest(
         &self,
         manifest_reference: &ManifestReference,
         manifest: &[u8],
     ) -> Result<Digest, Error>;
+
+    /// Records the media type a manifest was pushed as.
+    async fn record_manifest_media_type(
+        &self,
+        digest: Digest,
+        media_type: &str,
+    ) -> Result<(), Error>;
+
+    /// Returns the media type a manifest was pushed as, if recorded.
+    async fn manifest_media_type(&self, digest: Digest) -> Result<Option<String>, Error>;
+
+    /// Records `owner` as having stored the blob or manifest with the given digest.
+    async fn record_owner(&self, owner: &str, digest: Digest) -> Result<(), Error>;
+
+    /// Returns whether `owner` has stored the blob or manifest with the given digest.
+    async fn is_owner(&self, owner: &str, digest: Digest) -> Result<bool, Error>;
+
+    /// Returns the total size of all blobs and manifests stored by `owner`.
+    async fn owner_usage(&self, owner: &str) -> Result<u64, Error>;
+
+    /// Returns the digests of all manifests in a location whose `subject` is the given digest.
+    async fn get_referrers(
+        &self,
+        location: &ImageLocation,
+        subject: Digest,
+    ) -> Result<Vec<Digest>, Error>;
+
+    /// Returns the digests of all stored manifests whose `subject` is the given digest, whichever
+    /// location they were pushed to.
+    async fn all_referrers(&self, subject: Digest) -> Result<Vec<Digest>, Error>;
+
+    /// Removes a tag, leaving the manifest it points to in place.
+    ///
+    /// Returns `None` if the tag does not exist.
+    async fn delete_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Option<DeletedTag>, Error>;
+
+    /// Removes a tag like [`Self::delete_tag`], keeping a tombstone to restore it from.
+    ///
+    /// Manifests pointed to by tombstones are not removed by [`Self::delete_manifest`].
+    async fn soft_delete_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Option<DeletedTag>, Error>;
+
+    /// Restores a tag deleted by [`Self::soft_delete_tag`] at most `window` ago.
+    ///
+    /// Returns the digest of the manifest the tag points to again, or `None` if there is no
+    /// recent enough tombstone. Fails if the tag has been pushed again in the meantime.
+    async fn undelete_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+        window: Duration,
+    ) -> Result<Option<Digest>, Error>;
+
+    /// Removes all tombstones older than `window`, reporting them.
+    async fn sweep_tombstones(&self, window: Duration) -> Result<Vec<Tombstone>, Error>;
+
+    /// Lists all tags of a location, sorted and without duplicates.
+    ///
+    /// Returns `None` if nothing was ever pushed to the location. Tags added or removed while
+    /// listing may or may not be included, but listing never fails because of them.
+    async fn list_tags(&self, location: &ImageLocation) -> Result<Option<Vec<String>>, Error>;
+
+    /// Lists all tags of a location along with the time they were last pushed, least recently
+    /// pushed first.
+    async fn tags_by_age(
+        &self,
+        location: &ImageLocation,
+    ) -> Result<Vec<(String, SystemTime)>, Error>;
+
+    /// Lists all locations holding tags, sorted.
+    async fn tagged_locations(&self) -> Result<Vec<ImageLocation>, Error>;
+
+    /// Returns whether a tag, a tombstone or another manifest refers to a manifest.
+    ///
+    /// Manifests referring to it only through their `subject` are not considered.
+    async fn manifest_in_use(&self, digest: Digest) -> Result<bool, Error>;
+
+    /// Removes a manifest no tag or other manifest refers to.
+    ///
+    /// Returns whether the manifest was removed, manifests still referred to are left in place.
+    /// Blobs referenced by the manifest are left for garbage collection.
+    async fn delete_manifest(
+        &self,
+        location: &ImageLocation,
+        digest: Digest,
+    ) -> Result<bool, Error>;
+
+    /// Appends an entry to the history of a tag, keeping only the latest `retention` entries.
+    ///
+    /// Nothing is recorded if the tag already pointed to the same manifest.
+    async fn append_tag_history(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+        entry: TagHistoryEntry,
+        retention: usize,
+    ) -> Result<(), Error>;
+
+    /// Returns the history of a tag, oldest entry first.
+    async fn tag_history(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Vec<TagHistoryEntry>, Error>;
+
+    /// Records an access of the blob or manifest with the given digest.
+    ///
+    /// To limit writes, the recorded time is only updated once it is older than `resolution`.
+    async fn record_access(&self, digest: Digest, resolution: Duration) -> Result<(), Error>;
+
+    /// Returns up to `limit` stored blobs and manifests, least recently accessed first.
+    ///
+    /// Objects never accessed count as accessed when they were stored.
+    async fn least_recently_used(&self, limit: usize) -> Result<Vec<AccessTime>, Error>;
+
+    /// Removes blobs not referenced by any stored manifest.
+    ///
+    /// Blobs stored more recently than `min_age` are kept, as the manifest referencing them may
+    /// not have been pushed yet. Finalizing an upload of a blob already stored counts as storing
+    /// it again. With `dry_run`, storage is left untouched and only the blobs that
+    /// would be removed are reported.
+    async fn garbage_collect(
+        &self,
+        min_age: Duration,
+        dry_run: bool,
+    ) -> Result<GarbageCollection, Error>;
+
+    /// Lists all uploads in progress, ordered by identifier.
+    async fn list_uploads(&self) -> Result<Vec<UploadInfo>, Error>;
+
+    /// Cancels all uploads that have not been active for at least `ttl`, reporting them.
+    ///
+    /// An upload is active when receiving data or touched by [`Self::touch_upload`].
+    async fn prune_uploads(&self, ttl: Duration) -> Result<Vec<UploadInfo>, Error>;
+
+    /// Hashes every stored blob, reporting those not matching their digest.
+    ///
+    /// Blobs are streamed, up to `concurrency` at a time, and left in place even if corrupted.
+    async fn verify_blobs(&self, concurrency: usize) -> Result<BlobVerification, Error>;
+
+    /// Moves all tags, their history and referrers from one location to another.
+    ///
+    /// Manifests and blobs are shared between locations and stay in place. Fails if the
+    /// destination already exists, unless `overwrite` is set, in which case it is replaced. A move
+    /// interrupted by a crash is completed when the storage is opened again.
+    async fn move_repository(
+        &self,
+        from: &ImageLocation,
+        to: &ImageLocation,
+        overwrite: bool,
+    ) -> Result<(), Error>;
 }
 
 /// A filesystem backend error.
@@ -305,19 +804,130 @@ pub enum FilesystemStorageError {
         #[source]
         err: io::Error,
     },
+    /// The blob shard depth exceeds [`MAX_BLOB_SHARD_DEPTH`].
+    #[error("blob shard depth {0} exceeds maximum of {MAX_BLOB_SHARD_DEPTH}")]
+    InvalidShardDepth(usize),
+    /// Failed to move existing blobs into the configured layout.
+    #[error("could not migrate blobs to shard depth {depth}")]
+    FailedToMigrateBlobs {
+        depth: usize,
+        #[source]
+        err: io::Error,
+    },
+    /// Failed to complete a repository move interrupted earlier.
+    #[error("could not complete interrupted repository move {}", path.display())]
+    FailedToResumeMove {
+        path: PathBuf,
+        #[source]
+        err: io::Error,
+    },
+    /// Objects are, or are configured to be, stored compressed, but the
+    /// `small-object-compression` feature is disabled.
+    #[error("storing objects compressed requires the `small-object-compression` feature")]
+    CompressionUnsupported,
+}
+
+/// Sets the modification time of a file to now.
+async fn touch(path: &Path) -> Result<(), Error> {
+    let file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .await
+        .map_err(Error::Io)?;
+    file.into_std()
+        .await
+        .set_modified(SystemTime::now())
+        .map_err(Error::Io)
+}
+
+/// Configuration of the storage backend.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum StorageConfig {
+    /// Stores everything in a directory on the local filesystem.
+    Filesystem {
+        /// Root directory of the storage, which must exist.
+        path: PathBuf,
+        /// Number of directory levels blobs are sharded into, by pairs of hex digits of their
+        /// digest. Existing blobs are moved once the depth changes.
+        #[serde(default = "default_blob_shard_depth")]
+        shard_depth: usize,
+        /// Whether to flush blobs and manifests to disk before acknowledging their upload, so a
+        /// crash or power loss cannot lose them. Costs throughput, off by default.
+        #[serde(default)]
+        durable_writes: bool,
+        /// Size in bytes below which manifests and blobs are stored zstd-compressed, at most
+        /// [`MAX_COMPRESSION_THRESHOLD`]. Objects are served and hashed uncompressed regardless.
+        /// Off by default, requires the `small-object-compression` feature.
+        #[serde(default)]
+        compress_small_objects: Option<u64>,
+    },
+}
+
+fn default_blob_shard_depth() -> usize {
+    DEFAULT_BLOB_SHARD_DEPTH
+}
+
+/// Constructs the storage backend described by a configuration.
+pub(crate) fn build_storage(
+    config: &StorageConfig,
+) -> Result<Box<dyn RegistryStorage>, FilesystemStorageError> {
+    match config {
+        StorageConfig::Filesystem {
+            path,
+            shard_depth,
+            durable_writes,
+            compress_small_objects,
+        } => {
+            if compress_small_objects.is_some() && !cfg!(feature = "small-object-compression") {
+                return Err(FilesystemStorageError::CompressionUnsupported);
+            }
+
+            Ok(Box::new(
+                FilesystemStorage::new(path, *shard_depth)?
+                    .with_durable_writes(*durable_writes)
+                    .with_small_object_compression(*compress_small_objects),
+            ))
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct FilesystemStorage {
     uploads: PathBuf,
     blobs: PathBuf,
+    blob_shard_depth: usize,
     manifests: PathBuf,
     tags: PathBuf,
+    referrers: PathBuf,
+    owners: PathBuf,
+    media_types: PathBuf,
+    manifest_media_types: PathBuf,
+    compressed_blobs: PathBuf,
+    compressed_manifests: PathBuf,
+    repositories: PathBuf,
+    tag_history: PathBuf,
+    moves: PathBuf,
+    tombstones: PathBuf,
+    access_times: PathBuf,
+    quarantine: PathBuf,
     rel_manifest_to_blobs: PathBuf,
+    durable_writes: bool,
+    compression_threshold: Option<u64>,
+    /// Whether any object may be stored compressed, if not their markers are not even looked for.
+    compressed_objects: bool,
 }
 
 impl FilesystemStorage {
-    pub(crate) fn new<P: AsRef<Path>>(root: P) -> Result<Self, FilesystemStorageError> {
+    pub(crate) fn new<P: AsRef<Path>>(
+        root: P,
+        blob_shard_depth: usize,
+    ) -> Result<Self, FilesystemStorageError> {
+        if blob_shard_depth > MAX_BLOB_SHARD_DEPTH {
+            return Err(FilesystemStorageError::InvalidShardDepth(blob_shard_depth));
+        }
+
         let raw_root = root.as_ref();
         let root = raw_root.canonicalize().map_err(|err| {
             FilesystemStorageError::CouldNotCanonicalizeRoot {
@@ -330,9 +940,38 @@ impl FilesystemStorage {
         let blobs = root.join("blobs");
         let manifests = root.join("manifests");
         let tags = root.join("tags");
+        let referrers = root.join("referrers");
+        let owners = root.join("owners");
+        let media_types = root.join("media_types");
+        let manifest_media_types = root.join("manifest_media_types");
+        let compressed_blobs = root.join("compressed_blobs");
+        let compressed_manifests = root.join("compressed_manifests");
+        let repositories = root.join("repositories");
+        let tag_history = root.join("tag_history");
+        let moves = root.join("moves");
+        let tombstones = root.join("tombstones");
+        let access_times = root.join("access_times");
+        let quarantine = root.join("quarantine");
         let rel_manifest_to_blobs = PathBuf::from("../../../manifests");
 
-        for dir in [&uploads, &blobs, &manifests, &tags] {
+        for dir in [
+            &uploads,
+            &blobs,
+            &manifests,
+            &tags,
+            &referrers,
+            &owners,
+            &media_types,
+            &manifest_media_types,
+            &compressed_blobs,
+            &compressed_manifests,
+            &repositories,
+            &tag_history,
+            &moves,
+            &tombstones,
+            &access_times,
+            &quarantine,
+        ] {
             if !dir.exists() {
                 fs::create_dir(dir).map_err(|err| FilesystemStorageError::FailedToCreateDir {
                     path: dir.to_owned(),
@@ -341,209 +980,3044 @@ impl FilesystemStorage {
             }
         }
 
-        Ok(FilesystemStorage {
+        migrate_blobs(&blobs, blob_shard_depth).map_err(|err| {
+            FilesystemStorageError::FailedToMigrateBlobs {
+                depth: blob_shard_depth,
+                err,
+            }
+        })?;
+
+        let mut storage = FilesystemStorage {
             uploads,
             blobs,
+            blob_shard_depth,
             manifests,
             tags,
+            referrers,
+            owners,
+            media_types,
+            manifest_media_types,
+            compressed_blobs,
+            compressed_manifests,
+            repositories,
+            tag_history,
+            moves,
+            tombstones,
+            access_times,
+            quarantine,
             rel_manifest_to_blobs,
-        })
-    }
-    fn blob_path(&self, digest: Digest) -> PathBuf {
-        self.blobs.join(format!("{}", digest))
-    }
-    fn upload_path(&self, upload: Uuid) -> PathBuf {
-        self.uploads.join(format!("{}.partial", upload))
-    }
+            durable_writes: false,
+            compression_threshold: None,
+            compressed_objects: false,
+        };
 
-    fn manifest_path(&self, digest: Digest) -> PathBuf {
-        self.manifests.join(format!("{}", digest))
-    }
+        resume_moves(&storage.moves, &storage.location_dirs())?;
 
-    fn blob_rel_path(&self, digest: Digest) -> PathBuf {
-        self.rel_manifest_to_blobs.join(format!("{}", digest))
-    }
+        for dir in [&storage.compressed_blobs, &storage.compressed_manifests] {
+            let mut markers =
+                fs::read_dir(dir).map_err(|err| FilesystemStorageError::FailedToCreateDir {
+                    path: dir.to_owned(),
+                    err,
+                })?;
+            storage.compressed_objects |= markers.next().is_some();
+        }
+        if storage.compressed_objects && !cfg!(feature = "small-object-compression") {
+            return Err(FilesystemStorageError::CompressionUnsupported);
+        }
 
-    fn tag_path(&self, location: &ImageLocation, tag: &str) -> PathBuf {
-        self.tags
-            .join(location.repository())
-            .join(location.image())
-            .join(tag)
+        Ok(storage)
     }
 
-    fn temp_tag_path(&self) -> PathBuf {
-        self.tags.join(Uuid::new_v4().to_string())
+    /// Enables flushing blobs and manifests to disk before reporting them as stored.
+    pub(crate) fn with_durable_writes(mut self, durable_writes: bool) -> Self {
+        self.durable_writes = durable_writes;
+        self
     }
-}
 
-#[async_trait]
-impl RegistryStorage for FilesystemStorage {
-    async fn begin_new_upload(&self) -> Result<Uuid, Error> {
-        let upload = Uuid::new_v4();
-        let out_path = self.upload_path(upload);
+    /// Enables storing manifests and blobs smaller than `threshold` bytes compressed.
+    ///
+    /// Requires the `small-object-compression` feature, see [`build_storage`].
+    pub(crate) fn with_small_object_compression(mut self, threshold: Option<u64>) -> Self {
+        self.compression_threshold =
+            threshold.map(|threshold| threshold.min(MAX_COMPRESSION_THRESHOLD));
+        self.compressed_objects |= self.compression_threshold.is_some();
+        self
+    }
 
-        // Write zero-sized file.
-        let _file = tokio::fs::File::create(out_path).await.map_err(Error::Io)?;
+    /// Returns the form to store an object in, compressed if enabled and worthwhile.
+    fn compress_object<'a>(&self, contents: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        match self.compression_threshold {
+            Some(threshold) if (contents.len() as u64) < threshold => {
+                let compressed = compress_object(contents).map_err(Error::Io)?;
+                if compressed.len() < contents.len() {
+                    return Ok(Cow::Owned(compressed));
+                }
+                Ok(Cow::Borrowed(contents))
+            }
+            _ => Ok(Cow::Borrowed(contents)),
+        }
+    }
 
-        Ok(upload)
+    /// Directory of the markers of blobs stored compressed, `None` if there are none.
+    fn compressed_blob_dir(&self) -> Option<&Path> {
+        self.compressed_objects
+            .then_some(self.compressed_blobs.as_path())
     }
 
-    async fn get_blob_metadata(&self, digest: Digest) -> Result<Option<BlobMetadata>, Error> {
-        let blob_path = self.blob_path(digest);
+    /// Directory of the markers of manifests stored compressed, `None` if there are none.
+    fn compressed_manifest_dir(&self) -> Option<&Path> {
+        self.compressed_objects
+            .then_some(self.compressed_manifests.as_path())
+    }
 
-        if !blob_path.exists() {
+    /// Returns the original size of a blob stored compressed, `None` if it is stored as is.
+    async fn compressed_blob_size(&self, digest: Digest) -> Result<Option<u64>, Error> {
+        let Some(dir) = self.compressed_blob_dir() else {
             return Ok(None);
+        };
+        match tokio::fs::read_to_string(dir.join(format!("{}", digest))).await {
+            Ok(size) => Ok(size.trim().parse().ok()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
         }
+    }
 
-        let metadata = tokio::fs::metadata(blob_path).await.map_err(Error::Io)?;
+    /// Returns the original contents of a blob stored compressed, `None` if it is stored as is.
+    async fn read_compressed_blob(&self, digest: Digest) -> Result<Option<Vec<u8>>, Error> {
+        if self.compressed_blob_size(digest).await?.is_none() {
+            return Ok(None);
+        }
 
-        Ok(Some(BlobMetadata {
-            digest,
-            size: metadata.len(),
-        }))
+        match tokio::fs::read(self.blob_path(digest)).await {
+            Ok(stored) => Ok(decompress_object(&stored)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
     }
 
-    async fn get_blob_reader(
+    /// Records whether a manifest is stored compressed, see [`FilesystemStorage::compress_object`].
+    async fn mark_compressed_manifest(
         &self,
         digest: Digest,
-    ) -> Result<Option<Box<dyn AsyncRead + Send + Unpin>>, Error> {
-        let blob_path = self.blob_path(digest);
-
-        if !blob_path.exists() {
-            return Ok(None);
+        original_size: Option<usize>,
+    ) -> Result<(), Error> {
+        let Some(dir) = self.compressed_manifest_dir() else {
+            return Ok(());
+        };
+        let marker = dir.join(format!("{}", digest));
+        match original_size {
+            Some(size) => tokio::fs::write(marker, size.to_string())
+                .await
+                .map_err(Error::Io),
+            None => remove_if_exists(&marker).await,
         }
+    }
 
-        let reader = tokio::fs::File::open(blob_path).await.map_err(Error::Io)?;
+    /// Flushes a file renamed into place and the directory entry pointing to it to disk, if
+    /// durable writes are enabled. Symlinks are not followed, only their entry is flushed.
+    async fn persist(&self, path: &Path) -> Result<(), Error> {
+        if !self.durable_writes {
+            return Ok(());
+        }
 
-        Ok(Some(Box::new(reader)))
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            if !path.is_symlink() {
+                fs::File::open(&path)?.sync_all()?;
+            }
+            fs::File::open(path.parent().expect("should have parent"))?.sync_all()
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+        .map_err(Error::Io)
     }
 
-    async fn get_upload_writer(
-        &self,
-        start_at: u64,
-        upload: Uuid,
-    ) -> Result<Box<dyn AsyncWrite + Send + Unpin>, Error> {
-        let location = self.upload_path(upload);
+    fn blob_path(&self, digest: Digest) -> PathBuf {
+        sharded_blob_path(&self.blobs, digest, self.blob_shard_depth)
+    }
+    fn upload_path(&self, upload: Uuid) -> PathBuf {
+        self.uploads.join(format!("{}.partial", upload))
+    }
 
-        if !location.exists() {
-            return Err(Error::UploadDoesNotExit);
-        }
+    fn upload_hasher_path(&self, upload: Uuid) -> PathBuf {
+        self.uploads.join(format!("{}.sha256", upload))
+    }
 
-        let mut file = tokio::fs::OpenOptions::new()
-            .append(true)
-            .truncate(false)
-            .open(location)
-            .await
-            .map_err(Error::Io)?;
+    fn upload_location_path(&self, upload: Uuid) -> PathBuf {
+        self.uploads.join(format!("{}.location", upload))
+    }
 
-        file.seek(io::SeekFrom::Start(start_at))
-            .await
-            .map_err(Error::Io)?;
+    fn upload_expected_size_path(&self, upload: Uuid) -> PathBuf {
+        self.uploads.join(format!("{}.size", upload))
+    }
 
-        Ok(Box::new(file))
+    fn upload_reservation_path(&self, upload: Uuid) -> PathBuf {
+        self.uploads.join(format!("{}.reservation", upload))
     }
 
-    async fn finalize_upload(&self, upload: Uuid, digest: Digest) -> Result<(), Error> {
-        // We are to validate the uploaded partial, then move it into the proper store.
-        // TODO: Lock in place so that the hash cannot be corrupted/attacked.
+    /// Path of the marker file whose modification time is the last activity on an upload.
+    fn upload_activity_path(&self, upload: Uuid) -> PathBuf {
+        self.uploads.join(format!("{}.activity", upload))
+    }
 
-        let upload_path = self.upload_path(upload);
+    /// Path of the marker recording a blob as pushed to a location.
+    fn repository_blob_path(&self, location: &ImageLocation, digest: Digest) -> PathBuf {
+        self.repositories
+            .join(location.repository())
+            .join(location.image())
+            .join(format!("{}", digest))
+    }
 
-        if !upload_path.exists() {
-            return Err(Error::UploadDoesNotExit);
+    /// Records a blob of `size` bytes as pushed to a location.
+    async fn mark_repository_blob(
+        &self,
+        location: &ImageLocation,
+        digest: Digest,
+        size: u64,
+    ) -> Result<(), Error> {
+        let marker = self.repository_blob_path(location, digest);
+        if let Some(parent) = marker.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(Error::Io)?;
         }
+        tokio::fs::write(marker, size.to_string())
+            .await
+            .map_err(Error::Io)
+    }
 
-        // We offload hashing to a blocking thread.
-        let actual = {
-            let upload_path = upload_path.clone();
-            tokio::task::spawn_blocking::<_, Result<Digest, Error>>(move || {
-                let mut src = fs::File::open(upload_path).map_err(Error::Io)?;
-
-                // Uses `vec!` instead of `Box`, as initializing the latter blows the stack:
-                let mut buf = vec![0; BUFFER_SIZE];
-                let mut hasher = sha2::Sha256::new();
-
-                loop {
-                    let read = src.read(buf.as_mut()).map_err(Error::Io)?;
-                    if read == 0 {
-                        break;
-                    }
-                    hasher.update(&buf[..read]);
-                }
+    /// Removes the location sidecar record of an upload, if any.
+    async fn remove_upload_location(&self, upload: Uuid) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.upload_location_path(upload)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
 
-                let actual = hasher.finalize();
-                Ok(Digest::new(actual.into()))
-            })
+    /// Returns the declared size of an upload, if any.
+    async fn get_upload_expected_size(&self, upload: Uuid) -> Result<Option<u64>, Error> {
+        match tokio::fs::read_to_string(self.upload_expected_size_path(upload)).await {
+            Ok(size) => size.trim().parse().map(Some).map_err(|_| {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid declared upload size",
+                ))
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::Io(err)),
         }
-        .await
-        .map_err(Error::BackgroundTaskPanicked)??;
+    }
 
-        if actual != digest {
-            return Err(Error::DigestMismatch);
+    /// Removes the declared size sidecar record of an upload, if any.
+    async fn remove_upload_expected_size(&self, upload: Uuid) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.upload_expected_size_path(upload)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
         }
+    }
 
-        // The uploaded file matches, we can rename it now.
-        let dest = self.blob_path(digest);
-        tokio::fs::rename(upload_path, dest)
-            .await
-            .map_err(Error::Io)?;
+    /// Removes the quota reservation of an upload, if any.
+    async fn remove_upload_reservation(&self, upload: Uuid) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.upload_reservation_path(upload)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
 
-        // All good.
-        Ok(())
+    /// Removes the activity marker of an upload, if any.
+    async fn remove_upload_activity(&self, upload: Uuid) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.upload_activity_path(upload)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
+        }
     }
 
-    async fn get_manifest(
-        &self,
-        manifest_reference: &ManifestReference,
-    ) -> Result<Option<Vec<u8>>, Error> {
-        let manifest_path = match manifest_reference.reference() {
-            Reference::Tag(ref tag) => self.tag_path(manifest_reference.location(), tag),
-            Reference::Digest(digest) => self.manifest_path(*digest),
+    /// Restores the hasher state of an upload that has received `size` bytes so far.
+    ///
+    /// If the sidecar record is missing or out of date, e.g. after a crash during a write, the
+    /// partial upload is hashed from scratch instead.
+    async fn load_upload_hasher(&self, upload: Uuid, size: u64) -> Result<UploadHasher, Error> {
+        match tokio::fs::read(self.upload_hasher_path(upload)).await {
+            Ok(raw) => {
+                if let Some(hasher) = UploadHasher::from_bytes(&raw) {
+                    if hasher.length == size {
+                        return Ok(hasher);
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(Error::Io(err)),
+        }
+
+        // We offload hashing to a blocking thread.
+        let upload_path = self.upload_path(upload);
+        tokio::task::spawn_blocking::<_, Result<UploadHasher, Error>>(move || {
+            let mut src = fs::File::open(upload_path).map_err(Error::Io)?;
+
+            // Uses `vec!` instead of `Box`, as initializing the latter blows the stack:
+            let mut buf = vec![0; BUFFER_SIZE];
+            let mut hasher = UploadHasher::new();
+
+            loop {
+                let read = src.read(buf.as_mut()).map_err(Error::Io)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buf[..read]);
+            }
+
+            Ok(hasher)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    /// Removes the hasher sidecar record of an upload, if any.
+    async fn remove_upload_hasher(&self, upload: Uuid) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.upload_hasher_path(upload)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    fn media_type_path(&self, digest: Digest) -> PathBuf {
+        self.media_types.join(format!("{}", digest))
+    }
+
+    /// Path of the media type a manifest was pushed as, kept apart from those of blobs.
+    fn manifest_media_type_path(&self, digest: Digest) -> PathBuf {
+        self.manifest_media_types.join(format!("{}", digest))
+    }
+
+    /// Path of the marker file whose modification time is the last access of an object.
+    fn access_time_path(&self, digest: Digest) -> PathBuf {
+        self.access_times.join(format!("{}", digest))
+    }
+
+    fn manifest_path(&self, digest: Digest) -> PathBuf {
+        self.manifests.join(format!("{}", digest))
+    }
+
+    fn blob_rel_path(&self, digest: Digest) -> PathBuf {
+        self.rel_manifest_to_blobs.join(format!("{}", digest))
+    }
+
+    fn tag_path(&self, location: &ImageLocation, tag: &str) -> PathBuf {
+        self.tags
+            .join(location.repository())
+            .join(location.image())
+            .join(tag)
+    }
+
+    fn tag_history_path(&self, location: &ImageLocation, tag: &str) -> PathBuf {
+        self.tag_history
+            .join(location.repository())
+            .join(location.image())
+            .join(tag)
+    }
+
+    fn tombstone_path(&self, location: &ImageLocation, tag: &str) -> PathBuf {
+        self.tombstones
+            .join(location.repository())
+            .join(location.image())
+            .join(tag)
+    }
+
+    fn referrers_path(&self, location: &ImageLocation, subject: Digest) -> PathBuf {
+        self.referrers
+            .join(location.repository())
+            .join(location.image())
+            .join(format!("{}", subject))
+    }
+
+    fn owner_path(&self, owner: &str) -> PathBuf {
+        // Owners are hex encoded, as usernames are not safe to use as path components.
+        self.owners.join(hex::encode(owner))
+    }
+
+    fn temp_manifest_path(&self) -> PathBuf {
+        self.uploads.join(format!("{}.manifest", Uuid::new_v4()))
+    }
+
+    fn temp_tag_path(&self) -> PathBuf {
+        self.tags.join(Uuid::new_v4().to_string())
+    }
+
+    /// Directories holding state per location, as `<dir>/<repository>/<image>`.
+    fn location_dirs(&self) -> [PathBuf; 5] {
+        [
+            &self.repositories,
+            &self.tag_history,
+            &self.referrers,
+            &self.tags,
+            &self.tombstones,
+        ]
+        .map(Clone::clone)
+    }
+}
+
+#[async_trait]
+impl RegistryStorage for FilesystemStorage {
+    async fn health_check(&self) -> Result<(), Error> {
+        // Writing, reading back and removing a probe file ensures the storage is actually usable.
+        let probe = self.uploads.join(format!("{}.probe", Uuid::new_v4()));
+        let contents = probe.to_string_lossy().into_owned().into_bytes();
+        tokio::fs::write(&probe, &contents)
+            .await
+            .map_err(Error::Io)?;
+        let read_back = tokio::fs::read(&probe).await.map_err(Error::Io);
+        tokio::fs::remove_file(&probe).await.map_err(Error::Io)?;
+
+        if read_back? != contents {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "probe file read back differs from contents written",
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats, Error> {
+        let dirs = [
+            self.blobs.clone(),
+            self.manifests.clone(),
+            self.uploads.clone(),
+            self.repositories.clone(),
+        ];
+
+        let blob_shard_depth = self.blob_shard_depth;
+        tokio::task::spawn_blocking(move || {
+            let [blobs, manifests, uploads, repository_dirs] = dirs;
+
+            // Returns number of files and their total size, ignoring anything but regular files.
+            let sum_dir = |dir: &Path, extension: Option<&str>| -> Result<(u64, u64), Error> {
+                let mut count = 0;
+                let mut bytes = 0;
+                for entry in fs::read_dir(dir).map_err(Error::Io)? {
+                    let entry = entry.map_err(Error::Io)?;
+                    let metadata = entry.metadata().map_err(Error::Io)?;
+                    if !metadata.is_file() {
+                        continue;
+                    }
+                    if let Some(extension) = extension {
+                        if entry.path().extension().and_then(|ext| ext.to_str()) != Some(extension)
+                        {
+                            continue;
+                        }
+                    }
+                    count += 1;
+                    bytes += metadata.len();
+                }
+                Ok((count, bytes))
+            };
+
+            let (mut blob_count, mut blob_bytes) = (0, 0);
+            for blob in blob_files(&blobs).map_err(Error::Io)? {
+                blob_count += 1;
+                blob_bytes += fs::metadata(blob).map_err(Error::Io)?.len();
+            }
+            let (manifest_count, manifest_bytes) = sum_dir(&manifests, None)?;
+            let (upload_count, upload_bytes) = sum_dir(&uploads, Some("partial"))?;
+
+            // Repositories hold markers named after the blobs pushed to them.
+            let mut repositories = BTreeMap::new();
+            for repository in read_dirs(&repository_dirs)? {
+                for image in read_dirs(&repository)? {
+                    let mut stats = RepositoryStats::default();
+                    for entry in fs::read_dir(&image).map_err(Error::Io)? {
+                        let entry = entry.map_err(Error::Io)?;
+                        let Some(digest) = entry
+                            .file_name()
+                            .to_str()
+                            .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+                            .map(Digest::new)
+                        else {
+                            continue;
+                        };
+                        let path = sharded_blob_path(&blobs, digest, blob_shard_depth);
+                        let Ok(metadata) = fs::metadata(path) else {
+                            continue;
+                        };
+                        stats.blob_count += 1;
+                        stats.blob_bytes += metadata.len();
+                    }
+
+                    let name = format!(
+                        "{}/{}",
+                        repository.file_name().unwrap_or_default().to_string_lossy(),
+                        image.file_name().unwrap_or_default().to_string_lossy()
+                    );
+                    repositories.insert(name, stats);
+                }
+            }
+
+            Ok(StorageStats {
+                total_bytes: blob_bytes + manifest_bytes + upload_bytes,
+                blob_count,
+                manifest_count,
+                upload_count,
+                repositories,
+            })
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn begin_new_upload(&self, location: &ImageLocation) -> Result<Uuid, Error> {
+        let upload = Uuid::new_v4();
+        let out_path = self.upload_path(upload);
+
+        // Remember where the blob is pushed to, for attributing it once finalized.
+        let location = serde_json::to_vec(location).expect("serialization should not fail");
+        tokio::fs::write(self.upload_location_path(upload), location)
+            .await
+            .map_err(Error::Io)?;
+
+        // Write zero-sized file.
+        let _file = tokio::fs::File::create(out_path).await.map_err(Error::Io)?;
+        let _activity = tokio::fs::File::create(self.upload_activity_path(upload))
+            .await
+            .map_err(Error::Io)?;
+
+        Ok(upload)
+    }
+
+    async fn begin_reserved_upload(
+        &self,
+        location: &ImageLocation,
+        owner: &str,
+        size: u64,
+    ) -> Result<Uuid, Error> {
+        let upload = self.begin_new_upload(location).await?;
+
+        let reservation = Reservation {
+            owner: owner.to_owned(),
+            size,
+        };
+        tokio::fs::write(
+            self.upload_reservation_path(upload),
+            serde_json::to_vec(&reservation).expect("serialization should not fail"),
+        )
+        .await
+        .map_err(Error::Io)?;
+
+        Ok(upload)
+    }
+
+    async fn reserved_quota(&self, owner: &str, except: Option<Uuid>) -> Result<u64, Error> {
+        let mut entries = tokio::fs::read_dir(&self.uploads)
+            .await
+            .map_err(Error::Io)?;
+
+        let mut reserved = 0;
+        while let Some(entry) = entries.next_entry().await.map_err(Error::Io)? {
+            let is_counted = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".reservation"))
+                .and_then(|upload| Uuid::parse_str(upload).ok())
+                .is_some_and(|upload| Some(upload) != except);
+            if !is_counted {
+                continue;
+            }
+
+            // Reservations vanish once their upload is finalized or cancelled.
+            let reservation: Reservation = match tokio::fs::read(entry.path()).await {
+                Ok(raw) => serde_json::from_slice(&raw)
+                    .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Io(e)),
+            };
+            if reservation.owner == owner {
+                reserved += reservation.size;
+            }
+        }
+
+        Ok(reserved)
+    }
+
+    async fn get_upload_location(&self, upload: Uuid) -> Result<Option<ImageLocation>, Error> {
+        match tokio::fs::read(self.upload_location_path(upload)).await {
+            Ok(raw) => serde_json::from_slice(&raw)
+                .map(Some)
+                .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::InvalidData, err))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    async fn get_blob_metadata(&self, digest: Digest) -> Result<Option<BlobMetadata>, Error> {
+        let blob_path = self.blob_path(digest);
+
+        if !blob_path.exists() {
+            return Ok(None);
+        }
+
+        let size = match self.compressed_blob_size(digest).await? {
+            Some(size) => size,
+            None => tokio::fs::metadata(blob_path)
+                .await
+                .map_err(Error::Io)?
+                .len(),
+        };
+
+        let media_type = match tokio::fs::read_to_string(self.media_type_path(digest)).await {
+            Ok(media_type) => Some(media_type),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        Ok(Some(BlobMetadata {
+            digest,
+            size,
+            media_type,
+        }))
+    }
+
+    async fn blob_exists(&self, digest: Digest) -> Result<bool, Error> {
+        tokio::fs::try_exists(self.blob_path(digest))
+            .await
+            .map_err(Error::Io)
+    }
+
+    async fn quarantine_blob(&self, digest: Digest) -> Result<bool, Error> {
+        match tokio::fs::rename(
+            self.blob_path(digest),
+            self.quarantine.join(format!("{}", digest)),
+        )
+        .await
+        {
+            Ok(()) => {
+                remove_if_exists(&self.compressed_blobs.join(format!("{}", digest))).await?;
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn record_blob_media_type(&self, digest: Digest, media_type: &str) -> Result<(), Error> {
+        if !self.blob_path(digest).exists() {
+            return Ok(());
+        }
+
+        tokio::fs::write(self.media_type_path(digest), media_type)
+            .await
+            .map_err(Error::Io)
+    }
+
+    async fn get_blob_reader(&self, digest: Digest) -> Result<Option<Box<dyn BlobReader>>, Error> {
+        let blob_path = self.blob_path(digest);
+
+        if !blob_path.exists() {
+            return Ok(None);
+        }
+
+        if let Some(contents) = self.read_compressed_blob(digest).await? {
+            return Ok(Some(Box::new(io::Cursor::new(contents))));
+        }
+
+        let reader = tokio::fs::File::open(blob_path).await.map_err(Error::Io)?;
+
+        Ok(Some(Box::new(reader)))
+    }
+
+    async fn get_upload_writer(
+        &self,
+        start_at: u64,
+        upload: Uuid,
+    ) -> Result<Box<dyn AsyncWrite + Send + Unpin>, Error> {
+        let location = self.upload_path(upload);
+
+        if !location.exists() {
+            return Err(Error::UploadDoesNotExit);
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .truncate(false)
+            .open(location)
+            .await
+            .map_err(Error::Io)?;
+
+        file.seek(io::SeekFrom::Start(start_at))
+            .await
+            .map_err(Error::Io)?;
+
+        let size = file.metadata().await.map_err(Error::Io)?.len();
+        let hasher = self.load_upload_hasher(upload, size).await?;
+        self.touch_upload(upload).await?;
+
+        Ok(Box::new(HashingWriter {
+            file,
+            hasher,
+            hasher_path: self.upload_hasher_path(upload),
+            saving: None,
+        }))
+    }
+
+    async fn get_upload_size(&self, upload: Uuid) -> Result<u64, Error> {
+        match tokio::fs::metadata(self.upload_path(upload)).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::UploadDoesNotExit),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn set_upload_expected_size(&self, upload: Uuid, size: u64) -> Result<(), Error> {
+        if !self.upload_path(upload).exists() {
+            return Err(Error::UploadDoesNotExit);
+        }
+
+        tokio::fs::write(self.upload_expected_size_path(upload), size.to_string())
+            .await
+            .map_err(Error::Io)
+    }
+
+    async fn touch_upload(&self, upload: Uuid) -> Result<(), Error> {
+        if !self.upload_path(upload).exists() {
+            return Err(Error::UploadDoesNotExit);
+        }
+
+        let activity = self.upload_activity_path(upload);
+        match touch(&activity).await {
+            // Uploads begun by earlier versions have no marker yet.
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                tokio::fs::File::create(activity)
+                    .await
+                    .map(drop)
+                    .map_err(Error::Io)
+            }
+            result => result,
+        }
+    }
+
+    async fn cancel_upload(&self, upload: Uuid) -> Result<(), Error> {
+        self.remove_upload_hasher(upload).await?;
+        self.remove_upload_location(upload).await?;
+        self.remove_upload_expected_size(upload).await?;
+        self.remove_upload_reservation(upload).await?;
+        self.remove_upload_activity(upload).await?;
+
+        match tokio::fs::remove_file(self.upload_path(upload)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::UploadDoesNotExit),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn finalize_upload(&self, upload: Uuid, digest: Digest) -> Result<(), Error> {
+        // We are to validate the uploaded partial, then move it into the proper store.
+        // TODO: Lock in place so that the hash cannot be corrupted/attacked.
+
+        let upload_path = self.upload_path(upload);
+
+        if !upload_path.exists() {
+            return Err(Error::UploadDoesNotExit);
+        }
+
+        // The digest has been calculated while the data was written, only the final padding
+        // remains to be hashed.
+        let size = tokio::fs::metadata(&upload_path)
+            .await
+            .map_err(Error::Io)?
+            .len();
+        if let Some(expected) = self.get_upload_expected_size(upload).await? {
+            if expected != size {
+                return Err(Error::SizeMismatch {
+                    expected,
+                    actual: size,
+                });
+            }
+        }
+        let actual = self.load_upload_hasher(upload, size).await?.finalize();
+
+        if actual != digest {
+            return Err(Error::DigestMismatch);
+        }
+
+        // The uploaded file matches, we can rename it now, unless identical content is stored.
+        let dest = self.blob_path(digest);
+        if tokio::fs::try_exists(&dest).await.map_err(Error::Io)? {
+            tokio::fs::remove_file(upload_path)
+                .await
+                .map_err(Error::Io)?;
+
+            // Uploading the blob again restarts its garbage collection grace period.
+            touch(&dest).await?;
+        } else {
+            if self
+                .compression_threshold
+                .is_some_and(|threshold| size < threshold)
+            {
+                let contents = tokio::fs::read(&upload_path).await.map_err(Error::Io)?;
+                if let Cow::Owned(compressed) = self.compress_object(&contents)? {
+                    tokio::fs::write(&upload_path, compressed)
+                        .await
+                        .map_err(Error::Io)?;
+                    // The marker is written first, a blob is never stored compressed without it.
+                    tokio::fs::write(
+                        self.compressed_blobs.join(format!("{}", digest)),
+                        size.to_string(),
+                    )
+                    .await
+                    .map_err(Error::Io)?;
+                }
+            }
+
+            tokio::fs::create_dir_all(dest.parent().expect("should have parent"))
+                .await
+                .map_err(Error::Io)?;
+            tokio::fs::rename(upload_path, &dest)
+                .await
+                .map_err(Error::Io)?;
+            self.persist(&dest).await?;
+        }
+        self.remove_upload_hasher(upload).await?;
+        self.remove_upload_expected_size(upload).await?;
+        self.remove_upload_reservation(upload).await?;
+        self.remove_upload_activity(upload).await?;
+
+        if let Some(location) = self.get_upload_location(upload).await? {
+            self.mark_repository_blob(&location, digest, size).await?;
+            self.remove_upload_location(upload).await?;
+        }
+
+        // All good.
+        Ok(())
+    }
+
+    async fn reuse_blob(&self, location: &ImageLocation, digest: Digest) -> Result<bool, Error> {
+        let blob_path = self.blob_path(digest);
+        match touch(&blob_path).await {
+            Ok(()) => {}
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        let Some(metadata) = self.get_blob_metadata(digest).await? else {
+            return Ok(false);
+        };
+        self.mark_repository_blob(location, digest, metadata.size)
+            .await?;
+        Ok(true)
+    }
+
+    async fn repository_blob_size(
+        &self,
+        location: &ImageLocation,
+        digest: Digest,
+    ) -> Result<Option<u64>, Error> {
+        match tokio::fs::read_to_string(self.repository_blob_path(location, digest)).await {
+            Ok(size) => Ok(size.trim().parse().ok()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn resolve_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Option<Digest>, Error> {
+        // Tags link to the manifest, which is named after its digest. Links are replaced
+        // atomically, so a tag always resolves to either its old or its new manifest.
+        let target = match tokio::fs::read_link(self.tag_path(location, tag)).await {
+            Ok(target) => target,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        tag_target_digest(&target).map(Some).ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tag links to unexpected path {}", target.display()),
+            ))
+        })
+    }
+
+    async fn get_manifest(
+        &self,
+        manifest_reference: &ManifestReference,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .get_manifest_with_digest(manifest_reference)
+            .await?
+            .map(|(data, _)| data))
+    }
+
+    async fn get_manifest_with_digest(
+        &self,
+        manifest_reference: &ManifestReference,
+    ) -> Result<Option<(Vec<u8>, Digest)>, Error> {
+        let digest = match manifest_reference.reference() {
+            Reference::Digest(digest) => *digest,
+            Reference::Tag(ref tag) => {
+                match self.resolve_tag(manifest_reference.location(), tag).await? {
+                    Some(digest) => digest,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        match tokio::fs::read(self.manifest_path(digest)).await {
+            Ok(data) => Ok(Some((
+                decode_object(data, digest, self.compressed_manifest_dir()),
+                digest,
+            ))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn referenced_blobs(
+        &self,
+        manifest: &[u8],
+        media_type: Option<&str>,
+    ) -> Result<Vec<Digest>, Error> {
+        let mut referenced =
+            manifest::referenced_blobs(manifest, media_type).map_err(Error::UnsupportedManifest)?;
+        let mut seen: HashSet<_> = referenced.iter().copied().collect();
+
+        // Only child manifests are stored as manifests, anything else is not found.
+        let mut next = 0;
+        while let Some(&digest) = referenced.get(next) {
+            next += 1;
+            let child = match tokio::fs::read(self.manifest_path(digest)).await {
+                Ok(child) => decode_object(child, digest, self.compressed_manifest_dir()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Io(e)),
+            };
+
+            // Children of unknown kinds, e.g. artifacts, reference nothing that can be followed.
+            let Ok(grandchildren) = manifest::referenced_blobs(&child, None) else {
+                continue;
+            };
+            for grandchild in grandchildren {
+                if seen.insert(grandchild) {
+                    referenced.push(grandchild);
+                }
+            }
+        }
+
+        Ok(referenced)
+    }
+
+    async fn put_manifest(
+        &self,
+        manifest_reference: &ManifestReference,
+        manifest: &[u8],
+    ) -> Result<Digest, Error> {
+        // TODO: Validate all blobs are completely uploaded.
+        let parsed: ManifestSubject =
+            serde_json::from_slice(manifest).map_err(Error::InvalidManifest)?;
+
+        let digest = Digest::from_contents(manifest);
+
+        // Manifests stored by digest are not tagged, but must actually hash to said digest.
+        let tag = match manifest_reference.reference() {
+            Reference::Tag(tag) => Some(tag),
+            Reference::Digest(expected) if *expected != digest => {
+                return Err(Error::DigestMismatch)
+            }
+            Reference::Digest(_) => None,
+        };
+
+        // Manifests are rewritten on every push, replace them atomically to not expose partial ones.
+        let dest = self.manifest_path(digest);
+        let tmp_manifest = self.temp_manifest_path();
+        let stored = self.compress_object(manifest)?;
+        let compressed = matches!(stored, Cow::Owned(_));
+        tokio::fs::write(&tmp_manifest, stored)
+            .await
+            .map_err(Error::Io)?;
+        // Markers of compressed manifests are written before and removed after replacing them,
+        // readers fall back to the stored form if it is not compressed after all.
+        if compressed {
+            self.mark_compressed_manifest(digest, Some(manifest.len()))
+                .await?;
+        }
+        tokio::fs::rename(tmp_manifest, &dest)
+            .await
+            .map_err(Error::Io)?;
+        self.persist(&dest).await?;
+        if !compressed {
+            self.mark_compressed_manifest(digest, None).await?;
+        }
+
+        // Referrers are indexed by an empty marker file named after the referring manifest.
+        if let Some(subject) = parsed.subject {
+            let referrers =
+                self.referrers_path(manifest_reference.location(), subject.digest().digest());
+            tokio::fs::create_dir_all(&referrers)
+                .await
+                .map_err(Error::Io)?;
+            tokio::fs::write(referrers.join(format!("{}", digest)), b"")
+                .await
+                .map_err(Error::Io)?;
+        }
+
+        let Some(tag) = tag else {
+            return Ok(digest);
+        };
+        let tag = self.tag_path(manifest_reference.location(), tag);
+
+        let tag_parent = tag.parent().expect("should have parent");
+
+        if !tag_parent.exists() {
+            tokio::fs::create_dir_all(tag_parent)
+                .await
+                .map_err(Error::Io)?;
+        }
+
+        // Renaming a new symlink over the tag is atomic, readers never observe a missing tag.
+        let tmp_tag = self.temp_tag_path();
+
+        tokio::fs::symlink(self.blob_rel_path(digest), &tmp_tag)
+            .await
+            .map_err(Error::Io)?;
+        tokio::fs::rename(tmp_tag, &tag).await.map_err(Error::Io)?;
+        self.persist(&tag).await?;
+
+        Ok(digest)
+    }
+
+    async fn record_manifest_media_type(
+        &self,
+        digest: Digest,
+        media_type: &str,
+    ) -> Result<(), Error> {
+        tokio::fs::write(self.manifest_media_type_path(digest), media_type)
+            .await
+            .map_err(Error::Io)
+    }
+
+    async fn manifest_media_type(&self, digest: Digest) -> Result<Option<String>, Error> {
+        match tokio::fs::read_to_string(self.manifest_media_type_path(digest)).await {
+            Ok(media_type) => Ok(Some(media_type)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn record_owner(&self, owner: &str, digest: Digest) -> Result<(), Error> {
+        // Ownership is recorded by an empty marker file named after the digest.
+        let owner_path = self.owner_path(owner);
+        tokio::fs::create_dir_all(&owner_path)
+            .await
+            .map_err(Error::Io)?;
+        tokio::fs::write(owner_path.join(format!("{}", digest)), b"")
+            .await
+            .map_err(Error::Io)
+    }
+
+    async fn is_owner(&self, owner: &str, digest: Digest) -> Result<bool, Error> {
+        tokio::fs::try_exists(self.owner_path(owner).join(format!("{}", digest)))
+            .await
+            .map_err(Error::Io)
+    }
+
+    async fn owner_usage(&self, owner: &str) -> Result<u64, Error> {
+        let mut entries = match tokio::fs::read_dir(self.owner_path(owner)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        let mut usage = 0;
+        while let Some(entry) = entries.next_entry().await.map_err(Error::Io)? {
+            let Some(digest) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+                .map(Digest::new)
+            else {
+                continue;
+            };
+
+            // A digest is either a blob or a manifest, removed ones no longer count.
+            for path in [self.blob_path(digest), self.manifest_path(digest)] {
+                match tokio::fs::metadata(path).await {
+                    Ok(metadata) => {
+                        usage += metadata.len();
+                        break;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(Error::Io(e)),
+                }
+            }
+        }
+
+        Ok(usage)
+    }
+
+    async fn get_referrers(
+        &self,
+        location: &ImageLocation,
+        subject: Digest,
+    ) -> Result<Vec<Digest>, Error> {
+        let mut entries = match tokio::fs::read_dir(self.referrers_path(location, subject)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        let mut referrers = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(Error::Io)? {
+            let Some(digest) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+            else {
+                continue;
+            };
+            referrers.push(Digest::new(digest));
+        }
+        referrers.sort();
+
+        Ok(referrers)
+    }
+
+    async fn all_referrers(&self, subject: Digest) -> Result<Vec<Digest>, Error> {
+        let manifests = self.manifests.clone();
+        let compressed = self.compressed_manifest_dir().map(Path::to_owned);
+        tokio::task::spawn_blocking(move || {
+            referring_manifests(&manifests, compressed.as_deref(), subject)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn delete_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Option<DeletedTag>, Error> {
+        let Some(digest) = self.resolve_tag(location, tag).await? else {
+            return Ok(None);
+        };
+
+        tokio::fs::remove_file(self.tag_path(location, tag))
+            .await
+            .map_err(Error::Io)?;
+
+        let tags = self.tags.clone();
+        let referenced = tokio::task::spawn_blocking(move || is_tagged(&tags, digest))
+            .await
+            .map_err(Error::BackgroundTaskPanicked)??;
+
+        Ok(Some(DeletedTag {
+            digest,
+            unreferenced: !referenced,
+        }))
+    }
+
+    async fn soft_delete_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Option<DeletedTag>, Error> {
+        let target = match tokio::fs::read_link(self.tag_path(location, tag)).await {
+            Ok(target) => target,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        // The tombstone is written first, the manifest is never left unreferenced in between.
+        // Its modification time records when the tag was deleted.
+        let tombstone = self.tombstone_path(location, tag);
+        tokio::fs::create_dir_all(tombstone.parent().expect("should have parent"))
+            .await
+            .map_err(Error::Io)?;
+        tokio::fs::write(&tombstone, target.as_os_str().as_encoded_bytes())
+            .await
+            .map_err(Error::Io)?;
+
+        self.delete_tag(location, tag).await
+    }
+
+    async fn undelete_tag(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+        window: Duration,
+    ) -> Result<Option<Digest>, Error> {
+        let tombstone = self.tombstone_path(location, tag);
+        let deleted = match tokio::fs::metadata(&tombstone).await {
+            Ok(metadata) => metadata.modified().map_err(Error::Io)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        if deleted.elapsed().unwrap_or_default() >= window {
+            return Ok(None);
+        }
+
+        let target = tokio::fs::read_to_string(&tombstone)
+            .await
+            .map_err(Error::Io)?;
+        let digest = tag_target_digest(Path::new(target.trim())).ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "tombstone does not point to a manifest",
+            ))
+        })?;
+
+        let tag_path = self.tag_path(location, tag);
+        if tokio::fs::symlink_metadata(&tag_path).await.is_ok() {
+            return Err(Error::TagExists(tag.to_owned()));
+        }
+        tokio::fs::create_dir_all(tag_path.parent().expect("should have parent"))
+            .await
+            .map_err(Error::Io)?;
+
+        let tmp_tag = self.temp_tag_path();
+        tokio::fs::symlink(self.blob_rel_path(digest), &tmp_tag)
+            .await
+            .map_err(Error::Io)?;
+        tokio::fs::rename(tmp_tag, &tag_path)
+            .await
+            .map_err(Error::Io)?;
+        self.persist(&tag_path).await?;
+        tokio::fs::remove_file(tombstone).await.map_err(Error::Io)?;
+
+        Ok(Some(digest))
+    }
+
+    async fn sweep_tombstones(&self, window: Duration) -> Result<Vec<Tombstone>, Error> {
+        let tombstones = self.tombstones.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut swept = Vec::new();
+            for repository in read_dirs(&tombstones)? {
+                for image in read_dirs(&repository)? {
+                    let (Some(repository), Some(image_name)) = (
+                        repository.file_name().and_then(|name| name.to_str()),
+                        image.file_name().and_then(|name| name.to_str()),
+                    ) else {
+                        continue;
+                    };
+                    let location = ImageLocation::new(repository.to_owned(), image_name.to_owned());
+
+                    for entry in fs::read_dir(&image).map_err(Error::Io)? {
+                        let entry = entry.map_err(Error::Io)?;
+                        let deleted = entry
+                            .metadata()
+                            .and_then(|metadata| metadata.modified())
+                            .map_err(Error::Io)?;
+                        if deleted.elapsed().unwrap_or_default() < window {
+                            continue;
+                        }
+
+                        let target = fs::read_to_string(entry.path()).map_err(Error::Io)?;
+                        fs::remove_file(entry.path()).map_err(Error::Io)?;
+                        let name = entry.file_name();
+                        let (Some(tag), Some(digest)) =
+                            (name.to_str(), tag_target_digest(Path::new(target.trim())))
+                        else {
+                            continue;
+                        };
+                        swept.push(Tombstone {
+                            location: location.clone(),
+                            tag: tag.to_owned(),
+                            digest,
+                        });
+                    }
+                }
+            }
+            Ok(swept)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn list_tags(&self, location: &ImageLocation) -> Result<Option<Vec<String>>, Error> {
+        let tags_dir = self.tags.join(location.repository()).join(location.image());
+        let mut entries = match tokio::fs::read_dir(tags_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // Images with blobs pushed, but no tags yet, still exist.
+                let blobs_dir = self
+                    .repositories
+                    .join(location.repository())
+                    .join(location.image());
+                let exists = tokio::fs::try_exists(blobs_dir).await.map_err(Error::Io)?;
+                return Ok(exists.then(Vec::new));
+            }
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        // Tags are only ever renamed into place, anything not named like one is not a tag.
+        let mut tags = Vec::new();
+        loop {
+            match entries.next_entry().await {
+                Ok(Some(entry)) => {
+                    if let Some(tag) = entry.file_name().to_str().filter(|tag| is_valid_tag(tag)) {
+                        tags.push(tag.to_owned());
+                    }
+                }
+                Ok(None) => break,
+                // The image was removed while listing, e.g. by a move.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+
+        // Directories being renamed into may be listed with entries appearing twice.
+        tags.sort();
+        tags.dedup();
+
+        Ok(Some(tags))
+    }
+
+    async fn tags_by_age(
+        &self,
+        location: &ImageLocation,
+    ) -> Result<Vec<(String, SystemTime)>, Error> {
+        let tags_dir = self.tags.join(location.repository()).join(location.image());
+        let Some(tags) = self.list_tags(location).await? else {
+            return Ok(Vec::new());
+        };
+
+        // Tags are replaced by new links on every push, their modification time is the last push.
+        let mut aged = Vec::with_capacity(tags.len());
+        for tag in tags {
+            match tokio::fs::symlink_metadata(tags_dir.join(&tag)).await {
+                Ok(metadata) => aged.push((tag, metadata.modified().map_err(Error::Io)?)),
+                // Deleted since listing.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        aged.sort_by(|(a, a_pushed), (b, b_pushed)| a_pushed.cmp(b_pushed).then_with(|| a.cmp(b)));
+
+        Ok(aged)
+    }
+
+    async fn tagged_locations(&self) -> Result<Vec<ImageLocation>, Error> {
+        let tags = self.tags.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut locations = Vec::new();
+            for repository in read_dirs(&tags)? {
+                for image in read_dirs(&repository)? {
+                    let (Some(repository), Some(image)) = (
+                        repository.file_name().and_then(|name| name.to_str()),
+                        image.file_name().and_then(|name| name.to_str()),
+                    ) else {
+                        continue;
+                    };
+                    locations.push(ImageLocation::new(repository.to_owned(), image.to_owned()));
+                }
+            }
+            locations.sort_by(|a, b| (a.repository(), a.image()).cmp(&(b.repository(), b.image())));
+
+            Ok(locations)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn manifest_in_use(&self, digest: Digest) -> Result<bool, Error> {
+        let tags = self.tags.clone();
+        let tombstones = self.tombstones.clone();
+        let manifests = self.manifests.clone();
+        let compressed = self.compressed_manifest_dir().map(Path::to_owned);
+        tokio::task::spawn_blocking(move || {
+            Ok(is_tagged(&tags, digest)?
+                || is_tombstoned(&tombstones, digest)?
+                || referenced_by_manifests(&manifests, compressed.as_deref(), Some(digest), false)?
+                    .contains(&digest))
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn delete_manifest(
+        &self,
+        location: &ImageLocation,
+        digest: Digest,
+    ) -> Result<bool, Error> {
+        let tags = self.tags.clone();
+        let tombstones = self.tombstones.clone();
+        let manifests = self.manifests.clone();
+        let compressed = self.compressed_manifest_dir().map(Path::to_owned);
+        let referenced = tokio::task::spawn_blocking(move || {
+            Ok::<_, Error>(
+                is_tagged(&tags, digest)?
+                    || is_tombstoned(&tombstones, digest)?
+                    || referenced_by_manifests(
+                        &manifests,
+                        compressed.as_deref(),
+                        Some(digest),
+                        true,
+                    )?
+                    .contains(&digest),
+            )
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)??;
+        if referenced {
+            return Ok(false);
+        }
+
+        let manifest_path = self.manifest_path(digest);
+        let manifest = match tokio::fs::read(&manifest_path).await {
+            Ok(manifest) => decode_object(manifest, digest, self.compressed_manifest_dir()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        // A manifest no longer stored must not be listed as a referrer either.
+        if let Ok(ManifestSubject {
+            subject: Some(subject),
+        }) = serde_json::from_slice(&manifest)
+        {
+            let marker = self
+                .referrers_path(location, subject.digest().digest())
+                .join(format!("{}", digest));
+            match tokio::fs::remove_file(marker).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+
+        for sidecar in [
+            self.manifest_media_type_path(digest),
+            self.access_time_path(digest),
+            self.compressed_manifests.join(format!("{}", digest)),
+        ] {
+            match tokio::fs::remove_file(sidecar).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+
+        match tokio::fs::remove_file(manifest_path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn append_tag_history(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+        entry: TagHistoryEntry,
+        retention: usize,
+    ) -> Result<(), Error> {
+        let mut history = self.tag_history(location, tag).await?;
+        if history
+            .last()
+            .is_some_and(|last| last.digest.digest == entry.digest.digest)
+        {
+            return Ok(());
+        }
+        history.push(entry);
+        history.drain(..history.len().saturating_sub(retention));
+
+        // History is stored as JSON lines, replaced atomically on every change.
+        let mut contents = Vec::new();
+        for entry in &history {
+            serde_json::to_writer(&mut contents, entry).expect("serialization should not fail");
+            contents.push(b'\n');
+        }
+
+        let path = self.tag_history_path(location, tag);
+        tokio::fs::create_dir_all(path.parent().expect("should have parent"))
+            .await
+            .map_err(Error::Io)?;
+        let tmp_path = self.tag_history.join(Uuid::new_v4().to_string());
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .map_err(Error::Io)?;
+        tokio::fs::rename(tmp_path, path).await.map_err(Error::Io)
+    }
+
+    async fn tag_history(
+        &self,
+        location: &ImageLocation,
+        tag: &str,
+    ) -> Result<Vec<TagHistoryEntry>, Error> {
+        let contents = match tokio::fs::read(self.tag_history_path(location, tag)).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        contents
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                serde_json::from_slice(line)
+                    .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+            })
+            .collect()
+    }
+
+    async fn record_access(&self, digest: Digest, resolution: Duration) -> Result<(), Error> {
+        let path = self.access_time_path(digest);
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) => {
+                let recorded = metadata.modified().map_err(Error::Io)?;
+                if recorded.elapsed().is_ok_and(|elapsed| elapsed < resolution) {
+                    return Ok(());
+                }
+                touch(&path).await
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                tokio::fs::write(&path, b"").await.map_err(Error::Io)
+            }
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn least_recently_used(&self, limit: usize) -> Result<Vec<AccessTime>, Error> {
+        let blobs = self.blobs.clone();
+        let manifests = self.manifests.clone();
+        let access_times = self.access_times.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let digest_of = |path: &Path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+                    .map(Digest::new)
+            };
+
+            let mut manifest_files = Vec::new();
+            for entry in fs::read_dir(&manifests).map_err(Error::Io)? {
+                let entry = entry.map_err(Error::Io)?;
+                if entry.file_type().map_err(Error::Io)?.is_file() {
+                    manifest_files.push(entry.path());
+                }
+            }
+
+            let mut objects = Vec::new();
+            for (path, manifest) in blob_files(&blobs)
+                .map_err(Error::Io)?
+                .into_iter()
+                .map(|path| (path, false))
+                .chain(manifest_files.into_iter().map(|path| (path, true)))
+            {
+                let Some(digest) = digest_of(&path) else {
+                    continue;
+                };
+
+                // Objects may be removed concurrently, they are skipped.
+                let accessed = fs::metadata(access_times.join(format!("{}", digest)));
+                let last_accessed = match accessed.or_else(|_| fs::metadata(&path)) {
+                    Ok(metadata) => metadata.modified().map_err(Error::Io)?,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(Error::Io(e)),
+                };
+
+                objects.push(AccessTime {
+                    digest,
+                    manifest,
+                    last_accessed,
+                });
+            }
+
+            objects.sort_by_key(|object| object.last_accessed);
+            objects.truncate(limit);
+            Ok(objects)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn garbage_collect(
+        &self,
+        min_age: Duration,
+        dry_run: bool,
+    ) -> Result<GarbageCollection, Error> {
+        let blobs = self.blobs.clone();
+        let manifests = self.manifests.clone();
+        let compressed_manifests = self.compressed_manifest_dir().map(Path::to_owned);
+        let media_types = self.media_types.clone();
+        let access_times = self.access_times.clone();
+        let compressed_blobs = self.compressed_blobs.clone();
+
+        tokio::task::spawn_blocking(move || {
+            // Everything referenced by a stored manifest is kept, whether it is tagged or not.
+            let referenced =
+                referenced_by_manifests(&manifests, compressed_manifests.as_deref(), None, true)?;
+
+            let cutoff = SystemTime::now().checked_sub(min_age).unwrap_or(UNIX_EPOCH);
+            let mut collection = GarbageCollection {
+                dry_run,
+                ..Default::default()
+            };
+            for blob in blob_files(&blobs).map_err(Error::Io)? {
+                let Some(digest) = blob
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+                    .map(Digest::new)
+                else {
+                    continue;
+                };
+                if referenced.contains(&digest) {
+                    continue;
+                }
+
+                let metadata = fs::metadata(&blob).map_err(Error::Io)?;
+                if metadata.modified().map_err(Error::Io)? > cutoff {
+                    continue;
+                }
+
+                if !dry_run {
+                    fs::remove_file(&blob).map_err(Error::Io)?;
+                    for sidecar in [&media_types, &access_times, &compressed_blobs] {
+                        match fs::remove_file(sidecar.join(format!("{}", digest))) {
+                            Ok(()) => {}
+                            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                            Err(e) => return Err(Error::Io(e)),
+                        }
+                    }
+                }
+
+                collection.blobs.push(ImageDigest::new(digest));
+                collection.bytes += metadata.len();
+            }
+            collection.blobs.sort_by_key(|digest| digest.digest);
+
+            Ok(collection)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+    }
+
+    async fn list_uploads(&self) -> Result<Vec<UploadInfo>, Error> {
+        let mut entries = tokio::fs::read_dir(&self.uploads)
+            .await
+            .map_err(Error::Io)?;
+
+        let now = SystemTime::now();
+        let mut uploads = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(Error::Io)? {
+            let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".partial"))
+                .and_then(|upload| Uuid::parse_str(upload).ok())
+            else {
+                continue;
+            };
+
+            // Uploads finalized or cancelled while listing are skipped.
+            let metadata = match entry.metadata().await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Io(e)),
+            };
+            // Uploads begun by earlier versions have no marker, their data is the last activity.
+            let last_active = match tokio::fs::metadata(self.upload_activity_path(id)).await {
+                Ok(activity) => activity.modified(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => metadata.modified(),
+                Err(e) => return Err(Error::Io(e)),
+            }
+            .map_err(Error::Io)?;
+            let age = now.duration_since(last_active).unwrap_or_default();
+
+            uploads.push(UploadInfo {
+                id,
+                location: self.get_upload_location(id).await?,
+                offset: metadata.len(),
+                age: age.as_secs(),
+            });
+        }
+        uploads.sort_by_key(|upload| upload.id);
+
+        Ok(uploads)
+    }
+
+    async fn prune_uploads(&self, ttl: Duration) -> Result<Vec<UploadInfo>, Error> {
+        let mut pruned = Vec::new();
+        for upload in self.list_uploads().await? {
+            if upload.age < ttl.as_secs() {
+                continue;
+            }
+
+            match self.cancel_upload(upload.id).await {
+                Ok(()) => pruned.push(upload),
+                Err(Error::UploadDoesNotExit) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    async fn verify_blobs(&self, concurrency: usize) -> Result<BlobVerification, Error> {
+        let blobs = self.blobs.clone();
+        let files = tokio::task::spawn_blocking(move || blob_files(&blobs))
+            .await
+            .map_err(Error::BackgroundTaskPanicked)?
+            .map_err(Error::Io)?;
+
+        let compressed = self.compressed_blob_dir().map(Path::to_owned);
+        let mut hashed = futures::stream::iter(files)
+            .map(|file| {
+                let compressed = compressed.clone();
+                tokio::task::spawn_blocking(move || hash_blob_file(&file, compressed.as_deref()))
+            })
+            .buffer_unordered(concurrency.max(1));
+
+        let mut verification = BlobVerification::default();
+        while let Some(result) = hashed.next().await {
+            // Blobs removed by garbage collection in the meantime are skipped.
+            let Some((digest, actual, size)) = result
+                .map_err(Error::BackgroundTaskPanicked)?
+                .map_err(Error::Io)?
+            else {
+                continue;
+            };
+            verification.scanned += 1;
+            verification.bytes += size;
+            if actual != digest {
+                verification.corrupted.push(ImageDigest::new(digest));
+            }
+        }
+        verification.corrupted.sort_by_key(|digest| digest.digest);
+
+        Ok(verification)
+    }
+
+    async fn move_repository(
+        &self,
+        from: &ImageLocation,
+        to: &ImageLocation,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        if from == to {
+            return Err(Error::LocationExists(to.clone()));
+        }
+        if self.list_tags(from).await?.is_none() {
+            return Err(Error::LocationUnknown(from.clone()));
+        }
+        if !overwrite && self.list_tags(to).await?.is_some() {
+            return Err(Error::LocationExists(to.clone()));
+        }
+
+        let pending = PendingMove {
+            from: from.clone(),
+            to: to.clone(),
+        };
+        let location_dirs = self.location_dirs();
+        let journal = self.moves.join(Uuid::new_v4().to_string());
+
+        tokio::task::spawn_blocking(move || {
+            for dir in &location_dirs {
+                fs::create_dir_all(
+                    dir.join(pending.from.repository())
+                        .join(pending.from.image()),
+                )?;
+            }
+
+            // From here on, the move is completed even if interrupted.
+            let tmp_journal = journal.with_extension("tmp");
+            fs::write(
+                &tmp_journal,
+                serde_json::to_vec(&pending).expect("serialization should not fail"),
+            )?;
+            fs::rename(&tmp_journal, &journal)?;
+
+            complete_move(&location_dirs, &pending)?;
+            fs::remove_file(&journal)
+        })
+        .await
+        .map_err(Error::BackgroundTaskPanicked)?
+        .map_err(Error::Io)
+    }
+}
+
+/// Quota reserved by an unfinished upload.
+#[derive(Debug, Deserialize, Serialize)]
+struct Reservation {
+    owner: String,
+    size: u64,
+}
+
+/// A repository move in progress, journaled to be completed after a crash.
+#[derive(Debug, Deserialize, Serialize)]
+struct PendingMove {
+    from: ImageLocation,
+    to: ImageLocation,
+}
+
+/// Moves the state of a location out of every location directory still holding it.
+///
+/// Once journaled, all source directories exist, thus a missing one has been moved already and
+/// an existing destination is left over from before and may be replaced.
+fn complete_move(location_dirs: &[PathBuf], pending: &PendingMove) -> io::Result<()> {
+    for dir in location_dirs {
+        let src = dir
+            .join(pending.from.repository())
+            .join(pending.from.image());
+        let dest = dir.join(pending.to.repository()).join(pending.to.image());
+        if !src.try_exists()? {
+            continue;
+        }
+
+        match fs::remove_dir_all(&dest) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&src, &dest)?;
+
+        // Repository directories are left behind once empty, failing to remove them is harmless.
+        if let Some(parent) = src.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+    Ok(())
+}
+
+/// Completes all moves journaled in `moves`, which were interrupted by a crash.
+fn resume_moves(moves: &Path, location_dirs: &[PathBuf]) -> Result<(), FilesystemStorageError> {
+    let resume = |path: &Path| -> io::Result<()> {
+        let pending: PendingMove = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        complete_move(location_dirs, &pending)?;
+        fs::remove_file(path)
+    };
+
+    let entries =
+        fs::read_dir(moves).map_err(|err| FilesystemStorageError::FailedToResumeMove {
+            path: moves.to_owned(),
+            err,
+        })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|err| FilesystemStorageError::FailedToResumeMove {
+                path: moves.to_owned(),
+                err,
+            })?
+            .path();
+        // Journals are written to a temporary file first, those were never started.
+        if path.extension().is_some_and(|extension| extension == "tmp") {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        resume(&path).map_err(|err| FilesystemStorageError::FailedToResumeMove { path, err })?;
+    }
+    Ok(())
+}
+
+/// Collects the digests referenced anywhere in a manifest of unknown format.
+///
+/// Rather than guessing at the format, all `digest` and `blobSum` fields are considered
+/// references, erring on the side of keeping blobs.
+fn referenced_digests(value: &serde_json::Value, referenced: &mut HashSet<Digest>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                if let ("digest" | "blobSum", Some(Ok(digest))) =
+                    (key.as_str(), value.as_str().map(ImageDigest::from_str))
+                {
+                    referenced.insert(digest.digest);
+                }
+                referenced_digests(value, referenced);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                referenced_digests(value, referenced);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the path of a blob, sharded into `depth` levels of directories.
+///
+/// Each level is named after the next pair of hex digits of the digest, e.g. at depth 2 a blob is
+/// stored at `<blobs>/sha256/ab/cd/abcd...`.
+fn sharded_blob_path(blobs: &Path, digest: Digest, depth: usize) -> PathBuf {
+    let hex = digest.to_string();
+
+    let mut path = blobs.join("sha256");
+    for level in 0..depth {
+        path.push(&hex[level * 2..level * 2 + 2]);
+    }
+    path.push(hex);
+    path
+}
+
+/// Hashes a blob file, returning the digest it is stored under, its actual digest and size.
+///
+/// Returns `None` if the blob no longer exists.
+fn hash_blob_file(
+    path: &Path,
+    compressed: Option<&Path>,
+) -> io::Result<Option<(Digest, Digest, u64)>> {
+    let Some(digest) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+        .map(Digest::new)
+    else {
+        return Ok(None);
+    };
+
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    // Compressed blobs are small, they are decompressed in memory.
+    if compressed.is_some_and(|dir| dir.join(format!("{}", digest)).exists()) {
+        let mut stored = Vec::new();
+        file.read_to_end(&mut stored)?;
+        let contents = decode_object(stored, digest, compressed);
+        return Ok(Some((
+            digest,
+            Digest::from_contents(&contents),
+            contents.len() as u64,
+        )));
+    }
+
+    let mut hasher = UploadHasher::new();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    let size = hasher.length;
+    Ok(Some((digest, hasher.finalize(), size)))
+}
+
+/// Compresses an object to store it.
+#[cfg(feature = "small-object-compression")]
+fn compress_object(contents: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(contents, 0)
+}
+
+/// Compresses an object to store it, unsupported without the `small-object-compression` feature.
+#[cfg(not(feature = "small-object-compression"))]
+fn compress_object(_contents: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Returns the original contents of an object stored compressed, `None` if it is not compressed.
+#[cfg(feature = "small-object-compression")]
+fn decompress_object(stored: &[u8]) -> Option<Vec<u8>> {
+    zstd::bulk::decompress(stored, MAX_COMPRESSION_THRESHOLD as usize).ok()
+}
+
+/// Objects are never stored compressed without the `small-object-compression` feature.
+#[cfg(not(feature = "small-object-compression"))]
+fn decompress_object(_stored: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Returns the original contents of a stored object.
+///
+/// Objects stored compressed are recorded by a marker named after their digest in `compressed`,
+/// which is `None` if no object is. A marker may briefly outlive the compressed form of a
+/// manifest being replaced, objects that fail to decompress are returned as stored.
+fn decode_object(stored: Vec<u8>, digest: Digest, compressed: Option<&Path>) -> Vec<u8> {
+    match compressed {
+        Some(dir) if dir.join(format!("{}", digest)).exists() => {
+            decompress_object(&stored).unwrap_or(stored)
+        }
+        _ => stored,
+    }
+}
+
+/// Removes a file, succeeding if it does not exist.
+async fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// Returns the paths of all blobs stored below `blobs`, regardless of their layout.
+fn blob_files(blobs: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![blobs.to_owned()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file()
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| <[u8; SHA256_LEN]>::from_hex(name).is_ok())
+            {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Moves all blobs into the layout of the given shard depth.
+///
+/// Blobs used to be stored flat, directly inside `blobs`. The depth in use is recorded in a
+/// `layout` file, blobs are only walked if it differs, thus once per change of depth.
+fn migrate_blobs(blobs: &Path, depth: usize) -> io::Result<()> {
+    let layout = blobs.join("layout");
+    let current = match fs::read_to_string(&layout) {
+        Ok(current) => current.trim().parse::<usize>().ok(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if current == Some(depth) {
+        return Ok(());
+    }
+
+    for blob in blob_files(blobs)? {
+        let Some(digest) = blob
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+            .map(Digest::new)
+        else {
+            continue;
         };
 
-        match tokio::fs::read(manifest_path).await {
-            Ok(data) => Ok(Some(data)),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(Error::Io(e)),
+        let dest = sharded_blob_path(blobs, digest, depth);
+        if dest != blob {
+            fs::create_dir_all(dest.parent().expect("should have parent"))?;
+            fs::rename(&blob, &dest)?;
         }
     }
+    remove_empty_dirs(&blobs.join("sha256"))?;
 
-    async fn put_manifest(
-        &self,
-        manifest_reference: &ManifestReference,
-        manifest: &[u8],
-    ) -> Result<Digest, Error> {
-        // TODO: Validate all blobs are completely uploaded.
-        let _manifest: ImageManifest =
-            serde_json::from_slice(manifest).map_err(Error::InvalidManifest)?;
+    fs::write(layout, depth.to_string())
+}
 
-        let digest = Digest::from_contents(manifest);
-        let dest = self.manifest_path(digest);
-        tokio::fs::write(dest, &manifest).await.map_err(Error::Io)?;
-
-        let tag = self.tag_path(
-            manifest_reference.location(),
-            manifest_reference
-                .reference()
-                .as_tag()
-                .ok_or(Error::NotATag)?,
+/// Removes all empty directories below `dir`, keeping `dir` itself.
+fn remove_empty_dirs(dir: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            let path = entry.path();
+            remove_empty_dirs(&path)?;
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extracts the manifest digest from the target of a tag symlink.
+fn tag_target_digest(target: &Path) -> Option<Digest> {
+    target
+        .file_name()?
+        .to_str()
+        .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+        .map(Digest::new)
+}
+
+/// Returns whether any tag points to the manifest with the given digest.
+///
+/// Tags are stored as `tags/<repository>/<image>/<tag>`.
+fn is_tagged(tags: &Path, digest: Digest) -> Result<bool, Error> {
+    for repository in read_dirs(tags)? {
+        for image in read_dirs(&repository)? {
+            for entry in fs::read_dir(image).map_err(Error::Io)? {
+                let entry = entry.map_err(Error::Io)?;
+                let Ok(target) = fs::read_link(entry.path()) else {
+                    continue;
+                };
+                if tag_target_digest(&target) == Some(digest) {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Collects the digests of everything referenced by the stored manifests, except `skip`.
+///
+/// Subjects are only included with `subjects`, manifests that cannot be parsed always count as
+/// referencing every digest they contain.
+fn referenced_by_manifests(
+    manifests: &Path,
+    compressed: Option<&Path>,
+    skip: Option<Digest>,
+    subjects: bool,
+) -> Result<HashSet<Digest>, Error> {
+    let skip = skip.map(|digest| digest.to_string());
+
+    let mut referenced = HashSet::new();
+    for entry in fs::read_dir(manifests).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        if !entry.file_type().map_err(Error::Io)?.is_file()
+            || skip
+                .as_deref()
+                .is_some_and(|skip| entry.file_name() == skip)
+        {
+            continue;
+        }
+        let mut stored = fs::read(entry.path()).map_err(Error::Io)?;
+        if let Some(digest) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+        {
+            stored = decode_object(stored, Digest::new(digest), compressed);
+        }
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&stored).map_err(Error::InvalidManifest)?;
+        match Manifest::deserialize(&manifest) {
+            Ok(parsed) if subjects => referenced.extend(
+                parsed
+                    .descriptors()
+                    .map(|descriptor| descriptor.digest().digest),
+            ),
+            Ok(parsed) => referenced.extend(
+                parsed
+                    .children()
+                    .map(|descriptor| descriptor.digest().digest),
+            ),
+            Err(_) => referenced_digests(&manifest, &mut referenced),
+        }
+    }
+
+    Ok(referenced)
+}
+
+/// Returns the digests of all manifests in `manifests` whose `subject` is the given digest, sorted.
+fn referring_manifests(
+    manifests: &Path,
+    compressed: Option<&Path>,
+    subject: Digest,
+) -> Result<Vec<Digest>, Error> {
+    let mut referrers = Vec::new();
+    for entry in fs::read_dir(manifests).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let Some(digest) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| <[u8; SHA256_LEN]>::from_hex(name).ok())
+            .map(Digest::new)
+        else {
+            continue;
+        };
+        if !entry.file_type().map_err(Error::Io)?.is_file() {
+            continue;
+        }
+
+        let stored = decode_object(
+            fs::read(entry.path()).map_err(Error::Io)?,
+            digest,
+            compressed,
         );
+        if let Ok(ManifestSubject {
+            subject: Some(referred),
+        }) = serde_json::from_slice(&stored)
+        {
+            if referred.digest().digest == subject {
+                referrers.push(digest);
+            }
+        }
+    }
+    referrers.sort();
 
-        let tag_parent = tag.parent().expect("should have parent");
+    Ok(referrers)
+}
 
-        if !tag_parent.exists() {
-            tokio::fs::create_dir_all(tag_parent)
+/// Returns whether any deleted tag kept for restoring points to the manifest with the given
+/// digest.
+///
+/// Tombstones are stored as `tombstones/<repository>/<image>/<tag>`, holding the digest.
+fn is_tombstoned(tombstones: &Path, digest: Digest) -> Result<bool, Error> {
+    for repository in read_dirs(tombstones)? {
+        for image in read_dirs(&repository)? {
+            for entry in fs::read_dir(image).map_err(Error::Io)? {
+                let entry = entry.map_err(Error::Io)?;
+                let Ok(contents) = fs::read_to_string(entry.path()) else {
+                    continue;
+                };
+                if tag_target_digest(Path::new(contents.trim())) == Some(digest) {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Lists all subdirectories of a directory.
+fn read_dirs(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        if entry.file_type().map_err(Error::Io)?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Initial hash value of SHA256, see FIPS 180-4, section 5.3.3.
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Size of a SHA256 block in bytes.
+const SHA256_BLOCK_LEN: usize = 64;
+
+/// An SHA256 hasher for uploads.
+///
+/// Unlike [`sha2::Sha256`], its state can be saved and restored, allowing the digest of an upload
+/// to be calculated across multiple requests without reading back data already received.
+#[derive(Clone, Debug)]
+struct UploadHasher {
+    /// Intermediate hash value.
+    state: [u32; 8],
+    /// Total number of bytes hashed.
+    length: u64,
+    /// Bytes not yet forming a full block.
+    pending: Vec<u8>,
+}
+
+impl UploadHasher {
+    fn new() -> Self {
+        Self {
+            state: SHA256_INITIAL_STATE,
+            length: 0,
+            pending: Vec::with_capacity(SHA256_BLOCK_LEN),
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        if !self.pending.is_empty() {
+            let take = (SHA256_BLOCK_LEN - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.pending.len() < SHA256_BLOCK_LEN {
+                return;
+            }
+
+            sha2::compress256(&mut self.state, &[*GenericArray::from_slice(&self.pending)]);
+            self.pending.clear();
+        }
+
+        let mut blocks = data.chunks_exact(SHA256_BLOCK_LEN);
+        for block in &mut blocks {
+            sha2::compress256(&mut self.state, &[*GenericArray::from_slice(block)]);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    fn finalize(mut self) -> Digest {
+        // Padding: a single `1` bit, zeros and the message length in bits.
+        let bit_length = self.length * 8;
+        self.pending.push(0x80);
+        while self.pending.len() % SHA256_BLOCK_LEN != SHA256_BLOCK_LEN - 8 {
+            self.pending.push(0);
+        }
+        self.pending.extend_from_slice(&bit_length.to_be_bytes());
+
+        for block in self.pending.chunks_exact(SHA256_BLOCK_LEN) {
+            sha2::compress256(&mut self.state, &[*GenericArray::from_slice(block)]);
+        }
+
+        let mut output = [0; SHA256_LEN];
+        for (chunk, word) in output.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        Digest::new(output)
+    }
+
+    /// Serializes the state as the state words and length, followed by the pending bytes.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(SHA256_LEN + 8 + self.pending.len());
+        for word in self.state {
+            buf.extend_from_slice(&word.to_be_bytes());
+        }
+        buf.extend_from_slice(&self.length.to_be_bytes());
+        buf.extend_from_slice(&self.pending);
+        buf
+    }
+
+    fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let (state_raw, rest) = raw.split_at_checked(SHA256_LEN)?;
+        let (length_raw, pending) = rest.split_at_checked(8)?;
+
+        let length = u64::from_be_bytes(length_raw.try_into().ok()?);
+        if pending.len() as u64 != length % SHA256_BLOCK_LEN as u64 {
+            return None;
+        }
+
+        let mut state = [0; 8];
+        for (word, chunk) in state.iter_mut().zip(state_raw.chunks_exact(4)) {
+            *word = u32::from_be_bytes(chunk.try_into().ok()?);
+        }
+
+        Some(Self {
+            state,
+            length,
+            pending: pending.to_vec(),
+        })
+    }
+}
+
+/// Writer for uploads, hashing all data as it is written.
+///
+/// The hasher state is saved to a sidecar record whenever the writer is flushed.
+struct HashingWriter {
+    file: tokio::fs::File,
+    hasher: UploadHasher,
+    hasher_path: PathBuf,
+    /// Save of the hasher state started by a flush that has not completed yet.
+    saving: Option<BoxFuture<'static, io::Result<()>>>,
+}
+
+impl AsyncWrite for HashingWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = ready!(Pin::new(&mut this.file).poll_write(cx, buf))?;
+        this.hasher.update(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let saving = match this.saving {
+            Some(ref mut saving) => saving,
+            None => {
+                ready!(Pin::new(&mut this.file).poll_flush(cx))?;
+                this.saving.insert(Box::pin(tokio::fs::write(
+                    this.hasher_path.clone(),
+                    this.hasher.to_bytes(),
+                )))
+            }
+        };
+
+        let result = ready!(saving.poll_unpin(cx));
+        this.saving = None;
+        Poll::Ready(result)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::Digest as Sha2Digest;
+
+    use std::{collections::HashSet, path::Path, sync::Arc, time::Duration};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::manifest::OCI_IMAGE_MANIFEST;
+
+    use super::{
+        build_storage, sharded_blob_path, Digest, FilesystemStorage, FilesystemStorageError,
+        ImageLocation, ManifestReference, Reference, RegistryStorage, StorageConfig, UploadHasher,
+    };
+
+    fn tempdir() -> tempdir::TempDir {
+        tempdir::TempDir::new("container-registry-test").expect("could not create tempdir")
+    }
+
+    #[test]
+    fn upload_hasher_matches_sha256() {
+        let data: Vec<u8> = (0..1000u32).map(|n| (n * 7) as u8).collect();
+
+        for len in [0, 1, 55, 56, 63, 64, 65, 119, 128, 1000] {
+            let expected = Digest::new(sha2::Sha256::digest(&data[..len]).into());
+
+            let mut hasher = UploadHasher::new();
+            for chunk in data[..len].chunks(17) {
+                hasher.update(chunk);
+
+                // Round-trip through the sidecar format after every update.
+                hasher = UploadHasher::from_bytes(&hasher.to_bytes()).expect("invalid state");
+            }
+
+            assert_eq!(hasher.finalize(), expected, "length {len}");
+        }
+    }
+
+    #[test]
+    fn storage_config_selects_filesystem_backend() {
+        let dir = tempdir();
+        let config: StorageConfig = serde_json::from_value(serde_json::json!({
+            "type": "filesystem",
+            "path": dir.path(),
+        }))
+        .expect("could not deserialize config");
+
+        assert_eq!(
+            config,
+            StorageConfig::Filesystem {
+                path: dir.path().to_owned(),
+                shard_depth: 2,
+                durable_writes: false,
+                compress_small_objects: None,
+            }
+        );
+        assert!(build_storage(&config).is_ok());
+    }
+
+    #[test]
+    fn storage_config_with_missing_root_fails() {
+        let config = StorageConfig::Filesystem {
+            path: "/does/not/exist".into(),
+            shard_depth: 2,
+            durable_writes: false,
+            compress_small_objects: None,
+        };
+
+        assert!(matches!(
+            build_storage(&config),
+            Err(FilesystemStorageError::CouldNotCanonicalizeRoot { .. })
+        ));
+    }
+
+    #[test]
+    fn blob_paths_are_sharded_by_depth() {
+        let digest = Digest::from_contents(b"sharded");
+        let hex = digest.to_string();
+        let blobs = Path::new("/storage/blobs");
+
+        assert_eq!(
+            sharded_blob_path(blobs, digest, 0),
+            blobs.join("sha256").join(&hex)
+        );
+        assert_eq!(
+            sharded_blob_path(blobs, digest, 1),
+            blobs.join("sha256").join(&hex[0..2]).join(&hex)
+        );
+        assert_eq!(
+            sharded_blob_path(blobs, digest, 2),
+            blobs
+                .join("sha256")
+                .join(&hex[0..2])
+                .join(&hex[2..4])
+                .join(&hex)
+        );
+    }
+
+    #[test]
+    fn excessive_shard_depth_is_rejected() {
+        let dir = tempdir();
+
+        assert!(matches!(
+            FilesystemStorage::new(dir.path(), 33),
+            Err(FilesystemStorageError::InvalidShardDepth(33))
+        ));
+    }
+
+    async fn store_blob(storage: &FilesystemStorage, contents: &[u8]) -> Digest {
+        let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+        store_blob_in(storage, &location, contents).await
+    }
+
+    async fn store_blob_in(
+        storage: &FilesystemStorage,
+        location: &ImageLocation,
+        contents: &[u8],
+    ) -> Digest {
+        let upload = storage.begin_new_upload(location).await.unwrap();
+        let mut writer = storage.get_upload_writer(0, upload).await.unwrap();
+        writer.write_all(contents).await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+
+        let digest = Digest::from_contents(contents);
+        storage.finalize_upload(upload, digest).await.unwrap();
+        digest
+    }
+
+    async fn read_blob(storage: &FilesystemStorage, digest: Digest) -> Vec<u8> {
+        let mut contents = Vec::new();
+        storage
+            .get_blob_reader(digest)
+            .await
+            .unwrap()
+            .expect("blob should exist")
+            .read_to_end(&mut contents)
+            .await
+            .unwrap();
+        contents
+    }
+
+    #[tokio::test]
+    async fn blobs_round_trip_under_sharding() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+
+        let digest = store_blob(&storage, b"sharded blob").await;
+        assert!(sharded_blob_path(&dir.path().join("blobs"), digest, 2).is_file());
+        assert_eq!(read_blob(&storage, digest).await, b"sharded blob");
+
+        let stats = storage.storage_stats().await.unwrap();
+        assert_eq!(stats.blob_count, 1);
+        assert_eq!(stats.repositories["tests/sample"].blob_count, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_tag_updates_are_never_torn() {
+        let dir = tempdir();
+        let storage = Arc::new(FilesystemStorage::new(dir.path(), 2).unwrap());
+        let reference = ManifestReference::new(
+            ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+            Reference::new_tag("latest"),
+        );
+
+        // Large manifests make partial writes observable, if they happen.
+        let manifests: Arc<Vec<Vec<u8>>> = Arc::new(
+            (0..4)
+                .map(|n| {
+                    serde_json::to_vec(&serde_json::json!({
+                        "schemaVersion": 2,
+                        "annotations": { "writer": n.to_string(), "padding": "x".repeat(256 * 1024) },
+                    }))
+                    .unwrap()
+                })
+                .collect(),
+        );
+
+        let writers: Vec<_> = (0..manifests.len())
+            .map(|n| {
+                let (storage, reference, manifests) =
+                    (storage.clone(), reference.clone(), manifests.clone());
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        storage
+                            .put_manifest(&reference, &manifests[n])
+                            .await
+                            .expect("failed to store manifest");
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (storage, reference, manifests) =
+                    (storage.clone(), reference.clone(), manifests.clone());
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        if let Some(observed) = storage.get_manifest(&reference).await.unwrap() {
+                            assert!(manifests.contains(&observed), "observed torn manifest");
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for task in writers.into_iter().chain(readers) {
+            task.await.expect("task panicked");
+        }
+
+        let last = storage.get_manifest(&reference).await.unwrap().unwrap();
+        assert!(manifests.contains(&last));
+    }
+
+    #[tokio::test]
+    async fn blob_existence_is_reported() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+
+        let missing = Digest::from_contents(b"never stored");
+        assert!(!storage.blob_exists(missing).await.unwrap());
+
+        let digest = store_blob(&storage, b"stored layer").await;
+        assert!(storage.blob_exists(digest).await.unwrap());
+        assert!(!storage.blob_exists(missing).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn identical_blobs_are_written_once() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+
+        let digest = store_blob(&storage, b"base layer").await;
+        let blob_path = sharded_blob_path(&dir.path().join("blobs"), digest, 2);
+        let inode = std::fs::metadata(&blob_path).unwrap().ino();
+
+        // A second upload of the same content is discarded, leaving the stored blob in place.
+        store_blob(&storage, b"base layer").await;
+        assert_eq!(std::fs::metadata(&blob_path).unwrap().ino(), inode);
+        assert_eq!(storage.storage_stats().await.unwrap().upload_count, 0);
+        assert_eq!(read_blob(&storage, digest).await, b"base layer");
+    }
+
+    #[tokio::test]
+    async fn blobs_are_shared_between_repositories() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+        let first = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+        let second = ImageLocation::new("other".to_owned(), "image".to_owned());
+
+        let digest = store_blob_in(&storage, &first, b"common layer").await;
+        store_blob_in(&storage, &second, b"common layer").await;
+
+        // One copy is stored, both repositories merely record having it.
+        assert_eq!(storage.storage_stats().await.unwrap().blob_count, 1);
+        for location in [&first, &second] {
+            assert_eq!(
+                storage
+                    .repository_blob_size(location, digest)
+                    .await
+                    .unwrap(),
+                Some(12)
+            );
+        }
+        assert_eq!(read_blob(&storage, digest).await, b"common layer");
+    }
+
+    #[tokio::test]
+    async fn blobs_are_migrated_to_new_shard_depth() {
+        let dir = tempdir();
+        let blobs = dir.path().join("blobs");
+
+        // Blobs used to be stored flat, without a recorded layout.
+        std::fs::create_dir(&blobs).unwrap();
+        let legacy = Digest::from_contents(b"legacy blob");
+        std::fs::write(blobs.join(legacy.to_string()), b"legacy blob").unwrap();
+
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+        assert!(!blobs.join(legacy.to_string()).exists());
+        assert_eq!(read_blob(&storage, legacy).await, b"legacy blob");
+        let digest = store_blob(&storage, b"current blob").await;
+        drop(storage);
+
+        let storage = FilesystemStorage::new(dir.path(), 1).unwrap();
+        for (digest, contents) in [(legacy, &b"legacy blob"[..]), (digest, b"current blob")] {
+            assert!(sharded_blob_path(&blobs, digest, 1).is_file());
+            assert_eq!(read_blob(&storage, digest).await, contents);
+        }
+        assert_eq!(std::fs::read_to_string(blobs.join("layout")).unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn garbage_collection_keeps_referenced_and_recent_blobs() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+
+        let layer = store_blob(&storage, b"referenced layer").await;
+        let orphan = store_blob(&storage, b"orphaned layer").await;
+        let manifest =
+            format!(r#"{{"schemaVersion":2,"layers":[{{"digest":"sha256:{layer}","size":16}}]}}"#);
+        let reference = ManifestReference::new(
+            ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+            Reference::new_tag("latest"),
+        );
+        storage
+            .put_manifest(&reference, manifest.as_bytes())
+            .await
+            .unwrap();
+
+        // Freshly stored blobs may belong to a push in progress.
+        let collection = storage
+            .garbage_collect(Duration::from_secs(3600), false)
+            .await
+            .unwrap();
+        assert!(collection.blobs.is_empty());
+
+        let collection = storage
+            .garbage_collect(Duration::ZERO, false)
+            .await
+            .unwrap();
+        let removed: Vec<_> = collection.blobs.iter().map(|blob| blob.digest).collect();
+        assert_eq!(removed, [orphan]);
+        assert_eq!(collection.bytes, b"orphaned layer".len() as u64);
+        assert!(storage.get_blob_metadata(orphan).await.unwrap().is_none());
+        assert_eq!(read_blob(&storage, layer).await, b"referenced layer");
+    }
+
+    #[tokio::test]
+    async fn uploading_a_blob_again_restarts_its_grace_period() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+
+        let digest = store_blob(&storage, b"layer pushed again").await;
+        std::fs::File::options()
+            .append(true)
+            .open(storage.blob_path(digest))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+
+        store_blob(&storage, b"layer pushed again").await;
+        let collection = storage
+            .garbage_collect(Duration::from_secs(3600), false)
+            .await
+            .unwrap();
+        assert!(collection.blobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn interrupted_repository_moves_are_completed_on_open() {
+        let dir = tempdir();
+        let from = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+        let to = ImageLocation::new("moved".to_owned(), "sample".to_owned());
+        let manifest = br#"{"schemaVersion":2}"#;
+
+        {
+            let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+            for tag in ["latest", "stable"] {
+                storage
+                    .put_manifest(
+                        &ManifestReference::new(from.clone(), Reference::new_tag(tag)),
+                        manifest,
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+
+        // Crash after journaling the move and moving the tags, but nothing else.
+        let root = dir.path();
+        for location_dir in ["repositories", "tag_history", "referrers", "tags"] {
+            std::fs::create_dir_all(root.join(location_dir).join("tests/sample")).unwrap();
+        }
+        std::fs::write(
+            root.join("moves/interrupted"),
+            serde_json::to_vec(&serde_json::json!({
+                "from": { "repository": "tests", "image": "sample" },
+                "to": { "repository": "moved", "image": "sample" },
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("tags/moved")).unwrap();
+        std::fs::rename(
+            root.join("tags/tests/sample"),
+            root.join("tags/moved/sample"),
+        )
+        .unwrap();
+
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+        assert_eq!(storage.list_tags(&from).await.unwrap(), None);
+        let mut tags = storage.list_tags(&to).await.unwrap().unwrap();
+        tags.sort();
+        assert_eq!(tags, ["latest", "stable"]);
+        assert!(!root.join("moves/interrupted").exists());
+
+        // Completed moves leave nothing behind to be repeated.
+        storage.move_repository(&to, &from, false).await.unwrap();
+        assert!(storage
+            .get_manifest(&ManifestReference::new(
+                from.clone(),
+                Reference::new_tag("stable")
+            ))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(matches!(
+            storage.move_repository(&to, &from, true).await,
+            Err(super::Error::LocationUnknown(_))
+        ));
+        assert!(std::fs::read_dir(root.join("moves"))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn tags_are_listed_consistently_during_pushes() {
+        let dir = tempdir();
+        let storage = Arc::new(FilesystemStorage::new(dir.path(), 2).unwrap());
+        let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+        let tag_names: Arc<Vec<String>> = Arc::new((0..64).map(|n| format!("v{n}")).collect());
+
+        storage
+            .put_manifest(
+                &ManifestReference::new(location.clone(), Reference::new_tag("v0")),
+                br#"{"schemaVersion":2}"#,
+            )
+            .await
+            .unwrap();
+        // Leftovers of other tools are not tags.
+        std::fs::write(dir.path().join("tags/tests/sample/.v1.swp"), b"").unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let (storage, location, tag_names) =
+                    (storage.clone(), location.clone(), tag_names.clone());
+                tokio::spawn(async move {
+                    for (n, tag) in tag_names.iter().enumerate().skip(writer) {
+                        let manifest = format!(r#"{{"schemaVersion":2,"n":{n}}}"#);
+                        storage
+                            .put_manifest(
+                                &ManifestReference::new(location.clone(), Reference::new_tag(tag)),
+                                manifest.as_bytes(),
+                            )
+                            .await
+                            .expect("failed to store manifest");
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (storage, location, tag_names) =
+                    (storage.clone(), location.clone(), tag_names.clone());
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        let tags = storage.list_tags(&location).await.unwrap().unwrap();
+                        assert!(tags.windows(2).all(|pair| pair[0] < pair[1]), "{tags:?}");
+                        assert!(tags.iter().all(|tag| tag_names.contains(tag)), "{tags:?}");
+                    }
+                })
+            })
+            .collect();
+
+        for task in writers.into_iter().chain(readers) {
+            task.await.expect("task panicked");
+        }
+
+        let mut expected = tag_names.to_vec();
+        expected.sort();
+        assert_eq!(
+            storage.list_tags(&location).await.unwrap().unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn referenced_blobs_follow_stored_children() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+        let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+
+        let descriptor = |media_type: &str, contents: &[u8]| {
+            serde_json::json!({
+                "mediaType": media_type,
+                "size": contents.len(),
+                "digest": format!("sha256:{}", Digest::from_contents(contents)),
+            })
+        };
+        let image = |config: &[u8], layer: &[u8]| {
+            serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": descriptor("application/vnd.oci.image.config.v1+json", config),
+                "layers": [descriptor("application/vnd.oci.image.layer.v1.tar", layer)],
+            })
+            .to_string()
+        };
+        let index = |children: &[&[u8]]| {
+            let manifests: Vec<_> = children
+                .iter()
+                .map(|child| descriptor("application/vnd.oci.image.manifest.v1+json", child))
+                .collect();
+            serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.index.v1+json",
+                "manifests": manifests,
+            })
+            .to_string()
+        };
+        let digests = |contents: &[&[u8]]| -> HashSet<Digest> {
+            contents.iter().map(|c| Digest::from_contents(c)).collect()
+        };
+
+        // Both images share a layer, the outer index references a child not stored locally.
+        let amd64 = image(b"amd64 config", b"shared layer");
+        let arm64 = image(b"arm64 config", b"shared layer");
+        let inner = index(&[amd64.as_bytes()]);
+        let outer = index(&[inner.as_bytes(), arm64.as_bytes(), b"missing child"]);
+        for manifest in [&amd64, &arm64, &inner] {
+            storage
+                .put_manifest(
+                    &ManifestReference::new(
+                        location.clone(),
+                        Reference::Digest(Digest::from_contents(manifest.as_bytes())),
+                    ),
+                    manifest.as_bytes(),
+                )
                 .await
-                .map_err(Error::Io)?;
+                .unwrap();
         }
 
-        let tmp_tag = self.temp_tag_path();
+        let referenced = storage
+            .referenced_blobs(amd64.as_bytes(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            referenced,
+            [
+                Digest::from_contents(b"amd64 config"),
+                Digest::from_contents(b"shared layer")
+            ]
+        );
 
-        tokio::fs::symlink(self.blob_rel_path(digest), &tmp_tag)
+        let referenced = storage
+            .referenced_blobs(outer.as_bytes(), None)
             .await
-            .map_err(Error::Io)?;
-        tokio::fs::rename(tmp_tag, tag).await.map_err(Error::Io)?;
+            .unwrap();
+        assert_eq!(referenced.len(), 7, "digests should be reported once");
+        assert_eq!(
+            referenced.into_iter().collect::<HashSet<_>>(),
+            digests(&[
+                inner.as_bytes(),
+                amd64.as_bytes(),
+                arm64.as_bytes(),
+                b"missing child",
+                b"amd64 config",
+                b"arm64 config",
+                b"shared layer",
+            ])
+        );
+    }
 
-        Ok(digest)
+    /// Stores `count` blobs and a manifest tagging each of them.
+    async fn push_images(storage: &FilesystemStorage, count: usize, blob_size: usize) {
+        for n in 0..count {
+            let mut contents = vec![0; blob_size];
+            contents[..8].copy_from_slice(&n.to_le_bytes());
+            let layer = store_blob(storage, &contents).await;
+            let manifest = format!(
+                r#"{{"schemaVersion":2,"layers":[{{"digest":"sha256:{layer}","size":{blob_size}}}]}}"#
+            );
+            let reference = ManifestReference::new(
+                ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+                Reference::new_tag(format!("v{n}")),
+            );
+            storage
+                .put_manifest(&reference, manifest.as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_resolve_to_the_manifest_digest() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+        push_images(&storage, 2, 16).await;
+
+        let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+        let manifest = storage
+            .get_manifest(&ManifestReference::new(
+                location.clone(),
+                Reference::new_tag("v1"),
+            ))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            storage.resolve_tag(&location, "v1").await.unwrap(),
+            Some(Digest::from_contents(&manifest))
+        );
+        assert_eq!(
+            storage.resolve_tag(&location, "unknown").await.unwrap(),
+            None
+        );
+
+        storage.delete_tag(&location, "v1").await.unwrap();
+        assert_eq!(storage.resolve_tag(&location, "v1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn manifest_media_types_are_kept_apart_from_blob_media_types() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2).unwrap();
+
+        // The same contents may be stored as a blob and pushed as a manifest.
+        let digest = store_blob(&storage, b"{}").await;
+        storage
+            .record_blob_media_type(digest, "application/vnd.oci.empty.v1+json")
+            .await
+            .unwrap();
+        storage
+            .record_manifest_media_type(digest, OCI_IMAGE_MANIFEST)
+            .await
+            .unwrap();
+
+        let metadata = storage.get_blob_metadata(digest).await.unwrap().unwrap();
+        assert_eq!(
+            metadata.media_type(),
+            Some("application/vnd.oci.empty.v1+json")
+        );
+        assert_eq!(
+            storage
+                .manifest_media_type(digest)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(OCI_IMAGE_MANIFEST)
+        );
+    }
+
+    #[tokio::test]
+    async fn durable_writes_store_blobs_and_manifests() {
+        let dir = tempdir();
+        let storage = FilesystemStorage::new(dir.path(), 2)
+            .unwrap()
+            .with_durable_writes(true);
+
+        push_images(&storage, 2, 64).await;
+
+        let stats = storage.storage_stats().await.unwrap();
+        assert_eq!(stats.blob_count, 2);
+        assert_eq!(stats.manifest_count, 2);
+        let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+        let tags = storage.list_tags(&location).await.unwrap();
+        assert_eq!(tags.map(|tags| tags.len()), Some(2));
+    }
+
+    /// Compares push throughput with and without durable writes.
+    ///
+    /// Run with `cargo test --all-features durable_writes_throughput -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn durable_writes_throughput() {
+        const IMAGES: usize = 200;
+        const BLOB_SIZE: usize = 256 * 1024;
+
+        for durable_writes in [false, true] {
+            let dir = tempdir();
+            let storage = FilesystemStorage::new(dir.path(), 2)
+                .unwrap()
+                .with_durable_writes(durable_writes);
+
+            let started = std::time::Instant::now();
+            push_images(&storage, IMAGES, BLOB_SIZE).await;
+            let elapsed = started.elapsed();
+
+            eprintln!(
+                "durable_writes={durable_writes}: {IMAGES} images in {elapsed:?}, {:.1} MiB/s",
+                (IMAGES * BLOB_SIZE) as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+            );
+        }
     }
 }
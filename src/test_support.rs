@@ -123,12 +123,15 @@ impl TestingContainerRegistry {
                 let listener = tokio::net::TcpListener::from_std(listener)
                     .expect("could not create tokio listener");
 
-                axum::serve(listener, app)
-                    .with_graceful_shutdown(async move {
-                        shutdown_receiver.recv().await;
-                    })
-                    .await
-                    .expect("axum io error");
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+                )
+                .with_graceful_shutdown(async move {
+                    shutdown_receiver.recv().await;
+                })
+                .await
+                .expect("axum io error");
             })
         });
 
@@ -1,9 +1,15 @@
-use std::sync::Arc;
+mod e2e;
+
+use std::{io::Read, sync::Arc, time::Duration};
 
 use axum::{
     body::Body,
     http::{
-        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, LOCATION},
+        header::{
+            ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, ALLOW, AUTHORIZATION, CONTENT_ENCODING,
+            CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK,
+            LOCATION, RANGE, RETRY_AFTER, VARY, WWW_AUTHENTICATE,
+        },
         Request, StatusCode,
     },
 };
@@ -14,10 +20,17 @@ use tokio::io::AsyncWriteExt;
 use tower::{util::ServiceExt, Service};
 
 use crate::{
-    auth::Anonymous,
+    auth::{
+        self, AllOf, Anonymous, AnyOf, AuthProvider, CachingAuthProvider, Permissions, Unverified,
+        ValidCredentials, Visibility,
+    },
+    manifest::{DOCKER_MANIFEST_V2, OCI_IMAGE_INDEX, OCI_IMAGE_MANIFEST},
+    metrics,
+    retention::RetentionPolicy,
+    schema1::DOCKER_MANIFEST_V1,
     storage::{ImageLocation, ManifestReference, Reference},
     test_support::TestingContainerRegistry,
-    ImageDigest,
+    BlobReadVerification, ImageDigest, MultiRangePolicy, ReferrerDeletePolicy, TagLimitPolicy,
 };
 
 use super::{storage::Digest, ContainerRegistry};
@@ -44,6 +57,13 @@ fn registry_with_test_password() -> TestingContainerRegistry {
         .build_for_testing()
 }
 
+fn registry_with_test_password_unverified_blobs() -> TestingContainerRegistry {
+    ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .verify_manifest_blobs(false)
+        .build_for_testing()
+}
+
 fn registry_with_test_password_and_full_anon_access() -> TestingContainerRegistry {
     ContainerRegistry::builder()
         .auth_provider(Arc::new(Anonymous::new(
@@ -175,7 +195,7 @@ const MANIFEST_DIGEST: ImageDigest = ImageDigest::new(Digest::new([
 #[tokio::test]
 async fn chunked_upload() {
     // See https://github.com/opencontainers/distribution-spec/blob/v1.0.1/spec.md#pushing-a-blob-in-chunks
-    let ctx = registry_with_test_password();
+    let ctx = registry_with_test_password_unverified_blobs();
     let mut service = ctx.make_service();
     let app = service.ready().await.expect("could not launch service");
 
@@ -206,7 +226,7 @@ async fn chunked_upload() {
     let mut sent = 0;
     for chunk in RAW_IMAGE.chunks(32) {
         assert!(!chunk.is_empty());
-        let range = format!("{sent}-{}", chunk.len() - 1);
+        let range = format!("{sent}-{}", sent + chunk.len() - 1);
         sent += chunk.len();
 
         let response = app
@@ -331,7 +351,9 @@ async fn chunked_upload() {
 /// Similar to `chunked_upload`, but uses no credentials to log in.
 #[tokio::test]
 async fn anonymous_upload() {
-    let ctx = ContainerRegistry::builder().build_for_testing();
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .build_for_testing();
 
     let mut service = ctx.make_service();
     let app = service.ready().await.expect("could not launch service");
@@ -362,7 +384,7 @@ async fn anonymous_upload() {
     let mut sent = 0;
     for chunk in RAW_IMAGE.chunks(32) {
         assert!(!chunk.is_empty());
-        let range = format!("{sent}-{}", chunk.len() - 1);
+        let range = format!("{sent}-{}", sent + chunk.len() - 1);
         sent += chunk.len();
 
         let response = app
@@ -471,7 +493,7 @@ async fn image_download() {
     let upload = ctx
         .registry
         .storage
-        .begin_new_upload()
+        .begin_new_upload(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
         .await
         .expect("could not start upload");
     let mut writer = ctx
@@ -594,3 +616,7179 @@ async fn collect_body(mut body: Body) -> Vec<u8> {
 
     rv
 }
+
+/// A mock upstream registry serving the fixtures under `library/sample`, requiring a bearer token.
+struct MockUpstream {
+    addr: std::net::SocketAddr,
+    manifest_hits: Arc<std::sync::atomic::AtomicUsize>,
+    blob_hits: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl MockUpstream {
+    async fn spawn() -> Self {
+        use axum::{
+            extract::State,
+            http::HeaderMap,
+            response::{IntoResponse, Response},
+            routing::get,
+            Router,
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone)]
+        struct MockState {
+            realm: String,
+            manifest_hits: Arc<AtomicUsize>,
+            blob_hits: Arc<AtomicUsize>,
+        }
+
+        /// Returns a bearer challenge if the request does not carry the mock token.
+        fn challenge(state: &MockState, headers: &HeaderMap) -> Option<Response> {
+            if headers.get(AUTHORIZATION).map(|v| v.as_bytes()) == Some(b"Bearer mock-token") {
+                return None;
+            }
+            let challenge = format!(
+                "Bearer realm=\"{}\",service=\"mock\",scope=\"repository:library/sample:pull\"",
+                state.realm
+            );
+            Some((StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, challenge)]).into_response())
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("could not bind mock upstream");
+        let addr = listener.local_addr().unwrap();
+
+        let state = MockState {
+            realm: format!("http://{addr}/token"),
+            manifest_hits: Default::default(),
+            blob_hits: Default::default(),
+        };
+
+        let app = Router::new()
+            .route("/token", get(|| async { r#"{"token":"mock-token"}"# }))
+            .route(
+                "/v2/library/sample/manifests/:reference",
+                get(
+                    |State(state): State<MockState>, headers: HeaderMap| async move {
+                        if let Some(challenge) = challenge(&state, &headers) {
+                            return challenge;
+                        }
+                        state.manifest_hits.fetch_add(1, Ordering::SeqCst);
+                        RAW_MANIFEST.into_response()
+                    },
+                ),
+            )
+            .route(
+                "/v2/library/sample/blobs/:digest",
+                get(
+                    |State(state): State<MockState>, headers: HeaderMap| async move {
+                        if let Some(challenge) = challenge(&state, &headers) {
+                            return challenge;
+                        }
+                        state.blob_hits.fetch_add(1, Ordering::SeqCst);
+                        RAW_IMAGE.into_response()
+                    },
+                )
+                .head(
+                    |State(state): State<MockState>, headers: HeaderMap| async move {
+                        if let Some(challenge) = challenge(&state, &headers) {
+                            return challenge;
+                        }
+                        [(CONTENT_LENGTH, RAW_IMAGE.len())].into_response()
+                    },
+                ),
+            )
+            .with_state(state.clone());
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        MockUpstream {
+            addr,
+            manifest_hits: state.manifest_hits,
+            blob_hits: state.blob_hits,
+        }
+    }
+
+    fn upstream(&self) -> crate::proxy::Upstream {
+        crate::proxy::Upstream::new(format!("http://{}", self.addr), "library")
+    }
+}
+
+#[tokio::test]
+async fn pull_through_cache_fetches_and_stores() {
+    use std::sync::atomic::Ordering;
+
+    let upstream = MockUpstream::spawn().await;
+    let ctx = ContainerRegistry::builder()
+        .upstream("mirror", upstream.upstream())
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    // Cache miss: fetches the manifest from upstream.
+    for expected_hits in [1, 1] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v2/mirror/sample/manifests/latest")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response.into_body()).await, RAW_MANIFEST);
+        assert_eq!(upstream.manifest_hits.load(Ordering::SeqCst), expected_hits);
+    }
+
+    // The manifest has been stored locally.
+    assert!(ctx
+        .registry
+        .storage
+        .get_manifest(&ManifestReference::new(
+            ImageLocation::new("mirror".to_owned(), "sample".to_owned()),
+            Reference::new_digest(MANIFEST_DIGEST.digest),
+        ))
+        .await
+        .expect("failed to get manifest")
+        .is_some());
+
+    // Checking for a blob asks the upstream, without fetching it.
+    let response = app
+        .call(
+            Request::builder()
+                .method("HEAD")
+                .uri(format!("/v2/mirror/sample/blobs/{}", IMAGE_DIGEST))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_LENGTH).unwrap(),
+        &RAW_IMAGE.len().to_string()
+    );
+    assert_eq!(upstream.blob_hits.load(Ordering::SeqCst), 0);
+    assert!(!ctx
+        .registry
+        .storage
+        .blob_exists(IMAGE_DIGEST.digest)
+        .await
+        .expect("failed to check blob"));
+
+    // Blobs are cached the same way, but only fetched once.
+    for _ in 0..2 {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/v2/mirror/sample/blobs/{}", IMAGE_DIGEST))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response.into_body()).await, RAW_IMAGE);
+        assert_eq!(upstream.blob_hits.load(Ordering::SeqCst), 1);
+    }
+
+    // Repositories that are not mirrored never reach the upstream.
+    let response = app
+        .call(
+            Request::builder()
+                .method("GET")
+                .uri("/v2/other/sample/manifests/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(upstream.manifest_hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn pull_through_cache_discards_rejected_blobs() {
+    use std::sync::atomic::Ordering;
+
+    let upstream = MockUpstream::spawn().await;
+
+    // The mock upstream serves the same blob for every digest, so a different digest mismatches.
+    let too_large = (RAW_IMAGE.len() as u64 - 1, IMAGE_DIGEST.digest);
+    let mismatching = (
+        RAW_IMAGE.len() as u64,
+        Digest::from_contents(b"something else"),
+    );
+    for (max_blob_size, digest) in [too_large, mismatching] {
+        let ctx = ContainerRegistry::builder()
+            .upstream("mirror", upstream.upstream())
+            .max_blob_size(max_blob_size)
+            .build_for_testing();
+        let mut service = ctx.make_service();
+        let app = service.ready().await.expect("could not launch service");
+
+        let response = app
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!(
+                        "/v2/mirror/sample/blobs/{}",
+                        ImageDigest::new(digest)
+                    ))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        // Nothing is stored, not even a partial upload.
+        assert!(!ctx
+            .registry
+            .storage
+            .blob_exists(digest)
+            .await
+            .expect("failed to check blob"));
+        assert!(ctx
+            .registry
+            .storage
+            .list_uploads()
+            .await
+            .expect("failed to list uploads")
+            .is_empty());
+    }
+
+    assert_eq!(upstream.blob_hits.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn pull_through_cache_refreshes_stale_tags() {
+    use std::sync::atomic::Ordering;
+
+    let upstream = MockUpstream::spawn().await;
+    let ctx = ContainerRegistry::builder()
+        .upstream(
+            "mirror",
+            upstream.upstream().tag_ttl(std::time::Duration::ZERO),
+        )
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for uri in [
+        "/v2/mirror/sample/manifests/latest".to_owned(),
+        "/v2/mirror/sample/manifests/latest".to_owned(),
+        format!("/v2/mirror/sample/manifests/{}", MANIFEST_DIGEST),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Both tag pulls went upstream, the digest was already known from the tag.
+    assert_eq!(upstream.manifest_hits.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn pull_through_cache_stops_contacting_failing_upstreams() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // An upstream never answering in time.
+    let hits = Arc::new(AtomicUsize::new(0));
+    let slow_upstream = axum::Router::new()
+        .route(
+            "/v2/library/sample/manifests/:reference",
+            axum::routing::get(
+                |axum::extract::State(hits): axum::extract::State<Arc<AtomicUsize>>| async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    RAW_MANIFEST
+                },
+            ),
+        )
+        .with_state(hits.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, slow_upstream).await });
+
+    let ctx = ContainerRegistry::builder()
+        .upstream(
+            "mirror",
+            crate::proxy::Upstream::new(format!("http://{addr}"), "library")
+                .read_timeout(Duration::from_millis(100))
+                .circuit_breaker(2, Duration::from_secs(3600)),
+        )
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let pull = || {
+        Request::builder()
+            .uri("/v2/mirror/sample/manifests/latest")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // Requests time out instead of waiting for the upstream.
+    for expected_hits in [1, 2] {
+        let response = tokio::time::timeout(Duration::from_secs(5), app.call(pull()))
+            .await
+            .expect("upstream request did not time out")
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(hits.load(Ordering::SeqCst), expected_hits);
+    }
+
+    // Past the threshold, the upstream is no longer contacted.
+    let response = app.call(pull()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn manifest_push_notifies_webhooks() {
+    use axum::{extract::State, routing::post, Router};
+
+    // A receiver forwarding all received events through a channel.
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<serde_json::Value>(4);
+    let receiver_app = Router::new()
+        .route(
+            "/events",
+            post(
+                |State(sender): State<tokio::sync::mpsc::Sender<serde_json::Value>>,
+                 headers: axum::http::HeaderMap,
+                 body: String| async move {
+                    assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Token webhook-secret");
+                    sender
+                        .send(serde_json::from_str(&body).unwrap())
+                        .await
+                        .unwrap();
+                },
+            ),
+        )
+        .with_state(sender);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, receiver_app).await });
+
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .webhook(
+            crate::webhooks::Webhook::new(format!("http://{receiver_addr}/events"))
+                .authorization(Secret::new("Token webhook-secret".to_owned())),
+        )
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, basic_auth())
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let payload = tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("timed out waiting for webhook")
+        .expect("receiver closed");
+
+    let events = payload["events"].as_array().expect("missing events");
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event["action"], "push");
+    assert_eq!(event["actor"]["name"], "user");
+    assert!(event["id"].is_string());
+    assert!(event["timestamp"].as_str().unwrap().ends_with('Z'));
+    assert_eq!(event["target"]["repository"], "tests/sample");
+    assert_eq!(event["target"]["tag"], "latest");
+    assert_eq!(event["target"]["digest"], MANIFEST_DIGEST.to_string());
+    assert_eq!(event["target"]["size"], RAW_MANIFEST.len());
+    assert_eq!(
+        event["target"]["mediaType"],
+        "application/vnd.docker.distribution.manifest.v2+json"
+    );
+}
+
+#[tokio::test]
+async fn health_endpoints_do_not_require_auth() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for endpoint in ["/healthz", "/readyz"] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri(endpoint)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn version_endpoint_reports_build_info() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/version")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let info: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!info["git_hash"].as_str().unwrap().is_empty());
+    assert!(info["build_timestamp"].as_str().unwrap().ends_with('Z'));
+}
+
+#[tokio::test]
+async fn capabilities_endpoint_reflects_configuration() {
+    let get_root = || Request::builder().uri("/").body(Body::empty()).unwrap();
+
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let response = app.call(get_root()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .capabilities_endpoint(true)
+        .delete_enabled(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let response = app.call(get_root()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let capabilities: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(capabilities["version"], env!("CARGO_PKG_VERSION"));
+    assert!(capabilities["manifest_media_types"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!(OCI_IMAGE_MANIFEST)));
+    assert_eq!(capabilities["delete_enabled"], true);
+    assert_eq!(capabilities["schema1_manifests"], false);
+    assert_eq!(capabilities["referrers_api"], true);
+    assert_eq!(capabilities["tags_pagination"], true);
+    assert_eq!(capabilities["auth_scheme"], "basic");
+}
+
+#[tokio::test]
+async fn readiness_fails_if_storage_is_unavailable() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    // Pull the rug out from under the storage backend.
+    let storage_path = ctx.temp_storage.as_ref().unwrap().path();
+    std::fs::remove_dir_all(storage_path.join("uploads")).unwrap();
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("GET")
+                .uri("/readyz")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Liveness is unaffected.
+    let response = app
+        .call(
+            Request::builder()
+                .method("GET")
+                .uri("/healthz")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn shutdown_finishes_ongoing_requests() {
+    use std::io::{Read, Write};
+
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let running = ctx.run_in_background();
+    let addr = running.bound_addr();
+
+    // Start a manifest upload, but only send half of the body.
+    let mut conn = std::net::TcpStream::connect(addr).expect("could not connect");
+    let (first_half, second_half) = RAW_MANIFEST.split_at(RAW_MANIFEST.len() / 2);
+    write!(
+        conn,
+        "PUT /v2/tests/sample/manifests/latest HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        RAW_MANIFEST.len()
+    )
+    .unwrap();
+    conn.write_all(first_half).unwrap();
+    conn.flush().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    // Trigger the shutdown, which blocks until the server has exited.
+    let shutdown = std::thread::spawn(move || drop(running));
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // No new connections are accepted...
+    assert!(std::net::TcpStream::connect(addr).is_err());
+
+    // ...but the ongoing request is still completed.
+    conn.write_all(second_half).unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 201"),
+        "unexpected response: {response}"
+    );
+
+    shutdown.join().expect("shutdown failed");
+}
+
+#[tokio::test]
+async fn oversized_blob_upload_is_rejected_and_removed() {
+    let ctx = ContainerRegistry::builder()
+        .max_blob_size(RAW_IMAGE.len() as u64 - 1)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let upload = ctx
+        .registry
+        .storage
+        .begin_new_upload(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
+        .await
+        .expect("could not start upload");
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .uri(format!("/v2/tests/sample/uploads/{upload}"))
+                .body(Body::from(RAW_IMAGE))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = collect_body(response.into_body()).await;
+    assert!(String::from_utf8(body).unwrap().contains("SIZE_INVALID"));
+
+    // The partial upload has been cleaned up.
+    assert!(matches!(
+        ctx.registry.storage.get_upload_size(upload).await,
+        Err(crate::storage::Error::UploadDoesNotExit)
+    ));
+}
+
+#[tokio::test]
+async fn oversized_manifest_is_rejected() {
+    let ctx = ContainerRegistry::builder()
+        .max_manifest_size(RAW_MANIFEST.len() as u64 - 1)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    assert!(ctx
+        .registry
+        .storage
+        .get_manifest(&ManifestReference::new(
+            ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+            Reference::new_tag("latest"),
+        ))
+        .await
+        .expect("failed to query manifest")
+        .is_none());
+}
+
+#[tokio::test]
+async fn manifest_body_limit_applies_before_buffering() {
+    let ctx = ContainerRegistry::builder()
+        .max_manifest_size(1024 * 1024)
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let huge_manifest = vec![b' '; 2 * 1024 * 1024];
+
+    // Rejected both with a known content length and when streaming.
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from(huge_manifest.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let stream = futures::stream::iter(
+        huge_manifest
+            .chunks(64 * 1024)
+            .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()))
+            .collect::<Vec<_>>(),
+    );
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from_stream(stream))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // A regular manifest still passes.
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn blob_uploads_are_exempt_from_body_limit() {
+    let ctx = ContainerRegistry::builder()
+        .max_manifest_size(16)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let upload = ctx
+        .registry
+        .storage
+        .begin_new_upload(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
+        .await
+        .expect("could not start upload");
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .uri(format!("/v2/tests/sample/uploads/{upload}"))
+                .body(Body::from(RAW_IMAGE))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn concurrent_requests_beyond_limit_are_shed() {
+    let ctx = ContainerRegistry::builder()
+        .max_concurrent_requests(2)
+        .max_concurrent_transfers(1)
+        .build_for_testing();
+    let storage = &ctx.registry.storage;
+    let upload = storage
+        .begin_new_upload(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
+        .await
+        .unwrap();
+    let mut writer = storage.get_upload_writer(0, upload).await.unwrap();
+    writer.write_all(RAW_IMAGE).await.unwrap();
+    writer.flush().await.unwrap();
+    drop(writer);
+    storage
+        .finalize_upload(upload, IMAGE_DIGEST.digest())
+        .await
+        .unwrap();
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let get = |uri: &str| {
+        Request::builder()
+            .uri(uri.to_owned())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let blob_uri = format!("/v2/tests/sample/blobs/{IMAGE_DIGEST}");
+
+    // A download is in flight as long as its body has not been consumed.
+    let download = app.call(get(&blob_uri)).await.unwrap();
+    assert_eq!(download.status(), StatusCode::OK);
+
+    // Another transfer exceeds the transfer limit, other requests are still allowed.
+    let response = app.call(get(&blob_uri)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[RETRY_AFTER], "1");
+    let body = collect_body(response.into_body()).await;
+    assert!(String::from_utf8_lossy(&body).contains("TOOMANYREQUESTS"));
+
+    let healthz = app.call(get("/healthz")).await.unwrap();
+    assert_eq!(healthz.status(), StatusCode::OK);
+
+    // With the health check response also pending, the overall limit is reached.
+    let response = app.call(get("/healthz")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    assert_eq!(collect_body(download.into_body()).await, RAW_IMAGE);
+    drop(healthz);
+
+    let response = app.call(get(&blob_uri)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn json_responses_are_compressed_on_request() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    ctx.registry
+        .storage
+        .put_manifest(
+            &ManifestReference::new(
+                ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+                Reference::new_tag("latest"),
+            ),
+            RAW_MANIFEST,
+        )
+        .await
+        .expect("failed to store manifest");
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    assert_eq!(
+        push_blob(app, &basic_auth(), RAW_IMAGE).await,
+        StatusCode::CREATED
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+    assert!(response.headers()[VARY]
+        .to_str()
+        .unwrap()
+        .contains("accept-encoding"));
+
+    let compressed = collect_body(response.into_body()).await;
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .expect("invalid gzip body");
+    assert_eq!(decompressed, RAW_MANIFEST);
+
+    // Without asking for it, no compression is applied.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!(collect_body(response.into_body()).await, RAW_MANIFEST);
+
+    // Blobs are never compressed.
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/blobs/{IMAGE_DIGEST}"))
+                .header(ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!(collect_body(response.into_body()).await, RAW_IMAGE);
+}
+
+#[tokio::test]
+async fn manifest_get_supports_conditional_requests() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    ctx.registry
+        .storage
+        .put_manifest(
+            &ManifestReference::new(
+                ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+                Reference::new_tag("latest"),
+            ),
+            RAW_MANIFEST,
+        )
+        .await
+        .expect("failed to store manifest");
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers().get(ETAG).expect("missing etag").clone();
+    assert_eq!(
+        etag.to_str().unwrap(),
+        format!(
+            "\"{}\"",
+            ImageDigest::new(Digest::from_contents(RAW_MANIFEST))
+        )
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("HEAD")
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(ETAG), Some(&etag));
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(IF_NONE_MATCH, etag.clone())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(ETAG), Some(&etag));
+    assert!(collect_body(response.into_body()).await.is_empty());
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(IF_NONE_MATCH, "\"sha256:outdated\"")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deleting_a_tag_keeps_shared_manifest() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .build_for_testing();
+    let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+    for tag in ["latest", "stable"] {
+        ctx.registry
+            .storage
+            .put_manifest(
+                &ManifestReference::new(location.clone(), Reference::new_tag(tag)),
+                RAW_MANIFEST,
+            )
+            .await
+            .expect("failed to store manifest");
+    }
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let delete_latest = || {
+        Request::builder()
+            .method("DELETE")
+            .header(AUTHORIZATION, basic_auth())
+            .uri("/v2/tests/sample/tags/latest")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(delete_latest()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    // Deleting again fails, the tag is gone.
+    let response = app.call(delete_latest()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    for (reference, expected) in [
+        ("latest".to_owned(), StatusCode::NOT_FOUND),
+        ("stable".to_owned(), StatusCode::OK),
+        (MANIFEST_DIGEST.to_string(), StatusCode::OK),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .header(AUTHORIZATION, basic_auth())
+                    .uri(format!("/v2/tests/sample/manifests/{reference}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected, "{reference}");
+    }
+
+    // Removing the last tag leaves the manifest unreferenced.
+    let deleted = ctx
+        .registry
+        .storage
+        .delete_tag(&location, "stable")
+        .await
+        .unwrap()
+        .expect("tag should exist");
+    assert_eq!(deleted.digest, MANIFEST_DIGEST.digest());
+    assert!(deleted.unreferenced);
+}
+
+#[tokio::test]
+async fn deletes_are_rejected_unless_enabled() {
+    let ctx = registry_with_test_password();
+    store_image_and_index(&ctx).await;
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("DELETE")
+                .header(AUTHORIZATION, basic_auth())
+                .uri("/v2/tests/sample/tags/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let body = collect_body(response.into_body()).await;
+    assert!(String::from_utf8_lossy(&body).contains("UNSUPPORTED"));
+
+    // The tag is untouched.
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, basic_auth())
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Stores [`RAW_MANIFEST`] as `tests/sample:latest` and an index pointing to it as `:multi`.
+async fn store_image_and_index(ctx: &TestingContainerRegistry) {
+    let index = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_INDEX}",
+            "manifests": [
+                {{
+                    "mediaType": "{DOCKER_MANIFEST_V2}",
+                    "digest": "{MANIFEST_DIGEST}",
+                    "size": {},
+                    "platform": {{ "architecture": "amd64", "os": "linux" }}
+                }}
+            ]
+        }}"#,
+        RAW_MANIFEST.len()
+    );
+
+    for (tag, body) in [("latest", RAW_MANIFEST), ("multi", index.as_bytes())] {
+        ctx.registry
+            .storage
+            .put_manifest(
+                &ManifestReference::new(
+                    ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+                    Reference::new_tag(tag),
+                ),
+                body,
+            )
+            .await
+            .expect("failed to store manifest");
+    }
+}
+
+#[tokio::test]
+async fn manifest_get_negotiates_media_types() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    store_image_and_index(&ctx).await;
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (tag, accept, expected) in [
+        ("latest", None, StatusCode::OK),
+        ("latest", Some(DOCKER_MANIFEST_V2), StatusCode::OK),
+        ("latest", Some("*/*"), StatusCode::OK),
+        ("latest", Some(OCI_IMAGE_MANIFEST), StatusCode::NOT_FOUND),
+        ("multi", None, StatusCode::OK),
+        ("multi", Some(OCI_IMAGE_INDEX), StatusCode::OK),
+        ("multi", Some(DOCKER_MANIFEST_V2), StatusCode::NOT_FOUND),
+        ("multi", Some(OCI_IMAGE_MANIFEST), StatusCode::NOT_FOUND),
+    ] {
+        let mut request = Request::builder().uri(format!("/v2/tests/sample/manifests/{tag}"));
+        if let Some(accept) = accept {
+            request = request.header(ACCEPT, format!("{accept};q=0.9, text/plain"));
+        }
+
+        let response = app
+            .call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected, "{tag} with {accept:?}");
+
+        if expected == StatusCode::NOT_FOUND {
+            let body = collect_body(response.into_body()).await;
+            assert!(String::from_utf8_lossy(&body).contains("MANIFEST_UNKNOWN"));
+        }
+    }
+}
+
+#[tokio::test]
+async fn manifest_get_resolves_indexes_to_platform() {
+    let ctx = ContainerRegistry::builder()
+        .resolve_index_platform("linux", "amd64")
+        .build_for_testing();
+    store_image_and_index(&ctx).await;
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/multi")
+                .header(ACCEPT, DOCKER_MANIFEST_V2)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        DOCKER_MANIFEST_V2
+    );
+    assert_eq!(
+        response.headers().get("Docker-Content-Digest").unwrap(),
+        &MANIFEST_DIGEST.to_string()
+    );
+    assert_eq!(collect_body(response.into_body()).await, RAW_MANIFEST);
+
+    // No child manifest is an OCI image manifest.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/multi")
+                .header(ACCEPT, OCI_IMAGE_MANIFEST)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn referrers_lists_manifests_by_subject() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let signature = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": "application/vnd.dev.cosign.artifact.sig.v1+json",
+            "config": {{
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2
+            }},
+            "layers": [],
+            "subject": {{
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "digest": "{MANIFEST_DIGEST}",
+                "size": {}
+            }},
+            "annotations": {{ "org.example.signer": "tests" }}
+        }}"#,
+        RAW_MANIFEST.len()
+    );
+    let signature_digest = ImageDigest::new(Digest::from_contents(signature.as_bytes()));
+
+    for (reference, body) in [
+        ("latest".to_owned(), RAW_MANIFEST.to_vec()),
+        (signature_digest.to_string(), signature.clone().into_bytes()),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/sample/manifests/{reference}"))
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/referrers/{MANIFEST_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/vnd.oci.image.index.v1+json"
+    );
+    let index: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(
+        index,
+        serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": signature_digest.to_string(),
+                "size": signature.len(),
+                "annotations": { "org.example.signer": "tests" },
+                "artifactType": "application/vnd.dev.cosign.artifact.sig.v1+json"
+            }]
+        })
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!(
+                    "/v2/tests/sample/referrers/{MANIFEST_DIGEST}?artifactType=application/spdx%2Bjson"
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("OCI-Filters-Applied").unwrap(),
+        "artifactType"
+    );
+    let index: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(index["manifests"], serde_json::json!([]));
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/referrers/{IMAGE_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let index: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(index["manifests"], serde_json::json!([]));
+}
+
+/// Returns a signature referring to [`RAW_MANIFEST`] as its `subject`.
+fn signature_manifest() -> String {
+    format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_MANIFEST}",
+            "artifactType": "application/vnd.dev.cosign.artifact.sig.v1+json",
+            "config": {{
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2
+            }},
+            "layers": [],
+            "subject": {{
+                "mediaType": "{DOCKER_MANIFEST_V2}",
+                "digest": "{MANIFEST_DIGEST}",
+                "size": {}
+            }}
+        }}"#,
+        RAW_MANIFEST.len()
+    )
+}
+
+/// Pushes [`RAW_MANIFEST`] by digest along with a signature referring to it as its `subject`.
+///
+/// Returns the digest of the signature.
+async fn push_signed_manifest(app: &mut axum::routing::RouterIntoService<Body>) -> ImageDigest {
+    let signature = signature_manifest();
+    let signature_digest = ImageDigest::new(Digest::from_contents(signature.as_bytes()));
+
+    for (digest, body) in [
+        (MANIFEST_DIGEST.to_string(), RAW_MANIFEST.to_vec()),
+        (signature_digest.to_string(), signature.into_bytes()),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/sample/manifests/{digest}"))
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    signature_digest
+}
+
+/// Returns the digests listed by the referrers API for [`MANIFEST_DIGEST`].
+async fn referrer_digests(app: &mut axum::routing::RouterIntoService<Body>) -> Vec<String> {
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/referrers/{MANIFEST_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let index: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    index["manifests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|descriptor| descriptor["digest"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn deleting_manifests_with_referrers_is_refused_by_default() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .delete_enabled(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let signature_digest = push_signed_manifest(app).await;
+
+    let delete = |digest: String| {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/v2/tests/sample/manifests/{digest}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(delete(MANIFEST_DIGEST.to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(errors["errors"][0]["code"], "DENIED");
+    assert_eq!(
+        errors["errors"][0]["detail"]["referrers"],
+        serde_json::json!([signature_digest.to_string()])
+    );
+    assert_eq!(
+        referrer_digests(app).await,
+        vec![signature_digest.to_string()]
+    );
+
+    // Once the signature is gone, the manifest can be deleted.
+    let response = app
+        .call(delete(signature_digest.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app.call(delete(MANIFEST_DIGEST.to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app.call(delete(MANIFEST_DIGEST.to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn deleting_manifests_can_cascade_to_referrers() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .delete_enabled(true)
+        .referrer_delete_policy(ReferrerDeletePolicy::Cascade)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let signature_digest = push_signed_manifest(app).await;
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/v2/tests/sample/manifests/{MANIFEST_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    for digest in [MANIFEST_DIGEST.to_string(), signature_digest.to_string()] {
+        let response = app
+            .call(
+                Request::builder()
+                    .uri(format!("/v2/tests/sample/manifests/{digest}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{digest}");
+    }
+    assert!(referrer_digests(app).await.is_empty());
+}
+
+#[tokio::test]
+async fn referrers_in_other_repositories_block_deletes() {
+    for policy in [ReferrerDeletePolicy::Refuse, ReferrerDeletePolicy::Cascade] {
+        let ctx = ContainerRegistry::builder()
+            .verify_manifest_blobs(false)
+            .delete_enabled(true)
+            .referrer_delete_policy(policy)
+            .build_for_testing();
+        let mut service = ctx.make_service();
+        let app = service.ready().await.expect("could not launch service");
+
+        let signature = signature_manifest();
+        let signature_digest = ImageDigest::new(Digest::from_contents(signature.as_bytes()));
+        for (location, digest, body) in [
+            ("tests/sample", MANIFEST_DIGEST, RAW_MANIFEST.to_vec()),
+            ("tests/other", signature_digest, signature.into_bytes()),
+        ] {
+            let response = app
+                .call(
+                    Request::builder()
+                        .method("PUT")
+                        .uri(format!("/v2/{location}/manifests/{digest}"))
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let response = app
+            .call(
+                Request::builder()
+                    .method("DELETE")
+                    .uri(format!("/v2/tests/sample/manifests/{MANIFEST_DIGEST}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT, "{policy:?}");
+        let errors: serde_json::Value =
+            serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+        assert_eq!(
+            errors["errors"][0]["detail"]["referrers"],
+            serde_json::json!([signature_digest.to_string()])
+        );
+
+        for (location, digest) in [
+            ("tests/sample", MANIFEST_DIGEST),
+            ("tests/other", signature_digest),
+        ] {
+            let response = app
+                .call(
+                    Request::builder()
+                        .uri(format!("/v2/{location}/manifests/{digest}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{policy:?} {location}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn deleting_tagged_manifests_is_refused() {
+    let ctx = ContainerRegistry::builder()
+        .delete_enabled(true)
+        .build_for_testing();
+    store_image_and_index(&ctx).await;
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/v2/tests/sample/manifests/{MANIFEST_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Returns the status of a `GET /v2/` request with the given `Authorization` header.
+async fn index_status(ctx: &TestingContainerRegistry, authorization: String) -> StatusCode {
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    app.call(
+        Request::builder()
+            .uri("/v2/")
+            .header(AUTHORIZATION, authorization)
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap()
+    .status()
+}
+
+#[tokio::test]
+async fn any_of_accepts_credentials_valid_for_any_provider() {
+    let users: std::collections::HashMap<String, Secret<String>> =
+        [("user".to_owned(), Secret::new(TEST_PASSWORD.to_owned()))].into();
+    let providers: Vec<Box<dyn AuthProvider>> = vec![
+        Box::new(Secret::new("master-key".to_owned())),
+        Box::new(users),
+    ];
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(AnyOf(providers)))
+        .build_for_testing();
+
+    assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&ctx, invalid_basic_auth()).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn all_of_requires_all_providers_to_accept() {
+    let providers: Vec<Box<dyn AuthProvider>> = vec![
+        Box::new(Permissions::ReadOnly),
+        Box::new(Secret::new(TEST_PASSWORD.to_owned())),
+    ];
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(AllOf(providers)))
+        .build_for_testing();
+
+    assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&ctx, invalid_basic_auth()).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Permissions are restricted to those granted by every provider.
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .uri("/v2/tests/sample/blobs/uploads/")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+/// Auth provider counting credential checks, deferring to the [`TEST_PASSWORD`].
+#[derive(Debug, Default)]
+struct CountingAuthProvider {
+    checks: std::sync::atomic::AtomicUsize,
+}
+
+#[axum::async_trait]
+impl AuthProvider for CountingAuthProvider {
+    async fn check_credentials(&self, unverified: &Unverified) -> Option<ValidCredentials> {
+        self.checks
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Secret::new(TEST_PASSWORD.to_owned())
+            .check_credentials(unverified)
+            .await
+    }
+
+    async fn image_permissions(
+        &self,
+        _creds: &ValidCredentials,
+        _image: &ImageLocation,
+    ) -> Permissions {
+        Permissions::ReadWrite
+    }
+
+    async fn blob_permissions(
+        &self,
+        _creds: &ValidCredentials,
+        _blob: &ImageDigest,
+    ) -> Permissions {
+        Permissions::ReadWrite
+    }
+
+    async fn registry_permissions(&self, _creds: &ValidCredentials) -> Permissions {
+        Permissions::ReadWrite
+    }
+}
+
+#[tokio::test]
+async fn caching_auth_provider_checks_credentials_once() {
+    let counting = Arc::new(CountingAuthProvider::default());
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(CachingAuthProvider::new(
+            counting.clone(),
+            std::time::Duration::from_secs(60),
+        )))
+        .build_for_testing();
+
+    for _ in 0..5 {
+        assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    }
+    assert_eq!(counting.checks.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // Failed checks are not cached.
+    for _ in 0..2 {
+        assert_eq!(
+            index_status(&ctx, invalid_basic_auth()).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    assert_eq!(counting.checks.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn caching_auth_provider_expires_entries() {
+    let counting = Arc::new(CountingAuthProvider::default());
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(CachingAuthProvider::new(
+            counting.clone(),
+            std::time::Duration::ZERO,
+        )))
+        .build_for_testing();
+
+    for _ in 0..3 {
+        assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    }
+    assert_eq!(counting.checks.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+/// A captured `tracing` event.
+#[derive(Debug)]
+struct CapturedEvent {
+    level: tracing::Level,
+    target: String,
+    fields: std::collections::HashMap<String, String>,
+}
+
+/// A `tracing` layer storing all events.
+#[derive(Clone, Debug, Default)]
+struct CaptureLayer {
+    events: Arc<std::sync::Mutex<Vec<CapturedEvent>>>,
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CaptureLayer {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        struct Visitor<'a>(&'a mut std::collections::HashMap<String, String>);
+
+        impl tracing::field::Visit for Visitor<'_> {
+            fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                self.0.insert(field.name().to_owned(), value.to_owned());
+            }
+
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                self.0
+                    .insert(field.name().to_owned(), format!("{:?}", value));
+            }
+        }
+
+        let mut fields = std::collections::HashMap::new();
+        event.record(&mut Visitor(&mut fields));
+        self.events.lock().unwrap().push(CapturedEvent {
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_owned(),
+            fields,
+        });
+    }
+}
+
+#[tokio::test]
+async fn failed_authentication_is_audited() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let capture = CaptureLayer::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(AUTHORIZATION, invalid_basic_auth())
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    [192, 0, 2, 1],
+                    4321,
+                ))))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let events = capture.events.lock().unwrap();
+    let audit: Vec<_> = events
+        .iter()
+        .filter(|event| event.target == crate::auth::AUDIT_TARGET)
+        .collect();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].level, tracing::Level::WARN);
+    let fields = &audit[0].fields;
+    assert_eq!(fields["username"], "user");
+    assert_eq!(fields["method"], "GET");
+    assert_eq!(
+        fields["route"],
+        "/v2/:repository/:image/manifests/:reference"
+    );
+    assert_eq!(fields["client_ip"], "192.0.2.1");
+    assert_eq!(fields["outcome"], "failure");
+    assert!(fields.values().all(|value| !value.contains(TEST_PASSWORD)));
+}
+
+/// Builds a registry authenticating `user` with the given stored password.
+fn registry_with_password_hash(stored: &str) -> TestingContainerRegistry {
+    let users: std::collections::HashMap<String, crate::auth::PasswordHash> =
+        [("user".to_owned(), stored.parse().unwrap())].into();
+    ContainerRegistry::builder()
+        .auth_provider(Arc::new(users))
+        .build_for_testing()
+}
+
+#[tokio::test]
+async fn hashmap_auth_accepts_bcrypt_hashes() {
+    let hash = bcrypt::hash(TEST_PASSWORD, 4).expect("failed to hash password");
+    assert!(matches!(
+        hash.parse(),
+        Ok(crate::auth::PasswordHash::Bcrypt(_))
+    ));
+
+    let ctx = registry_with_password_hash(&hash);
+    assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&ctx, invalid_basic_auth()).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn hashmap_auth_accepts_argon2_hashes() {
+    use argon2::{password_hash::SaltString, PasswordHasher};
+
+    let salt = SaltString::encode_b64(b"not-a-random-salt").expect("failed to encode salt");
+
+    let hash = argon2::Argon2::default()
+        .hash_password(TEST_PASSWORD.as_bytes(), &salt)
+        .expect("failed to hash password")
+        .to_string();
+    assert!(matches!(
+        hash.parse(),
+        Ok(crate::auth::PasswordHash::Argon2(_))
+    ));
+
+    let ctx = registry_with_password_hash(&hash);
+    assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&ctx, invalid_basic_auth()).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn hashmap_auth_accepts_plaintext_passwords() {
+    let ctx = registry_with_password_hash(TEST_PASSWORD);
+    assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&ctx, invalid_basic_auth()).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// Reads an auth config from the given environment.
+fn auth_config_from_env(
+    env: &[(&str, &str)],
+) -> Result<Option<auth::AuthConfig>, auth::AuthConfigError> {
+    auth::AuthConfig::from_lookup(|name| {
+        env.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| (*value).to_owned())
+    })
+}
+
+/// Builds a registry using the auth provider selected by the given environment.
+fn registry_with_auth_env(env: &[(&str, &str)]) -> TestingContainerRegistry {
+    let config = auth_config_from_env(env)
+        .expect("should parse auth config")
+        .expect("should select auth provider");
+    let provider = auth::build_auth_provider(config).expect("should build auth provider");
+
+    ContainerRegistry::builder()
+        .auth_provider(Arc::from(provider))
+        .build_for_testing()
+}
+
+#[tokio::test]
+async fn auth_providers_are_selected_by_environment() {
+    let htpasswd = tempdir::TempDir::new("container-registry-test").unwrap();
+    let htpasswd_path = htpasswd.path().join("htpasswd");
+    std::fs::write(
+        &htpasswd_path,
+        format!(
+            "# users\nuser:{}\n",
+            bcrypt::hash(TEST_PASSWORD, 4).expect("failed to hash password")
+        ),
+    )
+    .unwrap();
+    let users = serde_json::json!({ "user": TEST_PASSWORD }).to_string();
+
+    let none = registry_with_auth_env(&[(auth::AUTH_ENV, "none")]);
+    let master_key = registry_with_auth_env(&[
+        (auth::AUTH_ENV, "master_key"),
+        (auth::MASTER_KEY_ENV, TEST_PASSWORD),
+    ]);
+    let htpasswd = registry_with_auth_env(&[
+        (auth::AUTH_ENV, "htpasswd"),
+        (auth::HTPASSWD_ENV, htpasswd_path.to_str().unwrap()),
+    ]);
+    let users = registry_with_auth_env(&[(auth::AUTH_ENV, "users"), (auth::USERS_ENV, &users)]);
+
+    assert_eq!(index_status(&none, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&none, invalid_basic_auth()).await,
+        StatusCode::OK
+    );
+    for ctx in [master_key, htpasswd, users] {
+        assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+        assert_eq!(
+            index_status(&ctx, invalid_basic_auth()).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
+
+#[test]
+fn unconfigured_auth_providers_are_rejected() {
+    let build = |env: &[(&str, &str)]| {
+        auth_config_from_env(env)
+            .and_then(|config| auth::build_auth_provider(config.expect("should select provider")))
+            .err()
+    };
+
+    assert!(auth_config_from_env(&[]).unwrap().is_none());
+    assert!(matches!(
+        build(&[(auth::AUTH_ENV, "ldap")]),
+        Some(auth::AuthConfigError::UnknownProvider(_))
+    ));
+    assert!(matches!(
+        build(&[(auth::AUTH_ENV, "master_key")]),
+        Some(auth::AuthConfigError::NotConfigured {
+            setting: auth::MASTER_KEY_ENV,
+            ..
+        })
+    ));
+    assert!(matches!(
+        build(&[(auth::AUTH_ENV, "master_key"), (auth::MASTER_KEY_ENV, "")]),
+        Some(auth::AuthConfigError::NotConfigured { .. })
+    ));
+    assert!(matches!(
+        build(&[
+            (auth::AUTH_ENV, "htpasswd"),
+            (auth::HTPASSWD_ENV, "/nonexistent/htpasswd")
+        ]),
+        Some(auth::AuthConfigError::ReadHtpasswd { .. })
+    ));
+    assert!(matches!(
+        build(&[(auth::AUTH_ENV, "users"), (auth::USERS_ENV, "{}")]),
+        Some(auth::AuthConfigError::NoUsers("users"))
+    ));
+
+    // Apache MD5 hashes are not supported.
+    let htpasswd = tempdir::TempDir::new("container-registry-test").unwrap();
+    let path = htpasswd.path().join("htpasswd");
+    std::fs::write(&path, "user:$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/\n").unwrap();
+    assert!(matches!(
+        build(&[
+            (auth::AUTH_ENV, "htpasswd"),
+            (auth::HTPASSWD_ENV, path.to_str().unwrap())
+        ]),
+        Some(auth::AuthConfigError::InvalidHtpasswd { line: 1, .. })
+    ));
+}
+
+#[test]
+fn secrets_are_compared_as_fixed_length_digests() {
+    let secret = |value: &str| Secret::new(value.to_owned());
+
+    for value in ["", "a", TEST_PASSWORD, &TEST_PASSWORD.repeat(100)] {
+        assert_eq!(auth::secret_digest(&secret(value)).len(), 32);
+    }
+
+    assert!(auth::secrets_match(
+        &secret(TEST_PASSWORD),
+        &secret(TEST_PASSWORD)
+    ));
+    for wrong in ["", &TEST_PASSWORD[1..], &format!("{TEST_PASSWORD}x")] {
+        assert!(!auth::secrets_match(&secret(TEST_PASSWORD), &secret(wrong)));
+    }
+}
+
+#[tokio::test]
+async fn master_key_rejects_password_prefixes() {
+    let ctx = registry_with_test_password();
+    let prefix = base64::prelude::BASE64_STANDARD
+        .encode(format!("user:{}", &TEST_PASSWORD[..TEST_PASSWORD.len() - 1]).as_bytes());
+
+    assert_eq!(index_status(&ctx, basic_auth()).await, StatusCode::OK);
+    assert_eq!(
+        index_status(&ctx, format!("Basic {prefix}")).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn manifest_put_accepts_missing_foreign_layers() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    assert_eq!(
+        push_blob(app, &basic_auth(), RAW_IMAGE).await,
+        StatusCode::CREATED
+    );
+
+    let foreign_layer = ImageDigest::new(Digest::from_contents(b"windows base layer"));
+    let manifest = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {{
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "digest": "{IMAGE_DIGEST}",
+                "size": {}
+            }},
+            "layers": [{{
+                "mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+                "digest": "{foreign_layer}",
+                "size": 18,
+                "urls": ["https://mcr.microsoft.com/v2/windows/servercore/blobs/{foreign_layer}"]
+            }}]
+        }}"#,
+        RAW_IMAGE.len()
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(CONTENT_TYPE, DOCKER_MANIFEST_V2)
+                .body(Body::from(manifest))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn manifest_put_enforces_allowed_media_types() {
+    let ctx = ContainerRegistry::builder()
+        .allowed_media_type("application/vnd.oci.image.*")
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let push = |media_type: &str, config_media_type: &str| {
+        let manifest = format!(
+            r#"{{
+                "schemaVersion": 2,
+                "mediaType": "{media_type}",
+                "config": {{
+                    "mediaType": "{config_media_type}",
+                    "digest": "{IMAGE_DIGEST}",
+                    "size": {}
+                }},
+                "layers": []
+            }}"#,
+            RAW_IMAGE.len()
+        );
+        Request::builder()
+            .method("PUT")
+            .uri("/v2/tests/sample/manifests/latest")
+            .header(CONTENT_TYPE, media_type)
+            .body(Body::from(manifest))
+            .unwrap()
+    };
+
+    let response = app
+        .call(push(
+            OCI_IMAGE_MANIFEST,
+            "application/vnd.oci.image.config.v1+json",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    for (media_type, config_media_type) in [
+        (
+            DOCKER_MANIFEST_V2,
+            "application/vnd.docker.container.image.v1+json",
+        ),
+        (OCI_IMAGE_MANIFEST, "application/vnd.example.sbom.v1+json"),
+    ] {
+        let response = app.call(push(media_type, config_media_type)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let errors: serde_json::Value =
+            serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+        assert_eq!(errors["errors"][0]["code"], "MANIFEST_INVALID");
+    }
+}
+
+#[tokio::test]
+async fn manifest_put_validates_tags() {
+    let ctx = registry_with_test_password_unverified_blobs();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let push = |tag: &str| {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/v2/tests/sample/manifests/{tag}"))
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::from(RAW_MANIFEST))
+            .unwrap()
+    };
+
+    for tag in ["latest", "v1.2.3-rc_1", "_internal", &"a".repeat(128)] {
+        let response = app.call(push(tag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED, "{tag}");
+    }
+
+    for tag in [
+        &"a".repeat(129),
+        ".hidden",
+        "-dash",
+        "bad~tag",
+        "..",
+        "v1%2B2",
+    ] {
+        let response = app.call(push(tag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{tag}");
+        let errors: serde_json::Value =
+            serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+        assert_eq!(errors["errors"][0]["code"], "MANIFEST_INVALID");
+    }
+
+    // Invalid tags can never exist, reading them is rejected the same way.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/..")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn manifest_put_validates_manifests() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let put_manifest = |body: Vec<u8>| {
+        Request::builder()
+            .method("PUT")
+            .uri("/v2/tests/sample/manifests/latest")
+            .header(CONTENT_TYPE, "application/vnd.oci.image.manifest.v1+json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let manifest = |layer: &str| {
+        format!(
+            r#"{{
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": {{
+                    "mediaType": "application/vnd.oci.image.config.v1+json",
+                    "digest": "{IMAGE_DIGEST}",
+                    "size": {}
+                }},
+                "layers": [{{
+                    "mediaType": "application/vnd.oci.image.layer.v1.tar",
+                    "digest": "{layer}",
+                    "size": {}
+                }}]
+            }}"#,
+            RAW_IMAGE.len(),
+            RAW_IMAGE.len()
+        )
+        .into_bytes()
+    };
+
+    // Store the only blob referenced by the valid manifest.
+    let upload = ctx
+        .registry
+        .storage
+        .begin_new_upload(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
+        .await
+        .expect("failed to begin upload");
+    let mut writer = ctx
+        .registry
+        .storage
+        .get_upload_writer(0, upload)
+        .await
+        .expect("failed to get writer");
+    writer.write_all(RAW_IMAGE).await.unwrap();
+    writer.flush().await.unwrap();
+    drop(writer);
+    ctx.registry
+        .storage
+        .finalize_upload(upload, IMAGE_DIGEST.digest())
+        .await
+        .expect("failed to finalize upload");
+
+    let response = app
+        .call(put_manifest(b"{\"schemaVersion\": 2".to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(collect_body(response.into_body()).await)
+        .unwrap()
+        .contains("MANIFEST_INVALID"));
+
+    let response = app
+        .call(put_manifest(manifest("sha256:bad")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(collect_body(response.into_body()).await)
+        .unwrap()
+        .contains("MANIFEST_INVALID"));
+
+    let response = app
+        .call(put_manifest(manifest(&MANIFEST_DIGEST.to_string())))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(collect_body(response.into_body()).await)
+        .unwrap()
+        .contains("MANIFEST_BLOB_UNKNOWN"));
+
+    let response = app
+        .call(put_manifest(manifest(&IMAGE_DIGEST.to_string())))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[test]
+fn glob_patterns_match() {
+    use super::glob_matches;
+
+    assert!(glob_matches("v*", "v1.2.3"));
+    assert!(glob_matches("v*", "v"));
+    assert!(glob_matches("release-*", "release-2024"));
+    assert!(glob_matches("*-stable", "1.0-stable"));
+    assert!(glob_matches("v?.*", "v1.0"));
+    assert!(glob_matches("latest", "latest"));
+    assert!(!glob_matches("v*", "latest"));
+    assert!(!glob_matches("release-*", "release"));
+    assert!(!glob_matches("v?", "v10"));
+}
+
+#[tokio::test]
+async fn immutable_tags_cannot_be_overwritten() {
+    let ctx = ContainerRegistry::builder()
+        .immutable_tag("v*")
+        .immutable_tag("release-*")
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    // A second, different but valid manifest.
+    let other_manifest = String::from_utf8(RAW_MANIFEST.to_vec()).unwrap().replacen(
+        '{',
+        "{\"annotations\": {\"variant\": \"other\"},",
+        1,
+    );
+
+    let mut push = |tag: &'static str, body: Vec<u8>| {
+        app.call(
+            Request::builder()
+                .method("PUT")
+                .uri(format!("/v2/tests/sample/manifests/{tag}"))
+                .body(Body::from(body))
+                .unwrap(),
+        )
+    };
+
+    for tag in ["v1.0.0", "release-1"] {
+        let response = push(tag, RAW_MANIFEST.to_vec()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // Pushing the identical manifest again is idempotent.
+        let response = push(tag, RAW_MANIFEST.to_vec()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = push(tag, other_manifest.clone().into_bytes())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(String::from_utf8(collect_body(response.into_body()).await)
+            .unwrap()
+            .contains("DENIED"));
+    }
+
+    let response = push("latest", RAW_MANIFEST.to_vec()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = push("latest", other_manifest.clone().into_bytes())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // The immutable tag still points to the original manifest.
+    let stored = ctx
+        .registry
+        .storage
+        .get_manifest(&ManifestReference::new(
+            ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+            Reference::new_tag("v1.0.0"),
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored, RAW_MANIFEST);
+}
+
+#[tokio::test]
+async fn write_once_registries_never_move_tags() {
+    let ctx = ContainerRegistry::builder()
+        .write_once(true)
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let other_manifest = String::from_utf8(RAW_MANIFEST.to_vec()).unwrap().replacen(
+        '{',
+        "{\"annotations\": {\"variant\": \"other\"},",
+        1,
+    );
+
+    let mut push = |reference: String, body: Vec<u8>| {
+        app.call(
+            Request::builder()
+                .method("PUT")
+                .uri(format!("/v2/tests/sample/manifests/{reference}"))
+                .body(Body::from(body))
+                .unwrap(),
+        )
+    };
+
+    let response = push("latest".to_owned(), RAW_MANIFEST.to_vec())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = push("latest".to_owned(), other_manifest.clone().into_bytes())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert!(String::from_utf8(collect_body(response.into_body()).await)
+        .unwrap()
+        .contains("DENIED"));
+
+    // New tags and pushes by digest are still accepted.
+    let response = push("stable".to_owned(), other_manifest.clone().into_bytes())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let digest = ImageDigest::new(Digest::from_contents(RAW_MANIFEST));
+    let response = push(digest.to_string(), RAW_MANIFEST.to_vec())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn admin_usage_reports_storage_usage() {
+    let ctx = registry_with_test_password_and_full_anon_access();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blobs: [&[u8]; 2] = [b"first blob", b"the second blob"];
+    for blob in blobs {
+        let upload = ctx
+            .registry
+            .storage
+            .begin_new_upload(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
+            .await
+            .unwrap();
+        let mut writer = ctx
+            .registry
+            .storage
+            .get_upload_writer(0, upload)
+            .await
+            .unwrap();
+        writer.write_all(blob).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        ctx.registry
+            .storage
+            .finalize_upload(upload, Digest::from_contents(blob))
+            .await
+            .unwrap();
+    }
+
+    // Anonymous users may not query usage.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/admin/usage")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/admin/usage")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let usage: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(
+        usage,
+        serde_json::json!({
+            "total_bytes": blobs[0].len() + blobs[1].len(),
+            "blob_count": 2,
+            "manifest_count": 0,
+            "upload_count": 0,
+            "repositories": {
+                "tests/sample": {
+                    "blob_count": 2,
+                    "blob_bytes": blobs[0].len() + blobs[1].len(),
+                },
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn cors_preflight_is_answered_for_allowed_origins() {
+    let ctx = ContainerRegistry::builder()
+        .cors(crate::cors::Cors::new([
+            axum::http::HeaderValue::from_static("https://ui.example.com"),
+        ]))
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let preflight = |origin: &'static str| {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/v2/tests/sample/manifests/latest")
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "PUT")
+            .header(
+                "Access-Control-Request-Headers",
+                "authorization,docker-content-digest",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(preflight("https://ui.example.com")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://ui.example.com"
+    );
+    assert!(headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .contains("PUT"));
+    let allowed_headers = headers["access-control-allow-headers"].to_str().unwrap();
+    for header in ["authorization", "docker-content-digest", "range"] {
+        assert!(allowed_headers.contains(header), "{header} not allowed");
+    }
+
+    let response = app
+        .call(preflight("https://evil.example.com"))
+        .await
+        .unwrap();
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+
+    // Actual requests expose the registry's response headers.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/")
+                .header("Origin", "https://ui.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let exposed = response.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap();
+    for header in ["docker-content-digest", "location", "range"] {
+        assert!(exposed.contains(header), "{header} not exposed");
+    }
+}
+
+#[tokio::test]
+async fn cors_is_disabled_by_default() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/")
+                .header("Origin", "https://ui.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+}
+
+/// Pushes a blob in a single chunk, returning the status of the finalizing `PUT`.
+async fn push_blob(
+    app: &mut axum::routing::RouterIntoService<Body>,
+    authorization: &str,
+    blob: &[u8],
+) -> StatusCode {
+    let location = stage_blob(app, authorization, blob).await;
+
+    app.call(finalize_request(authorization, &location, blob))
+        .await
+        .unwrap()
+        .status()
+}
+
+/// Starts an upload to `tests/sample` and sends the blob, returning the location to finalize it.
+async fn stage_blob(
+    app: &mut axum::routing::RouterIntoService<Body>,
+    authorization: &str,
+    blob: &[u8],
+) -> String {
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, authorization)
+                .uri("/v2/tests/sample/blobs/uploads/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .header(AUTHORIZATION, authorization)
+                .header(CONTENT_LENGTH, blob.len())
+                .header(CONTENT_RANGE, format!("0-{}", blob.len() - 1))
+                .uri(&location)
+                .body(Body::from(blob.to_vec()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    location
+}
+
+fn finalize_request(authorization: &str, location: &str, blob: &[u8]) -> Request<Body> {
+    let digest = ImageDigest::new(Digest::from_contents(blob));
+    Request::builder()
+        .method("PUT")
+        .header(AUTHORIZATION, authorization)
+        .uri(format!("{location}?digest={digest}"))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Starts an upload to `tests/sample`, returning its location.
+async fn start_upload(app: &mut axum::routing::RouterIntoService<Body>) -> String {
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, basic_auth())
+                .uri("/v2/tests/sample/blobs/uploads/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    response.headers()[LOCATION].to_str().unwrap().to_owned()
+}
+
+fn chunk_request(location: &str, content_range: &str, chunk: &[u8]) -> Request<Body> {
+    Request::builder()
+        .method("PATCH")
+        .header(AUTHORIZATION, basic_auth())
+        .header(CONTENT_LENGTH, chunk.len())
+        .header(CONTENT_RANGE, content_range)
+        .uri(location)
+        .body(Body::from(chunk.to_vec()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn uploads_resume_after_reconnect() {
+    let ctx = registry_with_test_password();
+    let blob: Vec<u8> = (0..100u32).map(|n| n as u8).collect();
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let location = start_upload(app).await;
+    for (range, chunk) in [("0-29", &blob[..30]), ("30-59", &blob[30..60])] {
+        let response = app
+            .call(chunk_request(&location, range, chunk))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    // The connection drops, the client reconnects and asks how far the upload got.
+    drop(service);
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, basic_auth())
+                .uri(&location)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()[RANGE], "0-59");
+    assert_eq!(response.headers()[LOCATION], location.as_str());
+
+    // Resuming anywhere but the current offset is rejected.
+    let response = app
+        .call(chunk_request(&location, "30-99", &blob[30..]))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[RANGE], "0-59");
+
+    let response = app
+        .call(chunk_request(&location, "60-99", &blob[60..]))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()[RANGE], "0-99");
+
+    let response = app
+        .call(finalize_request(&basic_auth(), &location, &blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let mut reader = ctx
+        .registry
+        .storage
+        .get_blob_reader(Digest::from_contents(&blob))
+        .await
+        .unwrap()
+        .expect("blob should be stored");
+    let mut stored = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut stored)
+        .await
+        .unwrap();
+    assert_eq!(stored, blob);
+}
+
+#[tokio::test]
+async fn finalize_rejects_short_final_chunk() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let location = start_upload(app).await;
+    let response = app
+        .call(chunk_request(&location, "0-9", b"first part"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    // The final chunk declares ten bytes, but only four arrive.
+    let response = app
+        .call(chunk_request(&location, "10-19", b"last"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = app
+        .call(finalize_request(
+            &basic_auth(),
+            &location,
+            b"first partlast",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(body["errors"][0]["code"], "SIZE_INVALID");
+
+    // The bad upload is deleted.
+    let response = app
+        .call(finalize_request(
+            &basic_auth(),
+            &location,
+            b"first partlast",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn finalize_checks_declared_size() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blob = b"exactly twenty bytes";
+    let location = start_upload(app).await;
+    let response = app
+        .call(chunk_request(&location, "0-19", blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let mut request = finalize_request(&basic_auth(), &location, blob);
+    request
+        .headers_mut()
+        .insert(CONTENT_RANGE, "0-29".parse().unwrap());
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let location = start_upload(app).await;
+    app.call(chunk_request(&location, "0-19", blob))
+        .await
+        .unwrap();
+    let mut request = finalize_request(&basic_auth(), &location, blob);
+    request
+        .headers_mut()
+        .insert(CONTENT_RANGE, "0-19".parse().unwrap());
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn out_of_order_chunks_are_rejected() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let location = start_upload(app).await;
+    let response = app
+        .call(chunk_request(&location, "0-9", b"first part"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    for content_range in ["5-14", "20-29", "garbage"] {
+        let response = app
+            .call(chunk_request(&location, content_range, b"next part!"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            "{content_range}"
+        );
+        assert_eq!(response.headers()[RANGE], "0-9");
+    }
+
+    // The upload is unaffected and can be continued.
+    let response = app
+        .call(chunk_request(&location, "10-19", b"next part!"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app
+        .call(finalize_request(
+            &basic_auth(),
+            &location,
+            b"first partnext part!",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn concurrent_finalizes_of_identical_blobs_succeed() {
+    let ctx = registry_with_test_password();
+    let mut first_service = ctx.make_service();
+    let first = first_service
+        .ready()
+        .await
+        .expect("could not launch service");
+    let mut second_service = ctx.make_service();
+    let second = second_service
+        .ready()
+        .await
+        .expect("could not launch service");
+
+    let blob = b"popular base layer";
+    let first_location = stage_blob(first, &basic_auth(), blob).await;
+    let second_location = stage_blob(second, &basic_auth(), blob).await;
+
+    let (first_response, second_response) = tokio::join!(
+        first.call(finalize_request(&basic_auth(), &first_location, blob)),
+        second.call(finalize_request(&basic_auth(), &second_location, blob)),
+    );
+    assert_eq!(first_response.unwrap().status(), StatusCode::CREATED);
+    assert_eq!(second_response.unwrap().status(), StatusCode::CREATED);
+
+    // Both uploads are gone, only a single blob remains.
+    let stats = ctx.registry.storage.storage_stats().await.unwrap();
+    assert_eq!(stats.upload_count, 0);
+    assert_eq!(stats.blob_count, 1);
+}
+
+#[tokio::test]
+async fn finalize_asks_to_retry_while_blob_is_finalized_concurrently() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .concurrent_finalize_wait(Duration::from_millis(10))
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blob = b"slowly finalized layer";
+    let location = stage_blob(app, &basic_auth(), blob).await;
+
+    let finalizing = ctx
+        .registry
+        .finalizing
+        .acquire(Digest::from_contents(blob), Some(Duration::ZERO))
+        .await
+        .expect("should not be in flight");
+    let response = app
+        .call(finalize_request(&basic_auth(), &location, blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()[RETRY_AFTER], "1");
+    assert_eq!(response.headers()[LOCATION], location.as_str());
+
+    // The upload is kept, retrying succeeds once the concurrent finalization completes.
+    drop(finalizing);
+    let response = app
+        .call(finalize_request(&basic_auth(), &location, blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+/// Starts an upload with the given extra headers, returning the `Location` of the response.
+async fn upload_location(ctx: &TestingContainerRegistry, headers: &[(&str, &str)]) -> String {
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let mut request = Request::builder()
+        .method("POST")
+        .header(AUTHORIZATION, basic_auth())
+        .uri("/v2/tests/sample/blobs/uploads/");
+    for &(name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    let response = app
+        .call(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    response.headers()[LOCATION].to_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn upload_location_honors_forwarded_headers() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .base_url("http://internal:3000/")
+        .trust_forwarded_headers(true)
+        .build_for_testing();
+
+    for headers in [
+        &[
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "public-host, proxy-2"),
+        ][..],
+        &[(
+            "Forwarded",
+            "for=192.0.2.60;proto=https;host=\"public-host\"",
+        )][..],
+        &[("X-Forwarded-Proto", "https"), ("Host", "public-host")][..],
+    ] {
+        let location = upload_location(&ctx, headers).await;
+        assert!(
+            location.starts_with("https://public-host/v2/tests/sample/blobs/uploads/"),
+            "{location} for {headers:?}"
+        );
+    }
+
+    // Without forwarding headers, or with forged ones, the configured base is used.
+    for headers in [&[][..], &[("X-Forwarded-Host", "evil.example/path")][..]] {
+        let location = upload_location(&ctx, headers).await;
+        assert!(
+            location.starts_with("http://internal:3000/v2/tests/sample/blobs/uploads/"),
+            "{location} for {headers:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn upload_locations_are_routable() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let location = upload_location(&ctx, &[]).await;
+    assert!(location.starts_with("/v2/tests/sample/blobs/uploads/"));
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .header(AUTHORIZATION, &auth)
+                .uri(&location)
+                .body(Body::from(RAW_IMAGE))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()[LOCATION], location.as_str());
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .uri(format!("{location}?digest={IMAGE_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // The finished upload points to the blob.
+    let blob_location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+    assert_eq!(
+        blob_location,
+        format!("/v2/tests/sample/blobs/{IMAGE_DIGEST}")
+    );
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, &auth)
+                .uri(blob_location)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(collect_body(response.into_body()).await, RAW_IMAGE);
+}
+
+#[tokio::test]
+async fn upload_chunks_are_written() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let location = upload_location(&ctx, &[]).await;
+    let upload: uuid::Uuid = location.rsplit('/').next().unwrap().parse().unwrap();
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .header(AUTHORIZATION, basic_auth())
+                .uri(&location)
+                .body(Body::from(RAW_IMAGE))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()["Docker-Upload-UUID"], upload.to_string());
+
+    assert_eq!(
+        ctx.registry.storage.get_upload_size(upload).await.unwrap(),
+        RAW_IMAGE.len() as u64
+    );
+}
+
+#[tokio::test]
+async fn absolute_locations_fall_back_to_host() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .absolute_locations(true)
+        .build_for_testing();
+
+    let location = upload_location(&ctx, &[("Host", "registry.local:3000")]).await;
+    assert!(
+        location.starts_with("http://registry.local:3000/v2/tests/sample/blobs/uploads/"),
+        "{location}"
+    );
+}
+
+#[tokio::test]
+async fn upload_location_ignores_untrusted_forwarded_headers() {
+    let ctx = registry_with_test_password();
+
+    let location = upload_location(
+        &ctx,
+        &[
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "public-host"),
+        ],
+    )
+    .await;
+    assert!(
+        location.starts_with("/v2/tests/sample/blobs/uploads/"),
+        "{location}"
+    );
+}
+
+#[tokio::test]
+async fn blob_check_reports_media_type_from_manifest() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let config: &[u8] = b"{}";
+    let layer: &[u8] = b"not referenced by any manifest";
+    assert_eq!(push_blob(app, &auth, config).await, StatusCode::CREATED);
+    assert_eq!(push_blob(app, &auth, layer).await, StatusCode::CREATED);
+
+    let config_digest = ImageDigest::new(Digest::from_contents(config));
+    let manifest = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_MANIFEST}",
+            "config": {{
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": "{config_digest}",
+                "size": {}
+            }},
+            "layers": []
+        }}"#,
+        config.len()
+    );
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .header(CONTENT_TYPE, OCI_IMAGE_MANIFEST)
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from(manifest))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    for (blob, media_type) in [
+        (config, "application/vnd.oci.image.config.v1+json"),
+        (layer, "application/octet-stream"),
+    ] {
+        let digest = ImageDigest::new(Digest::from_contents(blob));
+        let response = app
+            .call(
+                Request::builder()
+                    .method("HEAD")
+                    .header(AUTHORIZATION, &auth)
+                    .uri(format!("/v2/tests/sample/blobs/{digest}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], media_type);
+        assert_eq!(response.headers()[CONTENT_LENGTH], blob.len().to_string());
+    }
+}
+
+#[tokio::test]
+async fn chunked_uploads_are_hashed_incrementally() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let blob: Vec<u8> = (0..300u32).map(|n| (n % 251) as u8).collect();
+    let digest = ImageDigest::new(Digest::from_contents(&blob));
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, &auth)
+                .uri("/v2/tests/sample/blobs/uploads/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+    let upload = location.rsplit('/').next().unwrap().to_owned();
+
+    // Chunk sizes are chosen to not line up with SHA256 blocks.
+    let mut offset = 0;
+    for chunk in [&blob[..37], &blob[37..200], &blob[200..]] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PATCH")
+                    .header(AUTHORIZATION, &auth)
+                    .header(CONTENT_LENGTH, chunk.len())
+                    .header(
+                        CONTENT_RANGE,
+                        format!("{}-{}", offset, offset + chunk.len() - 1),
+                    )
+                    .uri(&location)
+                    .body(Body::from(chunk.to_vec()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        offset += chunk.len();
+    }
+
+    // Overwrite the received data: finalizing must rely on the digest calculated while
+    // receiving, not read the upload back.
+    let partial = ctx
+        .temp_storage
+        .as_ref()
+        .unwrap()
+        .path()
+        .join("uploads")
+        .join(format!("{upload}.partial"));
+    std::fs::write(&partial, vec![0; blob.len()]).unwrap();
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .uri(format!("{location}?digest={digest}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()["Docker-Content-Digest"],
+        digest.to_string()
+    );
+
+    // A single-shot upload of the same data yields the same digest.
+    assert_eq!(push_blob(app, &auth, &blob).await, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn large_blobs_are_streamed() {
+    use sha2::Digest as _;
+
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    // The blob is generated while sending, it is never held in memory as a whole.
+    const CHUNK_LEN: usize = 1024 * 1024;
+    const CHUNKS: usize = 32;
+    let chunk = |n: usize| -> Vec<u8> { (0..CHUNK_LEN).map(|i| ((i + n) % 251) as u8).collect() };
+
+    let mut hasher = sha2::Sha256::new();
+    for n in 0..CHUNKS {
+        hasher.update(chunk(n));
+    }
+    let digest = ImageDigest::new(Digest::new(hasher.finalize().into()));
+
+    let location = start_upload(app).await;
+    let body = futures::stream::iter((0..CHUNKS).map(move |n| Ok::<_, std::io::Error>(chunk(n))));
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .header(AUTHORIZATION, basic_auth())
+                .uri(&location)
+                .body(Body::from_stream(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        response.headers()[RANGE],
+        format!("0-{}", CHUNK_LEN * CHUNKS - 1)
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, basic_auth())
+                .uri(format!("{location}?digest={digest}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let metadata = ctx
+        .registry
+        .storage
+        .get_blob_metadata(digest.digest)
+        .await
+        .unwrap()
+        .expect("blob should be stored");
+    assert_eq!(metadata.size(), (CHUNK_LEN * CHUNKS) as u64);
+}
+
+#[tokio::test]
+async fn interrupted_chunks_can_be_resumed() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blob = b"partial data, then the rest";
+    let (received, rest) = blob.split_at(12);
+    let location = start_upload(app).await;
+
+    let body = futures::stream::iter([
+        Ok(received.to_vec()),
+        Err(std::io::Error::other("client went away")),
+    ]);
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .header(AUTHORIZATION, basic_auth())
+                .uri(&location)
+                .body(Body::from_stream(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 499);
+
+    // The data received before the disconnect is kept.
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, basic_auth())
+                .uri(&location)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()[RANGE], "0-11");
+
+    let response = app
+        .call(chunk_request(
+            &location,
+            &format!("12-{}", blob.len() - 1),
+            rest,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = app
+        .call(finalize_request(&basic_auth(), &location, blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn pushes_exceeding_quota_are_rejected() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .quota("user", 20)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let first: &[u8] = b"ten bytes!";
+    let second: &[u8] = b"fifteen bytes!!";
+    assert_eq!(push_blob(app, &auth, first).await, StatusCode::CREATED);
+
+    // Pushing content already owned does not count against the quota.
+    assert_eq!(push_blob(app, &auth, first).await, StatusCode::CREATED);
+
+    assert_eq!(
+        push_blob(app, &auth, second).await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert!(ctx
+        .registry
+        .storage
+        .get_blob_metadata(Digest::from_contents(second))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        ctx.registry
+            .storage
+            .storage_stats()
+            .await
+            .unwrap()
+            .upload_count,
+        0
+    );
+
+    // Other users are not limited.
+    let other_auth = format!(
+        "Basic {}",
+        base64::prelude::BASE64_STANDARD.encode(format!("other:{TEST_PASSWORD}"))
+    );
+    assert_eq!(
+        push_blob(app, &other_auth, second).await,
+        StatusCode::CREATED
+    );
+}
+
+#[tokio::test]
+async fn challenges_use_configured_realm() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .realm("https://registry.example.com")
+        .build_for_testing();
+
+    let index = || Request::builder().uri("/v2/").body(Body::empty()).unwrap();
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let response = app.call(index()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()[WWW_AUTHENTICATE],
+        "Basic realm=\"https://registry.example.com\""
+    );
+
+    // Unsupported schemes are challenged with the same realm.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(AUTHORIZATION, "Bearer some-token")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()[WWW_AUTHENTICATE],
+        "Basic realm=\"https://registry.example.com\""
+    );
+
+    // Routers serving another listener may override it.
+    let mut service = ctx
+        .registry
+        .clone()
+        .make_router()
+        .layer(axum::Extension(crate::Realm::new("internal \"registry\"")))
+        .into_service::<Body>();
+    let app = service.ready().await.expect("could not launch service");
+    let response = app.call(index()).await.unwrap();
+    assert_eq!(
+        response.headers()[WWW_AUTHENTICATE],
+        "Basic realm=\"internal \\\"registry\\\"\""
+    );
+}
+
+/// A writer collecting everything written, e.g. an access log.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn access_log_records_authenticated_requests() {
+    let buffer = SharedBuffer::default();
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .access_log(buffer.clone())
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/tags/list")
+                .header(AUTHORIZATION, basic_auth())
+                .header("User-Agent", "docker/24.0.5")
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    [192, 0, 2, 1],
+                    50000,
+                ))))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = collect_body(response.into_body()).await;
+
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let line = log.strip_suffix('\n').expect("should log a single line");
+    assert!(!line.contains('\n'));
+
+    // `<ip> - <user> [<time>] "<request line>" <status> <bytes> "<referer>" "<user agent>"`
+    let (prefix, rest) = line.split_once(" [").unwrap();
+    assert_eq!(prefix, "192.0.2.1 - user");
+    let (time, rest) = rest.split_once("] ").unwrap();
+    assert!(time.ends_with(" +0000"));
+    assert_eq!(
+        rest,
+        format!(
+            "\"GET /v2/tests/sample/tags/list HTTP/1.1\" {} {} \"-\" \"docker/24.0.5\"",
+            status.as_u16(),
+            body.len()
+        )
+    );
+
+    // Anonymous requests are logged without user or client IP.
+    buffer.0.lock().unwrap().clear();
+    app.call(Request::builder().uri("/v2/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert!(log.starts_with("- - - ["), "{log}");
+}
+
+#[tokio::test]
+async fn garbage_collection_dry_run_leaves_blobs_in_place() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .gc_grace_period(Duration::ZERO)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let orphan = b"layer of an abandoned push";
+    assert_eq!(
+        push_blob(app, &basic_auth(), orphan).await,
+        StatusCode::CREATED
+    );
+    let digest = Digest::from_contents(orphan);
+
+    let collect = |query: &str| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/admin/gc{query}"))
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(collect("?dry_run=true")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(
+        report["blobs"],
+        serde_json::json!([ImageDigest::new(digest).to_string()])
+    );
+    assert_eq!(report["bytes"], orphan.len());
+    assert!(ctx
+        .registry
+        .storage
+        .get_blob_metadata(digest)
+        .await
+        .unwrap()
+        .is_some());
+
+    let response = app.call(collect("")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(report["dry_run"], false);
+    assert_eq!(report["bytes"], orphan.len());
+    assert!(ctx
+        .registry
+        .storage
+        .get_blob_metadata(digest)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn deleting_admin_operations_require_deletes_enabled() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .gc_grace_period(Duration::ZERO)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (method, uri, status) in [
+        ("POST", "/admin/gc?dry_run=true", StatusCode::OK),
+        ("POST", "/admin/gc", StatusCode::METHOD_NOT_ALLOWED),
+        (
+            "DELETE",
+            "/admin/uploads?older_than=0",
+            StatusCode::METHOD_NOT_ALLOWED,
+        ),
+        ("POST", "/admin/sweep", StatusCode::METHOD_NOT_ALLOWED),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(AUTHORIZATION, basic_auth())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{method} {uri}");
+    }
+}
+
+#[tokio::test]
+async fn registry_wide_admin_operations_require_write_access() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Permissions::ReadOnly))
+        .delete_enabled(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (method, uri, status) in [
+        ("POST", "/admin/gc?dry_run=true", StatusCode::OK),
+        ("POST", "/admin/gc", StatusCode::FORBIDDEN),
+        ("POST", "/admin/retention?dry_run=true", StatusCode::OK),
+        ("POST", "/admin/retention", StatusCode::FORBIDDEN),
+        ("POST", "/admin/verify", StatusCode::FORBIDDEN),
+        ("POST", "/admin/sweep", StatusCode::FORBIDDEN),
+        (
+            "DELETE",
+            "/admin/uploads?older_than=0",
+            StatusCode::FORBIDDEN,
+        ),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(AUTHORIZATION, basic_auth())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{method} {uri}");
+    }
+}
+
+#[tokio::test]
+async fn request_ids_are_echoed_or_generated() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/")
+                .header("X-Request-Id", "my-request-1234")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-request-id"], "my-request-1234");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/missing")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    uuid::Uuid::parse_str(&request_id).expect("generated request ID should be a UUID");
+
+    // Error responses include the request ID as well.
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(errors["errors"][0]["detail"]["requestId"], request_id);
+}
+
+#[tokio::test]
+async fn manifest_get_synthesizes_schema1_for_legacy_clients() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .synthesize_schema1_manifests(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let config: &[u8] = br#"{"architecture":"arm64","os":"linux","rootfs":{"type":"layers"}}"#;
+    let layers: [&[u8]; 2] = [b"base layer", b"top layer"];
+    for blob in [config, layers[0], layers[1]] {
+        assert_eq!(push_blob(app, &auth, blob).await, StatusCode::CREATED);
+    }
+    let [base, top] = layers.map(|layer| ImageDigest::new(Digest::from_contents(layer)));
+
+    let manifest = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_MANIFEST}",
+            "config": {{
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": "{}",
+                "size": {}
+            }},
+            "layers": [
+                {{ "mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": "{base}", "size": 10 }},
+                {{ "mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": "{top}", "size": 9 }}
+            ]
+        }}"#,
+        ImageDigest::new(Digest::from_contents(config)),
+        config.len()
+    );
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .header(CONTENT_TYPE, OCI_IMAGE_MANIFEST)
+                .uri("/v2/tests/sample/manifests/legacy")
+                .body(Body::from(manifest))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, &auth)
+                .header(ACCEPT, DOCKER_MANIFEST_V1)
+                .uri("/v2/tests/sample/manifests/legacy")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], DOCKER_MANIFEST_V1);
+    let synthesized = response.headers()["Docker-Content-Digest"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let body = collect_body(response.into_body()).await;
+    let v1: serde_json::Value = serde_json::from_slice(&body).expect("invalid schema 1 manifest");
+    assert_eq!(v1["schemaVersion"], 1);
+    assert_eq!(v1["name"], "tests/sample");
+    assert_eq!(v1["tag"], "legacy");
+    assert_eq!(v1["architecture"], "arm64");
+    assert_eq!(
+        v1["fsLayers"],
+        serde_json::json!([{ "blobSum": top.to_string() }, { "blobSum": base.to_string() }])
+    );
+
+    // The access is recorded for the stored manifest, not the synthesized one.
+    let access_times = ctx
+        .temp_storage
+        .as_ref()
+        .unwrap()
+        .path()
+        .join("access_times");
+    let stored = ctx
+        .registry
+        .storage
+        .resolve_tag(
+            &ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+            "legacy",
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(access_times.join(stored.to_string()).exists());
+    assert!(!access_times
+        .join(synthesized.trim_start_matches("sha256:"))
+        .exists());
+
+    // Every history entry is a JSON document, chained to the one of the layer below.
+    let history: Vec<serde_json::Value> = v1["history"]
+        .as_array()
+        .expect("missing history")
+        .iter()
+        .map(|entry| {
+            serde_json::from_str(entry["v1Compatibility"].as_str().unwrap())
+                .expect("invalid v1Compatibility")
+        })
+        .collect();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0]["parent"], history[1]["id"]);
+    assert!(history[1].get("parent").is_none());
+    assert_eq!(history[0]["os"], "linux");
+    assert!(history[0].get("rootfs").is_none());
+
+    // Clients accepting the stored type still get it.
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, &auth)
+                .header(
+                    ACCEPT,
+                    format!("{DOCKER_MANIFEST_V1}, {OCI_IMAGE_MANIFEST}"),
+                )
+                .uri("/v2/tests/sample/manifests/legacy")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], OCI_IMAGE_MANIFEST);
+
+    // Image indexes cannot be synthesized.
+    store_image_and_index(&ctx).await;
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, &auth)
+                .header(ACCEPT, DOCKER_MANIFEST_V1)
+                .uri("/v2/tests/sample/manifests/multi")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn requests_over_rate_limit_are_rejected_per_user() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .read_rate_limit(3, Duration::from_secs(60))
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let other_user = format!(
+        "Basic {}",
+        base64::prelude::BASE64_STANDARD.encode(format!("other:{TEST_PASSWORD}"))
+    );
+    let get_manifest = |auth: &str| {
+        Request::builder()
+            .header(AUTHORIZATION, auth)
+            .uri("/v2/tests/sample/manifests/latest")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    for _ in 0..3 {
+        let response = app.call(get_manifest(&basic_auth())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    let response = app.call(get_manifest(&basic_auth())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[RETRY_AFTER], "20");
+    let body = collect_body(response.into_body()).await;
+    assert!(String::from_utf8_lossy(&body).contains("TOOMANYREQUESTS"));
+
+    // Other users and writes are not affected.
+    let response = app.call(get_manifest(&other_user)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, basic_auth())
+                .uri("/v2/tests/sample/blobs/uploads/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn tags_list_is_paginated() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for tag in ["v2", "latest", "v1"] {
+        ctx.registry
+            .storage
+            .put_manifest(
+                &ManifestReference::new(
+                    ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+                    Reference::new_tag(tag),
+                ),
+                RAW_MANIFEST,
+            )
+            .await
+            .expect("failed to store manifest");
+    }
+
+    let list_tags = |uri: &str| {
+        Request::builder()
+            .uri(uri)
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app
+        .call(list_tags("/v2/tests/sample/tags/list"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(LINK).is_none());
+    let body = collect_body(response.into_body()).await;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        serde_json::json!({ "name": "tests/sample", "tags": ["latest", "v1", "v2"] })
+    );
+
+    let response = app
+        .call(list_tags("/v2/tests/sample/tags/list?n=2"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[LINK],
+        "</v2/tests/sample/tags/list?n=2&last=v1>; rel=\"next\""
+    );
+    let body = collect_body(response.into_body()).await;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["tags"],
+        serde_json::json!(["latest", "v1"])
+    );
+
+    let response = app
+        .call(list_tags("/v2/tests/sample/tags/list?n=2&last=v1"))
+        .await
+        .unwrap();
+    assert!(response.headers().get(LINK).is_none());
+    let body = collect_body(response.into_body()).await;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["tags"],
+        serde_json::json!(["v2"])
+    );
+
+    // Images nothing was pushed to are unknown.
+    let response = app
+        .call(list_tags("/v2/tests/unknown/tags/list"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = collect_body(response.into_body()).await;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["errors"][0]["code"],
+        "NAME_UNKNOWN"
+    );
+}
+
+#[tokio::test]
+async fn tags_list_supports_head() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let request = |method: &str, uri: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // An image with a blob, but no tags, exists.
+    push_blob(app, &basic_auth(), b"untagged").await;
+    let response = app
+        .call(request("HEAD", "/v2/tests/sample/tags/list"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(collect_body(response.into_body()).await.is_empty());
+
+    for tag in ["v1", "v2", "v3"] {
+        ctx.registry
+            .storage
+            .put_manifest(
+                &ManifestReference::new(
+                    ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+                    Reference::new_tag(tag),
+                ),
+                RAW_MANIFEST,
+            )
+            .await
+            .expect("failed to store manifest");
+    }
+
+    for uri in [
+        "/v2/tests/sample/tags/list",
+        "/v2/tests/sample/tags/list?n=1",
+        "/v2/tests/unknown/tags/list",
+    ] {
+        let get = app.call(request("GET", uri)).await.unwrap();
+        let head = app.call(request("HEAD", uri)).await.unwrap();
+
+        assert_eq!(head.status(), get.status(), "{uri}");
+        for name in [CONTENT_TYPE, LINK] {
+            assert_eq!(head.headers().get(&name), get.headers().get(&name), "{uri}");
+        }
+        assert!(collect_body(head.into_body()).await.is_empty());
+    }
+
+    let response = app
+        .call(request("HEAD", "/v2/tests/unknown/tags/list"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+/// Returns variants of [`RAW_MANIFEST`] differing only by an annotation.
+fn manifest_variants(count: usize) -> Vec<Vec<u8>> {
+    (0..count)
+        .map(|n| {
+            let mut manifest: serde_json::Value = serde_json::from_slice(RAW_MANIFEST).unwrap();
+            manifest["annotations"] = serde_json::json!({ "variant": n.to_string() });
+            serde_json::to_vec(&manifest).unwrap()
+        })
+        .collect()
+}
+
+async fn push_manifest_to_latest(
+    app: &mut axum::routing::RouterIntoService<Body>,
+    manifest: &[u8],
+) {
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::from(manifest.to_vec()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+async fn get_latest_history(app: &mut axum::routing::RouterIntoService<Body>) -> serde_json::Value {
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/admin/tests/sample/tags/latest/history")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    serde_json::from_slice(&collect_body(response.into_body()).await).unwrap()
+}
+
+#[tokio::test]
+async fn manifest_put_by_digest_is_idempotent() {
+    use std::os::unix::fs::MetadataExt;
+
+    let ctx = registry_with_test_password_unverified_blobs();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let manifest = manifest_variants(1).remove(0);
+    let digest = ImageDigest::new(Digest::from_contents(&manifest));
+    let push = || {
+        Request::builder()
+            .method("PUT")
+            .header(AUTHORIZATION, basic_auth())
+            .uri(format!("/v2/tests/sample/manifests/{digest}"))
+            .body(Body::from(manifest.clone()))
+            .unwrap()
+    };
+
+    let response = app.call(push()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let stored = ctx
+        .temp_storage
+        .as_ref()
+        .unwrap()
+        .path()
+        .join("manifests")
+        .join(digest.digest.to_string());
+    let inode = std::fs::metadata(&stored).unwrap().ino();
+
+    // Pushing again succeeds without replacing the stored manifest.
+    let response = app.call(push()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()["Docker-Content-Digest"],
+        digest.to_string()
+    );
+    assert_eq!(std::fs::metadata(&stored).unwrap().ino(), inode);
+}
+
+#[tokio::test]
+async fn manifest_put_by_digest_rejects_mismatched_body() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let manifests = manifest_variants(2);
+    let digest = ImageDigest::new(Digest::from_contents(&manifests[0]));
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, basic_auth())
+                .uri(format!("/v2/tests/sample/manifests/{digest}"))
+                .body(Body::from(manifests[1].clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(errors["errors"][0]["code"], "DIGEST_INVALID");
+}
+
+#[tokio::test]
+async fn manifest_put_honors_if_match() {
+    let ctx = registry_with_test_password_unverified_blobs();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let manifests = manifest_variants(3);
+    let etag =
+        |manifest: &[u8]| format!("\"{}\"", ImageDigest::new(Digest::from_contents(manifest)));
+    let conditional_put = |manifest: &[u8], if_match: &str| {
+        Request::builder()
+            .method("PUT")
+            .uri("/v2/tests/sample/manifests/latest")
+            .header(AUTHORIZATION, basic_auth())
+            .header(IF_MATCH, if_match)
+            .body(Body::from(manifest.to_vec()))
+            .unwrap()
+    };
+
+    // Nothing to compare against yet, not even `*` matches.
+    for if_match in ["*".to_owned(), etag(&manifests[0])] {
+        let response = app
+            .call(conditional_put(&manifests[0], &if_match))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    push_manifest_to_latest(app, &manifests[0]).await;
+
+    // Compare-and-swap from the current manifest succeeds.
+    let response = app
+        .call(conditional_put(&manifests[1], &etag(&manifests[0])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // A stale entity tag fails, leaving the tag untouched.
+    let response = app
+        .call(conditional_put(&manifests[2], &etag(&manifests[0])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let stored = ctx
+        .registry
+        .storage
+        .get_manifest(&ManifestReference::new(
+            ImageLocation::new("tests".to_owned(), "sample".to_owned()),
+            Reference::new_tag("latest"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(stored.as_deref(), Some(&manifests[1][..]));
+}
+
+#[tokio::test]
+async fn tag_history_lists_digests_in_order() {
+    let ctx = registry_with_test_password_unverified_blobs();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let manifests = manifest_variants(3);
+    for manifest in &manifests {
+        push_manifest_to_latest(app, manifest).await;
+    }
+    // Pushing the current manifest again does not change the tag.
+    push_manifest_to_latest(app, &manifests[2]).await;
+
+    let history = get_latest_history(app).await;
+    assert_eq!(history["name"], "tests/sample");
+    assert_eq!(history["tag"], "latest");
+
+    let entries = history["history"].as_array().unwrap();
+    assert_eq!(entries.len(), 3);
+    for (entry, manifest) in entries.iter().zip(&manifests) {
+        assert_eq!(
+            entry["digest"],
+            ImageDigest::new(Digest::from_contents(manifest)).to_string()
+        );
+        assert_eq!(entry["actor"], "user");
+        assert!(entry["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    // The history is not available anonymously.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/admin/tests/sample/tags/latest/history")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn tag_history_retention_drops_oldest_entries() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .tag_history_retention(2)
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let manifests = manifest_variants(3);
+    for manifest in &manifests {
+        push_manifest_to_latest(app, manifest).await;
+    }
+
+    let history = get_latest_history(app).await;
+    let digests: Vec<_> = history["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["digest"].clone())
+        .collect();
+    assert_eq!(
+        digests,
+        manifests[1..]
+            .iter()
+            .map(|manifest| {
+                serde_json::json!(ImageDigest::new(Digest::from_contents(manifest)).to_string())
+            })
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn blob_get_serves_byte_ranges() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let blob: Vec<u8> = (0..100u8).collect();
+    assert_eq!(push_blob(app, &auth, &blob).await, StatusCode::CREATED);
+    let uri = format!(
+        "/v2/tests/sample/blobs/{}",
+        ImageDigest::new(Digest::from_contents(&blob))
+    );
+
+    let get_range = |range: Option<&str>| {
+        let mut request = Request::builder().uri(&uri).header(AUTHORIZATION, &auth);
+        if let Some(range) = range {
+            request = request.header(RANGE, range);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    for (range, expected) in [
+        (None, &blob[..]),
+        // Multiple ranges are ignored by default.
+        (Some("bytes=0-1,5-6"), &blob[..]),
+    ] {
+        let response = app.call(get_range(range)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "100");
+        assert_eq!(collect_body(response.into_body()).await, expected);
+    }
+
+    for (range, content_range, expected) in [
+        ("bytes=10-19", "bytes 10-19/100", &blob[10..20]),
+        ("bytes=95-", "bytes 95-99/100", &blob[95..]),
+        ("bytes=-3", "bytes 97-99/100", &blob[97..]),
+    ] {
+        let response = app.call(get_range(Some(range))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{range}");
+        assert_eq!(response.headers()[CONTENT_RANGE], content_range);
+        assert_eq!(
+            response.headers()[CONTENT_LENGTH],
+            expected.len().to_string()
+        );
+        assert_eq!(collect_body(response.into_body()).await, expected);
+    }
+
+    let response = app.call(get_range(Some("bytes=100-"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes */100");
+}
+
+#[tokio::test]
+async fn artifacts_with_empty_config_are_accepted() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(true)
+        .build_for_testing();
+    store_image_and_index(&ctx).await;
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    // The empty blob itself is never pushed.
+    let empty_digest = ImageDigest::new(Digest::from_contents(b"{}"));
+    let artifact = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_MANIFEST}",
+            "artifactType": "application/vnd.example.sbom.v1+json",
+            "config": {{
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "{empty_digest}",
+                "size": 2,
+                "data": "e30="
+            }},
+            "layers": [{{
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "{empty_digest}",
+                "size": 2
+            }}],
+            "subject": {{
+                "mediaType": "{DOCKER_MANIFEST_V2}",
+                "digest": "{MANIFEST_DIGEST}",
+                "size": {}
+            }}
+        }}"#,
+        RAW_MANIFEST.len()
+    );
+    let artifact_digest = ImageDigest::new(Digest::from_contents(artifact.as_bytes()));
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(CONTENT_TYPE, OCI_IMAGE_MANIFEST)
+                .uri(format!("/v2/tests/sample/manifests/{artifact_digest}"))
+                .body(Body::from(artifact.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/manifests/{artifact_digest}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], OCI_IMAGE_MANIFEST);
+    assert_eq!(
+        collect_body(response.into_body()).await,
+        artifact.as_bytes()
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/blobs/{empty_digest}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(collect_body(response.into_body()).await, b"{}".as_slice());
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri(format!("/v2/tests/sample/referrers/{MANIFEST_DIGEST}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let index: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(
+        index["manifests"],
+        serde_json::json!([{
+            "mediaType": OCI_IMAGE_MANIFEST,
+            "digest": artifact_digest.to_string(),
+            "size": artifact.len(),
+            "artifactType": "application/vnd.example.sbom.v1+json"
+        }])
+    );
+}
+
+#[tokio::test]
+async fn public_repositories_allow_anonymous_pulls() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .repository_visibility("public/*", Visibility::Public)
+        .repository_visibility("private/*", Visibility::Private)
+        .build_for_testing();
+    for repository in ["public", "private"] {
+        ctx.registry
+            .storage
+            .put_manifest(
+                &ManifestReference::new(
+                    ImageLocation::new(repository.to_owned(), "app".to_owned()),
+                    Reference::new_tag("latest"),
+                ),
+                RAW_MANIFEST,
+            )
+            .await
+            .expect("failed to store manifest");
+    }
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blob: &[u8] = b"public blob";
+    assert_eq!(
+        push_blob(app, &basic_auth(), blob).await,
+        StatusCode::CREATED
+    );
+    let blob_digest = ImageDigest::new(Digest::from_contents(blob));
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, basic_auth())
+                .uri(format!(
+                    "/v2/public/app/blobs/uploads/?mount={blob_digest}&from=tests/sample"
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let private_blob: &[u8] = b"private blob";
+    assert_eq!(
+        push_blob(app, &basic_auth(), private_blob).await,
+        StatusCode::CREATED
+    );
+    let private_blob_digest = ImageDigest::new(Digest::from_contents(private_blob));
+
+    for (method, uri, auth, expected) in [
+        (
+            "GET",
+            "/v2/public/app/manifests/latest",
+            None,
+            StatusCode::OK,
+        ),
+        (
+            "HEAD",
+            "/v2/public/app/manifests/latest",
+            None,
+            StatusCode::OK,
+        ),
+        (
+            "GET",
+            &format!("/v2/public/app/blobs/{blob_digest}"),
+            None,
+            StatusCode::OK,
+        ),
+        // Blobs not pushed to the public repository stay private.
+        (
+            "GET",
+            &format!("/v2/public/app/blobs/{private_blob_digest}"),
+            None,
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "PUT",
+            "/v2/public/app/manifests/latest",
+            None,
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            "GET",
+            "/v2/private/app/manifests/latest",
+            None,
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            "GET",
+            "/v2/private/app/manifests/latest",
+            Some(basic_auth()),
+            StatusCode::OK,
+        ),
+        // Other repositories are left to the auth provider.
+        (
+            "GET",
+            "/v2/other/app/manifests/latest",
+            None,
+            StatusCode::UNAUTHORIZED,
+        ),
+    ] {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(auth) = auth {
+            request = request.header(AUTHORIZATION, auth);
+        }
+        let response = app
+            .call(request.body(Body::from(RAW_MANIFEST)).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected, "{method} {uri}");
+    }
+}
+
+#[tokio::test]
+async fn finalized_uploads_are_attributed_to_their_repository() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let blob: &[u8] = b"attributed blob";
+    assert_eq!(push_blob(app, &auth, blob).await, StatusCode::CREATED);
+
+    // Uploads cannot be finalized through another repository.
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, &auth)
+                .uri("/v2/tests/other/blobs/uploads/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+    let digest = ImageDigest::new(Digest::from_contents(b""));
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .uri(format!(
+                    "{}?digest={digest}",
+                    location.replace("/tests/other/", "/tests/sample/")
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let stats = ctx.registry.storage.storage_stats().await.unwrap();
+    assert_eq!(stats.repositories.len(), 1);
+    let repository = &stats.repositories["tests/sample"];
+    assert_eq!(repository.blob_count, 1);
+    assert_eq!(repository.blob_bytes, blob.len() as u64);
+}
+
+#[tokio::test]
+async fn self_check_detects_unusable_storage() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    ctx.registry
+        .self_check()
+        .await
+        .expect("self-check should pass on fresh storage");
+
+    let storage = ctx.temp_storage.as_ref().unwrap().path();
+    std::fs::remove_dir_all(storage.join("uploads")).unwrap();
+    assert!(ctx.registry.self_check().await.is_err());
+}
+
+#[tokio::test]
+async fn manifest_digests_are_recorded_on_push() {
+    for verify in [false, true] {
+        let ctx = ContainerRegistry::builder()
+            .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+            .verify_manifest_digests(verify)
+            .verify_manifest_blobs(false)
+            .build_for_testing();
+        let mut service = ctx.make_service();
+        let app = service.ready().await.expect("could not launch service");
+
+        let manifests = manifest_variants(2);
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .header(AUTHORIZATION, basic_auth())
+                    .uri("/v2/tests/sample/manifests/latest")
+                    .body(Body::from(manifests[0].clone()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let pushed = response.headers()["Docker-Content-Digest"].clone();
+
+        // Tamper with the stored manifest, a digest computed on read would change.
+        let stored = ctx
+            .temp_storage
+            .as_ref()
+            .unwrap()
+            .path()
+            .join("manifests")
+            .join(Digest::from_contents(&manifests[0]).to_string());
+        std::fs::write(stored, &manifests[1]).unwrap();
+
+        let response = app
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .header(AUTHORIZATION, basic_auth())
+                    .uri("/v2/tests/sample/manifests/latest")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        if verify {
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        } else {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["Docker-Content-Digest"], pushed);
+            assert_eq!(collect_body(response.into_body()).await, manifests[1]);
+        }
+    }
+}
+
+#[tokio::test]
+async fn garbage_collection_never_removes_blobs_of_concurrent_pushes() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .verify_manifest_blobs(true)
+        .gc_grace_period(Duration::ZERO)
+        .build_for_testing();
+    let mut push_service = ctx.make_service();
+    let push_app = push_service
+        .ready()
+        .await
+        .expect("could not launch service");
+    let mut gc_service = ctx.make_service();
+    let gc_app = gc_service.ready().await.expect("could not launch service");
+
+    for n in 0..20 {
+        let config = format!(r#"{{"architecture":"amd64","os":"linux","n":{n}}}"#);
+        assert_eq!(
+            push_blob(push_app, &basic_auth(), config.as_bytes()).await,
+            StatusCode::CREATED
+        );
+        let digest = Digest::from_contents(config.as_bytes());
+        let manifest = format!(
+            r#"{{
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": {{
+                    "mediaType": "application/vnd.oci.image.config.v1+json",
+                    "digest": "{}",
+                    "size": {}
+                }},
+                "layers": []
+            }}"#,
+            ImageDigest::new(digest),
+            config.len()
+        );
+
+        let push = push_app.call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(AUTHORIZATION, basic_auth())
+                .header(CONTENT_TYPE, "application/vnd.oci.image.manifest.v1+json")
+                .body(Body::from(manifest))
+                .unwrap(),
+        );
+        let collect = gc_app.call(
+            Request::builder()
+                .method("POST")
+                .uri("/admin/gc")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        );
+        let (pushed, collected) = tokio::join!(push, collect);
+        assert_eq!(collected.unwrap().status(), StatusCode::OK);
+
+        // Either the push referenced the blob first, or it was collected before the push.
+        let stored = ctx.registry.storage.blob_exists(digest).await.unwrap();
+        match pushed.unwrap().status() {
+            StatusCode::CREATED => assert!(stored, "blob of pushed manifest was collected"),
+            status => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert!(!stored);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn artifacts_with_custom_config_media_types_round_trip() {
+    const HELM_CONFIG: &str = "application/vnd.cncf.helm.config.v1+json";
+    const HELM_CHART: &str = "application/vnd.cncf.helm.chart.content.v1.tar+gzip";
+
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .verify_manifest_blobs(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let config = br#"{"name":"sample","version":"0.1.0","apiVersion":"v2"}"#;
+    let chart = b"\x1f\x8b\x08\x00 not really a gzipped chart";
+    for blob in [&config[..], &chart[..]] {
+        assert_eq!(
+            push_blob(app, &basic_auth(), blob).await,
+            StatusCode::CREATED
+        );
+    }
+    let config_digest = ImageDigest::new(Digest::from_contents(config));
+    let chart_digest = ImageDigest::new(Digest::from_contents(chart));
+
+    let manifest = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_MANIFEST}",
+            "config": {{
+                "mediaType": "{HELM_CONFIG}",
+                "digest": "{config_digest}",
+                "size": {}
+            }},
+            "layers": [{{
+                "mediaType": "{HELM_CHART}",
+                "digest": "{chart_digest}",
+                "size": {}
+            }}]
+        }}"#,
+        config.len(),
+        chart.len()
+    );
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/0.1.0")
+                .header(AUTHORIZATION, basic_auth())
+                .header(CONTENT_TYPE, OCI_IMAGE_MANIFEST)
+                .body(Body::from(manifest.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let get = |uri: String| {
+        Request::builder()
+            .uri(uri)
+            .header(AUTHORIZATION, basic_auth())
+            .header(ACCEPT, OCI_IMAGE_MANIFEST)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app
+        .call(get("/v2/tests/sample/manifests/0.1.0".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], OCI_IMAGE_MANIFEST);
+    assert_eq!(
+        collect_body(response.into_body()).await,
+        manifest.as_bytes()
+    );
+
+    for (digest, contents, media_type) in [
+        (config_digest, &config[..], HELM_CONFIG),
+        (chart_digest, &chart[..], HELM_CHART),
+    ] {
+        let response = app
+            .call(get(format!("/v2/tests/sample/blobs/{digest}")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], media_type);
+        assert_eq!(collect_body(response.into_body()).await, contents);
+    }
+
+    let response = app
+        .call(get("/v2/tests/sample/tags/list".to_owned()))
+        .await
+        .unwrap();
+    let tags: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(tags["tags"], serde_json::json!(["0.1.0"]));
+}
+
+#[tokio::test]
+async fn client_ips_are_only_forwarded_by_trusted_proxies() {
+    let buffer = SharedBuffer::default();
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .access_log(buffer.clone())
+        .trusted_proxy("10.0.0.0/8".parse().unwrap())
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (peer, expected) in [
+        ([10, 0, 0, 1], "203.0.113.5"),
+        ([192, 0, 2, 1], "192.0.2.1"),
+    ] {
+        app.call(
+            Request::builder()
+                .uri("/v2/")
+                .header(AUTHORIZATION, basic_auth())
+                .header("X-Forwarded-For", "198.51.100.7, 203.0.113.5, 10.0.0.2")
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    peer, 50000,
+                ))))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let log = String::from_utf8(std::mem::take(&mut *buffer.0.lock().unwrap())).unwrap();
+        assert!(
+            log.starts_with(&format!("{expected} - ")),
+            "unexpected log line {log:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn pushes_record_the_authenticated_actor() {
+    #[derive(Clone, Default)]
+    struct RecordingHook(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[axum::async_trait]
+    impl crate::hooks::RegistryHooks for RecordingHook {
+        async fn on_manifest_pushed(
+            &self,
+            manifest_reference: &ManifestReference,
+            actor: Option<&str>,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{manifest_reference} by {actor:?}"));
+        }
+    }
+
+    let hook = RecordingHook::default();
+    let users: std::collections::HashMap<String, Secret<String>> =
+        [("alice".to_owned(), Secret::new(TEST_PASSWORD.to_owned()))].into();
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(users))
+        .hooks(Box::new(hook.clone()))
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let auth = format!(
+        "Basic {}",
+        base64::prelude::BASE64_STANDARD.encode(format!("alice:{TEST_PASSWORD}"))
+    );
+    assert_eq!(push_blob(app, &auth, RAW_IMAGE).await, StatusCode::CREATED);
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/latest")
+                .header(AUTHORIZATION, &auth)
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    assert_eq!(
+        *hook.0.lock().unwrap(),
+        ["tests/sample:latest by Some(\"alice\")"]
+    );
+    for digest in [
+        Digest::from_contents(RAW_IMAGE),
+        Digest::from_contents(RAW_MANIFEST),
+    ] {
+        assert!(ctx
+            .registry
+            .storage
+            .is_owner("alice", digest)
+            .await
+            .unwrap());
+    }
+}
+
+/// Builds an OCI image manifest with layers of the given declared sizes.
+fn manifest_with_layers(sizes: &[u64]) -> String {
+    let layers: Vec<_> = sizes
+        .iter()
+        .enumerate()
+        .map(|(n, size)| {
+            serde_json::json!({
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": ImageDigest::new(Digest::from_contents(format!("layer {n}").as_bytes())),
+                "size": size,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": OCI_IMAGE_MANIFEST,
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": IMAGE_DIGEST,
+            "size": RAW_IMAGE.len(),
+        },
+        "layers": layers,
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn manifest_put_enforces_layer_limits() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .max_manifest_layers(3)
+        .max_manifest_layers_size(1024)
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (sizes, status) in [
+        (&[100, 200, 724][..], StatusCode::CREATED),
+        (&[1, 1, 1, 1], StatusCode::BAD_REQUEST),
+        (&[1000, 25], StatusCode::BAD_REQUEST),
+        (&[u64::MAX, 2], StatusCode::BAD_REQUEST),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/v2/tests/sample/manifests/latest")
+                    .header(AUTHORIZATION, basic_auth())
+                    .header(CONTENT_TYPE, OCI_IMAGE_MANIFEST)
+                    .body(Body::from(manifest_with_layers(sizes)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{sizes:?}");
+
+        if status == StatusCode::BAD_REQUEST {
+            let errors: serde_json::Value =
+                serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+            assert_eq!(errors["errors"][0]["code"], "MANIFEST_INVALID");
+        }
+    }
+}
+
+#[tokio::test]
+async fn upload_new_skips_uploads_of_stored_blobs() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    assert_eq!(
+        push_blob(app, &basic_auth(), RAW_IMAGE).await,
+        StatusCode::CREATED
+    );
+    let uploads = ctx.temp_storage.as_ref().unwrap().path().join("uploads");
+    let upload_count = || std::fs::read_dir(&uploads).unwrap().count();
+    assert_eq!(upload_count(), 0);
+
+    let start = |digest: ImageDigest| {
+        Request::builder()
+            .method("POST")
+            .header(AUTHORIZATION, basic_auth())
+            .uri(format!("/v2/tests/other/blobs/uploads/?digest={digest}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(start(IMAGE_DIGEST)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()[LOCATION],
+        format!("/v2/tests/other/blobs/{IMAGE_DIGEST}")
+    );
+    assert_eq!(
+        response.headers()["Docker-Content-Digest"],
+        IMAGE_DIGEST.to_string()
+    );
+    assert_eq!(upload_count(), 0);
+
+    let stats = ctx.registry.storage.storage_stats().await.unwrap();
+    assert_eq!(stats.repositories["tests/other"].blob_count, 1);
+
+    // Unknown blobs are uploaded as usual.
+    let response = app
+        .call(start(ImageDigest::new(Digest::from_contents(b"new layer"))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_ne!(upload_count(), 0);
+}
+
+#[tokio::test]
+async fn empty_blobs_round_trip() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let digest = ImageDigest::new(Digest::from_contents(b""));
+    assert_eq!(
+        digest.to_string(),
+        "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+
+    // The upload receives no data at all, neither in chunks nor when finalizing.
+    for empty_chunk in [false, true] {
+        let mut location = start_upload(app).await;
+        if empty_chunk {
+            let response = app
+                .call(
+                    Request::builder()
+                        .method("PATCH")
+                        .header(AUTHORIZATION, basic_auth())
+                        .uri(&location)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+        }
+
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .header(AUTHORIZATION, basic_auth())
+                    .uri(format!("{location}?digest={digest}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            response.headers()["Docker-Content-Digest"],
+            digest.to_string()
+        );
+    }
+
+    for method in ["HEAD", "GET"] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method(method)
+                    .header(AUTHORIZATION, basic_auth())
+                    .uri(format!("/v2/tests/sample/blobs/{digest}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{method}");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "0", "{method}");
+        assert!(collect_body(response.into_body()).await.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn admin_routes_are_only_served_by_the_admin_router() {
+    let ctx = registry_with_test_password();
+
+    let status = |router: axum::Router, uri: &'static str| async move {
+        let mut service = router.into_service::<Body>();
+        let app = service.ready().await.expect("could not launch service");
+        app.call(
+            Request::builder()
+                .uri(uri)
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+    };
+
+    let api = || ctx.registry.clone().make_api_router();
+    assert_eq!(status(api(), "/v2/").await, StatusCode::OK);
+    assert_eq!(status(api(), "/healthz").await, StatusCode::NOT_FOUND);
+    assert_eq!(status(api(), "/admin/usage").await, StatusCode::NOT_FOUND);
+
+    let admin = || ctx.registry.clone().make_admin_router();
+    assert_eq!(status(admin(), "/healthz").await, StatusCode::OK);
+    assert_eq!(status(admin(), "/admin/usage").await, StatusCode::OK);
+    assert_eq!(status(admin(), "/v2/").await, StatusCode::NOT_FOUND);
+
+    // Without a separate listener, a single router serves both.
+    let both = || ctx.registry.clone().make_router();
+    assert_eq!(status(both(), "/v2/").await, StatusCode::OK);
+    assert_eq!(status(both(), "/healthz").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn repositories_can_be_moved() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let get = |uri: String| {
+        Request::builder()
+            .uri(uri)
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let move_repository = |body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri("/admin/repositories/move")
+            .header(AUTHORIZATION, basic_auth())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    for (name, tag) in [
+        ("sample", "latest"),
+        ("sample", "stable"),
+        ("other", "latest"),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/{name}/manifests/{tag}"))
+                    .header(AUTHORIZATION, basic_auth())
+                    .body(Body::from(RAW_MANIFEST))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let sample = serde_json::json!({ "repository": "tests", "image": "sample" });
+    let moved = serde_json::json!({ "repository": "moved", "image": "sample" });
+    let other = serde_json::json!({ "repository": "tests", "image": "other" });
+
+    let response = app
+        .call(move_repository(
+            serde_json::json!({ "from": sample, "to": moved }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    for tag in ["latest", "stable"] {
+        let response = app
+            .call(get(format!("/v2/moved/sample/manifests/{tag}")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response.into_body()).await, RAW_MANIFEST);
+
+        let response = app
+            .call(get(format!("/v2/tests/sample/manifests/{tag}")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = app
+        .call(get("/v2/tests/sample/tags/list".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The source is gone now, existing destinations are only replaced when asked to.
+    let response = app
+        .call(move_repository(
+            serde_json::json!({ "from": sample, "to": other }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app
+        .call(move_repository(
+            serde_json::json!({ "from": moved, "to": other }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app
+        .call(move_repository(
+            serde_json::json!({ "from": moved, "to": other, "overwrite": true }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = app
+        .call(get("/v2/tests/other/manifests/stable".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .call(move_repository(serde_json::json!({
+            "from": other,
+            "to": { "repository": "..", "image": "sample" },
+        })))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn encoded_path_separators_cannot_escape_the_storage_root() {
+    let ctx = registry_with_test_password();
+    let root = ctx.temp_storage.as_ref().unwrap().path().to_owned();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let requests = [
+        ("PUT", "/v2/..%2F..%2F..%2Fescaped/img/manifests/latest"),
+        ("POST", "/v2/..%2F..%2Fescaped/img/blobs/uploads/"),
+        (
+            "POST",
+            "/v2/..%2F..%2Fescaped/img/blobs/uploads/?digest=sha256:\
+             0000000000000000000000000000000000000000000000000000000000000000",
+        ),
+        ("GET", "/v2/..%2F..%2F..%2Fescaped/img/manifests/latest"),
+        (
+            "PATCH",
+            "/v2/..%2F..%2Fescaped/img/blobs/uploads/8c5b1dc4-63e3-4b6e-9d5b-4f4c1c8a0f3e",
+        ),
+        (
+            "PUT",
+            "/v2/tests/..%2F..%2Fescaped/blobs/uploads/8c5b1dc4-63e3-4b6e-9d5b-4f4c1c8a0f3e\
+             ?digest=sha256:0000000000000000000000000000000000000000000000000000000000000000",
+        ),
+    ];
+    for (method, uri) in requests {
+        let response = app
+            .call(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(AUTHORIZATION, basic_auth())
+                    .body(Body::from(RAW_MANIFEST))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{method} {uri}");
+        let body = collect_body(response.into_body()).await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["errors"][0]["code"],
+            "NAME_INVALID"
+        );
+    }
+
+    assert!(root.ancestors().all(|dir| !dir.join("escaped").exists()));
+}
+
+#[tokio::test]
+async fn upload_uuid_headers_must_match_the_upload() {
+    let ctx = registry_with_test_password();
+    let blob: Vec<u8> = (0..60u32).map(|n| n as u8).collect();
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let location = start_upload(app).await;
+    let other_location = start_upload(app).await;
+    let upload_uuid = |location: &str| location.rsplit('/').next().unwrap().to_owned();
+    let (uuid, other_uuid) = (upload_uuid(&location), upload_uuid(&other_location));
+
+    let with_uuid = |mut request: Request<Body>, uuid: &str| {
+        request
+            .headers_mut()
+            .insert("Docker-Upload-UUID", uuid.parse().unwrap());
+        request
+    };
+
+    let response = app
+        .call(with_uuid(
+            chunk_request(&location, "0-29", &blob[..30]),
+            &other_uuid,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Matching and absent headers are accepted alike.
+    let response = app
+        .call(with_uuid(
+            chunk_request(&location, "0-29", &blob[..30]),
+            &uuid,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app
+        .call(chunk_request(&location, "30-59", &blob[30..]))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = app
+        .call(with_uuid(
+            finalize_request(&basic_auth(), &location, &blob),
+            &other_uuid,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .call(with_uuid(
+            finalize_request(&basic_auth(), &location, &blob),
+            &uuid,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn manifest_get_resolves_indexes_to_the_queried_platform() {
+    let ctx = ContainerRegistry::builder()
+        .platform_query(true)
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let arm64 = manifest_variants(1).remove(0);
+    let arm64_digest = ImageDigest::new(Digest::from_contents(&arm64));
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": OCI_IMAGE_INDEX,
+        "manifests": [{
+            "mediaType": DOCKER_MANIFEST_V2,
+            "digest": MANIFEST_DIGEST.to_string(),
+            "size": RAW_MANIFEST.len(),
+            "platform": { "os": "linux", "architecture": "amd64" }
+        }, {
+            "mediaType": DOCKER_MANIFEST_V2,
+            "digest": arm64_digest.to_string(),
+            "size": arm64.len(),
+            "platform": { "os": "linux", "architecture": "arm64", "variant": "v8" }
+        }]
+    })
+    .to_string();
+
+    for (reference, body) in [
+        (MANIFEST_DIGEST.to_string(), RAW_MANIFEST.to_vec()),
+        (arm64_digest.to_string(), arm64.clone()),
+        ("multi".to_owned(), index.clone().into_bytes()),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/sample/manifests/{reference}"))
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let get = |query: &str| {
+        Request::builder()
+            .uri(format!("/v2/tests/sample/manifests/multi{query}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    for query in ["?platform=linux/arm64", "?platform=linux/arm64/v8"] {
+        let response = app.call(get(query)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{query}");
+        assert_eq!(
+            response.headers()["Docker-Content-Digest"],
+            arm64_digest.to_string()
+        );
+        assert_eq!(response.headers()[CONTENT_TYPE], DOCKER_MANIFEST_V2);
+        assert_eq!(collect_body(response.into_body()).await, arm64);
+    }
+
+    for query in [
+        "?platform=linux/riscv64",
+        "?platform=linux/arm64/v7",
+        "?platform=linux",
+    ] {
+        let response = app.call(get(query)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{query}");
+    }
+
+    // Without a platform, the index itself is served.
+    let response = app.call(get("")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(collect_body(response.into_body()).await, index.as_bytes());
+}
+
+#[tokio::test]
+async fn verify_reports_corrupted_blobs() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blobs: [&[u8]; 3] = [b"intact", b"corrupted", b"also intact"];
+    for blob in blobs {
+        assert_eq!(
+            push_blob(app, &basic_auth(), blob).await,
+            StatusCode::CREATED
+        );
+    }
+
+    // Flip a byte on disk, keeping the size.
+    let corrupted = Digest::from_contents(blobs[1]).to_string();
+    let path = ctx
+        .temp_storage
+        .as_ref()
+        .unwrap()
+        .path()
+        .join("blobs/sha256")
+        .join(&corrupted[..2])
+        .join(&corrupted[2..4])
+        .join(&corrupted);
+    let mut contents = std::fs::read(&path).unwrap();
+    contents[0] ^= 0xff;
+    std::fs::write(&path, contents).unwrap();
+
+    let verify = |authorization: String| {
+        Request::builder()
+            .method("POST")
+            .uri("/admin/verify")
+            .header(AUTHORIZATION, authorization)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(verify(invalid_basic_auth())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app.call(verify(basic_auth())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "scanned": 3,
+            "bytes": 26,
+            "corrupted": [format!("sha256:{corrupted}")],
+        })
+    );
+
+    // Corrupted blobs are left in place.
+    assert!(path.exists());
+
+    // Only one verification runs at a time.
+    let _running = ctx.registry.verifying.lock().await;
+    let response = app.call(verify(basic_auth())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn corrupted_blobs_fail_downloads_if_verified_on_read() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .blob_read_verification(BlobReadVerification::Always)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blobs: [&[u8]; 2] = [b"intact", b"corrupted"];
+    for blob in blobs {
+        assert_eq!(
+            push_blob(app, &basic_auth(), blob).await,
+            StatusCode::CREATED
+        );
+    }
+
+    // Flip a byte on disk, keeping the size.
+    let corrupted = Digest::from_contents(blobs[1]).to_string();
+    let storage = ctx.temp_storage.as_ref().unwrap().path();
+    let path = storage
+        .join("blobs/sha256")
+        .join(&corrupted[..2])
+        .join(&corrupted[2..4])
+        .join(&corrupted);
+    let mut contents = std::fs::read(&path).unwrap();
+    contents[0] ^= 0xff;
+    std::fs::write(&path, contents).unwrap();
+
+    let download = |digest: &str| {
+        Request::builder()
+            .uri(format!("/v2/tests/sample/blobs/sha256:{digest}"))
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let intact = Digest::from_contents(blobs[0]).to_string();
+    let response = app.call(download(&intact)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(collect_body(response.into_body()).await, blobs[0]);
+
+    // The response starts out fine, but fails instead of sending the corrupted blob.
+    let response = app.call(download(&corrupted)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.into_body().collect().await.is_err());
+
+    // The blob is quarantined and no longer served.
+    assert!(!path.exists());
+    assert!(storage.join("quarantine").join(&corrupted).exists());
+    let response = app.call(download(&corrupted)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn browsers_denied_access_get_a_login_hint() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .login_url("https://example.com/access?team=a&b")
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+    let docker = "application/json";
+
+    for uri in ["/v2/", "/v2/tests/sample/tags/list"] {
+        for (accept, html) in [(Some(browser), true), (Some(docker), false), (None, false)] {
+            let mut request = Request::builder()
+                .uri(uri)
+                .header(AUTHORIZATION, invalid_basic_auth());
+            if let Some(accept) = accept {
+                request = request.header(ACCEPT, accept);
+            }
+            let response = app
+                .call(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{uri}");
+            assert_eq!(
+                response.headers()[WWW_AUTHENTICATE],
+                "Basic realm=\"ContainerRegistry\"",
+                "{uri}"
+            );
+            let content_type = response.headers().get(CONTENT_TYPE).cloned();
+            let body = collect_body(response.into_body()).await;
+            if html {
+                assert_eq!(content_type.unwrap(), "text/html; charset=utf-8");
+                let page = String::from_utf8(body.to_vec()).unwrap();
+                assert!(page.contains("docker login"), "{page}");
+                assert!(
+                    page.contains("href=\"https://example.com/access?team=a&amp;b\""),
+                    "{page}"
+                );
+            } else {
+                assert!(body.is_empty(), "{uri} with {accept:?}");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn uploads_exceeding_quota_are_rejected_at_start() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .quota("user", 20)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let begin = |size: u64| {
+        Request::builder()
+            .method("POST")
+            .header(AUTHORIZATION, basic_auth())
+            .header("X-Upload-Content-Length", size)
+            .uri("/v2/tests/sample/blobs/uploads/")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let upload_count = || async {
+        ctx.registry
+            .storage
+            .storage_stats()
+            .await
+            .unwrap()
+            .upload_count
+    };
+
+    let response = app.call(begin(21)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(upload_count().await, 0);
+
+    // A reservation counts against the quota until its upload is finalized.
+    let response = app.call(begin(15)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+
+    let response = app.call(begin(10)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Finalizing replaces the reservation with the actual size.
+    let blob: &[u8] = b"ten bytes!";
+    let response = app
+        .call(chunk_request(&location, "0-9", blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app
+        .call(finalize_request(&basic_auth(), &location, blob))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = app.call(begin(10)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let upload: uuid::Uuid = response.headers()["Docker-Upload-UUID"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // Cancelling releases the reservation.
+    ctx.registry.storage.cancel_upload(upload).await.unwrap();
+    let response = app.call(begin(10)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn digests_resolve_regardless_of_hex_case() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let head = |digest: String| {
+        Request::builder()
+            .method("HEAD")
+            .header(AUTHORIZATION, basic_auth())
+            .uri(format!("/v2/tests/sample/blobs/{digest}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let uppercase = |digest: &str| format!("sha256:{}", digest["sha256:".len()..].to_uppercase());
+
+    for (pushed, lowercase_first) in [
+        (b"pushed lowercase" as &[u8], true),
+        (b"pushed uppercase", false),
+    ] {
+        let digest = ImageDigest::new(Digest::from_contents(pushed)).to_string();
+        let (push_digest, head_digest) = if lowercase_first {
+            (digest.clone(), uppercase(&digest))
+        } else {
+            (uppercase(&digest), digest.clone())
+        };
+
+        let location = stage_blob(app, &auth, pushed).await;
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .header(AUTHORIZATION, &auth)
+                    .uri(format!("{location}?digest={push_digest}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["Docker-Content-Digest"], digest.as_str());
+
+        let response = app.call(head(head_digest)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Docker-Content-Digest"], digest.as_str());
+    }
+}
+
+#[tokio::test]
+async fn stale_uploads_can_be_listed_and_pruned() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let stale = stage_blob(app, &basic_auth(), b"abandoned").await;
+    let fresh = start_upload(app).await;
+    let upload_id = |location: &str| location.rsplit('/').next().unwrap().to_owned();
+
+    // Pretend the first upload was last active two hours ago.
+    backdate_upload(&ctx, &upload_id(&stale), Duration::from_secs(7200));
+
+    let admin = |method: &str, uri: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(admin("GET", "/admin/uploads")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    let mut uploads: Vec<_> = listed.as_array().unwrap().iter().collect();
+    uploads.sort_by_key(|upload| upload["offset"].as_u64());
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[0]["id"], upload_id(&fresh).as_str());
+    assert_eq!(uploads[0]["offset"], 0);
+    assert!(uploads[0]["age"].as_u64().unwrap() < 3600);
+    assert_eq!(uploads[1]["id"], upload_id(&stale).as_str());
+    assert_eq!(
+        uploads[1]["location"],
+        serde_json::json!({"repository": "tests", "image": "sample"})
+    );
+    assert_eq!(uploads[1]["offset"], 9);
+    assert!(uploads[1]["age"].as_u64().unwrap() >= 7200);
+
+    let response = app
+        .call(admin("DELETE", "/admin/uploads?older_than=3600"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let pruned: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(pruned.as_array().unwrap().len(), 1);
+    assert_eq!(pruned[0]["id"], upload_id(&stale).as_str());
+
+    // Only the fresh upload can still be resumed.
+    let response = app.call(admin("GET", &stale)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.call(admin("GET", &fresh)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+/// Moves the last activity of an upload `age` into the past.
+fn backdate_upload(ctx: &TestingContainerRegistry, upload: &str, age: Duration) {
+    let uploads = ctx.temp_storage.as_ref().unwrap().path().join("uploads");
+    std::fs::File::options()
+        .write(true)
+        .open(uploads.join(format!("{upload}.activity")))
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - age)
+        .unwrap();
+}
+
+#[tokio::test]
+async fn uploads_touched_within_ttl_are_not_pruned() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let untouched = stage_blob(app, &basic_auth(), b"abandoned").await;
+    let touched = stage_blob(app, &basic_auth(), b"slow").await;
+    let upload_id = |location: &str| location.rsplit('/').next().unwrap().to_owned();
+    for upload in [&untouched, &touched] {
+        backdate_upload(&ctx, &upload_id(upload), Duration::from_secs(7200));
+    }
+
+    // Querying the status of an upload keeps it alive without sending data.
+    let status = |location: &str| {
+        Request::builder()
+            .uri(location)
+            .header(AUTHORIZATION, basic_auth())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.call(status(&touched)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let pruned = ctx
+        .registry
+        .storage
+        .prune_uploads(Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert_eq!(pruned.len(), 1);
+    assert_eq!(pruned[0].id.to_string(), upload_id(&untouched));
+
+    let response = app.call(status(&untouched)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The surviving upload can be resumed.
+    let response = app
+        .call(chunk_request(&touched, "4-7", b" and"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn unknown_routes_return_json_errors() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (uri, code) in [
+        ("/v2/foo/bar", "UNSUPPORTED"),
+        ("/v2/tests/nested/sample/manifests/latest", "NAME_UNKNOWN"),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .uri(uri)
+                    .header(AUTHORIZATION, basic_auth())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(
+            response.headers()["Docker-Distribution-Api-Version"],
+            "registry/2.0"
+        );
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+        assert_eq!(body["errors"][0]["code"], code, "{uri}");
+        assert!(body["errors"][0]["message"].is_string());
+    }
+
+    let response = app
+        .call(Request::builder().uri("/nope").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    assert!(!response
+        .headers()
+        .contains_key("Docker-Distribution-Api-Version"));
+}
+
+#[tokio::test]
+async fn strict_mounts_fall_back_to_uploads_on_size_mismatch() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .strict_mounts(true)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let blob = b"layer to be mounted";
+    assert_eq!(
+        push_blob(app, &basic_auth(), blob).await,
+        StatusCode::CREATED
+    );
+    let digest = ImageDigest::new(Digest::from_contents(blob));
+
+    let mount = |image: &str| {
+        Request::builder()
+            .method("POST")
+            .header(AUTHORIZATION, basic_auth())
+            .uri(format!(
+                "/v2/tests/{image}/blobs/uploads/?mount={digest}&from=tests/sample"
+            ))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(mount("mounted")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()[LOCATION],
+        format!("/v2/tests/mounted/blobs/{digest}").as_str()
+    );
+
+    // Simulate a blob damaged on disk after being pushed.
+    let hex = digest.digest().to_string();
+    let path = ctx
+        .temp_storage
+        .as_ref()
+        .unwrap()
+        .path()
+        .join("blobs/sha256")
+        .join(&hex[..2])
+        .join(&hex[2..4])
+        .join(&hex);
+    std::fs::write(&path, &blob[..5]).unwrap();
+
+    let response = app.call(mount("fallback")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert!(response.headers()[LOCATION]
+        .to_str()
+        .unwrap()
+        .starts_with("/v2/tests/fallback/blobs/uploads/"));
+}
+
+/// Issues a client certificate with the given common name, signed by a freshly created CA.
+#[cfg(feature = "client-certs")]
+fn issue_client_cert(common_name: &str, alt_name: &str) -> Vec<u8> {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "Test CA");
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![alt_name.to_owned()]).unwrap();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    params.signed_by(&key, &ca, &ca_key).unwrap().der().to_vec()
+}
+
+#[cfg(feature = "client-certs")]
+#[tokio::test]
+async fn client_certificates_authenticate_by_allowed_identity() {
+    let der = issue_client_cert("ci-runner", "ci.example.com");
+    let cert = auth::ClientCertificate::from_der(&der).unwrap();
+    assert_eq!(cert.common_name(), Some("ci-runner"));
+    assert_eq!(cert.alt_names(), ["ci.example.com"]);
+
+    let provider = auth::ClientCertAuthProvider::new(["ci.example.com"]);
+    let creds = provider
+        .check_credentials(&Unverified::ClientCertificate(cert.clone()))
+        .await
+        .expect("certificate should be accepted by its alt name");
+    assert_eq!(
+        creds.extract_ref::<auth::ClientCertUser>().username,
+        "ci.example.com"
+    );
+
+    // The common name is only used if it is allowed itself.
+    let creds = auth::ClientCertAuthProvider::new(["ci.example.com", "ci-runner"])
+        .check_credentials(&Unverified::ClientCertificate(cert.clone()))
+        .await
+        .expect("certificate should be accepted by its common name");
+    assert_eq!(
+        creds.extract_ref::<auth::ClientCertUser>().username,
+        "ci-runner"
+    );
+
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(provider))
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let index = |cert: Option<auth::ClientCertificate>| {
+        let mut request = Request::builder().uri("/v2/");
+        if let Some(cert) = cert {
+            request = request.extension(cert);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let response = app.call(index(Some(cert))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let other = issue_client_cert("intruder", "intruder.example.com");
+    let other = auth::ClientCertificate::from_der(&other).unwrap();
+    let response = app.call(index(Some(other))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app.call(index(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Basic auth takes precedence over a certificate presented along with it.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/")
+                .header(AUTHORIZATION, basic_auth())
+                .extension(auth::ClientCertificate::from_der(&der).unwrap())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn tags_beyond_the_limit_are_rejected_or_evict_the_oldest() {
+    for policy in [TagLimitPolicy::Reject, TagLimitPolicy::EvictOldest] {
+        let ctx = ContainerRegistry::builder()
+            .max_tags_per_repo(2, policy)
+            .verify_manifest_blobs(false)
+            .build_for_testing();
+        let mut service = ctx.make_service();
+        let app = service.ready().await.expect("could not launch service");
+
+        let mut push = |tag: &'static str| {
+            app.call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/sample/manifests/{tag}"))
+                    .body(Body::from(RAW_MANIFEST))
+                    .unwrap(),
+            )
+        };
+
+        // Overwriting an existing tag is allowed at the limit.
+        for tag in ["a", "b", "b"] {
+            let response = push(tag).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let response = push("c").await.unwrap();
+        let tags = ctx
+            .registry
+            .storage
+            .list_tags(&ImageLocation::new("tests".to_owned(), "sample".to_owned()))
+            .await
+            .unwrap()
+            .unwrap();
+        match policy {
+            TagLimitPolicy::Reject => {
+                assert_eq!(response.status(), StatusCode::FORBIDDEN);
+                assert!(String::from_utf8(collect_body(response.into_body()).await)
+                    .unwrap()
+                    .contains("DENIED"));
+                assert_eq!(tags, ["a", "b"]);
+            }
+            TagLimitPolicy::EvictOldest => {
+                assert_eq!(response.status(), StatusCode::CREATED);
+                assert_eq!(tags, ["b", "c"]);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn credentials_are_redacted_in_logs() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let capture = CaptureLayer::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .sensitive_header(axum::http::HeaderName::from_static("x-api-key"))
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/")
+                .header(AUTHORIZATION, basic_auth())
+                .header("X-Api-Key", "api-key-value")
+                .header("X-Note", "visible")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let events = capture.events.lock().unwrap();
+    let headers = events
+        .iter()
+        .find_map(|event| event.fields.get("headers"))
+        .expect("request headers should be logged");
+    assert!(headers.contains("\"x-note\": \"visible\""));
+    assert!(headers.contains("\"authorization\": ..."));
+
+    let encoded = basic_auth();
+    let encoded = encoded.trim_start_matches("Basic ");
+    for event in events.iter() {
+        for value in event.fields.values() {
+            assert!(!value.contains(encoded));
+            assert!(!value.contains(TEST_PASSWORD));
+            assert!(!value.contains("api-key-value"));
+        }
+    }
+}
+
+#[tokio::test]
+async fn retention_keeps_the_most_recent_tags() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .gc_grace_period(Duration::ZERO)
+        .retention("tests/*", RetentionPolicy::new().keep_last(3))
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let mut digests = Vec::new();
+    for n in 1..=5 {
+        let manifest = String::from_utf8(RAW_MANIFEST.to_vec()).unwrap().replacen(
+            '{',
+            &format!("{{\"annotations\": {{\"build\": \"{n}\"}},"),
+            1,
+        );
+        digests.push(Digest::from_contents(manifest.as_bytes()));
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/sample/manifests/t{n}"))
+                    .header(AUTHORIZATION, basic_auth())
+                    .body(Body::from(manifest))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .uri("/admin/retention")
+                .header(AUTHORIZATION, basic_auth())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(report["tags"].as_array().unwrap().len(), 2);
+    assert_eq!(report["manifests"].as_array().unwrap().len(), 2);
+
+    let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+    let tags = ctx
+        .registry
+        .storage
+        .list_tags(&location)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tags, ["t3", "t4", "t5"]);
+
+    for (n, digest) in digests.into_iter().enumerate() {
+        let stored = ctx
+            .registry
+            .storage
+            .get_manifest(&ManifestReference::new(
+                location.clone(),
+                Reference::new_digest(digest),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(stored.is_some(), n >= 2, "manifest of t{}", n + 1);
+    }
+}
+
+#[tokio::test]
+async fn tags_can_be_streamed_as_ndjson() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for tag in ["latest", "v1", "v2"] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/v2/tests/sample/manifests/{tag}"))
+                    .body(Body::from(RAW_MANIFEST))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/tags/list")
+                .header(ACCEPT, "application/x-ndjson")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+
+    let body = String::from_utf8(collect_body(response.into_body()).await).unwrap();
+    let tags: Vec<String> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(tags, ["latest", "v1", "v2"]);
+    assert!(body.ends_with('\n'));
+
+    // Plain JSON remains the default.
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/tags/list")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+}
+
+#[tokio::test]
+async fn manifests_are_rejected_until_their_blobs_are_pushed() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let config = b"{\"architecture\":\"amd64\",\"os\":\"linux\"}";
+    let config_digest = ImageDigest::new(Digest::from_contents(config));
+    let layer_digest = ImageDigest::new(Digest::from_contents(RAW_IMAGE));
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": OCI_IMAGE_MANIFEST,
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": config_digest.to_string(),
+            "size": config.len(),
+        },
+        "layers": [{
+            "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+            "digest": layer_digest.to_string(),
+            "size": RAW_IMAGE.len(),
+        }],
+    })
+    .to_string();
+
+    let put_manifest = || {
+        Request::builder()
+            .method("PUT")
+            .uri("/v2/tests/sample/manifests/latest")
+            .header(CONTENT_TYPE, OCI_IMAGE_MANIFEST)
+            .body(Body::from(manifest.clone()))
+            .unwrap()
+    };
+
+    let response = app.call(put_manifest()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(errors["errors"][0]["code"], "MANIFEST_BLOB_UNKNOWN");
+    assert_eq!(
+        errors["errors"][0]["detail"]["missingBlobs"],
+        serde_json::json!([config_digest.to_string(), layer_digest.to_string()])
+    );
+
+    assert_eq!(
+        push_blob(app, &basic_auth(), config).await,
+        StatusCode::CREATED
+    );
+    let response = app.call(put_manifest()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(
+        errors["errors"][0]["detail"]["missingBlobs"],
+        serde_json::json!([layer_digest.to_string()])
+    );
+
+    assert_eq!(
+        push_blob(app, &basic_auth(), RAW_IMAGE).await,
+        StatusCode::CREATED
+    );
+    let response = app.call(put_manifest()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn manifest_media_types_are_inferred_without_content_type() {
+    let ctx = ContainerRegistry::builder()
+        .verify_manifest_blobs(false)
+        .default_manifest_media_type(DOCKER_MANIFEST_V2)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let manifest = |media_type: Option<&str>| {
+        let mut manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": ImageDigest::new(Digest::from_contents(b"{}")).to_string(),
+                "size": 2,
+            },
+            "layers": [],
+        });
+        if let Some(media_type) = media_type {
+            manifest["mediaType"] = media_type.into();
+        }
+        manifest.to_string()
+    };
+
+    let put_manifest = |tag: &str, body: String| {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/v2/tests/sample/manifests/{tag}"))
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let get_manifest = |tag: &str| {
+        Request::builder()
+            .uri(format!("/v2/tests/sample/manifests/{tag}"))
+            .header(ACCEPT, "*/*")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // The media type of the body is kept.
+    let response = app
+        .call(put_manifest("oci", manifest(Some(OCI_IMAGE_MANIFEST))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = app.call(get_manifest("oci")).await.unwrap();
+    assert_eq!(response.headers()[CONTENT_TYPE], OCI_IMAGE_MANIFEST);
+
+    // Without any, the configured default is stored and served.
+    let response = app
+        .call(put_manifest("docker", manifest(None)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = app.call(get_manifest("docker")).await.unwrap();
+    assert_eq!(response.headers()[CONTENT_TYPE], DOCKER_MANIFEST_V2);
+
+    // Bodies that are not recognizable as manifests are rejected.
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/v2/tests/sample/manifests/unknown")
+                .body(Body::from(r#"{"config":{},"layers":[]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(errors["errors"][0]["code"], "MANIFEST_INVALID");
+}
+
+#[tokio::test]
+async fn soft_deleted_tags_can_be_restored_until_swept() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .delete_enabled(true)
+        .gc_grace_period(Duration::ZERO)
+        .soft_delete(Duration::from_secs(3600))
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let request = |method: &str, uri: &str, body: Body| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, basic_auth())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap()
+    };
+    let undelete = || {
+        let body = serde_json::json!({
+            "location": { "repository": "tests", "image": "sample" },
+            "tag": "latest",
+        });
+        request("POST", "/admin/undelete", Body::from(body.to_string()))
+    };
+    let manifest_digest = Digest::from_contents(RAW_MANIFEST);
+
+    let response = app
+        .call(request(
+            "PUT",
+            "/v2/tests/sample/manifests/latest",
+            Body::from(RAW_MANIFEST),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Deleted tags are gone from pulls, until restored.
+    let response = app
+        .call(request(
+            "DELETE",
+            "/v2/tests/sample/tags/latest",
+            Body::empty(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app
+        .call(request(
+            "GET",
+            "/v2/tests/sample/manifests/latest",
+            Body::empty(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app.call(undelete()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = app
+        .call(request(
+            "GET",
+            "/v2/tests/sample/manifests/latest",
+            Body::empty(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(collect_body(response.into_body()).await, RAW_MANIFEST);
+
+    // Sweeping within the window keeps deleted tags.
+    let response = app
+        .call(request(
+            "DELETE",
+            "/v2/tests/sample/tags/latest",
+            Body::empty(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app
+        .call(request("POST", "/admin/sweep", Body::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let run: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(run["tags"], serde_json::json!([]));
+
+    // Past the window, the tag is swept along with its manifest.
+    let tombstone = ctx
+        .temp_storage
+        .as_ref()
+        .unwrap()
+        .path()
+        .join("tombstones/tests/sample/latest");
+    std::fs::File::options()
+        .append(true)
+        .open(tombstone)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(7200))
+        .unwrap();
+
+    let response = app
+        .call(request("POST", "/admin/sweep", Body::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let run: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(
+        run["tags"],
+        serde_json::json!([{
+            "location": { "repository": "tests", "image": "sample" },
+            "tag": "latest",
+        }])
+    );
+    assert_eq!(
+        run["manifests"],
+        serde_json::json!([format!("sha256:{manifest_digest}")])
+    );
+
+    let response = app.call(undelete()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app
+        .call(request(
+            "GET",
+            format!("/v2/tests/sample/manifests/sha256:{manifest_digest}").as_str(),
+            Body::empty(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn malformed_authorization_headers_are_told_apart() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    let index = |authorization: Option<&str>| {
+        let mut request = Request::builder().uri("/v2/");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let response = app.call(index(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers()[WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .starts_with("Basic "));
+
+    // Unsupported schemes prompt for basic authentication.
+    let response = app.call(index(Some("Bearer some-token"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers()[WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .starts_with("Basic "));
+
+    let response = app.call(index(Some("Basic not*base64"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let errors: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    assert_eq!(errors["errors"][0]["code"], "UNAUTHORIZED");
+    assert_eq!(
+        errors["errors"][0]["message"],
+        "malformed basic credentials: invalid base64"
+    );
+
+    let response = app.call(index(Some(&basic_auth()))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn multiple_ranges_are_rejected_if_configured() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .multi_range_policy(MultiRangePolicy::Reject)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let blob: Vec<u8> = (0..100u8).collect();
+    assert_eq!(push_blob(app, &auth, &blob).await, StatusCode::CREATED);
+    let uri = format!(
+        "/v2/tests/sample/blobs/{}",
+        ImageDigest::new(Digest::from_contents(&blob))
+    );
+
+    // Range support is advertised on full responses.
+    for method in ["HEAD", "GET"] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method(method)
+                    .uri(&uri)
+                    .header(AUTHORIZATION, &auth)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+    }
+
+    let get_range = |range: &str| {
+        Request::builder()
+            .uri(&uri)
+            .header(AUTHORIZATION, &auth)
+            .header(RANGE, range)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(get_range("bytes=0-10,20-30")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes */100");
+
+    let response = app.call(get_range("bytes=20-30")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(collect_body(response.into_body()).await, &blob[20..31]);
+}
+
+#[tokio::test]
+async fn upload_metrics_are_recorded_per_repository() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .max_metric_repositories(2)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    for (repository, size) in [
+        ("tests/sample", 100),
+        ("tests/other", 30),
+        ("tests/sample", 5),
+        ("more/first", 7),
+        ("more/second", 11),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method("POST")
+                    .header(AUTHORIZATION, &auth)
+                    .uri(format!("/v2/{}/blobs/uploads/", repository))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PATCH")
+                    .header(AUTHORIZATION, &auth)
+                    .uri(&location)
+                    .body(Body::from(vec![0u8; size]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    let metrics = &ctx.registry.upload_metrics;
+    assert_eq!(metrics.bytes_received("tests/sample"), 105);
+    assert_eq!(metrics.bytes_received("tests/other"), 30);
+    // Repositories beyond the limit share a label.
+    assert_eq!(metrics.bytes_received("more/first"), 0);
+    assert_eq!(metrics.bytes_received(metrics::OTHER_REPOSITORY), 18);
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/metrics")
+                .header(AUTHORIZATION, &auth)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(collect_body(response.into_body()).await).unwrap();
+    assert!(body.contains("registry_upload_bytes_total{repository=\"tests/sample\"} 105\n"));
+    assert!(body.contains("registry_upload_bytes_total{repository=\"tests/other\"} 30\n"));
+    assert!(body.contains("registry_uploads_active{repository=\"tests/sample\"} 0\n"));
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn manifest_get_serves_the_manifest_a_tag_resolves_to() {
+    let ctx = ContainerRegistry::builder().build_for_testing();
+    store_image_and_index(&ctx).await;
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let location = ImageLocation::new("tests".to_owned(), "sample".to_owned());
+
+    for tag in ["latest", "multi"] {
+        let resolved = ctx
+            .registry
+            .storage
+            .resolve_tag(&location, tag)
+            .await
+            .unwrap()
+            .expect("tag should resolve");
+
+        let response = app
+            .call(
+                Request::builder()
+                    .uri(format!("/v2/tests/sample/manifests/{tag}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["Docker-Content-Digest"],
+            ImageDigest::new(resolved).to_string()
+        );
+    }
+
+    assert_eq!(
+        ctx.registry
+            .storage
+            .resolve_tag(&location, "unknown")
+            .await
+            .unwrap(),
+        None
+    );
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/v2/tests/sample/manifests/unknown")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "small-object-compression")]
+#[tokio::test]
+async fn compressed_objects_are_served_as_pushed() {
+    let storage = tempdir::TempDir::new("container-registry-test").unwrap();
+    let ctx = ContainerRegistry::builder()
+        .storage_config(crate::storage::StorageConfig::Filesystem {
+            path: storage.path().to_owned(),
+            shard_depth: 2,
+            durable_writes: false,
+            compress_small_objects: Some(64 * 1024),
+        })
+        .auth_provider(Arc::new(Secret::new(TEST_PASSWORD.to_owned())))
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let config = br#"{"architecture":"amd64","os":"linux","rootfs":{"type":"layers","diff_ids":[]},"config":{"Env":["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"]}}"#;
+    assert_eq!(push_blob(app, &auth, config).await, StatusCode::CREATED);
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .uri("/v2/tests/sample/manifests/latest")
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Both are stored compressed, which is recorded along with their original size.
+    let config_digest = Digest::from_contents(config).to_string();
+    for (path, marker, contents) in [
+        (
+            storage
+                .path()
+                .join("manifests")
+                .join(MANIFEST_DIGEST.digest.to_string()),
+            storage
+                .path()
+                .join("compressed_manifests")
+                .join(MANIFEST_DIGEST.digest.to_string()),
+            RAW_MANIFEST,
+        ),
+        (
+            storage
+                .path()
+                .join("blobs/sha256")
+                .join(&config_digest[..2])
+                .join(&config_digest[2..4])
+                .join(&config_digest),
+            storage.path().join("compressed_blobs").join(&config_digest),
+            &config[..],
+        ),
+    ] {
+        let stored = std::fs::read(path).unwrap();
+        assert!(stored.len() < contents.len());
+        assert_eq!(
+            std::fs::read_to_string(marker).unwrap(),
+            contents.len().to_string()
+        );
+    }
+
+    for (uri, expected, digest) in [
+        (
+            "/v2/tests/sample/manifests/latest".to_owned(),
+            RAW_MANIFEST,
+            MANIFEST_DIGEST,
+        ),
+        (
+            format!(
+                "/v2/tests/sample/blobs/{}",
+                ImageDigest::new(Digest::from_contents(config))
+            ),
+            &config[..],
+            ImageDigest::new(Digest::from_contents(config)),
+        ),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .uri(&uri)
+                    .header(AUTHORIZATION, &auth)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["Docker-Content-Digest"],
+            digest.to_string()
+        );
+        assert_eq!(
+            response.headers()[CONTENT_LENGTH],
+            expected.len().to_string()
+        );
+        assert_eq!(collect_body(response.into_body()).await, expected);
+    }
+
+    // Compressed blobs verify against their uncompressed digest.
+    let verification = ctx.registry.storage.verify_blobs(1).await.unwrap();
+    assert!(verification.corrupted.is_empty());
+}
+
+#[tokio::test]
+async fn registries_can_be_nested_in_other_routers() {
+    let ctx = ContainerRegistry::builder()
+        .auth_provider(Arc::new(Anonymous::new(
+            Permissions::ReadWrite,
+            Permissions::ReadWrite,
+        )))
+        .verify_manifest_blobs(false)
+        .build_for_testing();
+    let mut service = axum::Router::new()
+        .route("/", axum::routing::get(|| async { "parent" }))
+        .nest("/registry", ctx.registry.clone().make_router())
+        .into_service::<Body>();
+    let app = service.ready().await.expect("could not launch service");
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/registry/v2/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("POST")
+                .uri("/registry/v2/tests/sample/blobs/uploads/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+    assert!(location.starts_with("/registry/v2/tests/sample/blobs/uploads/"));
+
+    let blob = b"nested blob";
+    let response = app
+        .call(
+            Request::builder()
+                .method("PATCH")
+                .uri(&location)
+                .body(Body::from(&blob[..]))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let digest = ImageDigest::new(Digest::from_contents(blob));
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri(format!("{location}?digest={digest}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()[LOCATION],
+        format!("/registry/v2/tests/sample/blobs/{digest}")
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri("/registry/v2/tests/sample/manifests/latest")
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()[LOCATION],
+        "/registry/v2/tests/sample/manifests/latest"
+    );
+
+    // The parent keeps serving its own routes.
+    let response = app
+        .call(Request::builder().uri("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(collect_body(response.into_body()).await, b"parent");
+}
+
+#[tokio::test]
+async fn least_recently_used_content_is_listed_first() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let blobs: [&[u8]; 3] = [b"first blob", b"second blob", b"third blob"];
+    for blob in blobs {
+        assert_eq!(push_blob(app, &auth, blob).await, StatusCode::CREATED);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let get_blob = |blob: &[u8]| {
+        Request::builder()
+            .uri(format!(
+                "/v2/tests/sample/blobs/{}",
+                ImageDigest::new(Digest::from_contents(blob))
+            ))
+            .header(AUTHORIZATION, &auth)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let least_recently_used = || async {
+        ctx.registry
+            .storage
+            .least_recently_used(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.digest)
+            .collect::<Vec<_>>()
+    };
+    let digests = blobs.map(Digest::from_contents);
+
+    assert_eq!(least_recently_used().await, digests);
+
+    // Accessing blobs moves them to the back.
+    for blob in [blobs[1], blobs[0]] {
+        let response = app.call(get_blob(blob)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(
+        least_recently_used().await,
+        [digests[2], digests[1], digests[0]]
+    );
+
+    // Access times are only updated once they are older than the resolution.
+    let response = app.call(get_blob(blobs[1])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        least_recently_used().await,
+        [digests[2], digests[1], digests[0]]
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .uri("/admin/lru?limit=2")
+                .header(AUTHORIZATION, &auth)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: serde_json::Value =
+        serde_json::from_slice(&collect_body(response.into_body()).await).unwrap();
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 2);
+    assert_eq!(
+        listed[0]["digest"],
+        ImageDigest::new(digests[2]).to_string()
+    );
+    assert_eq!(listed[0]["kind"], "blob");
+}
+
+#[tokio::test]
+async fn only_large_json_responses_are_compressed() {
+    let ctx = ContainerRegistry::builder()
+        .compression_min_size(1024)
+        .build_for_testing();
+    for n in 0..200 {
+        ctx.registry
+            .storage
+            .put_manifest(
+                &ManifestReference::new(
+                    ImageLocation::new("tests".to_owned(), "large".to_owned()),
+                    Reference::new_tag(format!("release-{n}")),
+                ),
+                RAW_MANIFEST,
+            )
+            .await
+            .expect("failed to store manifest");
+    }
+    ctx.registry
+        .storage
+        .put_manifest(
+            &ManifestReference::new(
+                ImageLocation::new("tests".to_owned(), "small".to_owned()),
+                Reference::new_tag("latest"),
+            ),
+            RAW_MANIFEST,
+        )
+        .await
+        .expect("failed to store manifest");
+
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let get = |uri: &str| {
+        Request::builder()
+            .uri(uri)
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.call(get("/v2/tests/large/tags/list")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+    let compressed = collect_body(response.into_body()).await;
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .expect("invalid gzip body");
+    assert!(compressed.len() < decompressed.len());
+    let tags: serde_json::Value = serde_json::from_slice(&decompressed).unwrap();
+    assert_eq!(tags["tags"].as_array().unwrap().len(), 200);
+
+    // Below the minimum size, responses are sent as is.
+    for uri in [
+        "/v2/tests/small/tags/list",
+        "/v2/tests/small/manifests/latest",
+    ] {
+        let response = app.call(get(uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    }
+}
+
+#[test]
+fn compressed_content_types_are_not_compressed_again() {
+    for content_type in [
+        "application/octet-stream",
+        "application/vnd.oci.image.layer.v1.tar+gzip",
+        "application/vnd.oci.image.layer.v1.tar+zstd",
+        "application/vnd.docker.image.rootfs.diff.tar.gzip",
+        "application/gzip; charset=binary",
+    ] {
+        assert!(super::is_precompressed(content_type), "{content_type}");
+    }
+    for content_type in [
+        "application/json",
+        "application/vnd.oci.image.manifest.v1+json",
+        "application/vnd.oci.image.layer.v1.tar",
+    ] {
+        assert!(!super::is_precompressed(content_type), "{content_type}");
+    }
+}
+
+#[tokio::test]
+async fn stored_manifests_can_be_promoted_to_new_tags() {
+    let ctx = registry_with_test_password_unverified_blobs();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+    let auth = basic_auth();
+
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .uri(format!("/v2/tests/sample/manifests/{MANIFEST_DIGEST}"))
+                .body(Body::from(RAW_MANIFEST))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let promote = |digest: &ImageDigest| {
+        Request::builder()
+            .method("PUT")
+            .header(AUTHORIZATION, &auth)
+            .uri(format!("/admin/tests/sample/tags/stable?digest={digest}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let unknown = ImageDigest::new(Digest::from_contents(b"not a stored manifest"));
+    let response = app.call(promote(&unknown)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app.call(promote(&MANIFEST_DIGEST)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()["Docker-Content-Digest"],
+        MANIFEST_DIGEST.to_string()
+    );
+
+    let response = app
+        .call(
+            Request::builder()
+                .header(AUTHORIZATION, &auth)
+                .uri("/v2/tests/sample/manifests/stable")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Docker-Content-Digest"],
+        MANIFEST_DIGEST.to_string()
+    );
+    assert_eq!(collect_body(response.into_body()).await, RAW_MANIFEST);
+
+    // Names are validated like for pushes.
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .header(AUTHORIZATION, &auth)
+                .uri(format!(
+                    "/admin/..%2F..%2Fescaped/sample/tags/stable?digest={MANIFEST_DIGEST}"
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Anonymous users cannot promote tags.
+    let response = app
+        .call(
+            Request::builder()
+                .method("PUT")
+                .uri(format!(
+                    "/admin/tests/sample/tags/other?digest={MANIFEST_DIGEST}"
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unsupported_methods_list_the_allowed_ones() {
+    let ctx = registry_with_test_password();
+    let mut service = ctx.make_service();
+    let app = service.ready().await.expect("could not launch service");
+
+    for (method, uri, allowed) in [
+        (
+            "POST",
+            "/v2/tests/sample/manifests/latest".to_owned(),
+            "GET, HEAD, PUT, DELETE",
+        ),
+        (
+            "PUT",
+            format!("/v2/tests/sample/blobs/{IMAGE_DIGEST}"),
+            "GET, HEAD",
+        ),
+    ] {
+        let response = app
+            .call(
+                Request::builder()
+                    .method(method)
+                    .header(AUTHORIZATION, basic_auth())
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers().get(ALLOW).unwrap().to_str().unwrap(),
+            allowed
+        );
+        let body = collect_body(response.into_body()).await;
+        assert!(String::from_utf8_lossy(&body).contains("UNSUPPORTED"));
+    }
+}
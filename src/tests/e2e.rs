@@ -0,0 +1,258 @@
+//! End-to-end tests.
+//!
+//! These run the registry on an ephemeral port and talk to it over HTTP using `reqwest`, covering
+//! routing and extractors the same way real clients do. New endpoints can be covered by starting
+//! a [`Harness`] and using its helpers or [`Harness::request`] directly.
+
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+
+use crate::{
+    manifest::{OCI_IMAGE_INDEX, OCI_IMAGE_MANIFEST},
+    storage::Digest,
+    test_support::RunningRegistry,
+    ContainerRegistry, ContainerRegistryBuilder, ImageDigest,
+};
+
+/// A registry running in the background, along with a client to access it.
+struct Harness {
+    /// HTTP client, dropped before the registry to close its connections.
+    client: reqwest::Client,
+    /// Base URL of the registry, e.g. `http://127.0.0.1:1234`.
+    base: String,
+    /// The running registry.
+    _running: RunningRegistry,
+}
+
+impl Harness {
+    /// Starts a registry built for testing from the given builder.
+    fn start(builder: ContainerRegistryBuilder) -> Self {
+        let running = builder.build_for_testing().run_in_background();
+
+        Self {
+            client: reqwest::Client::new(),
+            base: format!("http://{}", running.bound_addr()),
+            _running: running,
+        }
+    }
+
+    /// Returns the absolute URL for a path or `Location` header value.
+    fn url(&self, path_or_url: &str) -> String {
+        if path_or_url.starts_with('/') {
+            format!("{}{}", self.base, path_or_url)
+        } else {
+            path_or_url.to_owned()
+        }
+    }
+
+    /// Creates a request to the registry.
+    fn request(&self, method: Method, path_or_url: &str) -> RequestBuilder {
+        self.client.request(method, self.url(path_or_url))
+    }
+
+    /// Pushes a blob in the given chunks, checking every step of the upload.
+    async fn push_blob(&self, repository: &str, chunks: &[&[u8]]) -> ImageDigest {
+        let response = self
+            .request(Method::POST, &format!("/v2/{repository}/blobs/uploads/"))
+            .send()
+            .await
+            .expect("failed to start upload");
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let upload = header_str(&response, "Docker-Upload-UUID").to_owned();
+        let mut location = header_str(&response, header::LOCATION.as_str()).to_owned();
+
+        let mut offset = 0;
+        let mut contents = Vec::new();
+        for chunk in chunks {
+            let end = offset + chunk.len() - 1;
+            let response = self
+                .request(Method::PATCH, &location)
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .header(header::CONTENT_RANGE, format!("{offset}-{end}"))
+                .body(chunk.to_vec())
+                .send()
+                .await
+                .expect("failed to upload chunk");
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            assert_eq!(header_str(&response, "Range"), format!("0-{end}"));
+            assert_eq!(header_str(&response, "Docker-Upload-UUID"), upload);
+            location = header_str(&response, header::LOCATION.as_str()).to_owned();
+
+            offset = end + 1;
+            contents.extend_from_slice(chunk);
+        }
+
+        let digest = ImageDigest::new(Digest::from_contents(&contents));
+        let response = self
+            .request(Method::PUT, &format!("{location}?digest={digest}"))
+            .send()
+            .await
+            .expect("failed to finalize upload");
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            header_str(&response, header::LOCATION.as_str()),
+            format!("/v2/{repository}/blobs/{digest}")
+        );
+        assert_eq!(
+            header_str(&response, "Docker-Content-Digest"),
+            digest.to_string()
+        );
+
+        digest
+    }
+
+    /// Pushes a manifest under the given tag or digest.
+    async fn push_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+        media_type: &str,
+        manifest: &[u8],
+    ) -> ImageDigest {
+        let response = self
+            .request(
+                Method::PUT,
+                &format!("/v2/{repository}/manifests/{reference}"),
+            )
+            .header(header::CONTENT_TYPE, media_type)
+            .body(manifest.to_vec())
+            .send()
+            .await
+            .expect("failed to push manifest");
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let digest = ImageDigest::new(Digest::from_contents(manifest));
+        assert_eq!(
+            header_str(&response, header::LOCATION.as_str()),
+            format!("/v2/{repository}/manifests/{reference}")
+        );
+        assert_eq!(
+            header_str(&response, "Docker-Content-Digest"),
+            digest.to_string()
+        );
+
+        digest
+    }
+}
+
+/// Returns the value of a header that must be present.
+fn header_str<'a>(response: &'a reqwest::Response, name: &str) -> &'a str {
+    response
+        .headers()
+        .get(name)
+        .unwrap_or_else(|| panic!("missing header {name}"))
+        .to_str()
+        .expect("header is not a string")
+}
+
+#[tokio::test]
+async fn push_and_pull_image() {
+    let harness = Harness::start(ContainerRegistry::builder());
+    let repository = "tests/e2e";
+
+    let response = harness.request(Method::GET, "/v2/").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Push.
+    let config: &[u8] = br#"{"architecture":"amd64","os":"linux"}"#;
+    let layer: Vec<u8> = (0..10_000u32).map(|n| (n % 253) as u8).collect();
+    let config_digest = harness.push_blob(repository, &[config]).await;
+    let layer_digest = harness
+        .push_blob(
+            repository,
+            &[&layer[..4000], &layer[4000..9999], &layer[9999..]],
+        )
+        .await;
+
+    let manifest = format!(
+        r#"{{
+            "schemaVersion": 2,
+            "mediaType": "{OCI_IMAGE_MANIFEST}",
+            "config": {{
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": "{config_digest}",
+                "size": {}
+            }},
+            "layers": [{{
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": "{layer_digest}",
+                "size": {}
+            }}]
+        }}"#,
+        config.len(),
+        layer.len()
+    );
+    let manifest_digest = harness
+        .push_manifest(repository, "v1", OCI_IMAGE_MANIFEST, manifest.as_bytes())
+        .await;
+
+    // Pull.
+    for (reference, method) in [
+        ("v1".to_owned(), Method::HEAD),
+        ("v1".to_owned(), Method::GET),
+        (manifest_digest.to_string(), Method::GET),
+    ] {
+        let response = harness
+            .request(
+                method.clone(),
+                &format!("/v2/{repository}/manifests/{reference}"),
+            )
+            .header(
+                header::ACCEPT,
+                format!("{OCI_IMAGE_MANIFEST}, {OCI_IMAGE_INDEX}"),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{method} {reference}");
+        assert_eq!(
+            header_str(&response, header::CONTENT_TYPE.as_str()),
+            OCI_IMAGE_MANIFEST
+        );
+        assert_eq!(
+            header_str(&response, "Docker-Content-Digest"),
+            manifest_digest.to_string()
+        );
+        assert_eq!(
+            header_str(&response, header::CONTENT_LENGTH.as_str()),
+            manifest.len().to_string()
+        );
+        if method == Method::GET {
+            assert_eq!(response.bytes().await.unwrap(), manifest.as_bytes());
+        }
+    }
+
+    for (digest, contents) in [(config_digest, config), (layer_digest, &layer[..])] {
+        let blob = format!("/v2/{repository}/blobs/{digest}");
+
+        let response = harness.request(Method::HEAD, &blob).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header_str(&response, header::CONTENT_LENGTH.as_str()),
+            contents.len().to_string()
+        );
+        assert_eq!(
+            header_str(&response, "Docker-Content-Digest"),
+            digest.to_string()
+        );
+
+        let response = harness.request(Method::GET, &blob).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap(), contents);
+    }
+
+    // Unknown content.
+    let unknown = ImageDigest::new(Digest::from_contents(b"unknown"));
+    let response = harness
+        .request(Method::HEAD, &format!("/v2/{repository}/blobs/{unknown}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = harness
+        .request(Method::GET, &format!("/v2/{repository}/manifests/v2"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
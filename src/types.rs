@@ -1,43 +1,11 @@
-use std::{collections::HashMap, fmt::Display};
+use std::fmt::Display;
 
 use axum::{
     body::Body,
     http::header::CONTENT_TYPE,
     response::{IntoResponse, Response},
 };
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct ContentDescriptor {
-    media_type: String,
-    digest: String, // TODO: Use digest type
-    size: u64,
-    urls: Option<Vec<String>>,
-    annotations: Option<HashMap<String, String>>,
-    data: Option<String>,
-    artifact_type: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct ImageManifest {
-    schema_version: u32,
-
-    media_type: String,
-    annotations: Option<HashMap<String, String>>,
-    artifact_type: Option<String>,
-
-    config: ContentDescriptor,
-    layers: Vec<ContentDescriptor>,
-    subject: Option<ContentDescriptor>,
-}
-
-impl ImageManifest {
-    pub(crate) fn media_type(&self) -> &str {
-        self.media_type.as_ref()
-    }
-}
+use serde::Serialize;
 
 // TODO: Return error as:
 // {
@@ -54,7 +22,23 @@ impl ImageManifest {
 pub(crate) struct OciError {
     code: ErrorCode,
     message: String,
-    // not supported: detail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<ErrorDetail>,
+}
+
+/// Details attached to an error.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ErrorDetail {
+    /// ID of the request that caused the error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// Digests of the blobs a manifest referenced but were missing.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    missing_blobs: Vec<String>,
+    /// Digests of the manifests referring to a manifest through their `subject`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    referrers: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -75,8 +59,46 @@ impl OciError {
         Self {
             code,
             message: code.to_string(),
+            detail: crate::request_id::current().map(|request_id| ErrorDetail {
+                request_id: Some(request_id),
+                ..Default::default()
+            }),
         } // TODO: Use actual message
     }
+
+    /// Replaces the generic message of the error code.
+    pub(crate) fn with_message<M: Into<String>>(mut self, message: M) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Lists the digests of blobs missing for a manifest in the error detail.
+    pub(crate) fn with_missing_blobs<I, D>(mut self, digests: I) -> Self
+    where
+        I: IntoIterator<Item = D>,
+        D: Display,
+    {
+        self.detail
+            .get_or_insert_with(Default::default)
+            .missing_blobs = digests
+            .into_iter()
+            .map(|digest| digest.to_string())
+            .collect();
+        self
+    }
+
+    /// Lists the digests of referrers keeping a manifest from being deleted in the error detail.
+    pub(crate) fn with_referrers<I, D>(mut self, digests: I) -> Self
+    where
+        I: IntoIterator<Item = D>,
+        D: Display,
+    {
+        self.detail.get_or_insert_with(Default::default).referrers = digests
+            .into_iter()
+            .map(|digest| digest.to_string())
+            .collect();
+        self
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize)]
@@ -139,30 +161,3 @@ impl IntoResponse for OciErrors {
             .expect("did not expect body construction to fail")
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::ImageManifest;
-
-    #[test]
-    fn simple_example_schema_parse() {
-        let raw = r#"{
-            "schemaVersion": 2,
-            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
-            "config": {
-               "mediaType": "application/vnd.docker.container.image.v1+json",
-               "size": 2298,
-               "digest": "sha256:e4c58958181a5925816faa528ce959e487632f4cfd192f8132f71b32df2744b4"
-            },
-            "layers": [
-               {
-                  "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
-                  "size": 30439111,
-                  "digest": "sha256:43f89b94cd7df92a2f7e565b8fb1b7f502eff2cd225508cbd7ea2d36a9a3a601"
-               }
-            ]
-        }"#;
-
-        let _manifest: ImageManifest = serde_json::from_str(raw).expect("could not parse manifest");
-    }
-}
@@ -0,0 +1,115 @@
+//! Verification of blobs against their digest while they are downloaded.
+//!
+//! Blobs are hashed once when pushed and trusted afterwards. Verifying them on read detects blobs
+//! tampered with or corrupted on disk, at the cost of hashing them again for every download.
+
+use std::{future::Future, io};
+
+use axum::body::Bytes;
+use futures::{stream, Stream, StreamExt};
+use sha2::{Digest as _, Sha256};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use crate::storage::Digest;
+
+/// Streams a blob from `reader`, hashing it along the way.
+///
+/// The last chunk is held back until the whole blob has been hashed. If the blob does not match
+/// `digest`, `on_mismatch` is awaited and the stream fails instead of sending it, so clients never
+/// receive a complete corrupted blob.
+pub(crate) fn verified_stream<R, F, Fut>(
+    reader: R,
+    digest: Digest,
+    on_mismatch: F,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    R: AsyncRead,
+    F: FnOnce(Digest) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let state = (
+        Box::pin(ReaderStream::new(reader)),
+        Sha256::new(),
+        None::<Bytes>,
+        Some(on_mismatch),
+    );
+
+    stream::unfold(Some(state), move |state| async move {
+        let (mut chunks, mut hasher, mut held, mut on_mismatch) = state?;
+        loop {
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    hasher.update(&chunk);
+                    if let Some(previous) = held.replace(chunk) {
+                        return Some((Ok(previous), Some((chunks, hasher, held, on_mismatch))));
+                    }
+                }
+                Some(Err(err)) => return Some((Err(err), None)),
+                None => {
+                    let actual = Digest::new(hasher.finalize().into());
+                    if actual == digest {
+                        return held.map(|last| (Ok(last), None));
+                    }
+
+                    if let Some(on_mismatch) = on_mismatch.take() {
+                        on_mismatch(actual).await;
+                    }
+                    return Some((
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("blob does not match digest {digest}"),
+                        )),
+                        None,
+                    ));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use futures::StreamExt;
+
+    use super::verified_stream;
+    use crate::storage::Digest;
+
+    #[tokio::test]
+    async fn matching_blobs_are_streamed_completely() {
+        let blob = vec![7u8; 100_000];
+        let chunks: Vec<_> =
+            verified_stream(blob.as_slice(), Digest::from_contents(&blob), |_| async {
+                panic!("blob should match")
+            })
+            .collect()
+            .await;
+
+        let streamed: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap())
+            .collect();
+        assert_eq!(streamed, blob);
+    }
+
+    #[tokio::test]
+    async fn mismatching_blobs_fail_before_their_end() {
+        let blob = b"tampered";
+        let expected = Digest::from_contents(b"original");
+        let mismatched = AtomicBool::new(false);
+
+        let chunks: Vec<_> = verified_stream(blob.as_slice(), expected, |actual| {
+            assert_eq!(actual, Digest::from_contents(blob));
+            mismatched.store(true, Ordering::SeqCst);
+            async {}
+        })
+        .collect()
+        .await;
+
+        assert!(mismatched.load(Ordering::SeqCst));
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].is_err());
+    }
+}
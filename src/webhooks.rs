@@ -0,0 +1,322 @@
+//! Webhook notifications.
+//!
+//! The registry can notify external services about changes by `POST`ing a JSON event to one or
+//! more configured [`Webhook`]s. The payload is modeled loosely on the notifications sent by the
+//! reference `docker/distribution` registry:
+//!
+//! ```json
+//! {
+//!   "events": [{
+//!     "id": "3c1f51a5-9d5d-4a6f-8b0c-36b3e2b1e5a8",
+//!     "timestamp": "2024-08-14T12:00:00Z",
+//!     "action": "push",
+//!     "target": {
+//!       "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+//!       "size": 528,
+//!       "digest": "sha256:9ce67038e4f1297a0b1ce23be1b768ce3649fe9bd496ba8efe9ec1676d153430",
+//!       "repository": "tests/sample",
+//!       "tag": "latest"
+//!     },
+//!     "actor": { "name": "user" }
+//!   }]
+//! }
+//! ```
+//!
+//! Delivery happens in the background and never delays the response to the client. Failed
+//! deliveries (server errors or network failures) are retried with exponential backoff.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use sec::Secret;
+use serde::Serialize;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{storage::ManifestReference, ImageDigest};
+
+/// Default timeout for a single delivery attempt.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of delivery attempts before giving up.
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled after every failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// A webhook endpoint to notify.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    /// URL to `POST` events to.
+    url: String,
+    /// Value of the `Authorization` header to send along, if any.
+    authorization: Option<Secret<String>>,
+    /// Timeout for a single delivery attempt.
+    timeout: Duration,
+    /// Maximum number of delivery attempts.
+    max_attempts: u32,
+}
+
+impl Webhook {
+    /// Creates a new webhook that will `POST` events to the given URL.
+    pub fn new<U: Into<String>>(url: U) -> Self {
+        Self {
+            url: url.into(),
+            authorization: None,
+            timeout: DEFAULT_TIMEOUT,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Sets the `Authorization` header value to send with every event.
+    pub fn authorization(mut self, authorization: Secret<String>) -> Self {
+        self.authorization = Some(authorization);
+        self
+    }
+
+    /// Sets the timeout for a single delivery attempt.
+    ///
+    /// Defaults to five seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of delivery attempts.
+    ///
+    /// Defaults to five.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+}
+
+/// The action that triggered an event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// A manifest was pushed.
+    Push,
+    /// A manifest was deleted.
+    Delete,
+}
+
+/// A notification event.
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    /// Unique ID of the event.
+    id: Uuid,
+    /// Time the event occurred at, in RFC 3339 format.
+    timestamp: String,
+    /// Action that triggered the event.
+    action: Action,
+    /// Target of the action.
+    target: Target,
+    /// Originator of the action.
+    actor: Actor,
+}
+
+/// The target of an event.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    /// Media type of the manifest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    /// Size of the manifest in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// Digest of the manifest.
+    digest: ImageDigest,
+    /// Repository, including the image name, e.g. `bitnami/nginx`.
+    repository: String,
+    /// Tag, if the action involved one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+}
+
+/// The originator of an event.
+#[derive(Clone, Debug, Serialize)]
+pub struct Actor {
+    /// Username of the actor, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+impl Event {
+    /// Creates a new event for a manifest, timestamped with the current time.
+    pub fn manifest(
+        action: Action,
+        manifest_reference: &ManifestReference,
+        digest: ImageDigest,
+        media_type: Option<String>,
+        size: Option<u64>,
+        actor: Option<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            timestamp: rfc3339(SystemTime::now()),
+            action,
+            target: Target {
+                media_type,
+                size,
+                digest,
+                repository: manifest_reference.location().to_string(),
+                tag: manifest_reference
+                    .reference()
+                    .as_tag()
+                    .map(ToOwned::to_owned),
+            },
+            actor: Actor { name: actor },
+        }
+    }
+}
+
+/// Envelope around events sent to webhooks.
+#[derive(Debug, Serialize)]
+struct Envelope<'a> {
+    events: [&'a Event; 1],
+}
+
+/// Dispatcher for events to all configured webhooks.
+#[derive(Debug)]
+pub(crate) struct Webhooks {
+    /// Configured endpoints.
+    endpoints: Arc<Vec<Webhook>>,
+    /// HTTP client used for delivery.
+    http: reqwest::Client,
+}
+
+impl Webhooks {
+    /// Creates a new dispatcher for the given endpoints.
+    pub(crate) fn new(endpoints: Vec<Webhook>) -> Self {
+        Self {
+            endpoints: Arc::new(endpoints),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Enqueues an event for delivery to all endpoints.
+    ///
+    /// Returns immediately, delivery happens on a background task.
+    pub(crate) fn notify(&self, event: Event) {
+        if self.endpoints.is_empty() {
+            return;
+        }
+
+        let endpoints = self.endpoints.clone();
+        let http = self.http.clone();
+        tokio::spawn(async move {
+            let payload = Arc::new(
+                serde_json::to_vec(&Envelope { events: [&event] })
+                    .expect("event serialization should not fail"),
+            );
+
+            for endpoint in endpoints.iter() {
+                tokio::spawn(deliver(http.clone(), endpoint.clone(), payload.clone()));
+            }
+        });
+    }
+}
+
+/// Delivers a payload to a single endpoint, retrying with backoff.
+async fn deliver(http: reqwest::Client, endpoint: Webhook, payload: Arc<Vec<u8>>) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=endpoint.max_attempts {
+        let mut request = http
+            .post(&endpoint.url)
+            .timeout(endpoint.timeout)
+            .header(
+                CONTENT_TYPE,
+                "application/vnd.docker.distribution.events.v1+json",
+            )
+            .body(payload.as_ref().clone());
+        if let Some(ref authorization) = endpoint.authorization {
+            request = request.header(AUTHORIZATION, authorization.reveal());
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_server_error() => {
+                warn!(url = %endpoint.url, attempt, status = %response.status(), "webhook delivery failed");
+            }
+            Ok(response) => {
+                if !response.status().is_success() {
+                    warn!(url = %endpoint.url, status = %response.status(), "webhook rejected event");
+                } else {
+                    debug!(url = %endpoint.url, "webhook delivered");
+                }
+                return;
+            }
+            Err(err) => {
+                warn!(url = %endpoint.url, attempt, %err, "webhook delivery failed");
+            }
+        }
+
+        if attempt < endpoint.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    warn!(url = %endpoint.url, "giving up on webhook delivery");
+}
+
+/// Formats a point in time as an RFC 3339 timestamp in UTC, e.g. `2024-08-14T12:00:00Z`.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, secs_of_day) = civil_time(time);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        (secs_of_day / 60) % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Splits a point in time into the year, month, day and seconds of the day in UTC.
+pub(crate) fn civil_time(time: SystemTime) -> (i64, i64, i64, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Converts days since the epoch to a civil date, see
+    // <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, secs_of_day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::rfc3339;
+
+    #[test]
+    fn formats_rfc3339_timestamps() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(1723636800)),
+            "2024-08-14T12:00:00Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951782400 + 86399)),
+            "2000-02-29T23:59:59Z"
+        );
+    }
+}
@@ -1,8 +1,17 @@
+use std::fmt::{self, Display};
+
 use base64::Engine;
 use nom::{
+    branch::alt,
     bytes::complete::{tag_no_case, take_while, take_while1},
-    character::is_space,
-    combinator::map_res,
+    character::{
+        complete::{char, space0},
+        is_space,
+    },
+    combinator::{map, map_res},
+    error::{Error, ErrorKind},
+    multi::separated_list1,
+    sequence::{delimited, separated_pair},
     IResult,
 };
 
@@ -45,9 +54,127 @@ pub(crate) fn basic_auth_response(input: &[u8]) -> IResult<&[u8], BasicAuthRespo
     Ok((input, basic))
 }
 
+/// Returns a `Basic` challenge for the given realm, as sent in a `WWW-Authenticate` header.
+pub(crate) fn basic_challenge(realm: &str) -> String {
+    format!("Basic realm=\"{}\"", escape(realm))
+}
+
+/// A `Bearer` challenge, as sent in a `WWW-Authenticate` header.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct BearerChallenge {
+    /// URL of the token endpoint.
+    pub realm: String,
+    /// Name of the service to request a token for.
+    pub service: Option<String>,
+    /// Requested scopes, e.g. `repository:library/nginx:pull,push`.
+    pub scopes: Vec<String>,
+}
+
+impl Display for BearerChallenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bearer realm=\"{}\"", escape(&self.realm))?;
+
+        if let Some(ref service) = self.service {
+            write!(f, ",service=\"{}\"", escape(service))?;
+        }
+
+        // Multiple scopes are separated by spaces, commas are part of a scope.
+        if !self.scopes.is_empty() {
+            write!(f, ",scope=\"{}\"", escape(&self.scopes.join(" ")))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes a value for use inside a quoted string.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Parses a quoted string, removing escapes.
+fn quoted_string(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
+    let (mut input, _) = char('"')(input)?;
+
+    let mut value = Vec::new();
+    loop {
+        match input {
+            [b'"', rest @ ..] => return Ok((rest, value)),
+            [b'\\', c, rest @ ..] | [c, rest @ ..] => {
+                value.push(*c);
+                input = rest;
+            }
+            [] => return Err(nom::Err::Error(Error::new(input, ErrorKind::Char))),
+        }
+    }
+}
+
+/// Parses a single `key=value` parameter of a challenge, where `value` may be quoted.
+fn auth_param(input: &[u8]) -> IResult<&[u8], (String, String)> {
+    let token = take_while1(|c: u8| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c));
+    let unquoted = map(
+        take_while1(|c: u8| !c.is_ascii_whitespace() && c != b','),
+        <[u8]>::to_vec,
+    );
+
+    map_res(
+        separated_pair(
+            token,
+            delimited(space0, char('='), space0),
+            alt((quoted_string, unquoted)),
+        ),
+        |(key, value)| {
+            Ok::<_, std::string::FromUtf8Error>((
+                String::from_utf8(key.to_vec())?.to_ascii_lowercase(),
+                String::from_utf8(value)?,
+            ))
+        },
+    )(input)
+}
+
+pub(crate) fn bearer_challenge(input: &[u8]) -> IResult<&[u8], BearerChallenge> {
+    // Skip leading whitespace.
+    let input = skip_whitespace(input);
+
+    // Match tag.
+    let (input, _) = tag_no_case("bearer")(input)?;
+    let input = skip_whitespace(input);
+
+    let (input, params) = separated_list1(delimited(space0, char(','), space0), auth_param)(input)?;
+
+    let mut realm = None;
+    let mut service = None;
+    let mut scopes = Vec::new();
+    for (key, value) in params {
+        match key.as_str() {
+            "realm" => realm = Some(value),
+            "service" => service = Some(value),
+            "scope" => scopes.extend(value.split_ascii_whitespace().map(ToOwned::to_owned)),
+            _ => {
+                // Other parameters, e.g. `error`, are not of interest.
+            }
+        }
+    }
+
+    let Some(realm) = realm else {
+        return Err(nom::Err::Error(Error::new(input, ErrorKind::Verify)));
+    };
+
+    Ok((
+        input,
+        BearerChallenge {
+            realm,
+            service,
+            scopes,
+        },
+    ))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::www_authenticate::{basic_auth_response, BasicAuthResponse};
+    use crate::www_authenticate::{
+        basic_auth_response, bearer_challenge, BasicAuthResponse, BearerChallenge,
+    };
 
     #[test]
     fn can_parse_known_response() {
@@ -76,4 +203,66 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn can_parse_known_bearer_challenge() {
+        let input = br#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull,push""#;
+
+        assert_eq!(
+            bearer_challenge(input),
+            Ok((
+                &b""[..],
+                BearerChallenge {
+                    realm: "https://auth.docker.io/token".to_owned(),
+                    service: Some("registry.docker.io".to_owned()),
+                    scopes: vec!["repository:library/nginx:pull,push".to_owned()],
+                }
+            ))
+        );
+
+        // Unquoted values, extra whitespace and unknown parameters.
+        let input = b"bearer realm=https://example.com/token , error=\"insufficient_scope\"";
+        assert_eq!(
+            bearer_challenge(input).map(|(_, challenge)| challenge),
+            Ok(BearerChallenge {
+                realm: "https://example.com/token".to_owned(),
+                service: None,
+                scopes: Vec::new(),
+            })
+        );
+
+        assert!(bearer_challenge(b"Bearer service=\"no-realm\"").is_err());
+        assert!(bearer_challenge(b"Basic realm=\"registry\"").is_err());
+    }
+
+    #[test]
+    fn bearer_challenge_round_trips() {
+        for challenge in [
+            BearerChallenge {
+                realm: "https://example.com/token".to_owned(),
+                service: None,
+                scopes: Vec::new(),
+            },
+            BearerChallenge {
+                realm: "https://example.com/token".to_owned(),
+                service: Some("a \"quoted\" \\ service".to_owned()),
+                scopes: vec!["repository:library/nginx:pull".to_owned()],
+            },
+            BearerChallenge {
+                realm: "https://example.com/token".to_owned(),
+                service: Some("registry.example.com".to_owned()),
+                scopes: vec![
+                    "repository:library/nginx:pull,push".to_owned(),
+                    "repository:library/alpine:pull".to_owned(),
+                    "registry:catalog:*".to_owned(),
+                ],
+            },
+        ] {
+            let built = challenge.to_string();
+            let (rest, parsed) = bearer_challenge(built.as_bytes()).expect("failed to parse");
+
+            assert!(rest.is_empty(), "trailing input in {built}");
+            assert_eq!(parsed, challenge);
+        }
+    }
 }